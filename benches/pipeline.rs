@@ -0,0 +1,119 @@
+//! Benchmarks of the main stages of a build, on a small bundled excerpt of
+//! JMDict: parsing, lookup key generation, merging, html generation, and
+//! writing.
+//!
+//! The definitions of the words are taken from JMDict itself, standing in
+//! for a Yomichan dictionary.  Writing is measured with the StarDict
+//! writer, since the Kobo writer needs `marisa-build`.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use kobo_jp_dict::generic_dict::{self, EntrySettings};
+use kobo_jp_dict::jmdict::{self, WordEntry};
+use kobo_jp_dict::merged::{self, MergedEntry};
+use kobo_jp_dict::source::{SourceItem, Tables};
+use kobo_jp_dict::stardict;
+use kobo_jp_dict::yomichan::{Definition, InflectionType, TermEntry};
+
+const JMDICT: &[u8] = include_bytes!("fixtures/jmdict_mini.xml");
+
+fn words() -> Vec<WordEntry> {
+    jmdict::Parser::from_reader(JMDICT).collect()
+}
+
+/// The tables of the excerpt, with a term for each reading and writing of
+/// each word.
+fn tables(words: &[WordEntry]) -> Tables {
+    let dict_name: Arc<str> = "jmdict".into();
+    let mut tables = Tables::new();
+    for word in words.iter() {
+        for writing in word.writings.iter().chain(word.readings.iter().take(1)) {
+            tables.add(SourceItem::Term(TermEntry {
+                dict_name: dict_name.clone(),
+                writing: writing.clone(),
+                reading: word.readings[0].clone(),
+                definitions: Definition::List((
+                    "".into(),
+                    word.definitions
+                        .iter()
+                        .map(|d| Definition::Def(d.clone()))
+                        .collect(),
+                )),
+                infl: InflectionType::None,
+                tags: Vec::new(),
+                commonness: 0,
+            }));
+        }
+        tables.add(SourceItem::Word(word.clone()));
+    }
+    tables
+}
+
+fn merge(tables: Tables) -> Vec<MergedEntry> {
+    merged::merge_entries(
+        tables.yomi_term_table,
+        tables.yomi_name_table,
+        tables.yomi_kanji_table,
+        tables.jm_table,
+        tables.pa_table,
+        tables.pa_pos_table,
+        tables.phonetics_table,
+        EntrySettings::default(),
+    )
+    .unwrap()
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let words = words();
+    let merged = merge(tables(&words));
+    let entries = generic_dict::generate_entries(&merged, EntrySettings::default()).unwrap();
+
+    c.bench_function("jmdict parsing", |b| {
+        b.iter(|| jmdict::Parser::from_reader(JMDICT).count())
+    });
+
+    c.bench_function("lookup keys", |b| {
+        b.iter(|| {
+            words
+                .iter()
+                .map(|w| generic_dict::generate_lookup_keys(w, EntrySettings::default()).len())
+                .sum::<usize>()
+        })
+    });
+
+    let extensive = EntrySettings {
+        conjugation_level: generic_dict::ConjugationLevel::Extensive,
+        loanword_variants: true,
+        old_text_variants: true,
+        historical_kana: true,
+        kyujitai_keys: true,
+        ..EntrySettings::default()
+    };
+    c.bench_function("lookup keys, all variants", |b| {
+        b.iter(|| {
+            words
+                .iter()
+                .map(|w| generic_dict::generate_lookup_keys(w, extensive).len())
+                .sum::<usize>()
+        })
+    });
+
+    c.bench_function("merging", |b| {
+        b.iter_batched(|| tables(&words), merge, BatchSize::SmallInput)
+    });
+
+    c.bench_function("html generation", |b| {
+        b.iter(|| generic_dict::generate_entries(&merged, EntrySettings::default()))
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.zip");
+    c.bench_function("writing", |b| {
+        b.iter(|| stardict::write_dictionary(&entries, &path, false, None).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
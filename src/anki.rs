@@ -0,0 +1,53 @@
+//! Types and functions for exporting entries as Anki flashcards.
+//!
+//! Rather than writing an `.apkg` directly, this writes a tab-separated text
+//! file that Anki can import, with the word on the front of the card and the
+//! entry html on the back.
+
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::generic_dict::{Entry, EntryIndex};
+
+/// Writes a card for each word in `words` that has at least one matching
+/// entry.
+///
+/// Returns the words that had no matching entries.
+pub fn write_tsv(
+    entries: &[Entry],
+    words: &[String],
+    output_path: &Path,
+) -> std::io::Result<Vec<String>> {
+    let index = EntryIndex::new(entries);
+
+    let mut missing = Vec::new();
+    let mut out = BufWriter::new(std::fs::File::create(output_path)?);
+
+    // Tell Anki how to interpret the file.
+    out.write_all(b"#separator:tab\n#html:true\n")?;
+
+    for word in words.iter() {
+        let matches = index.lookup(word);
+        if matches.is_empty() {
+            missing.push(word.clone());
+            continue;
+        }
+
+        let mut back = String::new();
+        for entry in matches.iter() {
+            back.push_str(&entry.definition);
+        }
+
+        out.write_all(format!("{}\t{}\n", tsv_field(word), tsv_field(&back)).as_bytes())?;
+    }
+
+    out.flush()?;
+
+    Ok(missing)
+}
+
+/// Makes text safe to use as a single TSV field.
+fn tsv_field(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
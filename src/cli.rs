@@ -0,0 +1,749 @@
+//! The command line of the dictionary builder.
+//!
+//! The options of the main command are split by what they affect, and each
+//! group gets its own section in "--help".
+
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "Kobo Japanese Dictionary Builder",
+    version,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    /// The output filepath to write the new dictionary to.
+    #[arg(required = true)]
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub sources: SourceOptions,
+
+    #[command(flatten)]
+    pub keys: KeyOptions,
+
+    #[command(flatten)]
+    pub rendering: RenderingOptions,
+
+    #[command(flatten)]
+    pub output_options: OutputOptions,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Where the entries come from, and what's kept of them.
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Sources")]
+pub struct SourceOptions {
+    /// Path to a custom pitch accent file in .tsv format.  Will be used
+    /// instead of the bundled pitch accent data.  Can be given more than
+    /// once, with the accents of later files replacing those of earlier ones
+    /// for the same word.  Words whose accents are replaced are listed in
+    /// the warnings summary.
+    #[arg(short = 'p', long = "pitch_accent", value_name = "PATH")]
+    pub pitch_accent: Vec<PathBuf>,
+
+    /// Path to a JMDict file to use instead of the bundled one.  Both the
+    /// XML format and the JSON format of the jmdict-simplified project are
+    /// supported, optionally gzipped.
+    #[arg(long, alias = "jmdict-json", value_name = "PATH")]
+    pub jmdict: Option<PathBuf>,
+
+    /// Path to a kanji component file, in KRADFILE (e.g. kradfile-u) or IDS
+    /// (e.g. CHISE ids.txt) format.  The components or decomposition of each
+    /// kanji are shown in kanji entries.  Can be given more than once, e.g.
+    /// for one file of each format.
+    #[arg(long, value_name = "PATH")]
+    pub kanji_components: Vec<PathBuf>,
+
+    /// Path to the directory of KanjiVG SVG files (e.g. "kanji/" from the
+    /// KanjiVG release).  A stroke-order diagram is added to each kanji
+    /// entry.  This makes the dictionary noticeably larger.
+    #[arg(long, value_name = "DIR")]
+    pub kanjivg: Option<PathBuf>,
+
+    /// Path to a zipped Yomichan dictionary.  Will add either additional
+    /// definitions to existing entries or completely new entries, depending
+    /// the dictionary.
+    #[arg(short = 'y', long = "yomichan", value_name = "PATH")]
+    pub yomichan_dict: Vec<PathBuf>,
+
+    /// Path to a Japanese Wiktionary XML dump (e.g.
+    /// jawiktionary-latest-pages-articles.xml), uncompressed or gzipped.
+    /// The Japanese definitions and etymologies of its articles are added to
+    /// the entries of the words they match, after those of any Yomichan
+    /// dictionaries.
+    #[arg(long, value_name = "PATH")]
+    pub wiktionary: Option<PathBuf>,
+
+    /// Path to a PDIC text dictionary, in Eijiro ("■headword : translation")
+    /// or PDIC one-line ("headword /// translation") format, e.g. the J-E
+    /// part of Eijiro.  Its translations are added to the entries of the
+    /// Japanese words they match.  Can be given more than once.
+    #[arg(long, value_name = "PATH")]
+    pub pdic: Vec<PathBuf>,
+
+    /// Path to an existing Kobo dictionary.  Its entries will be added as-is
+    /// to the new dictionary.
+    #[arg(long, value_name = "PATH")]
+    pub add_kobo_dict: Vec<PathBuf>,
+
+    /// Path to a file of merged entries written with "--format jsonl".  Its
+    /// entries will be added to the new dictionary.
+    #[arg(long, value_name = "PATH")]
+    pub from_jsonl: Vec<PathBuf>,
+
+    /// The text encoding of an input text file (pitch accent, kanji
+    /// component, PDIC, word list or notes file), e.g. "kradfile=euc-jp",
+    /// for when it isn't detected correctly.  The file is named by its path
+    /// as given, or its file name.  Without this, UTF-8, UTF-16 with a byte
+    /// order mark, Shift-JIS and EUC-JP are detected.  Can be given more
+    /// than once.
+    #[arg(long, value_name = "PATH=ENCODING")]
+    pub encoding: Vec<String>,
+
+    /// Only read the first N entries of the given source, e.g.
+    /// "jmdict=1000".  The source is "jmdict" or the path or file name
+    /// (without extension) of a Yomichan or Kobo dictionary.  For Yomichan
+    /// dictionaries the limit applies separately to words, names, and kanji.
+    #[arg(long, value_name = "SOURCE=N")]
+    pub limit: Vec<String>,
+
+    /// Stop with an error on malformed Yomichan dictionary data, instead of
+    /// skipping it with a warning.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Remove images from Yomichan dictionary definitions, instead of
+    /// embedding them in the entries.
+    #[arg(long)]
+    pub strip_images: bool,
+
+    /// How to handle duplicate Yomichan data, e.g. from two versions of the
+    /// same dictionary.  "replace" drops the data of a dictionary if a later
+    /// one has the same title, "append" keeps both, and either way
+    /// definitions identical to ones already given for the same word are
+    /// dropped.  "off" keeps everything.
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = ["off", "replace", "append"],
+        default_value = "off"
+    )]
+    pub dedupe_yomichan: String,
+
+    /// Leave out the definitions a source dictionary gives for a word when
+    /// they mostly repeat the definitions of an earlier source, e.g. the
+    /// same JMDict glosses from two dictionaries.  THRESHOLD is the fraction
+    /// of their words that has to appear in the earlier definitions, over 0
+    /// and at most 1, e.g. 0.9.
+    #[arg(long, value_name = "THRESHOLD")]
+    pub dedupe_similar: Option<f64>,
+
+    /// Clean up the definitions of the source dictionaries so that merged
+    /// entries look uniform.  RULES is a comma-separated list of: "spaces"
+    /// to collapse repeated spaces, "separators" to remove trailing
+    /// semicolons and commas, and "periods" to remove the final period (.
+    /// or 。) of each definition.  Or "all" for all of them.
+    #[arg(long, value_name = "RULES")]
+    pub normalize_glosses: Option<String>,
+
+    /// Path to a word list, one word per line.  Only entries whose written
+    /// form or reading is in the list will be included in the dictionary.
+    #[arg(long, value_name = "PATH")]
+    pub include_words: Option<PathBuf>,
+
+    /// Path to a word list, one word per line.  Entries whose written form
+    /// or reading is in the list will be left out of the dictionary.
+    #[arg(long, value_name = "PATH")]
+    pub exclude_words: Option<PathBuf>,
+
+    /// A regular expression.  Definitions from Yomichan dictionaries that
+    /// match it will be left out of the dictionary.
+    #[arg(long, value_name = "REGEX")]
+    pub exclude_dict_pattern: Option<String>,
+
+    /// Only show the first N definitions a Yomichan dictionary gives for
+    /// each word, e.g. "大辞林=3", to keep entries short without leaving the
+    /// dictionary out.  The dictionary is named by its title, without any
+    /// parenthesized part.  Can be given more than once.
+    #[arg(long, value_name = "SOURCE=N")]
+    pub max_senses: Vec<String>,
+
+    /// Change the label shown above the definitions of a Yomichan
+    /// dictionary, e.g. "三省堂スーパー大辞林=大辞林".  The name to change
+    /// is the dictionary's title, without any parenthesized part.  An empty
+    /// new name hides the label.  Can be given more than once.
+    #[arg(long, value_name = "FROM=TO")]
+    pub rename_dict: Vec<String>,
+
+    /// Comma-separated list of name types to keep from name dictionaries,
+    /// e.g. "surname,given,place".  The types are the tags of the name
+    /// dictionary, such as JMnedict's "surname", "given", "place", "person",
+    /// "company", and "station".  "given" also includes names tagged "masc"
+    /// or "fem".
+    #[arg(long, value_name = "TYPES")]
+    pub name_types: Option<String>,
+
+    /// Path to a JSON file of corrections to specific words, applied after
+    /// the sources are merged.  Patches can delete a word or some of its
+    /// definitions, replace its readings or definitions, or change its
+    /// priority.  See src/patch.rs for the format.
+    #[arg(long, value_name = "PATH")]
+    pub patch: Option<PathBuf>,
+
+    /// Path to a file of notes to add to the entries of words, e.g.
+    /// mnemonics or corrections.  Each line has a headword, its reading, and
+    /// the note as html, separated by tabs.  The reading may be left empty
+    /// to add the note to all readings of the headword.
+    #[arg(long, value_name = "PATH")]
+    pub notes: Option<PathBuf>,
+
+    /// Path to a text file, or a directory of them, of the kind of text the
+    /// dictionary will be used on.  Kanji compounds in it that have no entry
+    /// are given a breakdown entry listing the words they're made of, so
+    /// that selecting them doesn't show nothing.
+    #[arg(long, value_name = "PATH")]
+    pub compound_corpus: Option<PathBuf>,
+
+    /// How many times a compound has to occur in the corpus given with
+    /// --compound-corpus to get a breakdown entry.
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub compound_min_count: usize,
+}
+
+// Which lookup keys entries get, and in what order entries sharing a key
+// are shown.
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Lookup keys")]
+pub struct KeyOptions {
+    /// How many inflected forms of verbs and adjectives to add as lookup
+    /// keys.  "minimal" only adds the conjugation stems (e.g. 書か, 書き),
+    /// "standard" also adds the most common forms (e.g. 書かない, 書いた),
+    /// and "extensive" also adds polite, desiderative, conditional and
+    /// volitional forms (e.g. 書きました, 書きたい, 書けば, 書こう).  More
+    /// keys make the dictionary larger and lookups slower.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = ["minimal", "standard", "extensive"],
+        default_value = "standard"
+    )]
+    pub conjugation_level: String,
+
+    /// Don't add katakana versions of hiragana lookup keys.  Older Kobo
+    /// firmware needs them to find hiragana words, but newer firmware
+    /// doesn't, and leaving them out makes the dictionary smaller.
+    #[arg(long)]
+    pub no_katakana_keys: bool,
+
+    /// Add lookup keys for the other common spellings of katakana loanwords:
+    /// with ー or an explicit vowel (メール/メイル), with or without a final
+    /// ー (コンピューター/コンピュータ), and with ヴ or the バ row
+    /// (ヴァイオリン/バイオリン).
+    #[arg(long)]
+    pub loanword_variants: bool,
+
+    /// Add lookup keys for the spellings of words in older printed text:
+    /// with iteration marks instead of repeated characters or the other way
+    /// around (こゝろ/こころ, 時々/時時, いすゞ/いすず), and with full-size
+    /// kana instead of small ones (きよう/きょう).  This makes the
+    /// dictionary noticeably larger.
+    #[arg(long)]
+    pub old_text_variants: bool,
+
+    /// Add lookup keys for likely historical kana spellings (旧仮名遣い) of
+    /// words, for older literature, e.g. てふてふ for ちょうちょう, 思ふ for
+    /// 思う, and ゐる for いる.  These are derived from the modern spellings
+    /// by rule, so not all of them are real historical spellings.  This
+    /// makes the dictionary noticeably larger.
+    #[arg(long)]
+    pub historical_kana: bool,
+
+    /// Add lookup keys with the traditional forms (旧字体) of kanji, for
+    /// texts printed before the character reform, e.g. 學校 for 学校 and 體
+    /// for 体.
+    #[arg(long)]
+    pub kyujitai_keys: bool,
+
+    /// Support onomatopoeia dictionaries: merge the definitions of katakana
+    /// headwords into JMDict's hiragana ones (ニコニコ into にこにこ), give
+    /// onomatopoeia hiragana lookup keys for their katakana forms, and mark
+    /// them as onomatopoeia in the header.
+    #[arg(long)]
+    pub onomatopoeia: bool,
+
+    /// Learner mode for readers who only know the kana of a word: add
+    /// hiragana lookup keys for all readings of kanji words, and prioritize
+    /// them like the kana forms of words usually written in kana.
+    #[arg(long)]
+    pub kana_keys_only: bool,
+
+    /// Make words from the frequency dictionaries that start with a prefix
+    /// or end with a suffix, and that have no entry of their own, look up
+    /// the affix's entry.  Only words with a frequency rank of at most N are
+    /// used.
+    #[arg(long, value_name = "N")]
+    pub affix_keys: Option<u32>,
+
+    /// Limit the dictionary to N distinct lookup keys.  The keys with the
+    /// lowest priority, such as rare conjugations and variants, are removed
+    /// first, from all entries.  Each entry keeps at least its headword.
+    #[arg(long, value_name = "N")]
+    pub max_keys: Option<usize>,
+
+    /// What to do with writings and readings that JMDict marks as irregular
+    /// or outdated (e.g. irregular kanji usage).  "keep" treats them like
+    /// any other form, "demote" lists them last in entry headers and lowers
+    /// their lookup priority, and "exclude" leaves them out of entry headers
+    /// and lookup keys.  Writings that don't apply to the reading shown are
+    /// also demoted or excluded.
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = ["keep", "demote", "exclude"],
+        default_value = "keep"
+    )]
+    pub irregular_forms: String,
+
+    /// Priority of kanji entries, which controls where they're shown
+    /// relative to word entries.  When several entries share a lookup key,
+    /// lower priorities are shown first.  Word priorities are derived from
+    /// JMDict, and range from roughly 0 to 100000.  [default: 0]
+    #[arg(long, value_name = "N")]
+    pub weight_kanji: Option<u32>,
+
+    /// Priority of name entries.  [default: 4294967295, i.e. always last]
+    #[arg(long, value_name = "N")]
+    pub weight_name: Option<u32>,
+
+    /// Divide the priority of words that JMDict marks as common by this.
+    /// [default: 1]
+    #[arg(long, value_name = "N")]
+    pub weight_common: Option<u32>,
+
+    /// Divide the priority of the kana forms of words that are usually
+    /// written in kana by this.  [default: 8]
+    #[arg(long, value_name = "N")]
+    pub weight_kana_preferred: Option<u32>,
+
+    /// Divide the priority of verbs by this.  [default: 4]
+    #[arg(long, value_name = "N")]
+    pub weight_verb: Option<u32>,
+
+    /// Divide the priority of i-adjectives by this.  [default: 2]
+    #[arg(long, value_name = "N")]
+    pub weight_i_adjective: Option<u32>,
+
+    /// Add this to the priority of inflected forms, so that exact matches
+    /// are shown before them.  [default: 200000]
+    #[arg(long, value_name = "N")]
+    pub weight_inflection: Option<u32>,
+
+    /// Add this to the priority of forms that JMDict marks as irregular or
+    /// outdated, when using "--irregular-forms demote".  [default: 100000]
+    #[arg(long, value_name = "N")]
+    pub weight_irregular: Option<u32>,
+}
+
+// What the entries look like.
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Rendering")]
+pub struct RenderingOptions {
+    /// Use katakana instead of hiragana for word pronunciation.
+    #[arg(short = 'k', long = "katakana")]
+    pub katakana_pronunciation: bool,
+
+    /// Use the terms "other-move" and "self-move" instead of "transitive"
+    /// and "intransitive".  The former is more accurate to how Japanese
+    /// works, but the latter are more commonly known and used.
+    #[arg(short = 'm', long = "use_move_terms")]
+    pub use_move_terms: bool,
+
+    /// Use the Japanese terms for "verb", "transitive", etc. instead of
+    /// English in entry headers.
+    #[arg(short = 'j', long = "use_japanese_terms")]
+    pub use_japanese_terms: bool,
+
+    /// Auto-generate furigana on native Japanese definitions.
+    #[arg(short = 'f', long = "generate_furigana")]
+    pub generate_furigana: bool,
+
+    /// The maximum number of writings to show in the header of each entry.
+    /// Writings are shown in JMDict's order, which puts the more common ones
+    /// first, after any reordering by "--irregular-forms".  See
+    /// "--extra-writings" for what happens to the rest.
+    #[arg(long, value_name = "N")]
+    pub max_header_writings: Option<usize>,
+
+    /// What to do with the writings beyond "--max-header-writings".
+    /// "other-forms" lists them on a smaller line under the header, and
+    /// "hide" leaves them out of the entry.  They're still used as lookup
+    /// keys either way.
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = ["other-forms", "hide"],
+        default_value = "other-forms"
+    )]
+    pub extra_writings: String,
+
+    /// Show at most N readings of each name.  Names with the same writing
+    /// are grouped into a single entry, which for common surnames can
+    /// otherwise get very long.
+    #[arg(long, value_name = "N")]
+    pub max_name_senses: Option<usize>,
+
+    /// List the N most common words containing each kanji in its kanji
+    /// entry, with their readings and first gloss.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub kanji_examples: usize,
+
+    /// What to put at the top of each entry, to separate it from the
+    /// previous one when Kobo shows several entries together.  "hr" is a
+    /// horizontal rule, "blank" an empty line, and "none" nothing, which
+    /// leaves the first line of the lookup popup for the entry itself.
+    /// [default: hr]
+    #[arg(long, value_name = "SEPARATOR", value_parser = ["hr", "blank", "none"])]
+    pub entry_separator: Option<String>,
+
+    /// Render entries for Kobo's small lookup popup: the reading, pitch
+    /// accent, writings and part of speech all on one line, with no spacing
+    /// before the definitions and no furigana.  Also uses no entry
+    /// separator, unless one is given with "--entry-separator".
+    #[arg(long)]
+    pub compact_popup: bool,
+
+    /// Show the JMDict part of speech and usage tags of words in their
+    /// headers, instead of the built-in part of speech text: as JMDict codes
+    /// ("v5k, vt, uk"), short labels ("godan verb, transitive, usu. kana"),
+    /// or JMDict's full descriptions ("Godan verb with 'ku' ending,
+    /// transitive verb, word usually written using kana alone").
+    #[arg(long, value_name = "STYLE", value_parser = ["code", "short", "long"])]
+    pub tag_style: Option<String>,
+
+    /// Note the source language of loanwords at the end of their entries,
+    /// e.g. "from Portuguese: pão" for パン.
+    #[arg(long)]
+    pub show_lsource: bool,
+
+    /// Show the polite, te-form, negative, and past forms of verbs and
+    /// i-adjectives under their entry headers.
+    #[arg(long)]
+    pub show_conjugations: bool,
+
+    /// Show the pitch accents of the polite, te-form, negative, and past
+    /// forms of verbs in a small table under their entry headers, derived
+    /// from the accent of the dictionary form.
+    #[arg(long)]
+    pub conjugation_accents: bool,
+
+    /// Show the reading larger in entry headers, and as furigana above the
+    /// kanji writings.
+    #[arg(long)]
+    pub furigana_headers: bool,
+
+    /// Mark prefixes and suffixes in entry headers with a 〜 on the side
+    /// they attach to, e.g. 〜的.
+    #[arg(long)]
+    pub mark_affixes: bool,
+
+    /// Mark devoiced morae (e.g. き̥) and nasalized が行 morae (e.g. か゚) in
+    /// the readings of entry headers.  This needs a Yomichan pitch accent
+    /// dictionary that has this information, such as an NHK-based one.
+    #[arg(long)]
+    pub phonetic_detail: bool,
+
+    /// Group the definitions of words that have more than one part of
+    /// speech (e.g. 勉強, a noun and a する verb) by part of speech, with a
+    /// header like "［名］" or "［動］" for each group, as in Japanese
+    /// dictionaries.  Only works for Yomichan dictionaries that tag their
+    /// definitions with parts of speech, such as JMdict-based ones.  Pitch
+    /// accents that only apply to some parts of speech (e.g. "(副)0,(名)3"
+    /// in the bundled data) are shown next to their group instead of in the
+    /// header.
+    #[arg(long)]
+    pub group_by_pos: bool,
+
+    /// Put the definitions of all but the first dictionary of a word,
+    /// conjugation tables, and kanji example words in collapsed sections
+    /// that can be tapped to expand, so long entries don't need scrolling.
+    /// E-readers that don't support collapsed sections show them expanded.
+    #[arg(long)]
+    pub collapse_sections: bool,
+
+    /// What to do with audio, video, and other media in definitions, which
+    /// e-readers can't play.  "strip" removes them, "keep-text" keeps only
+    /// their text, and "footnote" additionally lists the media files at the
+    /// end of the entry.
+    #[arg(
+        long,
+        value_name = "POLICY",
+        value_parser = ["strip", "keep-text", "footnote"],
+        default_value = "strip"
+    )]
+    pub media_policy: String,
+
+    /// The size of the smaller and larger text in entries, such as part of
+    /// speech labels and the kanji of kanji entries, relative to the
+    /// e-reader's font size.  "large" is easier to read on older,
+    /// low-resolution e-readers.
+    #[arg(
+        long,
+        value_name = "SCALE",
+        value_parser = ["compact", "normal", "large"],
+        default_value = "normal"
+    )]
+    pub text_scale: String,
+
+    /// The color scheme to style entries for.  "light" keeps the colors of
+    /// the source dictionaries.  "dark" removes all text and background
+    /// colors, which e-reader dark modes invert badly.  "auto" keeps text
+    /// colors that read well either way, but removes backgrounds and black,
+    /// white and gray text.
+    #[arg(
+        long,
+        value_name = "THEME",
+        value_parser = ["light", "dark", "auto"],
+        default_value = "light"
+    )]
+    pub theme: String,
+}
+
+// What gets written, and what's reported about the build.
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Output")]
+pub struct OutputOptions {
+    /// The output dictionary format.  "koreader" is a StarDict dictionary
+    /// that also includes inflected forms as synonyms, for use with
+    /// KOReader.  "slob" is an Aard 2 dictionary, for Aard 2 on Android and
+    /// GoldenDict-ng.  "jsonl" writes the merged entries as newline-delimited
+    /// JSON, which can be read back in with --from-jsonl.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = ["kobo", "stardict", "koreader", "slob", "jsonl"],
+        default_value = "kobo"
+    )]
+    pub format: String,
+
+    /// Generate a (zipped) StarDict dictionary instead of a Kobo dictionary.
+    /// Shorthand for "--format stardict".
+    #[arg(short = 's', long = "stardict_format")]
+    pub stardict_format: bool,
+
+    /// Compression level of the Kobo dictionary file, from 0 to 9, or
+    /// "store" for no compression at all.  Lower levels build faster but
+    /// produce larger files.  [default: 1]
+    #[arg(long, value_name = "LEVEL")]
+    pub compression: Option<kobo_jp_dict::kobo::Compression>,
+
+    /// Write the html of the entries more compactly, and for StarDict,
+    /// KOReader and slob output move the most repeated inline styles to a
+    /// stylesheet.  Prints how many bytes this saves.
+    #[arg(long)]
+    pub optimize_size: bool,
+
+    /// Only write the first N entries.  Useful for quick test builds.
+    #[arg(long, value_name = "N")]
+    pub limit_entries: Option<usize>,
+
+    /// Also write an English to Japanese Kobo dictionary to the given file,
+    /// keyed on the words of the JMDict glosses.  Useful for reading
+    /// parallel texts.
+    #[arg(long, value_name = "PATH")]
+    pub reverse_output: Option<PathBuf>,
+
+    /// Add an entry with information about the build (the builder version,
+    /// the build date, the files it was built from, and the command line
+    /// options), found by looking up "じしょばん".  Useful for telling
+    /// which build is installed on a device.
+    #[arg(long)]
+    pub build_info: bool,
+
+    /// Write the SQL that registers the Kobo dictionary in a Kobo's
+    /// ".kobo/KoboReader.sqlite" database to the given file.  Older
+    /// firmware versions only show registered dictionaries, which they
+    /// read from ".kobo/dict/".  Run it with e.g. "sqlite3 KoboReader.sqlite
+    /// < register.sql" while the Kobo is connected.  The dictionary's file
+    /// name has to be the one it will have on the device.
+    #[arg(long, value_name = "PATH")]
+    pub register_sql: Option<PathBuf>,
+
+    /// The name to show for the dictionary in the Kobo's dictionary list,
+    /// with "--register-sql".  [default: "日本語 - English" for
+    /// Japanese-English dictionaries, "日本語" otherwise]
+    #[arg(long, value_name = "TITLE", requires = "register_sql")]
+    pub title: Option<String>,
+
+    /// Write a list of the entries whose html had to be cleaned up, and what
+    /// was changed, to the given file.
+    #[arg(long, value_name = "PATH")]
+    pub report_sanitized: Option<PathBuf>,
+
+    /// Write the pitch accent rows, Yomichan terms and Kobo dictionary
+    /// entries that don't match any JMDict word, and so aren't part of any
+    /// entry, to the given file.  Kobo dictionary entries are still added
+    /// as-is.
+    #[arg(long, value_name = "PATH")]
+    pub report_unmatched: Option<PathBuf>,
+
+    /// Write every lookup key of the final dictionary to the given CSV file,
+    /// one "key,priority,headword" row per key of each entry, for analyzing
+    /// key collisions and coverage with other tools.
+    #[arg(long, value_name = "PATH")]
+    pub dump_keys: Option<PathBuf>,
+
+    /// Print statistics about the lookup keys: the total and average per
+    /// entry, the entries, keys and average keys per entry for each part of
+    /// speech, and the entries with the most keys.
+    /// Useful for seeing the effect of "--conjugation-level" and similar
+    /// options on the size of the dictionary.  Entries added with
+    /// "--add-kobo-dict" aren't included.
+    #[arg(long)]
+    pub key_stats: bool,
+
+    /// Print how long each phase of the build takes.
+    #[arg(long)]
+    pub profile_phases: bool,
+
+    /// How to print the progress of the build.  "json" prints one JSON
+    /// object per line for each event (steps, counts, phase timings, and
+    /// warnings), for use in scripts.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = ["text", "json"],
+        default_value = "text"
+    )]
+    pub log_format: String,
+
+    /// Write the warnings about problems in the input data (e.g. skipped
+    /// malformed entries) to the given file, as JSON.  A summary of them is
+    /// always printed at the end of the build.
+    #[arg(long, value_name = "PATH")]
+    pub warnings_json: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Export the entries of a list of words as an Anki-importable TSV file,
+    /// instead of building a dictionary.
+    ExportAnki {
+        /// Path to a text file with one word per line.
+        word_list: PathBuf,
+
+        /// The output filepath to write the TSV file to.
+        output: PathBuf,
+    },
+
+    /// Render the entries of some words to an html file styled like the
+    /// Kobo dictionary popup, instead of building a dictionary.
+    Preview {
+        /// A word to show the entries of.
+        #[arg(long = "word", value_name = "WORD")]
+        words: Vec<String>,
+
+        /// Also show the entries of N randomly chosen words.  [default: 10
+        /// if no words are given, otherwise 0]
+        #[arg(long, value_name = "N")]
+        sample: Option<usize>,
+
+        /// The output filepath to write the html file to.
+        output: PathBuf,
+    },
+
+    /// Measure how many of the words in a set of books would find an entry
+    /// in the dictionary, and list the most frequent ones that wouldn't,
+    /// instead of building a dictionary.
+    Coverage {
+        /// A book, or a directory of them.  Plain text (.txt) and EPUB
+        /// (.epub) books are read.
+        #[arg(long, value_name = "PATH")]
+        corpus: PathBuf,
+
+        /// How to split the text into words.  "mecab" uses MeCab, and
+        /// "longest-match" takes the longest dictionary key at each
+        /// position, roughly like selecting a word on an e-reader.  "auto"
+        /// uses MeCab if it's installed.
+        #[arg(
+            long,
+            value_name = "TOKENIZER",
+            value_parser = ["auto", "longest-match", "mecab"],
+            default_value = "auto"
+        )]
+        tokenizer: String,
+
+        /// List the N most frequent words without an entry.
+        #[arg(long, value_name = "N", default_value_t = 50)]
+        top: usize,
+    },
+
+    /// Build a small dictionary with only the words used in one book, for
+    /// faster lookups on older e-readers.  The words of the book are found
+    /// by matching the longest dictionary key at each position, so
+    /// conjugated forms are found too, and their entries keep all of their
+    /// keys.  The kanji in the book keep their kanji entries.
+    BuildForBook {
+        /// Path to the book, an EPUB (.epub) or plain text (.txt) file.
+        book: PathBuf,
+
+        /// The output filepath to write the new dictionary to.
+        output: PathBuf,
+    },
+
+    /// Build a dictionary into a directory only if the sources or options
+    /// changed since the last update, e.g. for nightly rebuilds.  The
+    /// dictionary is written with the date in its name (e.g.
+    /// "dicthtml-ja-en-20240501.zip"), and a "latest" link (e.g.
+    /// "dicthtml-ja-en-latest.zip") is pointed at it.  Changes are detected
+    /// from the size and modification time of each file given on the
+    /// command line.
+    Update {
+        /// The directory to write the dictionary to.
+        output_dir: PathBuf,
+
+        /// The start of the dictionary's file name.
+        #[arg(long, value_name = "NAME", default_value = "dicthtml-ja-en")]
+        name: String,
+
+        /// Build even if nothing changed.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Look up some selected strings in a Kobo dictionary the way a Kobo
+    /// e-reader does, and print the entries it would show, instead of
+    /// building a dictionary.
+    Simulate {
+        /// Path to the Kobo dictionary to look the strings up in.
+        dictionary: PathBuf,
+
+        /// A selected string, e.g. "食べた".
+        #[arg(value_name = "WORD", required = true)]
+        words: Vec<String>,
+    },
+
+    /// Print statistics about the prefix entry files of a Kobo dictionary,
+    /// instead of building a dictionary.  Kobo e-readers open a whole prefix
+    /// entry file for every lookup, so large ones make lookups slow.
+    Inspect {
+        /// Path to the Kobo dictionary to inspect.
+        dictionary: PathBuf,
+
+        /// List the N largest prefix entry files.
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        top: usize,
+    },
+
+    /// Print a script that completes the options and subcommands in the
+    /// given shell, instead of building a dictionary.
+    Completions {
+        /// The shell to complete the command line in.
+        #[arg(value_name = "SHELL")]
+        shell: clap_complete::Shell,
+    },
+}
@@ -0,0 +1,123 @@
+//! Breakdown entries for kanji compounds that have no entry of their own,
+//! e.g. rare four-kanji compounds, which otherwise show nothing when
+//! selected on an e-reader.
+//!
+//! The compounds are found in a corpus of the kind of text the dictionary
+//! will be used on, and split into the fewest words that do have entries.
+//! The breakdown entry lists the component words and their entries.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::encoding;
+use crate::generic_dict::{Entry, EntryIndex};
+use crate::kana::is_kanji;
+
+/// Compounds shorter than this are left alone: two-kanji "compounds" that
+/// aren't words are mostly segmentation accidents.
+const MIN_LENGTH: usize = 3;
+
+/// Counts the runs of kanji in the text files at `path`, which is either a
+/// file or a directory of them.  Files that aren't text in UTF-8, Shift-JIS
+/// or EUC-JP are skipped.
+pub fn count_compounds(path: &Path) -> std::io::Result<HashMap<String, usize>> {
+    let mut counts = HashMap::new();
+    let mut paths = vec![path.to_path_buf()];
+    while let Some(path) = paths.pop() {
+        if path.is_dir() {
+            for item in std::fs::read_dir(&path)? {
+                paths.push(item?.path());
+            }
+            continue;
+        }
+        let bytes = std::fs::read(&path)?;
+        let text = match encoding::decode_if_text(&bytes) {
+            Some(text) => text,
+            None => continue,
+        };
+        for run in text.split(|ch| !is_kanji(ch)) {
+            if run.chars().count() >= MIN_LENGTH && !run.starts_with('々') {
+                *counts.entry(run.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+/// Splits `compound` into the fewest keys that have entries, preferring
+/// longer leading words when there's a tie.  Returns `None` if it can't be
+/// split completely into at least two words.
+fn split(compound: &str, index: &EntryIndex) -> Option<Vec<String>> {
+    let chars: Vec<char> = compound.chars().collect();
+    let n = chars.len();
+
+    // best[i] = (word count, length of the first word) of the best split of
+    // chars[i..].
+    let mut best: Vec<Option<(usize, usize)>> = vec![None; n + 1];
+    best[n] = Some((0, 0));
+    for i in (0..n).rev() {
+        for len in (1..=n - i).rev() {
+            if i == 0 && len == n {
+                continue;
+            }
+            let count = match best[i + len] {
+                Some((count, _)) => count + 1,
+                None => continue,
+            };
+            let word: String = chars[i..i + len].iter().collect();
+            if best[i].is_none_or(|(c, _)| count < c) && !index.lookup(&word).is_empty() {
+                best[i] = Some((count, len));
+            }
+        }
+    }
+
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < n {
+        let (_, len) = best[i]?;
+        words.push(chars[i..i + len].iter().collect());
+        i += len;
+    }
+    Some(words)
+}
+
+/// Returns breakdown entries for the compounds in `counts` that occur at
+/// least `min_count` times and don't match any of `entries`.
+pub fn breakdown_entries(
+    entries: &[Entry],
+    counts: &HashMap<String, usize>,
+    min_count: usize,
+) -> Vec<Entry> {
+    let index = EntryIndex::new(entries);
+
+    let mut compounds: Vec<(&String, usize)> = counts
+        .iter()
+        .filter(|&(_, &count)| count >= min_count)
+        .map(|(compound, &count)| (compound, count))
+        .collect();
+    compounds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut breakdowns = Vec::new();
+    for (compound, _) in compounds {
+        if !index.lookup(compound).is_empty() {
+            continue;
+        }
+        let words = match split(compound, &index) {
+            Some(words) => words,
+            None => continue,
+        };
+
+        let mut definition = format!("<p><b>{}</b>: {}</p>", compound, words.join(" + "));
+        for word in words.iter() {
+            // Only the most likely entry, to keep the breakdown short.
+            definition.push_str(&index.lookup(word)[0].definition);
+        }
+
+        // Shown after any entry that shares the key.
+        breakdowns.push(Entry {
+            keys: vec![(compound.clone(), u32::MAX)],
+            definition,
+        });
+    }
+    breakdowns
+}
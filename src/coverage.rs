@@ -0,0 +1,227 @@
+//! Measures how much of the text of a set of books a dictionary covers:
+//! how many of their words would find an entry when looked up.
+//!
+//! Books are plain text files or EPUBs.  Their text is split into words
+//! either by MeCab, if it's installed, or by taking the longest dictionary
+//! key at each position, which is roughly what selecting a word on an
+//! e-reader does.
+//!
+//! The same splitting finds the vocabulary of a book for per-book
+//! dictionaries.
+
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::encoding;
+use crate::generic_dict::Entry;
+use crate::kana::{is_hiragana, is_kana, is_kanji};
+use crate::sanitize::strip_tags;
+
+/// Keys longer than this aren't tried when matching the longest key.
+const MAX_KEY_LENGTH: usize = 16;
+
+lazy_static! {
+    /// Furigana, which would otherwise end up in the middle of the text.
+    static ref RUBY_TEXT: Regex = Regex::new(r"(?s)<rt[^>]*>.*?</rt>|<rp[^>]*>.*?</rp>").unwrap();
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Tokenizer {
+    Auto, // MeCab if it's installed, otherwise `LongestMatch`.
+    LongestMatch,
+    Mecab,
+}
+
+pub struct Coverage {
+    pub tokens: usize,
+    pub resolved: usize,
+    pub misses: Vec<(String, usize)>, // (word, count), most frequent first.
+}
+
+/// Reads the text of the books at `path`, which is either a book or a
+/// directory of them.  Files other than .txt and .epub files are skipped.
+pub fn read_corpus(path: &Path) -> std::io::Result<Vec<String>> {
+    let mut texts = Vec::new();
+    let mut paths = vec![path.to_path_buf()];
+    while let Some(path) = paths.pop() {
+        if path.is_dir() {
+            for item in std::fs::read_dir(&path)? {
+                paths.push(item?.path());
+            }
+            continue;
+        }
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "epub" => texts.push(epub_text(&path)?),
+            "txt" => {
+                let name = path.to_string_lossy();
+                texts.push(encoding::decode(&std::fs::read(&path)?, None, &name).into_owned());
+            }
+            _ => {}
+        }
+    }
+    Ok(texts)
+}
+
+/// Returns the text of the (x)html documents of an EPUB, without markup or
+/// furigana.
+pub fn epub_text(path: &Path) -> std::io::Result<String> {
+    let mut zip_in = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    let mut text = String::new();
+    for i in 0..zip_in.len() {
+        let mut f = zip_in.by_index(i)?;
+        let name = String::from_utf8_lossy(f.name_raw()).to_lowercase();
+        if !(name.ends_with(".xhtml") || name.ends_with(".html") || name.ends_with(".htm")) {
+            continue;
+        }
+        let mut html = String::new();
+        f.read_to_string(&mut html)?;
+        text.push_str(&strip_tags(&RUBY_TEXT.replace_all(&html, "")));
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Splits `texts` into words and checks which of them match a key of
+/// `entries`.  Only words with Japanese characters are counted.
+pub fn measure(
+    entries: &[Entry],
+    texts: &[String],
+    tokenizer: Tokenizer,
+) -> std::io::Result<Coverage> {
+    let keys: HashSet<&str> = entries
+        .iter()
+        .flat_map(|e| e.keys.iter().map(|k| k.0.as_str()))
+        .collect();
+
+    let mut tokens = 0;
+    let mut resolved = 0;
+    let mut misses: HashMap<String, usize> = HashMap::new();
+    let mut count = |word: &str, found: bool| {
+        if !word.chars().any(is_japanese) {
+            return;
+        }
+        tokens += 1;
+        if found {
+            resolved += 1;
+        } else {
+            *misses.entry(word.into()).or_insert(0) += 1;
+        }
+    };
+
+    let use_mecab = match tokenizer {
+        Tokenizer::LongestMatch => false,
+        Tokenizer::Mecab => true,
+        Tokenizer::Auto => mecab_tokens("").is_ok(),
+    };
+    for text in texts.iter() {
+        if use_mecab {
+            for word in mecab_tokens(text)?.iter() {
+                count(word, keys.contains(word.as_str()));
+            }
+        } else {
+            for (word, found) in longest_match_tokens(text, &keys) {
+                count(word, found);
+            }
+        }
+    }
+
+    let mut misses: Vec<(String, usize)> = misses.into_iter().collect();
+    misses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    Ok(Coverage {
+        tokens,
+        resolved,
+        misses,
+    })
+}
+
+/// Returns the words of `texts`, split by the longest of `keys` at each
+/// position, and all of the kanji in them, for finding the entries a book
+/// needs.
+pub fn vocabulary(texts: &[String], keys: &HashSet<&str>) -> HashSet<String> {
+    let mut words = HashSet::new();
+    for text in texts.iter() {
+        for (word, found) in longest_match_tokens(text, keys) {
+            if found {
+                words.insert(word.to_string());
+            }
+        }
+        words.extend(text.chars().filter(|&c| is_kanji(c)).map(|c| c.to_string()));
+    }
+    words
+}
+
+fn is_japanese(ch: char) -> bool {
+    is_kana(ch) || is_kanji(ch)
+}
+
+/// Splits the Japanese text of `text` into the longest keys at each
+/// position, as (word, whether it's a key).  Where no key matches, the run
+/// of characters of the same kind (kanji, hiragana or katakana) is taken as
+/// an unmatched word.
+fn longest_match_tokens<'a>(text: &'a str, keys: &HashSet<&str>) -> Vec<(&'a str, bool)> {
+    let kind = |ch: char| {
+        if is_kanji(ch) {
+            0
+        } else if is_hiragana(ch) {
+            1
+        } else {
+            2
+        }
+    };
+
+    let mut tokens = Vec::new();
+    for run in text.split(|ch| !is_japanese(ch)) {
+        let chars: Vec<(usize, char)> = run.char_indices().collect();
+        let offset = |i: usize| chars.get(i).map_or(run.len(), |c| c.0);
+        let mut i = 0;
+        while i < chars.len() {
+            let start = chars[i].0;
+            let matched = (1..=MAX_KEY_LENGTH.min(chars.len() - i))
+                .rev()
+                .find(|&len| keys.contains(&run[start..offset(i + len)]));
+            let len = match matched {
+                Some(len) => len,
+                None => chars[i..]
+                    .iter()
+                    .take_while(|c| kind(c.1) == kind(chars[i].1))
+                    .count(),
+            };
+            tokens.push((&run[start..offset(i + len)], matched.is_some()));
+            i += len;
+        }
+    }
+    tokens
+}
+
+/// Splits `text` into words with MeCab.
+fn mecab_tokens(text: &str) -> std::io::Result<Vec<String>> {
+    let mut child = std::process::Command::new("mecab")
+        .arg("-Owakati")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+
+    // Written from another thread, so that MeCab's output doesn't fill up
+    // while it's waiting for input.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = text.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other("\"mecab\" exited with a failure"));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .map(|w| w.into())
+        .collect())
+}
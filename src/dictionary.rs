@@ -0,0 +1,56 @@
+//! In-memory lookups in merged entries, without building a dictionary file.
+
+use std::collections::HashMap;
+
+use crate::kana;
+use crate::merged::MergedEntry;
+
+/// Merged entries, indexed by their lookup keys.
+pub struct Dictionary {
+    entries: Vec<MergedEntry>,
+    // key -> Vec<(priority, entry index)>, one per entry, in lookup order.
+    key_table: HashMap<String, Vec<(u32, usize)>>,
+}
+
+impl Dictionary {
+    pub fn new(entries: Vec<MergedEntry>) -> Dictionary {
+        let mut key_table: HashMap<String, Vec<(u32, usize)>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            for key in entry.keys.iter() {
+                key_table.entry(key.0.clone()).or_default().push((key.1, i));
+            }
+        }
+        for matches in key_table.values_mut() {
+            // Keep only the highest priority for each entry.
+            matches.sort_unstable_by_key(|&(priority, i)| (i, priority));
+            matches.dedup_by_key(|a| a.1);
+            matches.sort_by_key(|&(priority, i)| (priority, i));
+        }
+
+        Dictionary { entries, key_table }
+    }
+
+    pub fn entries(&self) -> &[MergedEntry] {
+        &self.entries
+    }
+
+    /// Returns the entries for `surface`, most common first.
+    ///
+    /// Inflected forms are matched through the inflection keys of the
+    /// entries, so the entries must have been generated with inflection
+    /// keys for this to deinflect anything.  Like on a Kobo e-reader, if
+    /// nothing matches `surface` the last character is dropped and the
+    /// lookup is tried again, so e.g. "食べたい気持ち" finds 食べる.
+    pub fn lookup(&self, surface: &str) -> Vec<&MergedEntry> {
+        let mut candidate: Vec<char> = kana::normalize(surface).to_lowercase().chars().collect();
+        while !candidate.is_empty() {
+            let key: String = candidate.iter().collect();
+            if let Some(matches) = self.key_table.get(&key) {
+                return matches.iter().map(|&(_, i)| &self.entries[i]).collect();
+            }
+            candidate.pop();
+        }
+
+        Vec::new()
+    }
+}
@@ -0,0 +1,78 @@
+//! Decoding of input text files that aren't UTF-8, such as Shift-JIS word
+//! lists, EPWING text dumps and PDIC text, or the EUC-JP KRADFILE.
+//!
+//! Unless an encoding is given, it's taken from the byte order mark, or
+//! else guessed between UTF-8, Shift-JIS and EUC-JP.
+
+use std::borrow::Cow;
+
+use encoding_rs::{Encoding, EUC_JP, SHIFT_JIS, UTF_8};
+
+use crate::warnings;
+
+/// How much of a file is looked at to guess its encoding.
+const SAMPLE_SIZE: usize = 1 << 20;
+
+/// Looks up an encoding by name, e.g. "shift_jis", "sjis", "euc-jp" or
+/// "utf-8".  Accepts the WHATWG labels, case-insensitively.
+pub fn for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// Guesses the encoding of `bytes`.
+pub fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    let sample = &bytes[..bytes.len().min(SAMPLE_SIZE)];
+    match std::str::from_utf8(sample) {
+        Ok(_) => return UTF_8,
+        // Only invalid because the sample ends in the middle of a character.
+        Err(e) if e.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+
+    // EUC-JP text mostly decodes as Shift-JIS too, but as half-width
+    // katakana, which real text hardly uses.
+    let oddities = |encoding: &'static Encoding| {
+        let (text, _) = encoding.decode_without_bom_handling(sample);
+        text.chars()
+            .filter(|&c| c == '\u{fffd}' || ('\u{ff61}'..='\u{ff9f}').contains(&c))
+            .count()
+    };
+    if oddities(EUC_JP) < oddities(SHIFT_JIS) {
+        EUC_JP
+    } else {
+        SHIFT_JIS
+    }
+}
+
+/// Decodes `bytes` from `encoding`, or from the detected encoding if it's
+/// `None`.  Bytes that aren't valid in the encoding are replaced with
+/// U+FFFD and reported as a warning for `source`.
+pub fn decode<'a>(
+    bytes: &'a [u8],
+    encoding: Option<&'static Encoding>,
+    source: &str,
+) -> Cow<'a, str> {
+    let encoding = encoding.unwrap_or_else(|| detect(bytes));
+    let (text, had_errors) = encoding.decode_with_bom_removal(bytes);
+    if had_errors {
+        warnings::warn(
+            warnings::Category::TextEncoding,
+            source,
+            format!(
+                "invalid {} text, replaced with \"\u{fffd}\"",
+                encoding.name()
+            ),
+        );
+    }
+    text
+}
+
+/// Decodes `bytes` from the detected encoding, or returns `None` if they
+/// aren't valid text in it, e.g. for binary files.
+pub fn decode_if_text(bytes: &[u8]) -> Option<Cow<'_, str>> {
+    detect(bytes).decode_without_bom_handling_and_without_replacement(bytes)
+}
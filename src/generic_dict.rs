@@ -1,14 +1,18 @@
 //! Shared types and functions for use in generating all output dictionary
 //! formats.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::jmdict;
+use crate::kana::{
+    hiragana_to_katakana, is_all_kana, katakana_to_hiragana, latin_variants, loanword_variants,
+    mora_count, old_text_variants, strip_non_kana,
+};
+use crate::kyujitai;
+use crate::kyukana;
+use crate::merged::{MergedEntry, MergedKind};
+use crate::observer;
 use crate::yomichan;
-use crate::{hiragana_to_katakana, is_all_kana, katakana_to_hiragana};
-
-type TermTable = HashMap<(String, String), Vec<yomichan::TermEntry>>;
-type KanjiTable = HashMap<String, Vec<yomichan::KanjiEntry>>;
 
 #[derive(Clone, Debug)]
 pub struct Entry {
@@ -19,6 +23,44 @@ pub struct Entry {
     pub definition: String,
 }
 
+/// Looks up entries by key, the same way an e-reader does.
+pub struct EntryIndex<'a> {
+    entries: &'a [Entry],
+    // key -> Vec<(priority, entry index)>, one per entry, in lookup order.
+    key_table: HashMap<&'a str, Vec<(u32, usize)>>,
+}
+
+impl<'a> EntryIndex<'a> {
+    pub fn new(entries: &'a [Entry]) -> EntryIndex<'a> {
+        let mut key_table: HashMap<&str, Vec<(u32, usize)>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            for key in entry.keys.iter() {
+                key_table.entry(&key.0).or_default().push((key.1, i));
+            }
+        }
+        for matches in key_table.values_mut() {
+            // Keep only the highest priority for each entry.
+            matches.sort_unstable_by_key(|&(priority, i)| (i, priority));
+            matches.dedup_by_key(|a| a.1);
+            matches
+                .sort_by_key(|&(priority, i)| (priority, -(entries[i].definition.len() as isize)));
+        }
+
+        EntryIndex { entries, key_table }
+    }
+
+    /// Returns the entries matching `key`, in the order that the Kobo
+    /// dictionary shows them: higher-priority and more detailed entries
+    /// first.
+    pub fn lookup(&self, key: &str) -> Vec<&'a Entry> {
+        let entries = self.entries;
+        match self.key_table.get(key) {
+            Some(matches) => matches.iter().map(|&(_, i)| &entries[i]).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum LangMode {
     English,    // Standard English terms.
@@ -37,97 +79,431 @@ impl LangMode {
     }
 }
 
+/// Controls the priorities of lookup keys, which determine which entries are
+/// shown first when multiple entries share a key.  Lower priority values
+/// are shown first.
+///
+/// Word keys start from their JMDict priority, which is then divided by the
+/// divisors that apply to the word.
+#[derive(Debug, Copy, Clone)]
+pub struct PriorityWeights {
+    /// Priority of kanji entries.
+    pub kanji: u32,
+
+    /// Priority of name entries.
+    pub name: u32,
+
+    /// Divisor for words that JMDict marks as common.
+    pub common_divisor: u32,
+
+    /// Divisor for the kana forms of words that are usually written in kana.
+    pub kana_preferred_divisor: u32,
+
+    /// Divisor for verbs.
+    pub verb_divisor: u32,
+
+    /// Divisor for i-adjectives.
+    pub i_adjective_divisor: u32,
+
+    /// Added to the priority of inflected forms, so that words that are
+    /// exact matches are shown first.
+    pub inflection_penalty: u32,
+
+    /// Added to the priority of irregular and outdated forms when they're
+    /// demoted.  See `IrregularForms`.
+    pub irregular_penalty: u32,
+}
+
+impl Default for PriorityWeights {
+    fn default() -> PriorityWeights {
+        PriorityWeights {
+            kanji: 0,
+            name: u32::MAX, // Always sort names last.
+            common_divisor: 1,
+            kana_preferred_divisor: 8,
+            verb_divisor: 4,
+            i_adjective_divisor: 2,
+            inflection_penalty: 200000,
+            irregular_penalty: 100000,
+        }
+    }
+}
+
+/// What to do with the writings and readings that JMDict marks as irregular
+/// or outdated (e.g. "iK" and "ok").
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IrregularForms {
+    Keep,    // Treat them like any other form.
+    Demote,  // List them last in the header, and lower their key priority.
+    Exclude, // Leave them out of the header and the keys.
+}
+
+/// How to show the JMDict part of speech and usage tags of a word in its
+/// header.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TagStyle {
+    Code,  // The JMDict codes, e.g. "v5k, vt, uk".
+    Short, // Short labels, e.g. "godan verb, transitive, usu. kana".
+    Long,  // JMDict's full descriptions, e.g. "Godan verb with 'ku' ending".
+}
+
+/// The sizes of the text in entries, relative to the e-reader's font size.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextScale {
+    Compact,
+    Normal,
+    Large, // For older, low-resolution e-readers.
+}
+
+impl TextScale {
+    /// Secondary text: part of speech labels, conjugations, and notes.
+    pub fn small(self) -> &'static str {
+        match self {
+            TextScale::Compact => "0.75em",
+            TextScale::Normal => "0.8em",
+            TextScale::Large => "0.95em",
+        }
+    }
+
+    /// Readings in headers with furigana.
+    pub fn headword(self) -> &'static str {
+        match self {
+            TextScale::Compact => "1.2em",
+            TextScale::Normal => "1.3em",
+            TextScale::Large => "1.5em",
+        }
+    }
+
+    /// The kanji of kanji entries.
+    pub fn kanji(self) -> &'static str {
+        match self {
+            TextScale::Compact => "1.6em",
+            TextScale::Normal => "2.0em",
+            TextScale::Large => "2.5em",
+        }
+    }
+}
+
+/// How many inflected forms of verbs and adjectives to add as lookup keys.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConjugationLevel {
+    Minimal,   // Only the single-kana conjugation stems, e.g. 書か and 書き.
+    Standard,  // The stems and the most common forms, e.g. 書かない and 書いた.
+    Extensive, // Also polite, desiderative, conditional and volitional forms.
+}
+
+/// What to do with the writings of a word beyond the maximum number shown in
+/// entry headers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExtraWritings {
+    Hide,       // Leave them out of the entry.
+    OtherForms, // List them on a smaller "other forms" line under the header.
+}
+
+/// What to put at the top of each entry, to separate it from the previous
+/// one.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Separator {
+    Rule,      // A horizontal rule.
+    BlankLine, // An empty line.
+    None,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct EntrySettings {
     pub lang_mode: LangMode,
     pub use_katakana_pronunciation: bool,
 
+    /// Whether to mark devoiced and nasalized morae in the readings of
+    /// entry headers, when known.
+    pub phonetic_detail: bool,
+
     /// Whether to include word conjugations in the list of keys to look up
     /// words with.
     pub generate_inflection_keys: bool,
-
-    /// Will add a horizontal bar at the top of each entry.  This is mainly for
-    /// Kobo, which displays all entries together in a continuous page.
-    pub add_separators: bool,
+    pub conjugation_level: ConjugationLevel,
+
+    /// Whether to also include katakana versions of all-kana keys.  Older
+    /// Kobo firmware looks up hiragana words by their katakana form, and
+    /// fails to find them otherwise.
+    pub katakana_keys: bool,
+
+    /// Whether to add keys for the other common spellings of katakana
+    /// loanwords.  See `kana::loanword_variants()`.
+    pub loanword_variants: bool,
+
+    /// Whether to add keys for the spellings of words in older printed
+    /// text.  See `kana::old_text_variants()`.
+    pub old_text_variants: bool,
+
+    /// Whether to add keys for likely historical kana spellings (旧仮名遣い)
+    /// of words.  See `kyukana`.
+    pub historical_kana: bool,
+
+    /// Whether to add keys with the traditional forms (旧字体) of kanji.
+    /// See `kyujitai`.
+    pub kyujitai_keys: bool,
+
+    /// The maximum number of distinct lookup keys of all entries.  Keys
+    /// over the limit are evicted while they're generated, lowest priority
+    /// first.  `None` means no limit.
+    pub max_keys: Option<usize>,
+
+    /// Whether to give onomatopoeia (words JMDict marks "on-mim") hiragana
+    /// keys for their katakana forms, mark them in the header, and merge the
+    /// definitions of katakana headwords into them.
+    pub onomatopoeia: bool,
+
+    /// Whether to put the definitions of all but the first source
+    /// dictionary, conjugation tables, and kanji example words in collapsed
+    /// `<details>` sections.  Renderers that don't support them show the
+    /// sections expanded.
+    pub collapse_sections: bool,
+
+    pub text_scale: TextScale,
+
+    pub priority_weights: PriorityWeights,
+
+    pub irregular_forms: IrregularForms,
+
+    /// How to show the JMDict part of speech and usage tags in headers,
+    /// instead of the built-in part of speech text.  `None` keeps the
+    /// built-in text, and shows no usage tags.
+    pub tag_style: Option<TagStyle>,
+
+    /// Whether to note the source language of loanwords, e.g. "from
+    /// Portuguese: pão" for パン.
+    pub show_loan_sources: bool,
+
+    /// Whether to show the basic conjugations (polite, te-form, negative,
+    /// and past) of verbs and i-adjectives under the entry header.
+    pub show_conjugations: bool,
+
+    /// Whether to show the pitch accents of the basic conjugations of
+    /// verbs, derived from the dictionary form's accent, in a small table
+    /// under the entry header.
+    pub conjugation_accents: bool,
+
+    /// Learner mode: add hiragana lookup keys for all readings of kanji
+    /// words, with the same priority as the kana forms of words usually
+    /// written in kana, so that looking up a word by its reading finds it.
+    pub kana_learner_keys: bool,
+
+    /// Show the reading in entry headers larger, and as furigana above the
+    /// kanji writings.
+    pub furigana_headers: bool,
+
+    /// Whether to mark prefixes and suffixes in entry headers with a 〜 at
+    /// the side they attach to, e.g. 〜的.
+    pub mark_affixes: bool,
+
+    /// The number of common words containing the kanji to list in each
+    /// kanji entry.
+    pub kanji_examples: usize,
+
+    /// The maximum number of name records to show under each name
+    /// headword.  `None` means no limit.
+    pub max_name_senses: Option<usize>,
+
+    /// The maximum number of writings to show in the 【】 part of entry
+    /// headers, and what to do with the rest.  `None` means no limit.
+    pub max_header_writings: Option<usize>,
+    pub extra_writings: ExtraWritings,
+
+    /// Compact popup mode: fit the whole header on one line, with no extra
+    /// spacing before the definitions, so that more of the entry fits in
+    /// Kobo's small lookup popup.
+    pub compact_headers: bool,
+
+    /// What to add at the top of each entry.  This is mainly for Kobo,
+    /// which displays all entries together in a continuous page.
+    pub separator: Separator,
 }
 
-pub fn generate_entries(
-    yomi_term_table: &TermTable,
-    yomi_name_table: &TermTable,
-    yomi_kanji_table: &KanjiTable,
-    jm_table: &HashMap<(String, String), Vec<jmdict::WordEntry>>,
-    pa_table: &HashMap<(String, String), Vec<u32>>,
-    entry_settings: EntrySettings,
-) -> Vec<Entry> {
-    let mut entries = Vec::new();
+impl Default for EntrySettings {
+    /// The settings of the command line builder's defaults, for a Kobo
+    /// dictionary.
+    fn default() -> EntrySettings {
+        EntrySettings {
+            lang_mode: LangMode::English,
+            use_katakana_pronunciation: false,
+            phonetic_detail: false,
+            generate_inflection_keys: true,
+            conjugation_level: ConjugationLevel::Standard,
+            katakana_keys: true,
+            loanword_variants: false,
+            old_text_variants: false,
+            historical_kana: false,
+            kyujitai_keys: false,
+            max_keys: None,
+            onomatopoeia: false,
+            collapse_sections: false,
+            text_scale: TextScale::Normal,
+            priority_weights: PriorityWeights::default(),
+            irregular_forms: IrregularForms::Keep,
+            tag_style: None,
+            show_loan_sources: false,
+            show_conjugations: false,
+            conjugation_accents: false,
+            kana_learner_keys: false,
+            furigana_headers: false,
+            mark_affixes: false,
+            kanji_examples: 0,
+            max_name_senses: None,
+            max_header_writings: None,
+            extra_writings: ExtraWritings::OtherForms,
+            compact_headers: false,
+            separator: Separator::Rule,
+        }
+    }
+}
 
-    // Kanji entries.
-    for (kanji, items) in yomi_kanji_table.iter() {
-        let mut entry_text: String = "".into();
-        if entry_settings.add_separators {
-            entry_text.push_str("<hr/>");
+/// Limits the number of distinct lookup keys of all of `entries` to
+/// `max_keys`, for when conjugations and variants make the dictionary too
+/// big for the reader.  Most keys over the limit are already evicted while
+/// merging (see `EntrySettings::max_keys`); this is the final cap, which
+/// also counts the keys of the entries added after merging.
+///
+/// A key shared by several entries counts once, with the best priority any
+/// of them gives it.  The keys with the worst priority are removed first,
+/// from all entries at once, and longer keys before shorter ones at the
+/// same priority.  An entry's first key, its headword, is never removed, so
+/// the result can be over `max_keys` if there are more entries than that.
+///
+/// Returns the number of distinct keys removed.
+pub fn cap_keys(entries: &mut [Entry], max_keys: usize) -> usize {
+    let evicted: HashSet<String> = {
+        // Key -> (best priority, whether it's the first key of an entry).
+        let mut keys: HashMap<&str, (u32, bool)> = HashMap::new();
+        for entry in entries.iter() {
+            for (i, (key, priority)) in entry.keys.iter().enumerate() {
+                let info = keys.entry(key.as_str()).or_insert((*priority, false));
+                info.0 = info.0.min(*priority);
+                info.1 |= i == 0;
+            }
+        }
+        if keys.len() <= max_keys {
+            return 0;
         }
-        entry_text.push_str(&generate_kanji_entry_text(&items[0]));
 
-        entries.push(Entry {
-            keys: vec![(kanji.clone(), 0)],
-            definition: entry_text,
+        let excess = keys.len() - max_keys;
+        let mut candidates: Vec<(u32, &str)> = keys
+            .into_iter()
+            .filter(|(_, (_, first))| !first)
+            .map(|(key, (priority, _))| (priority, key))
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(b.1.len().cmp(&a.1.len()))
+                .then(a.1.cmp(b.1))
         });
-    }
-
-    // Term entries.
-    for ((kanji, kana), item) in jm_table.iter() {
-        for jm_entry in item.iter() {
-            // Find matching entries in the source dictionaries.
-            let pitch_accent = pa_table.get(&(kanji.clone(), kana.clone()));
-            let yomi_term_entries = yomi_term_table
-                .get(&(kanji.clone(), kana.clone()))
-                .map(|a| a.as_slice())
-                .unwrap_or(&[]);
-
-            if !yomi_term_entries.is_empty() {
-                let mut entry_text: String = "".into();
-                if entry_settings.add_separators {
-                    entry_text.push_str("<hr/>");
-                }
+        candidates
+            .into_iter()
+            .take(excess)
+            .map(|(_, key)| key.to_string())
+            .collect()
+    };
 
-                // Add header and definition to the entry text.
-                entry_text.push_str(&generate_header_text(
-                    entry_settings,
-                    &kana,
-                    pitch_accent,
-                    &jm_entry,
-                ));
-                entry_text.push_str(&generate_definition_text(yomi_term_entries));
+    for entry in entries.iter_mut() {
+        let mut i = 0;
+        entry.keys.retain(|key| {
+            i += 1;
+            i == 1 || !evicted.contains(&key.0)
+        });
+    }
+    evicted.len()
+}
 
-                // Add to the entry list.
-                entries.push(Entry {
-                    keys: generate_lookup_keys(jm_entry, entry_settings.generate_inflection_keys),
-                    definition: entry_text,
-                });
+/// Renders merged entries to html.
+///
+/// Fails only if the build is cancelled (see `observer`).
+pub fn generate_entries(
+    merged: &[MergedEntry],
+    entry_settings: EntrySettings,
+) -> std::io::Result<Vec<Entry>> {
+    observer::phase_started("Entry generation");
+    merged
+        .iter()
+        .enumerate()
+        .map(|(i, merged_entry)| {
+            if i % 10_000 == 0 {
+                observer::progress("Entry generation", i, merged.len());
+                observer::check_cancelled()?;
             }
-        }
-    }
 
-    // Name entries.
-    for ((writing, _reading), items) in yomi_name_table.iter() {
-        for item in items.iter() {
             let mut entry_text: String = "".into();
-            if entry_settings.add_separators {
-                entry_text.push_str("<hr/>");
+            match entry_settings.separator {
+                Separator::Rule => entry_text.push_str("<hr/>"),
+                Separator::BlankLine => entry_text.push_str("<br/>"),
+                Separator::None => {}
             }
 
-            entry_text.push_str(&generate_name_entry_text(entry_settings, item));
-            entries.push(Entry {
-                keys: vec![(writing.clone(), std::u32::MAX)], // Always sort names last.
-                definition: entry_text,
-            });
-        }
-    }
+            match merged_entry.kind {
+                MergedKind::Kanji(ref kanji) => {
+                    entry_text.push_str(&generate_kanji_entry_text(entry_settings, kanji));
+                }
 
-    entries.sort_by_key(|a| a.keys[0].0.len());
+                MergedKind::Term {
+                    ref kana,
+                    ref pitch_accent,
+                    ref phonetics,
+                    ref word,
+                    ref senses,
+                } => {
+                    entry_text.push_str(&generate_header_text(
+                        entry_settings,
+                        kana,
+                        Some(pitch_accent),
+                        Some(phonetics),
+                        word,
+                    ));
+                    let mut conjugation_text = String::new();
+                    if entry_settings.show_conjugations {
+                        conjugation_text.push_str(&generate_conjugation_text(entry_settings, word));
+                    }
+                    if entry_settings.conjugation_accents {
+                        conjugation_text.push_str(&generate_conjugation_accent_text(
+                            entry_settings,
+                            word,
+                            pitch_accent,
+                        ));
+                    }
+                    if entry_settings.collapse_sections && !conjugation_text.is_empty() {
+                        conjugation_text = collapsible(
+                            entry_settings,
+                            HEADER_TERMS["conjugations"][entry_settings.lang_mode.idx()],
+                            &conjugation_text,
+                        );
+                    }
+                    entry_text.push_str(&conjugation_text);
+                    if entry_settings.show_loan_sources && !word.loan_sources.is_empty() {
+                        let mut senses = senses.clone();
+                        let unplaced = place_loan_source_notes(entry_settings, word, &mut senses);
+                        entry_text.push_str(&generate_definition_text(entry_settings, &senses));
+                        entry_text.push_str(&unplaced);
+                    } else {
+                        entry_text.push_str(&generate_definition_text(entry_settings, senses));
+                    }
+                }
 
-    entries
+                MergedKind::Name {
+                    ref items,
+                    ref pitch_accents,
+                } => {
+                    entry_text.push_str(&generate_name_entry_text(
+                        entry_settings,
+                        items,
+                        pitch_accents,
+                    ));
+                }
+            }
+
+            Ok(Entry {
+                keys: merged_entry.keys.clone(),
+                definition: entry_text,
+            })
+        })
+        .collect()
 }
 
 lazy_static! {
@@ -143,6 +519,21 @@ lazy_static! {
         m.insert("i-adjective", &["i-adjective", "i-adjective", "形容詞"][..]);
         m.insert("adjective", &["adjective", "adjective", "形容"][..]);
         m.insert("name", &["name", "name", "名"][..]);
+        m.insert("noun", &["noun", "noun", "名詞"][..]);
+        m.insert("na-adjective", &["na-adjective", "na-adjective", "形容動詞"][..]);
+        m.insert(", suru", &[", suru verb", ", suru verb", "・サ変"][..]);
+        m.insert("adverb", &["adverb", "adverb", "副詞"][..]);
+        m.insert("particle", &["particle", "particle", "助詞"][..]);
+        m.insert("conjunction", &["conjunction", "conjunction", "接続詞"][..]);
+        m.insert("interjection", &["interjection", "interjection", "感動詞"][..]);
+        m.insert("pronoun", &["pronoun", "pronoun", "代名詞"][..]);
+        m.insert("counter", &["counter", "counter", "助数詞"][..]);
+        m.insert("auxiliary", &["auxiliary", "auxiliary", "助動詞"][..]);
+        m.insert("prefix", &["prefix", "prefix", "接頭辞"][..]);
+        m.insert("suffix", &["suffix", "suffix", "接尾辞"][..]);
+        m.insert("numeric", &["numeral", "numeral", "数詞"][..]);
+        m.insert("copula", &["copula", "copula", "助動詞"][..]);
+        m.insert("expression", &["expression", "expression", "連語"][..]);
         m.insert(
             ", transitive",
             &[", transitive", ", other-move", "、他動"][..],
@@ -154,142 +545,412 @@ lazy_static! {
         m.insert(", irregular", &[", irregular", ", irregular", ""][..]);
         m.insert(", ichidan", &[", ichidan", ", ichidan", "、一段"][..]);
         m.insert(", godan", &[", godan", ", godan", "、五段"][..]);
+        m.insert("polite", &["polite", "polite", "丁寧"][..]);
+        m.insert("te-form", &["te-form", "te-form", "て形"][..]);
+        m.insert("negative", &["negative", "negative", "否定"][..]);
+        m.insert("past", &["past", "past", "過去"][..]);
+        m.insert("other forms", &["other forms", "other forms", "他の表記"][..]);
+        m.insert("conjugations", &["conjugations", "conjugations", "活用"][..]);
+        m.insert("onomatopoeia", &["onomatopoeia", "onomatopoeia", "擬音語・擬態語"][..]);
 
         m
     };
 }
 
+/// Picks the reading and writings to show in the 【】 part of the header.
+///
+/// Search-only forms are never shown.  Unless irregular forms are kept
+/// as-is, the first regular reading is used, and writings that are irregular
+/// or that the reading doesn't apply to are moved to the end or left out.
+/// At least one writing is always kept if the word has any displayable
+/// ones.
+fn header_forms(
+    jm_entry: &jmdict::WordEntry,
+    irregular_forms: IrregularForms,
+) -> (&str, Vec<&str>) {
+    let mut writings: Vec<(usize, &str)> = jm_entry
+        .writings
+        .iter()
+        .map(|w| w.as_str())
+        .enumerate()
+        .filter(|&(i, _)| !jm_entry.is_search_only_writing(i))
+        .collect();
+    let displayable_readings: Vec<usize> = (0..jm_entry.readings.len())
+        .filter(|&i| !jm_entry.is_search_only_reading(i))
+        .collect();
+    let first_reading = displayable_readings.first().copied().unwrap_or(0);
+    if irregular_forms == IrregularForms::Keep {
+        return (
+            &jm_entry.readings[first_reading],
+            writings.iter().map(|&(_, w)| w).collect(),
+        );
+    }
+
+    let reading_i = displayable_readings
+        .iter()
+        .copied()
+        .find(|&i| !jm_entry.is_irregular_reading(i))
+        .unwrap_or(first_reading);
+    let is_demoted = |&(i, w): &(usize, &str)| {
+        jm_entry.is_irregular_writing(i) || !jm_entry.reading_applies_to(reading_i, w)
+    };
+
+    match irregular_forms {
+        IrregularForms::Demote => writings.sort_by_key(|w| is_demoted(w)),
+        _ => {
+            if writings.iter().any(|w| !is_demoted(w)) {
+                writings.retain(|w| !is_demoted(w));
+            }
+        }
+    }
+
+    (
+        &jm_entry.readings[reading_i],
+        writings.iter().map(|&(_, w)| w).collect(),
+    )
+}
+
+/// Whether JMDict marks the word as a prefix, e.g. 再.
+pub fn is_prefix(jm_entry: &jmdict::WordEntry) -> bool {
+    jm_entry.tags.contains("pos:pref") || jm_entry.tags.contains("pos:n-pref")
+}
+
+/// Whether JMDict marks the word as a suffix, e.g. 的.
+pub fn is_suffix(jm_entry: &jmdict::WordEntry) -> bool {
+    jm_entry.tags.contains("pos:suf") || jm_entry.tags.contains("pos:n-suf")
+}
+
+/// Marks the devoiced morae of a kana reading with a ring below (き̥), and
+/// its nasalized が行 morae with a handakuten (か゚).
+fn mark_phonetics(kana: &str, phonetics: &yomichan::Phonetics) -> String {
+    const SMALL_KANA: &str = "ぁぃぅぇぉゃゅょゎァィゥェォャュョヮ";
+    const NASAL: &[(char, char)] = &[
+        ('が', 'か'),
+        ('ぎ', 'き'),
+        ('ぐ', 'く'),
+        ('げ', 'け'),
+        ('ご', 'こ'),
+        ('ガ', 'カ'),
+        ('ギ', 'キ'),
+        ('グ', 'ク'),
+        ('ゲ', 'ケ'),
+        ('ゴ', 'コ'),
+    ];
+
+    let mut text = String::new();
+    let mut mora = 0;
+    let mut chars = kana.chars().peekable();
+    while let Some(ch) = chars.next() {
+        mora += 1;
+        match NASAL.iter().find(|n| n.0 == ch) {
+            Some(&(_, plain)) if phonetics.nasal.contains(&mora) => {
+                text.push(plain);
+                text.push('\u{309a}');
+            }
+            _ => text.push(ch),
+        }
+        // Small kana are part of the same mora.
+        while let Some(&next) = chars.peek() {
+            if !SMALL_KANA.contains(next) {
+                break;
+            }
+            text.push(next);
+            chars.next();
+        }
+        if phonetics.devoiced.contains(&mora) {
+            text.push('\u{325}');
+        }
+    }
+    text
+}
+
 /// Generate header text from the given entry information.
 fn generate_header_text(
     entry_settings: EntrySettings,
     kana: &str,
     pitch_accent: Option<&Vec<u32>>,
+    phonetics: Option<&yomichan::Phonetics>,
     jm_entry: &jmdict::WordEntry,
 ) -> String {
-    let mut text = format!(
-        "{}",
-        if entry_settings.use_katakana_pronunciation {
-            hiragana_to_katakana(&kana)
-        } else {
-            katakana_to_hiragana(&kana)
+    let mut pronunciation = if entry_settings.use_katakana_pronunciation {
+        hiragana_to_katakana(kana)
+    } else {
+        katakana_to_hiragana(kana)
+    };
+    if let Some(phonetics) = phonetics {
+        if entry_settings.phonetic_detail && !phonetics.is_empty() {
+            pronunciation = mark_phonetics(&pronunciation, phonetics);
         }
-    );
+    }
+    // Furigana make the header line taller, so they're left out of compact
+    // headers.
+    let furigana = entry_settings.furigana_headers && !entry_settings.compact_headers;
+    let mut text = if furigana {
+        format!(
+            "<span style=\"font-size: {}; font-weight: bold;\">{}</span>",
+            entry_settings.text_scale.headword(),
+            pronunciation
+        )
+    } else {
+        pronunciation
+    };
 
     if let Some(accent_list) = pitch_accent {
         if !accent_list.is_empty() {
-            text.push_str(" ");
+            text.push(' ');
             for a in accent_list.iter() {
                 text.push_str(&format!("[{}]", a));
             }
         }
     }
 
-    text.push_str(" &nbsp;&nbsp;&mdash; 【");
+    if entry_settings.compact_headers {
+        text.push_str(" 【");
+    } else {
+        text.push_str(" &nbsp;&nbsp;&mdash; 【");
+    }
+    let (reading, mut writings) = header_forms(jm_entry, entry_settings.irregular_forms);
+    let extra_writings = match entry_settings.max_header_writings {
+        Some(max) if writings.len() > max.max(1) => writings.split_off(max.max(1)),
+        _ => Vec::new(),
+    };
+    let (affix_start, affix_end) = match (is_prefix(jm_entry), is_suffix(jm_entry)) {
+        _ if !entry_settings.mark_affixes => ("", ""),
+        (true, false) => ("", "〜"),
+        (false, true) => ("〜", ""),
+        _ => ("", ""),
+    };
     let mut first = true;
-    if jm_entry.usually_kana || jm_entry.writings.is_empty() {
-        text.push_str(&jm_entry.readings[0]);
+    if jm_entry.usually_kana || writings.is_empty() {
+        text.push_str(&format!("{}{}{}", affix_start, reading, affix_end));
         first = false;
     }
-    for w in jm_entry.writings.iter() {
+    for w in writings.iter() {
         if !first {
-            text.push_str("／");
+            text.push('／');
+        }
+        text.push_str(affix_start);
+        if furigana && !is_all_kana(w) {
+            text.push_str(&format!("<ruby>{}<rt>{}</rt></ruby>", w, reading));
+        } else {
+            text.push_str(w);
         }
-        text.push_str(&w);
+        text.push_str(affix_end);
         first = false;
     }
-    text.push_str("】");
-
-    const WORD_TYPE_START: &'static str =
-        " <span style=\"font-size: 0.8em; font-style: italic; margin-left: 0; white-space: nowrap;\">";
-    const WORD_TYPE_END: &'static str = "</span>";
-    match jm_entry.pos {
-        jmdict::PartOfSpeech::Verb => {
-            use jmdict::ConjugationClass::*;
-            let conj_type_text = match jm_entry.conj {
-                IchidanVerb => HEADER_TERMS[", ichidan"][entry_settings.lang_mode.idx()],
-
-                GodanVerbU
-                | GodanVerbTsu
-                | GodanVerbRu
-                | GodanVerbKu
-                | GodanVerbGu
-                | GodanVerbNu
-                | GodanVerbBu
-                | GodanVerbMu
-                | GodanVerbSu => HEADER_TERMS[", godan"][entry_settings.lang_mode.idx()],
-
-                SuruVerb
-                | SuruVerbSC
-                | KuruVerb
-                | IkuVerb
-                | KureruVerb
-                | AruVerb
-                | SharuVerb
-                | GodanVerbHu // Doesn't exist in modern Japanese, so we're calling it irregular.
-                | IrregularVerb => HEADER_TERMS[", irregular"][entry_settings.lang_mode.idx()],
-
-                _ => "",
-            };
+    text.push('】');
 
-            let transitive = jm_entry.tags.contains("pos:vt");
-            let intransitive = jm_entry.tags.contains("pos:vi");
-            let transitive_text = match (transitive, intransitive) {
-                (true, false) => HEADER_TERMS[", transitive"][entry_settings.lang_mode.idx()],
-                (false, true) => HEADER_TERMS[", intransitive"][entry_settings.lang_mode.idx()],
-                _ => "",
-            };
-
-            text.push_str(&format!(
-                "{}{}{}{}{}",
-                WORD_TYPE_START,
-                HEADER_TERMS["verb"][entry_settings.lang_mode.idx()],
-                transitive_text,
-                conj_type_text,
-                WORD_TYPE_END
-            ));
+    let word_type_start = format!(
+        " <span style=\"font-size: {}; font-style: italic; margin-left: 0; white-space: nowrap;\">",
+        entry_settings.text_scale.small()
+    );
+    const WORD_TYPE_END: &str = "</span>";
+    if let Some(style) = entry_settings.tag_style {
+        let tags = tag_text(jm_entry, style);
+        if !tags.is_empty() {
+            text.push_str(&format!("{}{}{}", word_type_start, tags, WORD_TYPE_END));
         }
+    } else {
+        match jm_entry.pos {
+            jmdict::PartOfSpeech::Verb => {
+                use jmdict::ConjugationClass::*;
+                let conj_type_text = match jm_entry.conj {
+                    IchidanVerb => HEADER_TERMS[", ichidan"][entry_settings.lang_mode.idx()],
+
+                    GodanVerbU
+                    | GodanVerbTsu
+                    | GodanVerbRu
+                    | GodanVerbKu
+                    | GodanVerbGu
+                    | GodanVerbNu
+                    | GodanVerbBu
+                    | GodanVerbMu
+                    | GodanVerbSu => HEADER_TERMS[", godan"][entry_settings.lang_mode.idx()],
+
+                    SuruVerb
+                    | SuruVerbSC
+                    | ZuruVerb
+                    | KuruVerb
+                    | IkuVerb
+                    | KureruVerb
+                    | AruVerb
+                    | SharuVerb
+                    | GodanVerbHu // Doesn't exist in modern Japanese, so we're calling it irregular.
+                    | IrregularVerb => HEADER_TERMS[", irregular"][entry_settings.lang_mode.idx()],
+
+                    _ => "",
+                };
+
+                let transitive = jm_entry.tags.contains("pos:vt");
+                let intransitive = jm_entry.tags.contains("pos:vi");
+                let transitive_text = match (transitive, intransitive) {
+                    (true, false) => HEADER_TERMS[", transitive"][entry_settings.lang_mode.idx()],
+                    (false, true) => HEADER_TERMS[", intransitive"][entry_settings.lang_mode.idx()],
+                    _ => "",
+                };
+
+                text.push_str(&format!(
+                    "{}{}{}{}{}",
+                    word_type_start,
+                    HEADER_TERMS["verb"][entry_settings.lang_mode.idx()],
+                    transitive_text,
+                    conj_type_text,
+                    WORD_TYPE_END
+                ));
+            }
 
-        jmdict::PartOfSpeech::Adjective => {
-            use jmdict::ConjugationClass::*;
-            let adjective_type_text = match jm_entry.conj {
-                IAdjective | IrregularIAdjective => {
-                    HEADER_TERMS["i-adjective"][entry_settings.lang_mode.idx()]
-                }
-                _ => HEADER_TERMS["adjective"][entry_settings.lang_mode.idx()],
-            };
+            jmdict::PartOfSpeech::Adjective => {
+                use jmdict::ConjugationClass::*;
+                let adjective_type_text = match jm_entry.conj {
+                    IAdjective | IrregularIAdjective => {
+                        HEADER_TERMS["i-adjective"][entry_settings.lang_mode.idx()]
+                    }
+                    _ => HEADER_TERMS["adjective"][entry_settings.lang_mode.idx()],
+                };
+
+                let irregular_text = match jm_entry.conj {
+                    IrregularIAdjective => {
+                        HEADER_TERMS[", irregular"][entry_settings.lang_mode.idx()]
+                    }
+                    _ => "",
+                };
+
+                text.push_str(&format!(
+                    "{}{}{}{}",
+                    word_type_start, adjective_type_text, irregular_text, WORD_TYPE_END
+                ));
+            }
 
-            let irregular_text = match jm_entry.conj {
-                IrregularIAdjective => HEADER_TERMS[", irregular"][entry_settings.lang_mode.idx()],
-                _ => "",
-            };
+            jmdict::PartOfSpeech::Noun => {
+                // Na-adjectives and suru verbs are grammatically nouns, but
+                // calling them that isn't very helpful.
+                let noun_type_text = if jm_entry.tags.contains("pos:adj-na") {
+                    HEADER_TERMS["na-adjective"][entry_settings.lang_mode.idx()]
+                } else {
+                    HEADER_TERMS["noun"][entry_settings.lang_mode.idx()]
+                };
+                let suru_text = if jm_entry.tags.contains("pos:vs") {
+                    HEADER_TERMS[", suru"][entry_settings.lang_mode.idx()]
+                } else {
+                    ""
+                };
+
+                text.push_str(&format!(
+                    "{}{}{}{}",
+                    word_type_start, noun_type_text, suru_text, WORD_TYPE_END
+                ));
+            }
 
-            text.push_str(&format!(
-                "{}{}{}{}",
-                WORD_TYPE_START, adjective_type_text, irregular_text, WORD_TYPE_END
-            ));
+            jmdict::PartOfSpeech::Unknown => {}
+
+            pos => {
+                use jmdict::PartOfSpeech::*;
+                let term = match pos {
+                    Copula => "copula",
+                    Particle => "particle",
+                    Conjunction => "conjunction",
+                    Adverb => "adverb",
+                    Expression => "expression",
+                    Pronoun => "pronoun",
+                    Counter => "counter",
+                    Interjection => "interjection",
+                    Auxiliary => "auxiliary",
+                    Prefix => "prefix",
+                    Suffix => "suffix",
+                    Numeric => "numeric",
+                    Unknown | Noun | Verb | Adjective => unreachable!(),
+                };
+
+                text.push_str(&format!(
+                    "{}{}{}",
+                    word_type_start,
+                    HEADER_TERMS[term][entry_settings.lang_mode.idx()],
+                    WORD_TYPE_END
+                ));
+            }
         }
+    }
 
-        _ => {}
+    if entry_settings.onomatopoeia && jm_entry.tags.contains("misc:on-mim") {
+        text.push_str(&format!(
+            "{}{}{}",
+            word_type_start,
+            HEADER_TERMS["onomatopoeia"][entry_settings.lang_mode.idx()],
+            WORD_TYPE_END
+        ));
+    }
+
+    if !extra_writings.is_empty() && entry_settings.extra_writings == ExtraWritings::OtherForms {
+        text.push_str(&format!(
+            "<br/><span style=\"font-size: {};\">{}: {}</span>",
+            entry_settings.text_scale.small(),
+            HEADER_TERMS["other forms"][entry_settings.lang_mode.idx()],
+            extra_writings.join("／")
+        ));
     }
 
     text
 }
 
+/// Returns the part of speech tags of a word followed by its usage tags,
+/// e.g. "v5k, vt, uk", in the given style.  Labels that several tags share
+/// (e.g. "godan verb") are only listed once.
+fn tag_text(jm_entry: &jmdict::WordEntry, style: TagStyle) -> String {
+    let mut labels: Vec<&str> = Vec::new();
+    for prefix in ["pos:", "misc:"].iter() {
+        let mut names: Vec<&str> = jm_entry
+            .tags
+            .iter()
+            .filter_map(|tag| tag.strip_prefix(prefix))
+            .collect();
+        names.sort_unstable();
+        for name in names {
+            let label = match (style, jmdict::entity_description(name)) {
+                (TagStyle::Short, Some((short, _))) => short,
+                (TagStyle::Long, Some((_, long))) => long,
+                _ => name,
+            };
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+    }
+    labels.join(", ")
+}
+
 /// Generate English definition text from the given JMDict entry.
-fn generate_definition_text(yomi_entries: &[yomichan::TermEntry]) -> String {
+fn generate_definition_text(
+    entry_settings: EntrySettings,
+    yomi_entries: &[yomichan::TermEntry],
+) -> String {
     let mut text = String::new();
 
-    text.push_str("<div style=\"margin-top: 0.7em\">");
-    for entry in yomi_entries.iter() {
+    if entry_settings.compact_headers {
+        text.push_str("<div>");
+    } else {
+        text.push_str("<div style=\"margin-top: 0.7em\">");
+    }
+    for (i, entry) in yomi_entries.iter().enumerate() {
+        let definitions =
+            yomichan::definition_to_html(&entry.definitions, entry.definitions.depth(), true);
+        if entry_settings.collapse_sections && i > 0 {
+            let summary = if entry.dict_name.is_empty() {
+                "&hellip;"
+            } else {
+                &entry.dict_name
+            };
+            text.push_str(&collapsible(
+                entry_settings,
+                summary,
+                &format!("<p>{}</p>", definitions),
+            ));
+            continue;
+        }
         text.push_str("<p>");
-        if yomi_entries.len() > 1 {
+        if yomi_entries.len() > 1 && !entry.dict_name.is_empty() {
             text.push_str(&format!("{}:<br/>", entry.dict_name));
         }
-        text.push_str(&yomichan::definition_to_html(
-            &entry.definitions,
-            entry.definitions.depth(),
-            true,
-        ));
+        text.push_str(&definitions);
         text.push_str("</p>");
     }
     text.push_str("</div>");
@@ -297,59 +958,416 @@ fn generate_definition_text(yomi_entries: &[yomichan::TermEntry]) -> String {
     text
 }
 
+/// Wraps `content` in a `<details>` section, collapsed to a line with
+/// `summary`.
+fn collapsible(entry_settings: EntrySettings, summary: &str, content: &str) -> String {
+    format!(
+        "<details><summary style=\"font-size: {};\">{}</summary>{}</details>",
+        entry_settings.text_scale.small(),
+        summary,
+        content
+    )
+}
+
+/// Generates a line with the basic conjugations of the word, or an empty
+/// string if it's not a verb or i-adjective with a known conjugation.
+fn generate_conjugation_text(
+    entry_settings: EntrySettings,
+    jm_entry: &jmdict::WordEntry,
+) -> String {
+    let (reading, writings) = header_forms(jm_entry, entry_settings.irregular_forms);
+    let word = if jm_entry.usually_kana || writings.is_empty() {
+        reading
+    } else {
+        writings[0]
+    };
+
+    let forms = match conjugate(word, jm_entry.conj) {
+        Some(forms) => forms,
+        None => return String::new(),
+    };
+
+    let lang = entry_settings.lang_mode.idx();
+    let labels = ["polite", "te-form", "negative", "past"];
+    let parts: Vec<String> = labels
+        .iter()
+        .zip(forms.iter())
+        .map(|(label, form)| format!("{}: {}", HEADER_TERMS[label][lang], form))
+        .collect();
+
+    format!(
+        "<p style=\"font-size: {}; margin-top: 0.3em;\">{}</p>",
+        entry_settings.text_scale.small(),
+        parts.join(" &middot; ")
+    )
+}
+
+/// Generates a small table of the pitch accents of the basic conjugations
+/// of a verb, or an empty string if it's not a verb or has no known accent.
+fn generate_conjugation_accent_text(
+    entry_settings: EntrySettings,
+    jm_entry: &jmdict::WordEntry,
+    pitch_accent: &[u32],
+) -> String {
+    if jm_entry.pos != jmdict::PartOfSpeech::Verb || pitch_accent.is_empty() {
+        return String::new();
+    }
+    let reading = katakana_to_hiragana(header_forms(jm_entry, entry_settings.irregular_forms).0);
+    let forms = match conjugate(&reading, jm_entry.conj) {
+        Some(forms) => forms,
+        None => return String::new(),
+    };
+
+    let lang = entry_settings.lang_mode.idx();
+    let labels = ["polite", "te-form", "negative", "past"];
+    let mut text = format!(
+        "<table style=\"font-size: {}; margin-top: 0.3em;\">",
+        entry_settings.text_scale.small()
+    );
+    for (i, (label, form)) in labels.iter().zip(forms.iter()).enumerate() {
+        let accents: Vec<String> = pitch_accent
+            .iter()
+            .map(|&a| format!("[{}]", conjugation_accent(jm_entry.conj, a, form, i)))
+            .collect();
+        text.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            HEADER_TERMS[label][lang],
+            form,
+            accents.join("")
+        ));
+    }
+    text.push_str("</table>");
+
+    text
+}
+
+/// Derives the accent of a conjugated form of a verb from the accent of its
+/// dictionary form, using the standard Tokyo accent rules.  `form_i` is the
+/// index of the form as returned by `conjugate()`.
+///
+/// Accents are given as the mora the pitch drops after, with 0 meaning no
+/// drop (heiban).
+fn conjugation_accent(
+    conj: jmdict::ConjugationClass,
+    accent: u32,
+    form: &str,
+    form_i: usize,
+) -> u32 {
+    use jmdict::ConjugationClass::*;
+
+    let morae = mora_count(form) as u32;
+    match form_i {
+        // ～ます always drops after ま.
+        0 => morae.saturating_sub(1),
+
+        // Heiban verbs stay heiban.
+        _ if accent == 0 => 0,
+
+        // ～ない drops before な.
+        2 => morae.saturating_sub(2),
+
+        // ～て and ～た.  For ichidan verbs (and くる) the drop moves one
+        // mora earlier, e.g. たべ\る → た\べて.  Godan verbs keep it in
+        // place, e.g. はな\す → はな\して.
+        _ => match conj {
+            IchidanVerb | KureruVerb | KuruVerb => accent.saturating_sub(1).max(1),
+            _ => accent,
+        },
+    }
+}
+
+/// Returns the polite, te-form, negative, and past forms of `word`, or
+/// `None` if its conjugation class isn't one we know how to handle.
+///
+/// The forms are taken from the same endings as the lookup keys, the first
+/// of each kind, so the two can't disagree.  I-adjectives have no polite
+/// inflection, and just add です.
+fn conjugate(word: &str, conj: jmdict::ConjugationClass) -> Option<[String; 4]> {
+    use jmdict::ConjugationClass::*;
+
+    let extensive = extensive_endings(conj);
+    let mut forms: Vec<String> = Vec::new();
+    for (trail, endings) in inflection_endings(conj)
+        .iter()
+        .find(|(trail, _)| word.ends_with(trail))
+        .into_iter()
+        .map(|(trail, endings)| (*trail, endings.to_vec()))
+        .chain(
+            extensive
+                .iter()
+                .find(|(trail, _)| word.ends_with(trail))
+                .map(|(trail, endings)| (*trail, endings.iter().map(|e| e.as_str()).collect())),
+        )
+    {
+        let stem = &word[..(word.len() - trail.len())];
+        forms.extend(endings.iter().map(|end| format!("{}{}", stem, end)));
+    }
+
+    let first = |is_form: &dyn Fn(&str) -> bool| forms.iter().find(|f| is_form(f)).cloned();
+    let polite = first(&|f| f.ends_with("ます")).or_else(|| match conj {
+        IAdjective | IrregularIAdjective => Some(format!("{}です", word)),
+        _ => None,
+    });
+    let te_form = first(&|f| (f.ends_with('て') || f.ends_with('で')) && !f.ends_with("なくて"));
+    let negative = first(&|f| f.ends_with("ない"));
+    let past = first(&|f| {
+        (f.ends_with('た') || f.ends_with('だ'))
+            && !f.ends_with("なかった")
+            && !f.ends_with("ました")
+    });
+
+    Some([polite?, te_form?, negative?, past?])
+}
+
+/// Adds notes on the source languages of a loanword to the end of the
+/// senses they're given for, in the first source dictionary whose senses
+/// line up with the JMDict entry's.
+///
+/// Returns a paragraph with the notes that couldn't be placed that way,
+/// numbered by sense, or an empty string if there are none.
+fn place_loan_source_notes(
+    entry_settings: EntrySettings,
+    jm_entry: &jmdict::WordEntry,
+    senses: &mut [yomichan::TermEntry],
+) -> String {
+    let sense_count = jm_entry.definitions.len();
+    let mut placed = vec![false; jm_entry.loan_sources.len()];
+
+    let items = senses
+        .iter_mut()
+        .map(|sense| sense_items(&mut sense.definitions))
+        .find(|items| sense_count > 0 && items.len() == sense_count);
+    if let Some(mut items) = items {
+        for (i, item) in items.iter_mut().enumerate() {
+            let mut notes = Vec::new();
+            for (source, placed) in jm_entry.loan_sources.iter().zip(placed.iter_mut()) {
+                if source.sense == i {
+                    notes.push(loan_source_note(source));
+                    *placed = true;
+                }
+            }
+            if !notes.is_empty() {
+                append_to_last_def(
+                    item,
+                    &format!(
+                        " <span style=\"font-size: {}; font-style: italic;\">({})</span>",
+                        entry_settings.text_scale.small(),
+                        notes.join("; ")
+                    ),
+                );
+            }
+        }
+    }
+
+    let notes: Vec<String> = jm_entry
+        .loan_sources
+        .iter()
+        .zip(placed.iter())
+        .filter(|(_, &placed)| !placed)
+        .map(|(source, _)| {
+            if sense_count > 1 {
+                format!("{}. {}", source.sense + 1, loan_source_note(source))
+            } else {
+                loan_source_note(source)
+            }
+        })
+        .collect();
+    if notes.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "<p style=\"font-size: {}; font-style: italic;\">{}</p>",
+        entry_settings.text_scale.small(),
+        notes.join("; ")
+    )
+}
+
+/// The items of a definition list that correspond to JMDict senses, looking
+/// through the part of speech groups made by `yomichan::parse()`'s
+/// `group_by_pos`.
+fn sense_items(definitions: &mut yomichan::Definition) -> Vec<&mut yomichan::Definition> {
+    use yomichan::Definition;
+
+    if !definitions.is_list() {
+        return vec![definitions];
+    }
+    let list = match definitions {
+        Definition::List((_, list)) => list,
+        Definition::Def(_) => unreachable!(),
+    };
+    let is_pos_group =
+        |d: &Definition| matches!(d, Definition::List((header, _)) if header.starts_with('［'));
+    if !list.is_empty() && list.iter().all(is_pos_group) {
+        list.iter_mut()
+            .flat_map(|group| match group {
+                Definition::List((_, items)) => items.iter_mut(),
+                Definition::Def(_) => unreachable!(),
+            })
+            .collect()
+    } else {
+        list.iter_mut().collect()
+    }
+}
+
+/// Appends `text` to the last definition text within `definition`.
+fn append_to_last_def(definition: &mut yomichan::Definition, text: &str) {
+    match definition {
+        yomichan::Definition::List((header, list)) => match list.last_mut() {
+            Some(last) => append_to_last_def(last, text),
+            None => header.push_str(text),
+        },
+        yomichan::Definition::Def(s) => s.push_str(text),
+    }
+}
+
+/// A short note on one source language of a loanword, e.g. "from
+/// Portuguese: pão".
+fn loan_source_note(source: &jmdict::LoanSource) -> String {
+    let mut note = String::new();
+    if source.wasei {
+        note.push_str("coined in Japan ");
+    }
+    note.push_str(if source.partial {
+        "partly from "
+    } else {
+        "from "
+    });
+    note.push_str(source.language_name());
+    if !source.word.is_empty() {
+        note.push_str(&format!(": {}", source.word));
+    }
+    note
+}
+
 /// Generates the look-up keys for a JMDict word entry.
 ///
-/// If `generate_inflections == true`, then basic conjugations of the word are
-/// also added to the key list.
-fn generate_lookup_keys(
+/// If `entry_settings.generate_inflection_keys == true`, then conjugations
+/// of the word are also added to the key list, as many as
+/// `entry_settings.conjugation_level` calls for.  If
+/// `entry_settings.katakana_keys == true`, then katakana versions of all-kana
+/// keys are also added.
+pub fn generate_lookup_keys(
     jm_entry: &jmdict::WordEntry,
-    generate_inflections: bool,
+    entry_settings: EntrySettings,
 ) -> Vec<(String, u32)> {
     use jmdict::ConjugationClass::*;
 
+    let generate_inflections = entry_settings.generate_inflection_keys;
+    let katakana_keys = entry_settings.katakana_keys;
+    let weights = entry_settings.priority_weights;
+
     let jm_priority = jm_entry.priority + 256; // Ensure we never reach zero, since that's reserved for Kanji entries.
 
     // Give verbs and i-adjectives a priority boost, so they show up
     // earlier in search results.
     let priority_boost = match jm_entry.conj {
         IchidanVerb | GodanVerbU | GodanVerbTsu | GodanVerbRu | GodanVerbKu | GodanVerbGu
-        | GodanVerbNu | GodanVerbBu | GodanVerbMu | GodanVerbSu | IkuVerb | KuruVerb | SuruVerb => {
-            4
-        }
-        IAdjective => 2,
+        | GodanVerbNu | GodanVerbBu | GodanVerbMu | GodanVerbSu | IkuVerb | KuruVerb | SuruVerb
+        | SuruVerbSC | ZuruVerb | KureruVerb | AruVerb | SharuVerb => weights.verb_divisor,
+        IAdjective => weights.i_adjective_divisor,
         _ => 1,
+    }
+    .max(1);
+
+    // "news1", "ichi1", "gai1", and "nf01" through "nf24" are what JMDict
+    // considers common words.
+    let common_boost = if jm_entry.priority <= 11500 {
+        weights.common_divisor.max(1)
+    } else {
+        1
     };
 
+    // (form, is irregular).  When a form is listed both ways, the regular
+    // one is kept.  Search-only readings are always included, since that's
+    // what they're for.
+    let mut forms: Vec<(&str, bool)> = jm_entry
+        .writings
+        .iter()
+        .enumerate()
+        .map(|(i, w)| (w.as_str(), jm_entry.is_irregular_writing(i)))
+        .chain(
+            jm_entry
+                .readings
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| {
+                    jm_entry.usually_kana
+                        || entry_settings.kana_learner_keys
+                        || i == 0
+                        || jm_entry.is_search_only_reading(i)
+                })
+                .map(|(i, r)| (r.as_str(), jm_entry.is_irregular_reading(i))),
+        )
+        .collect();
+    forms.sort();
+    forms.dedup_by_key(|f| f.0);
+
+    match entry_settings.irregular_forms {
+        IrregularForms::Keep => {
+            for form in forms.iter_mut() {
+                form.1 = false;
+            }
+        }
+        IrregularForms::Demote => {}
+        IrregularForms::Exclude => {
+            if forms.iter().any(|f| !f.1) {
+                forms.retain(|f| !f.1);
+            }
+        }
+    }
+
     let mut keys = Vec::new();
 
     let mut end_replace_push = |word: &str, trail: &str, endings: &[&str]| {
+        let is_irregular = forms.iter().any(|&(w, irregular)| irregular && w == word);
+
         // If a word is usually written in kana, give the kana form a major
-        // priority boost.
-        let priority = if is_all_kana(word) && jm_entry.usually_kana {
-            jm_priority / 8
+        // priority boost.  Learners get the same boost for all words, since
+        // they often only know the reading.
+        let priority =
+            (if is_all_kana(word) && (jm_entry.usually_kana || entry_settings.kana_learner_keys) {
+                jm_priority / weights.kana_preferred_divisor.max(1)
+            } else {
+                jm_priority
+            } / priority_boost
+                / common_boost)
+                .max(1);
+
+        // Irregular forms come after the regular forms of all words.
+        let priority = if is_irregular {
+            priority.saturating_add(weights.irregular_penalty)
         } else {
-            jm_priority
-        } / priority_boost;
+            priority
+        };
 
         // We include the katakana version for all-hiragana
         // words as well because for some reason that's how Kobo
         // looks up hiragana words.  Leaving this out causes the Kobo
         // to completely fail to find entries for all-hirigana words.
-        if is_all_kana(word) {
+        if katakana_keys && is_all_kana(word) {
             keys.push((hiragana_to_katakana(word), priority));
         }
         keys.push((word.into(), priority));
 
-        if trail.len() > 0 && word.len() >= trail.len() && word.ends_with(trail) {
+        if !trail.is_empty() && word.len() >= trail.len() && word.ends_with(trail) {
             let stem = {
                 let mut stem: String = word.into();
                 stem.truncate(word.len() - trail.len());
                 stem
             };
 
+            // Inflected forms come after words that are exact matches.
+            let priority = priority.saturating_add(weights.inflection_penalty);
+
             for end in endings.iter() {
+                if entry_settings.conjugation_level == ConjugationLevel::Minimal
+                    && end.chars().count() > 1
+                {
+                    continue;
+                }
                 let variant = format!("{}{}", stem, end);
-                if is_all_kana(&variant) {
+                if katakana_keys && is_all_kana(&variant) {
                     keys.push((hiragana_to_katakana(&variant), priority));
                 }
                 keys.push((variant, priority));
@@ -357,184 +1375,462 @@ fn generate_lookup_keys(
         }
     };
 
-    let mut forms: Vec<_> = jm_entry
-        .writings
-        .iter()
-        .chain(if jm_entry.usually_kana {
-            jm_entry.readings.iter()
-        } else {
-            (&jm_entry.readings[0..1]).iter()
-        })
-        .collect();
-    forms.sort();
-    forms.dedup();
-
-    for word in forms.iter() {
+    for &(word, _) in forms.iter() {
         if !generate_inflections {
             end_replace_push(word, "", &[]);
             continue;
         }
 
-        match jm_entry.conj {
-            // We include the ～あない ending even though it should be covered by ～あ because
-            // there are some entries for exactly ～あない, and they prevent the verb entries
-            // from showing up.
-            IchidanVerb => {
-                end_replace_push(word, "る", &["", "ない", "られ", "させ", "ろ", "て", "た"]);
-            }
+        match inflection_endings(jm_entry.conj)
+            .iter()
+            .find(|(trail, _)| word.ends_with(trail))
+        {
+            Some((trail, endings)) => end_replace_push(word, trail, endings),
+            None => end_replace_push(word, "", &[]),
+        }
 
-            GodanVerbU => {
-                end_replace_push(
-                    word,
-                    "う",
-                    &["わない", "わ", "い", "え", "お", "って", "った"],
-                );
+        if entry_settings.conjugation_level == ConjugationLevel::Extensive {
+            let extensive = extensive_endings(jm_entry.conj);
+            if let Some((trail, endings)) =
+                extensive.iter().find(|(trail, _)| word.ends_with(trail))
+            {
+                let endings: Vec<&str> = endings.iter().map(|e| e.as_str()).collect();
+                end_replace_push(word, trail, &endings);
             }
+        }
+    }
 
-            GodanVerbTsu => {
-                end_replace_push(
-                    word,
-                    "つ",
-                    &["たない", "た", "ち", "て", "と", "って", "った"],
-                );
-            }
+    // Spelling variants of the keys.  Each kind is only generated from the
+    // keys above, not from the variants of other kinds, so that enabling
+    // several kinds doesn't multiply the number of keys.
+    let mut variants = Vec::new();
+
+    // Words with Latin letters (e.g. Tシャツ) may be written in either
+    // width, and in any case.
+    variants.extend(variant_keys(&keys, 0, latin_variants));
+
+    // Loanwords are spelled inconsistently, e.g. コンピューター or
+    // コンピュータ.  Other spellings come after exact matches.
+    if entry_settings.loanword_variants {
+        variants.extend(variant_keys(
+            &keys,
+            weights.inflection_penalty,
+            loanword_variants,
+        ));
+    }
 
-            GodanVerbRu => {
-                end_replace_push(
-                    word,
-                    "る",
-                    &["らない", "ら", "り", "れ", "ろ", "って", "った"],
-                );
-            }
+    // Older books use iteration marks and full-size kana differently,
+    // e.g. こゝろ for こころ.
+    if entry_settings.old_text_variants {
+        variants.extend(variant_keys(
+            &keys,
+            weights.inflection_penalty,
+            old_text_variants,
+        ));
+    }
 
-            GodanVerbKu => {
-                end_replace_push(
-                    word,
-                    "く",
-                    &["かない", "か", "き", "け", "こ", "いて", "いた"],
-                );
-            }
+    if entry_settings.historical_kana {
+        variants.extend(variant_keys(
+            &keys,
+            weights.inflection_penalty,
+            kyukana::historical_variants,
+        ));
+    }
 
-            GodanVerbGu => {
-                end_replace_push(
-                    word,
-                    "ぐ",
-                    &["がない", "が", "ぎ", "げ", "ご", "いで", "いだ"],
-                );
-            }
+    // Onomatopoeia are written in either script, e.g. ニコニコ and にこにこ.
+    if entry_settings.onomatopoeia && jm_entry.tags.contains("misc:on-mim") {
+        variants.extend(
+            keys.iter()
+                .filter(|(key, _)| is_all_kana(key))
+                .map(|(key, priority)| (katakana_to_hiragana(key), *priority)),
+        );
+    }
 
-            GodanVerbNu => {
-                end_replace_push(
-                    word,
-                    "ぬ",
-                    &["なない", "な", "に", "ね", "の", "んで", "んだ"],
-                );
-            }
+    if entry_settings.kyujitai_keys {
+        variants.extend(variant_keys(
+            &keys,
+            weights.inflection_penalty,
+            kyujitai::kyujitai_variants,
+        ));
+    }
 
-            GodanVerbBu => {
-                end_replace_push(
-                    word,
-                    "ぶ",
-                    &["ばない", "ば", "び", "べ", "ぼ", "んで", "んだ"],
-                );
-            }
+    keys.extend(variants);
 
-            GodanVerbMu => {
-                end_replace_push(
-                    word,
-                    "む",
-                    &["まない", "ま", "み", "め", "も", "んで", "んだ"],
-                );
-            }
+    // Only keep the highest priority of each key.
+    keys.sort_unstable_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    keys.dedup_by(|a, b| a.0 == b.0);
 
-            GodanVerbSu => {
-                end_replace_push(
-                    word,
-                    "す",
-                    &["さない", "さ", "し", "せ", "そ", "して", "した"],
-                );
-            }
+    keys.sort_by_key(|a| (a.1, a.0.len(), a.0.clone()));
+    keys
+}
 
-            IkuVerb => {
-                end_replace_push(
-                    word,
-                    "く",
-                    &["かない", "か", "き", "け", "こ", "って", "った"],
-                );
-            }
+/// Returns the spelling variants of `keys` that `variants` finds, with the
+/// priority of the key they're a variant of plus `penalty`.
+fn variant_keys(
+    keys: &[(String, u32)],
+    penalty: u32,
+    variants: impl Fn(&str) -> Vec<String>,
+) -> Vec<(String, u32)> {
+    keys.iter()
+        .flat_map(|(key, priority)| {
+            let priority = priority.saturating_add(penalty);
+            variants(key)
+                .into_iter()
+                .map(move |variant| (variant, priority))
+        })
+        .collect()
+}
 
-            KuruVerb => {
-                end_replace_push(
-                    word,
-                    "くる",
-                    &[
-                        "こない",
-                        "こなかった",
-                        "こなくて",
-                        "きて",
-                        "きた",
-                        "こられ",
-                        "こさせ",
-                        "こい",
-                        "きます",
-                        "きません",
-                        "きました",
-                    ],
-                );
-                end_replace_push(
-                    word,
-                    "来る",
-                    &[
-                        "来ない",
-                        "来なかった",
-                        "来なくて",
-                        "来て",
-                        "来た",
-                        "来られ",
-                        "来させ",
-                        "来い",
-                        "来ます",
-                        "来ません",
-                        "来ました",
-                    ],
-                );
-            }
+/// The endings of the inflected forms added as lookup keys, along with the
+/// dictionary form ending they replace.  Only the first row whose ending a
+/// word has applies to it.
+///
+/// `conjugate()` takes the forms shown in entries from these too, the first
+/// ending of each kind, so the order of the endings matters.
+fn inflection_endings(
+    conj: jmdict::ConjugationClass,
+) -> &'static [(&'static str, &'static [&'static str])] {
+    use jmdict::ConjugationClass::*;
 
-            SuruVerb => {
-                end_replace_push(
-                    word,
-                    "する",
-                    &[
-                        "しな",
-                        "しろ",
-                        "させ",
-                        "され",
-                        "でき",
-                        "した",
-                        "して",
-                        "しない",
-                        "します",
-                        "しません",
-                    ],
-                );
-            }
+    match conj {
+        // We include the ～あない ending even though it should be covered by ～あ because
+        // there are some entries for exactly ～あない, and they prevent the verb entries
+        // from showing up.
+        IchidanVerb => &[("る", &["", "ない", "られ", "させ", "ろ", "て", "た"])],
+
+        // The imperative is the bare stem, e.g. くれ.
+        KureruVerb => &[("る", &["", "ない", "られ", "させ", "て", "た"])],
+
+        // The negative of ある is ない, rather than あらない.
+        AruVerb => &[
+            (
+                "ある",
+                &[
+                    "あり",
+                    "あれ",
+                    "あろ",
+                    "あって",
+                    "あった",
+                    "ない",
+                    "なかった",
+                    "なくて",
+                ],
+            ),
+            ("る", &["り", "れ", "ろ", "って", "った"]),
+        ],
+
+        // E.g. いらっしゃる and おっしゃる, whose ～ます form and
+        // imperative use い instead of り.
+        SharuVerb => &[("る", &["らない", "ら", "い", "り", "れ", "って", "った"])],
+
+        GodanVerbU => &[("う", &["わない", "わ", "い", "え", "お", "って", "った"])],
+        // The te-form and past come before the た and て stems, which
+        // `conjugate()` would otherwise take for them.
+        GodanVerbTsu => &[("つ", &["たない", "って", "った", "た", "ち", "て", "と"])],
+        GodanVerbRu => &[("る", &["らない", "ら", "り", "れ", "ろ", "って", "った"])],
+        GodanVerbKu => &[("く", &["かない", "か", "き", "け", "こ", "いて", "いた"])],
+        GodanVerbGu => &[("ぐ", &["がない", "が", "ぎ", "げ", "ご", "いで", "いだ"])],
+        GodanVerbNu => &[("ぬ", &["なない", "な", "に", "ね", "の", "んで", "んだ"])],
+        GodanVerbBu => &[("ぶ", &["ばない", "ば", "び", "べ", "ぼ", "んで", "んだ"])],
+        GodanVerbMu => &[("む", &["まない", "ま", "み", "め", "も", "んで", "んだ"])],
+        GodanVerbSu => &[("す", &["さない", "さ", "し", "せ", "そ", "して", "した"])],
+        IkuVerb => &[("く", &["かない", "か", "き", "け", "こ", "って", "った"])],
+
+        KuruVerb => &[
+            (
+                "くる",
+                &[
+                    "こない",
+                    "こなかった",
+                    "こなくて",
+                    "きて",
+                    "きた",
+                    "こられ",
+                    "こさせ",
+                    "こい",
+                    "きます",
+                    "きません",
+                    "きました",
+                ],
+            ),
+            (
+                "来る",
+                &[
+                    "来ない",
+                    "来なかった",
+                    "来なくて",
+                    "来て",
+                    "来た",
+                    "来られ",
+                    "来させ",
+                    "来い",
+                    "来ます",
+                    "来ません",
+                    "来ました",
+                ],
+            ),
+        ],
+
+        SuruVerb => &[(
+            "する",
+            &[
+                "しな",
+                "しろ",
+                "させ",
+                "され",
+                "でき",
+                "した",
+                "して",
+                "しない",
+                "します",
+                "しません",
+            ],
+        )],
+
+        // E.g. 愛する, whose negative is 愛さない.  The plain する
+        // forms are also used, so they're included too.
+        SuruVerbSC => &[(
+            "する",
+            &[
+                "さない",
+                "さ",
+                "し",
+                "せ",
+                "しな",
+                "しろ",
+                "させ",
+                "され",
+                "した",
+                "して",
+                "します",
+                "しません",
+            ],
+        )],
+
+        // E.g. 感ずる, which is mostly conjugated like the ichidan
+        // verb 感じる, which the じる form is included for.
+        ZuruVerb => &[(
+            "ずる",
+            &[
+                "じる",
+                "じない",
+                "じ",
+                "ぜ",
+                "ずれ",
+                "じろ",
+                "じて",
+                "じた",
+                "じます",
+            ],
+        )],
+
+        IAdjective => &[("い", &["", "く", "くて", "け", "かった", "かって"])],
+
+        // いい and words ending with it, whose other forms come from よい.
+        // The rest, e.g. 良い, are regular.
+        IrregularIAdjective => &[
+            (
+                "いい",
+                &["よく", "よくて", "よけ", "よくない", "よかった", "よかって"],
+            ),
+            ("い", &["", "く", "くて", "け", "かった", "かって"]),
+        ],
+
+        _ => &[],
+    }
+}
 
-            IAdjective => {
-                end_replace_push(word, "い", &["", "く", "け", "かった", "かって"]);
-            }
+/// The endings of the extra inflected forms added at the extensive
+/// conjugation level: polite (past and negative), desiderative,
+/// conditional, volitional, and the past and te-form of the negative.
+///
+/// Returns them along with the dictionary form ending they replace, with
+/// one item per ending.  As with `inflection_endings()`, only the first
+/// item whose ending a word has applies to it.
+fn extensive_endings(conj: jmdict::ConjugationClass) -> Vec<(&'static str, Vec<String>)> {
+    use jmdict::ConjugationClass::*;
+
+    // The a, i, e, and o rows of godan verbs.
+    let godan = |trail: &'static str, rows: [&str; 4]| {
+        let [a, i, e, o] = rows;
+        vec![(
+            trail,
+            vec![
+                format!("{}ます", i),
+                format!("{}ません", i),
+                format!("{}ました", i),
+                format!("{}たい", i),
+                format!("{}ば", e),
+                format!("{}う", o),
+                format!("{}なかった", a),
+                format!("{}なくて", a),
+            ],
+        )]
+    };
+    let fixed = |trail: &'static str, endings: &[&str]| {
+        vec![(trail, endings.iter().map(|e| e.to_string()).collect())]
+    };
+
+    match conj {
+        IchidanVerb | KureruVerb => fixed(
+            "る",
+            &[
+                "ます",
+                "ません",
+                "ました",
+                "たい",
+                "れば",
+                "よう",
+                "なかった",
+                "なくて",
+            ],
+        ),
+        ZuruVerb => fixed(
+            "ずる",
+            &[
+                "じます",
+                "じません",
+                "じました",
+                "じたい",
+                "ずれば",
+                "じよう",
+                "じなかった",
+            ],
+        ),
+        GodanVerbU => godan("う", ["わ", "い", "え", "お"]),
+        GodanVerbTsu => godan("つ", ["た", "ち", "て", "と"]),
+        GodanVerbRu => godan("る", ["ら", "り", "れ", "ろ"]),
+        GodanVerbKu | IkuVerb => godan("く", ["か", "き", "け", "こ"]),
+        GodanVerbGu => godan("ぐ", ["が", "ぎ", "げ", "ご"]),
+        GodanVerbNu => godan("ぬ", ["な", "に", "ね", "の"]),
+        GodanVerbBu => godan("ぶ", ["ば", "び", "べ", "ぼ"]),
+        GodanVerbMu => godan("む", ["ま", "み", "め", "も"]),
+        GodanVerbSu => godan("す", ["さ", "し", "せ", "そ"]),
+        SharuVerb => godan("る", ["ら", "い", "れ", "ろ"]),
+        AruVerb => fixed("る", &["ります", "りません", "りました", "れば", "ろう"]),
+        SuruVerb | SuruVerbSC => fixed(
+            "する",
+            &[
+                "しました",
+                "したい",
+                "すれば",
+                "しよう",
+                "しなかった",
+                "しなくて",
+            ],
+        ),
+        KuruVerb => {
+            let mut endings = fixed(
+                "くる",
+                &["きました", "きたい", "くれば", "こよう", "こなくて"],
+            );
+            endings.extend(fixed(
+                "来る",
+                &["来ました", "来たい", "来れば", "来よう", "来なくて"],
+            ));
+            endings
+        }
+        IAdjective => fixed("い", &["くない", "くなかった", "ければ", "さ", "そう"]),
+        IrregularIAdjective => {
+            let mut endings = fixed(
+                "いい",
+                &["よくない", "よくなかった", "よければ", "よさ", "よさそう"],
+            );
+            endings.extend(extensive_endings(IAdjective));
+            endings
+        }
+        _ => Vec::new(),
+    }
+}
 
-            _ => {
-                end_replace_push(word, "", &[]);
+/// Generates the text of a name entry.  A single record is shown like a
+/// word entry, and several records for the same writing as a compact list
+/// under a shared headword.
+/// Returns the accents of a name's reading as in word headers, e.g.
+/// " [0][2]", or nothing if it has none.
+fn name_pitch_accent_text(reading: &str, pitch_accents: &BTreeMap<String, Vec<u32>>) -> String {
+    let key = strip_non_kana(&hiragana_to_katakana(reading.trim()));
+    match pitch_accents.get(&key) {
+        Some(accents) if !accents.is_empty() => {
+            let mut text = " ".to_string();
+            for a in accents.iter() {
+                text.push_str(&format!("[{}]", a));
             }
-        };
+            text
+        }
+        _ => String::new(),
     }
+}
 
-    keys.sort_by_key(|a| (a.1, a.0.len(), a.0.clone()));
-    keys.dedup();
-    keys
+fn generate_name_entry_text(
+    entry_settings: EntrySettings,
+    entries: &[yomichan::TermEntry],
+    pitch_accents: &BTreeMap<String, Vec<u32>>,
+) -> String {
+    if entries.len() == 1 {
+        return generate_single_name_text(entry_settings, &entries[0], pitch_accents);
+    }
+
+    let word_type_start = format!(
+        " <span style=\"font-size: {}; font-style: italic; margin-left: 0; white-space: nowrap;\">",
+        entry_settings.text_scale.small()
+    );
+    const WORD_TYPE_END: &str = "</span>";
+
+    let mut text = format!(
+        "【{}】{}{}{}",
+        entries[0].writing,
+        word_type_start,
+        HEADER_TERMS["name"][entry_settings.lang_mode.idx()],
+        WORD_TYPE_END
+    );
+
+    let shown = entries
+        .len()
+        .min(entry_settings.max_name_senses.unwrap_or(usize::MAX));
+    text.push_str("<ul>");
+    for entry in entries[..shown].iter() {
+        text.push_str("<li>");
+        if !entry.reading.trim().is_empty() {
+            text.push_str(&if entry_settings.use_katakana_pronunciation {
+                hiragana_to_katakana(&entry.reading)
+            } else {
+                katakana_to_hiragana(&entry.reading)
+            });
+            text.push_str(&name_pitch_accent_text(&entry.reading, pitch_accents));
+        }
+        if !entry.tags.is_empty() {
+            text.push_str(&word_type_start);
+            text.push_str(&entry.tags.join(", "));
+            text.push_str(WORD_TYPE_END);
+        }
+        if !entry.definitions.is_empty() {
+            text.push_str(" &mdash; ");
+            text.push_str(&yomichan::definition_to_html(
+                &entry.definitions,
+                entry.definitions.depth(),
+                false,
+            ));
+        }
+        text.push_str("</li>");
+    }
+    text.push_str("</ul>");
+    if shown < entries.len() {
+        text.push_str(&format!(
+            "<p style=\"font-size: {};\">(+{} more)</p>",
+            entry_settings.text_scale.small(),
+            entries.len() - shown
+        ));
+    }
+
+    text
 }
 
-fn generate_name_entry_text(entry_settings: EntrySettings, entry: &yomichan::TermEntry) -> String {
+fn generate_single_name_text(
+    entry_settings: EntrySettings,
+    entry: &yomichan::TermEntry,
+    pitch_accents: &BTreeMap<String, Vec<u32>>,
+) -> String {
     let mut text = String::new();
 
     if !entry.reading.trim().is_empty() {
@@ -543,17 +1839,20 @@ fn generate_name_entry_text(entry_settings: EntrySettings, entry: &yomichan::Ter
         } else {
             katakana_to_hiragana(&entry.reading)
         });
+        text.push_str(&name_pitch_accent_text(&entry.reading, pitch_accents));
         text.push_str(" &nbsp;&nbsp;&mdash; ");
     }
 
-    text.push_str("【");
+    text.push('【');
     text.push_str(&entry.writing);
-    text.push_str("】");
+    text.push('】');
 
-    const WORD_TYPE_START: &'static str =
-        " <span style=\"font-size: 0.8em; font-style: italic; margin-left: 0; white-space: nowrap;\">";
-    const WORD_TYPE_END: &'static str = "</span>";
-    text.push_str(WORD_TYPE_START);
+    let word_type_start = format!(
+        " <span style=\"font-size: {}; font-style: italic; margin-left: 0; white-space: nowrap;\">",
+        entry_settings.text_scale.small()
+    );
+    const WORD_TYPE_END: &str = "</span>";
+    text.push_str(&word_type_start);
     text.push_str(HEADER_TERMS["name"][entry_settings.lang_mode.idx()]);
     if !entry.tags.is_empty() {
         text.push_str(": ");
@@ -577,41 +1876,580 @@ fn generate_name_entry_text(entry_settings: EntrySettings, entry: &yomichan::Ter
     text
 }
 
-fn generate_kanji_entry_text(entry: &yomichan::KanjiEntry) -> String {
+/// Generates the text of a kanji entry.  `entries` are the data of each
+/// source dictionary for the same kanji, each shown in its own section.
+fn generate_kanji_entry_text(
+    entry_settings: EntrySettings,
+    entries: &[yomichan::KanjiEntry],
+) -> String {
     let mut text = String::new();
+    let entry = &entries[0];
 
-    text.push_str("<p style=\"margin-left: 2.5em; margin-bottom: 1.0em; text-indent: -2.5em;\"><span style=\"font-size: 2.0em;\">");
+    text.push_str(&format!(
+        "<p style=\"margin-left: 2.5em; margin-bottom: 1.0em; text-indent: -2.5em;\"><span style=\"font-size: {};\">",
+        entry_settings.text_scale.kanji()
+    ));
     text.push_str(&entry.kanji);
-    if !entry.meanings.is_empty() {
-        text.push_str("</span>　");
-        for meaning in entry.meanings.iter() {
-            text.push_str(meaning);
-            text.push_str(", ");
-        }
-        text.pop();
-        text.pop();
+    text.push_str("</span>");
+    if entries.len() == 1 && !entry.meanings.is_empty() {
+        text.push('　');
+        text.push_str(&entry.meanings.join(", "));
     }
     text.push_str("</p>");
 
-    if !entry.onyomi.is_empty() {
-        text.push_str("<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">音:　");
-        for onyomi in entry.onyomi.iter() {
-            text.push_str(onyomi);
-            text.push_str("／");
+    for source_entry in entries.iter() {
+        if entries.len() > 1 {
+            let mut lines = Vec::new();
+            if !source_entry.dict_name.is_empty() {
+                lines.push(format!("{}:", source_entry.dict_name));
+            }
+            if !source_entry.meanings.is_empty() {
+                lines.push(source_entry.meanings.join(", "));
+            }
+            text.push_str(&format!("<p>{}</p>", lines.join("<br/>")));
         }
-        text.pop();
-        text.push_str("</p>");
+        text.push_str(&generate_kanji_reading_text(source_entry));
     }
 
-    if !entry.kunyomi.is_empty() {
-        text.push_str("<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">訓:　");
-        for kunyomi in entry.kunyomi.iter() {
-            text.push_str(kunyomi);
-            text.push_str("／");
+    // The rest comes from data files shared by all source dictionaries.
+    if !entry.components.is_empty() {
+        text.push_str(&format!(
+            "<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">部:　{}</p>",
+            entry.components.join("／")
+        ));
+    }
+
+    if let Some(ref decomposition) = entry.decomposition {
+        text.push_str(&format!(
+            "<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">構:　{}</p>",
+            decomposition
+        ));
+    }
+
+    if !entry.examples.is_empty() {
+        let mut examples = String::new();
+        for (i, (writing, reading, gloss)) in entry.examples.iter().enumerate() {
+            if i > 0 {
+                examples.push_str("<br/>");
+            }
+            examples.push_str(&format!("{}【{}】 {}", writing, reading, gloss));
         }
-        text.pop();
-        text.push_str("</p>");
+        if entry_settings.collapse_sections {
+            text.push_str(&collapsible(
+                entry_settings,
+                "例",
+                &format!("<p>{}</p>", examples),
+            ));
+        } else {
+            text.push_str(&format!(
+                "<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">例:　{}</p>",
+                examples
+            ));
+        }
+    }
+
+    if let Some(ref stroke_order) = entry.stroke_order {
+        text.push_str(&format!(
+            "<p><img src=\"{}\" alt=\"stroke order\"/></p>",
+            stroke_order
+        ));
+    }
+
+    text
+}
+
+/// Generates the readings and stroke count of a kanji entry.
+fn generate_kanji_reading_text(entry: &yomichan::KanjiEntry) -> String {
+    let mut text = String::new();
+
+    if !entry.onyomi.is_empty() {
+        text.push_str(&format!(
+            "<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">音:　{}</p>",
+            entry.onyomi.join("／")
+        ));
+    }
+
+    if !entry.kunyomi.is_empty() {
+        text.push_str(&format!(
+            "<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">訓:　{}</p>",
+            entry.kunyomi.join("／")
+        ));
+    }
+
+    if let Some(strokes) = entry.strokes {
+        text.push_str(&format!(
+            "<p style=\"margin-left: 2.5em; text-indent: -2.5em;\">画:　{}</p>",
+            strokes
+        ));
     }
 
     text
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmdict::ConjugationClass::{self, *};
+
+    /// A word with the given writing (empty for kana words) and reading.
+    fn word(writing: &str, reading: &str, conj: ConjugationClass) -> jmdict::WordEntry {
+        let mut word = jmdict::WordEntry::new();
+        if writing.is_empty() {
+            word.usually_kana = true;
+        } else {
+            word.push_writing(writing.into());
+        }
+        word.push_reading(reading.into());
+        word.conj = conj;
+        word
+    }
+
+    fn keys(word: &jmdict::WordEntry, conjugation_level: ConjugationLevel) -> Vec<String> {
+        let settings = EntrySettings {
+            katakana_keys: false,
+            conjugation_level,
+            ..EntrySettings::default()
+        };
+        generate_lookup_keys(word, settings)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Representative words of each conjugation class, with forms that must
+    /// and must not be among their lookup keys at the standard level.
+    const STANDARD_KEYS: &[(ConjugationClass, &str, &str, &[&str], &[&str])] = &[
+        (
+            IchidanVerb,
+            "食べる",
+            "たべる",
+            &[
+                "食べる",
+                "食べ",
+                "食べない",
+                "食べられ",
+                "食べさせ",
+                "食べろ",
+                "食べて",
+                "食べた",
+                "たべない",
+            ],
+            &["食べます"],
+        ),
+        (
+            KureruVerb,
+            "",
+            "くれる",
+            &["くれ", "くれない", "くれて", "くれた"],
+            &["くれろ"],
+        ),
+        (
+            GodanVerbU,
+            "買う",
+            "かう",
+            &[
+                "買わない",
+                "買わ",
+                "買い",
+                "買え",
+                "買お",
+                "買って",
+                "買った",
+            ],
+            &["買いて"],
+        ),
+        (
+            GodanVerbTsu,
+            "待つ",
+            "まつ",
+            &["待たない", "待ち", "待って", "待った"],
+            &[],
+        ),
+        (
+            GodanVerbRu,
+            "取る",
+            "とる",
+            &["取らない", "取り", "取れ", "取ろ", "取って", "取った"],
+            &["取ちない", "取ない"],
+        ),
+        (
+            GodanVerbKu,
+            "書く",
+            "かく",
+            &["書かない", "書き", "書いて", "書いた"],
+            &["書って"],
+        ),
+        (
+            GodanVerbGu,
+            "泳ぐ",
+            "およぐ",
+            &["泳がない", "泳ぎ", "泳いで", "泳いだ"],
+            &[],
+        ),
+        (
+            GodanVerbNu,
+            "死ぬ",
+            "しぬ",
+            &["死なない", "死に", "死んで", "死んだ"],
+            &[],
+        ),
+        (
+            GodanVerbBu,
+            "遊ぶ",
+            "あそぶ",
+            &["遊ばない", "遊び", "遊んで", "遊んだ"],
+            &[],
+        ),
+        (
+            GodanVerbMu,
+            "読む",
+            "よむ",
+            &["読まない", "読み", "読んで", "読んだ"],
+            &[],
+        ),
+        (
+            GodanVerbSu,
+            "話す",
+            "はなす",
+            &["話さない", "話し", "話して", "話した"],
+            &[],
+        ),
+        (
+            IkuVerb,
+            "行く",
+            "いく",
+            &["行かない", "行き", "行って", "行った"],
+            &["行いて"],
+        ),
+        (
+            KuruVerb,
+            "来る",
+            "くる",
+            &[
+                "来ない",
+                "来て",
+                "来た",
+                "来ます",
+                "こない",
+                "きて",
+                "きた",
+                "きます",
+            ],
+            &["来らない"],
+        ),
+        (
+            SuruVerb,
+            "勉強する",
+            "べんきょうする",
+            &[
+                "勉強しない",
+                "勉強して",
+                "勉強した",
+                "勉強します",
+                "勉強させ",
+                "勉強でき",
+            ],
+            &["勉強さない"],
+        ),
+        (
+            SuruVerbSC,
+            "愛する",
+            "あいする",
+            &["愛さない", "愛して", "愛した", "愛します"],
+            &[],
+        ),
+        (
+            ZuruVerb,
+            "感ずる",
+            "かんずる",
+            &["感じる", "感じない", "感じて", "感じた"],
+            &[],
+        ),
+        (
+            AruVerb,
+            "",
+            "ある",
+            &["ない", "あって", "あった", "あり", "あれ"],
+            &["あらない"],
+        ),
+        (
+            AruVerb,
+            "有る",
+            "ある",
+            &["有って", "有った", "有り", "ない"],
+            &[],
+        ),
+        (
+            SharuVerb,
+            "",
+            "いらっしゃる",
+            &[
+                "いらっしゃらない",
+                "いらっしゃい",
+                "いらっしゃって",
+                "いらっしゃった",
+            ],
+            &[],
+        ),
+        (
+            IAdjective,
+            "高い",
+            "たかい",
+            &["高", "高く", "高くて", "高け", "高かった"],
+            &[],
+        ),
+        (
+            IrregularIAdjective,
+            "",
+            "いい",
+            &["よく", "よくて", "よくない", "よかった"],
+            &["いく"],
+        ),
+        (
+            IrregularIAdjective,
+            "良い",
+            "よい",
+            &["良く", "良くて", "良かった"],
+            &[],
+        ),
+        (Other, "本", "ほん", &["本", "ほん"], &["本い"]),
+    ];
+
+    #[test]
+    fn standard_conjugation_keys() {
+        for &(conj, writing, reading, present, absent) in STANDARD_KEYS.iter() {
+            let keys = keys(&word(writing, reading, conj), ConjugationLevel::Standard);
+            for form in present.iter() {
+                assert!(
+                    keys.iter().any(|k| k == form),
+                    "{:?}: no {} in {:?}",
+                    conj,
+                    form,
+                    keys
+                );
+            }
+            for form in absent.iter() {
+                assert!(
+                    !keys.iter().any(|k| k == form),
+                    "{:?}: {} in {:?}",
+                    conj,
+                    form,
+                    keys
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn conjugation_levels() {
+        let kaku = word("書く", "かく", GodanVerbKu);
+
+        let minimal = keys(&kaku, ConjugationLevel::Minimal);
+        assert!(minimal.iter().any(|k| k == "書か"));
+        assert!(!minimal.iter().any(|k| k == "書かない"));
+
+        let extensive = keys(&kaku, ConjugationLevel::Extensive);
+        for form in [
+            "書きます",
+            "書きました",
+            "書きたい",
+            "書けば",
+            "書こう",
+            "書かなかった",
+        ]
+        .iter()
+        {
+            assert!(extensive.iter().any(|k| k == form), "no {}", form);
+        }
+    }
+
+    #[test]
+    fn inflected_keys_come_after_the_word() {
+        let keys = generate_lookup_keys(&word("食べる", "たべる", IchidanVerb), Default::default());
+        let priority = |key: &str| keys.iter().find(|k| k.0 == key).unwrap().1;
+        assert!(priority("食べる") < priority("食べた"));
+        assert!(priority("たべる") < priority("たべない"));
+    }
+
+    #[test]
+    fn conjugation_line_forms() {
+        let table: &[(ConjugationClass, &str, Option<[&str; 4]>)] = &[
+            (
+                IchidanVerb,
+                "食べる",
+                Some(["食べます", "食べて", "食べない", "食べた"]),
+            ),
+            (
+                KureruVerb,
+                "くれる",
+                Some(["くれます", "くれて", "くれない", "くれた"]),
+            ),
+            (
+                GodanVerbU,
+                "買う",
+                Some(["買います", "買って", "買わない", "買った"]),
+            ),
+            (
+                GodanVerbTsu,
+                "待つ",
+                Some(["待ちます", "待って", "待たない", "待った"]),
+            ),
+            (
+                GodanVerbRu,
+                "取る",
+                Some(["取ります", "取って", "取らない", "取った"]),
+            ),
+            (
+                GodanVerbKu,
+                "書く",
+                Some(["書きます", "書いて", "書かない", "書いた"]),
+            ),
+            (
+                GodanVerbGu,
+                "泳ぐ",
+                Some(["泳ぎます", "泳いで", "泳がない", "泳いだ"]),
+            ),
+            (
+                GodanVerbNu,
+                "死ぬ",
+                Some(["死にます", "死んで", "死なない", "死んだ"]),
+            ),
+            (
+                GodanVerbBu,
+                "遊ぶ",
+                Some(["遊びます", "遊んで", "遊ばない", "遊んだ"]),
+            ),
+            (
+                GodanVerbMu,
+                "読む",
+                Some(["読みます", "読んで", "読まない", "読んだ"]),
+            ),
+            (
+                GodanVerbSu,
+                "話す",
+                Some(["話します", "話して", "話さない", "話した"]),
+            ),
+            (
+                IkuVerb,
+                "行く",
+                Some(["行きます", "行って", "行かない", "行った"]),
+            ),
+            (KuruVerb, "くる", Some(["きます", "きて", "こない", "きた"])),
+            (KuruVerb, "来る", Some(["来ます", "来て", "来ない", "来た"])),
+            (
+                SuruVerb,
+                "勉強する",
+                Some(["勉強します", "勉強して", "勉強しない", "勉強した"]),
+            ),
+            (
+                SuruVerbSC,
+                "愛する",
+                Some(["愛します", "愛して", "愛さない", "愛した"]),
+            ),
+            (
+                ZuruVerb,
+                "感ずる",
+                Some(["感じます", "感じて", "感じない", "感じた"]),
+            ),
+            (
+                AruVerb,
+                "ある",
+                Some(["あります", "あって", "ない", "あった"]),
+            ),
+            (
+                SharuVerb,
+                "いらっしゃる",
+                Some([
+                    "いらっしゃいます",
+                    "いらっしゃって",
+                    "いらっしゃらない",
+                    "いらっしゃった",
+                ]),
+            ),
+            (
+                IAdjective,
+                "高い",
+                Some(["高いです", "高くて", "高くない", "高かった"]),
+            ),
+            (
+                IrregularIAdjective,
+                "いい",
+                Some(["いいです", "よくて", "よくない", "よかった"]),
+            ),
+            (
+                IrregularIAdjective,
+                "良い",
+                Some(["良いです", "良くて", "良くない", "良かった"]),
+            ),
+            (Copula, "だ", None),
+            (Other, "本", None),
+        ];
+        for &(conj, word, expected) in table.iter() {
+            let expected = expected.map(|forms| forms.map(String::from));
+            assert_eq!(conjugate(word, conj), expected, "{:?} {}", conj, word);
+        }
+    }
+
+    #[test]
+    fn loan_source_notes_go_at_the_end_of_their_sense() {
+        use yomichan::Definition::{Def, List};
+
+        let mut word = word("", "パン", Other);
+        word.definitions = vec!["bread".into(), "pan (for cooking)".into()];
+        word.loan_sources.push(jmdict::LoanSource {
+            lang: "por".into(),
+            word: "pão".into(),
+            partial: false,
+            wasei: false,
+            sense: 0,
+        });
+        let settings = EntrySettings::default();
+        let note = |text: &str| {
+            format!(
+                " <span style=\"font-size: {}; font-style: italic;\">({})</span>",
+                settings.text_scale.small(),
+                text
+            )
+        };
+        let sense = |definitions| yomichan::TermEntry {
+            dict_name: "jmdict".into(),
+            writing: "".into(),
+            reading: "パン".into(),
+            definitions,
+            infl: yomichan::InflectionType::None,
+            tags: Vec::new(),
+            commonness: 0,
+        };
+
+        let mut senses = vec![sense(List((
+            "".into(),
+            vec![Def("bread".into()), Def("pan (for cooking)".into())],
+        )))];
+        assert_eq!(place_loan_source_notes(settings, &word, &mut senses), "");
+        assert_eq!(
+            senses[0].definitions,
+            List((
+                "".into(),
+                vec![
+                    Def(format!("bread{}", note("from Portuguese: pão"))),
+                    Def("pan (for cooking)".into()),
+                ],
+            ))
+        );
+
+        // Senses that don't line up with JMDict's get a numbered note
+        // after them instead.
+        let mut senses = vec![sense(List(("".into(), vec![Def("bread".into())])))];
+        assert_eq!(
+            place_loan_source_notes(settings, &word, &mut senses),
+            format!(
+                "<p style=\"font-size: {}; font-style: italic;\">1. from Portuguese: pão</p>",
+                settings.text_scale.small()
+            )
+        );
+        assert_eq!(
+            senses[0].definitions,
+            List(("".into(), vec![Def("bread".into())]))
+        );
+    }
+}
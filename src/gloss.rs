@@ -0,0 +1,116 @@
+//! Normalization of the definition text of the source dictionaries, which
+//! format their glosses differently (trailing semicolons, doubled spaces,
+//! final periods or not), so that merged entries look uniform.
+
+use crate::merged::{MergedEntry, MergedKind};
+use crate::yomichan::Definition;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GlossRule {
+    Spaces,     // Collapse runs of whitespace, and trim it from the ends.
+    Separators, // Remove trailing ; , 、 and the spaces before ; and ,.
+    Periods,    // Remove the final . or 。 of each gloss.
+}
+
+impl GlossRule {
+    /// Parses a comma-separated list of rule names, or "all".
+    pub fn parse_list(text: &str) -> Option<Vec<GlossRule>> {
+        if text.trim() == "all" {
+            return Some(vec![
+                GlossRule::Spaces,
+                GlossRule::Separators,
+                GlossRule::Periods,
+            ]);
+        }
+        text.split(',')
+            .map(|name| match name.trim() {
+                "spaces" => Some(GlossRule::Spaces),
+                "separators" => Some(GlossRule::Separators),
+                "periods" => Some(GlossRule::Periods),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Applies `rules`, in order, to the definitions of all term entries.
+pub fn normalize_entries(entries: &mut [MergedEntry], rules: &[GlossRule]) {
+    for entry in entries.iter_mut() {
+        if let MergedKind::Term { ref mut senses, .. } = entry.kind {
+            for sense in senses.iter_mut() {
+                normalize_definition(&mut sense.definitions, rules);
+            }
+        }
+    }
+}
+
+fn normalize_definition(definition: &mut Definition, rules: &[GlossRule]) {
+    match definition {
+        Definition::List((_, ref mut list)) => {
+            for item in list.iter_mut() {
+                normalize_definition(item, rules);
+            }
+        }
+        Definition::Def(ref mut text) => {
+            for &rule in rules.iter() {
+                *text = apply_rule(text, rule);
+            }
+        }
+    }
+}
+
+fn apply_rule(text: &str, rule: GlossRule) -> String {
+    match rule {
+        GlossRule::Spaces => {
+            let mut result = String::with_capacity(text.len());
+            for word in text.split_ascii_whitespace() {
+                if !result.is_empty() {
+                    result.push(' ');
+                }
+                result.push_str(word);
+            }
+            result
+        }
+        GlossRule::Separators => {
+            let (body, closing_tags) = split_closing_tags(text);
+            let body = body
+                .replace(" ;", ";")
+                .replace(" ,", ",")
+                .trim_end()
+                .trim_end_matches([';', '；', ',', '、'])
+                .trim_end()
+                .to_string();
+            body + closing_tags
+        }
+        GlossRule::Periods => {
+            let (body, closing_tags) = split_closing_tags(text);
+            let body = body.trim_end();
+            // Leave ellipses alone.
+            if body.ends_with("..") || body.ends_with("。。") {
+                return text.into();
+            }
+            let body = body
+                .strip_suffix('.')
+                .or_else(|| body.strip_suffix('。'))
+                .or_else(|| body.strip_suffix('．'))
+                .unwrap_or(body);
+            format!("{}{}", body, closing_tags)
+        }
+    }
+}
+
+/// Splits html into its text and the closing tags at its end, e.g.
+/// "<div>a gloss.</div>" into "<div>a gloss." and "</div>", so that the
+/// end of the text can be cleaned up.
+fn split_closing_tags(html: &str) -> (&str, &str) {
+    let mut end = html.trim_end().len();
+    while html[..end].ends_with('>') {
+        match html[..end].rfind('<') {
+            Some(start) if html[start..].starts_with("</") => {
+                end = html[..start].trim_end().len();
+            }
+            _ => break,
+        }
+    }
+    (&html[..end], &html[end..])
+}
@@ -0,0 +1,23 @@
+//! String interning, for the small strings (tags, dictionary names, etc.)
+//! that are repeated across huge numbers of entries.
+//!
+//! Interned strings are `Arc<str>`s, so copies of them are just a pointer
+//! and a reference count, and they can be used anywhere a `&str` can.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    static ref STRINGS: Mutex<HashSet<Arc<str>>> = Mutex::new(HashSet::new());
+}
+
+/// Returns the shared copy of `text`, creating it if it doesn't exist yet.
+pub fn intern(text: &str) -> Arc<str> {
+    let mut strings = STRINGS.lock().unwrap();
+    if let Some(s) = strings.get(text) {
+        return s.clone();
+    }
+    let s: Arc<str> = text.into();
+    strings.insert(s.clone());
+    s
+}
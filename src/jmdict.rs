@@ -3,11 +3,23 @@
 //! The `Parser` type takes a buffered reader, and acts as an iterator
 //! that yields a `WordEntry` for each entry in the dictionary, parsing
 //! the input as it goes.
+//!
+//! JMDict JSON files from the jmdict-simplified project are also
+//! supported, via `JsonSource`.
 
 use std::collections::HashSet;
-use std::io::BufRead;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
 
+use flate2::read::GzDecoder;
 use quick_xml::events::Event;
+use serde_json::Value;
+
+use crate::intern::intern;
+use crate::source::{DictionarySource, SourceItem};
+use crate::warnings;
+use crate::yomichan::escape_html;
 
 /// A parser for the JMDict xml format.
 pub struct Parser<R: BufRead> {
@@ -16,6 +28,7 @@ pub struct Parser<R: BufRead> {
     cur_entry: WordEntry,
     kanji_priorities: Vec<String>,
     kana_priorities: Vec<String>,
+    cur_gloss_type: Option<GlossType>,
     cur_xml_elem: Elem,
 }
 
@@ -27,6 +40,7 @@ impl<R: BufRead> Parser<R> {
             cur_entry: WordEntry::new(),
             kanji_priorities: Vec::new(),
             kana_priorities: Vec::new(),
+            cur_gloss_type: None,
             cur_xml_elem: Elem::None,
         }
     }
@@ -41,6 +55,21 @@ pub struct WordEntry {
     pub pos: PartOfSpeech,
     pub usually_kana: bool, // When true, indicates that the word is usually written in kana alone.
 
+    // Information markers for each writing, in the same order as
+    // `writings`.  For example "iK" for irregular kanji usage.  See the
+    // JMDict `ke_inf` element.
+    pub writing_info: Vec<Vec<Arc<str>>>,
+
+    // Same as `writing_info`, but for readings.  See `re_inf`.
+    pub reading_info: Vec<Vec<Arc<str>>>,
+
+    // The writings that each reading applies to, in the same order as
+    // `readings`.  Empty means all of them.  See `re_restr`.
+    pub reading_restrictions: Vec<Vec<String>>,
+
+    // The source languages of loanwords.  See the JMDict `lsource` element.
+    pub loan_sources: Vec<LoanSource>,
+
     // A very rough priority ranking indicating the commonness of the word.
     // A lower numerical value indicates a more common word.
     pub priority: u32,
@@ -51,7 +80,69 @@ pub struct WordEntry {
     // This can give more detailed information about the word than the
     // filtered and processed struct fields above, when needed.
     // See the JMDict XML file for details about possible tags.
-    pub tags: HashSet<String>,
+    pub tags: HashSet<Arc<str>>,
+}
+
+/// The origin of a loanword, e.g. Portuguese "pão" for パン.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoanSource {
+    pub lang: String,  // ISO 639-2 language code, e.g. "por".
+    pub word: String,  // The word in the source language.  May be empty.
+    pub partial: bool, // Whether only part of the word is from this source.
+    pub wasei: bool,   // Whether the word was coined in Japan, e.g. "wasei-eigo".
+    pub sense: usize,  // Index of the sense it's given for in `definitions`.
+}
+
+impl LoanSource {
+    /// The English name of the source language, or the language code if
+    /// it's not a common one.
+    pub fn language_name(&self) -> &str {
+        match self.lang.as_str() {
+            "ain" => "Ainu",
+            "ara" => "Arabic",
+            "chi" => "Chinese",
+            "dan" => "Danish",
+            "dut" => "Dutch",
+            "eng" => "English",
+            "fre" => "French",
+            "ger" => "German",
+            "grc" => "Ancient Greek",
+            "gre" => "Greek",
+            "heb" => "Hebrew",
+            "hin" => "Hindi",
+            "ita" => "Italian",
+            "kor" => "Korean",
+            "lat" => "Latin",
+            "may" => "Malay",
+            "mon" => "Mongolian",
+            "nor" => "Norwegian",
+            "per" => "Persian",
+            "pol" => "Polish",
+            "por" => "Portuguese",
+            "rus" => "Russian",
+            "san" => "Sanskrit",
+            "spa" => "Spanish",
+            "swe" => "Swedish",
+            "tha" => "Thai",
+            "tib" => "Tibetan",
+            "tur" => "Turkish",
+            "vie" => "Vietnamese",
+            lang => lang,
+        }
+    }
+}
+
+/// Writing and reading markers for irregular or outdated forms.
+pub const IRREGULAR_FORM_MARKERS: &[&str] = &["iK", "ik", "io", "oK", "ok", "rK", "rk"];
+
+/// Writing and reading markers for forms that are only meant for searching,
+/// such as common misspellings, and shouldn't be displayed.
+pub const SEARCH_ONLY_FORM_MARKERS: &[&str] = &["sK", "sk"];
+
+impl Default for WordEntry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WordEntry {
@@ -63,10 +154,62 @@ impl WordEntry {
             conj: ConjugationClass::Other,
             pos: PartOfSpeech::Unknown,
             usually_kana: false,
+            writing_info: Vec::new(),
+            reading_info: Vec::new(),
+            reading_restrictions: Vec::new(),
+            loan_sources: Vec::new(),
             priority: 100000,
             tags: HashSet::new(),
         }
     }
+
+    /// Adds a writing, with no markers.
+    pub fn push_writing(&mut self, writing: String) {
+        self.writings.push(writing);
+        self.writing_info.push(Vec::new());
+    }
+
+    /// Adds a reading, with no markers or restrictions.
+    pub fn push_reading(&mut self, reading: String) {
+        self.readings.push(reading);
+        self.reading_info.push(Vec::new());
+        self.reading_restrictions.push(Vec::new());
+    }
+
+    /// Whether writing `i` is marked as irregular or outdated.
+    pub fn is_irregular_writing(&self, i: usize) -> bool {
+        has_marker(self.writing_info.get(i), IRREGULAR_FORM_MARKERS)
+    }
+
+    /// Whether reading `i` is marked as irregular or outdated.
+    pub fn is_irregular_reading(&self, i: usize) -> bool {
+        has_marker(self.reading_info.get(i), IRREGULAR_FORM_MARKERS)
+    }
+
+    /// Whether writing `i` is only for searching, and shouldn't be shown.
+    pub fn is_search_only_writing(&self, i: usize) -> bool {
+        has_marker(self.writing_info.get(i), SEARCH_ONLY_FORM_MARKERS)
+    }
+
+    /// Whether reading `i` is only for searching, and shouldn't be shown.
+    pub fn is_search_only_reading(&self, i: usize) -> bool {
+        has_marker(self.reading_info.get(i), SEARCH_ONLY_FORM_MARKERS)
+    }
+
+    /// Whether reading `i` is a reading of `writing`.
+    pub fn reading_applies_to(&self, i: usize, writing: &str) -> bool {
+        match self.reading_restrictions.get(i) {
+            Some(restrictions) if !restrictions.is_empty() => {
+                restrictions.iter().any(|w| w == writing)
+            }
+            _ => true,
+        }
+    }
+}
+
+fn has_marker(info: Option<&Vec<Arc<str>>>, markers: &[&str]) -> bool {
+    info.map(|info| info.iter().any(|marker| markers.contains(&&**marker)))
+        .unwrap_or(false)
 }
 
 /// Indicates the conjugation rules that a word follows.
@@ -98,6 +241,7 @@ pub enum ConjugationClass {
     // Irregular verbs.
     SuruVerb,      // する and verbs that end with it and conjugate like it.
     SuruVerbSC,    // Verbs ending in する that don't quite conjugate like it.
+    ZuruVerb,      // Verbs ending in ずる, e.g. 感ずる.
     KuruVerb,      // 来る and verbs that end with it and conjugate like it.
     IkuVerb,       // 行く and verbs that end with it and conjugate like it.
     KureruVerb,    // 呉れる / くれる and verbs that end with it and conjugate like it.
@@ -129,6 +273,244 @@ pub enum PartOfSpeech {
     Adverb,
     Adjective, // i-adjectives only.  Na-adjectives are actually nouns.
     Expression,
+    Pronoun,
+    Counter,
+    Interjection,
+    Auxiliary, // Auxiliary verbs and adjectives, e.g. たい.
+    Prefix,
+    Suffix,
+    Numeric,
+}
+
+/// A JMDict XML file as a dictionary source.
+pub struct Source<R: BufRead> {
+    name: String,
+    reader: R,
+}
+
+impl<R: BufRead> Source<R> {
+    pub fn new(name: &str, reader: R) -> Source<R> {
+        Source {
+            name: name.into(),
+            reader,
+        }
+    }
+}
+
+impl<R: BufRead + 'static> DictionarySource for Source<R> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        Ok(Box::new(
+            Parser::from_reader(self.reader).map(SourceItem::Word),
+        ))
+    }
+}
+
+/// A JMDict JSON file in the jmdict-simplified format as a dictionary
+/// source.
+pub struct JsonSource<R: Read> {
+    name: String,
+    reader: R,
+}
+
+impl<R: Read> JsonSource<R> {
+    pub fn new(name: &str, reader: R) -> JsonSource<R> {
+        JsonSource {
+            name: name.into(),
+            reader,
+        }
+    }
+}
+
+impl<R: Read> DictionarySource for JsonSource<R> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        let name = self.name;
+        let json: Value = serde_json::from_reader(self.reader)?;
+        let words = json
+            .get("words")
+            .and_then(|w| w.as_array())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "not a jmdict-simplified JSON file",
+                )
+            })?;
+
+        let items: Vec<SourceItem> = words
+            .iter()
+            .filter_map(|word| {
+                let entry = word_from_simplified_json(word);
+                if entry.is_none() {
+                    let id = word.get("id").and_then(|id| id.as_str()).unwrap_or("?");
+                    warnings::warn(
+                        warnings::Category::JmdictWord,
+                        &name,
+                        format!("skipped malformed word {}", id),
+                    );
+                }
+                entry
+            })
+            .map(SourceItem::Word)
+            .collect();
+
+        Ok(Box::new(items.into_iter()))
+    }
+}
+
+/// Opens a JMDict file as a dictionary source, detecting whether it's XML
+/// or jmdict-simplified JSON, and whether it's gzipped, from its content.
+pub fn open_source(path: &Path) -> std::io::Result<Box<dyn DictionarySource>> {
+    let mut reader: Box<dyn BufRead> = Box::new(BufReader::new(std::fs::File::open(path)?));
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        reader = Box::new(BufReader::new(GzDecoder::new(reader)));
+    }
+
+    let first_char = reader
+        .fill_buf()?
+        .iter()
+        .copied()
+        .find(|b| !b.is_ascii_whitespace());
+    if first_char == Some(b'{') {
+        Ok(Box::new(JsonSource::new("jmdict", reader)))
+    } else {
+        Ok(Box::new(Source::new("jmdict", reader)))
+    }
+}
+
+/// Converts a word from a jmdict-simplified JSON file into a `WordEntry`.
+///
+/// Returns `None` if the word is malformed.
+fn word_from_simplified_json(json: &Value) -> Option<WordEntry> {
+    let mut entry = WordEntry::new();
+
+    let strings = |json: Option<&Value>| -> Vec<String> {
+        json.and_then(|j| j.as_array())
+            .map(|j| j.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|s| s.as_str())
+            .map(|s| s.into())
+            .collect()
+    };
+
+    for kanji in json.get("kanji")?.as_array()?.iter() {
+        entry.push_writing(kanji.get("text")?.as_str()?.into());
+        *entry.writing_info.last_mut().unwrap() = strings(kanji.get("tags"))
+            .iter()
+            .map(|t| intern(t))
+            .collect();
+    }
+    for kana in json.get("kana")?.as_array()?.iter() {
+        entry.push_reading(kana.get("text")?.as_str()?.into());
+        *entry.reading_info.last_mut().unwrap() = strings(kana.get("tags"))
+            .iter()
+            .map(|t| intern(t))
+            .collect();
+
+        // "*" means all writings.
+        let applies_to = strings(kana.get("appliesToKanji"));
+        if !applies_to.iter().any(|w| w == "*") {
+            *entry.reading_restrictions.last_mut().unwrap() = applies_to;
+        }
+    }
+    if entry.readings.is_empty() {
+        return None;
+    }
+
+    for sense in json.get("sense")?.as_array()?.iter() {
+        for &(elem, field) in &[
+            ("pos", "partOfSpeech"),
+            ("misc", "misc"),
+            ("dial", "dialect"),
+            ("field", "field"),
+        ] {
+            let tags = sense
+                .get(field)
+                .and_then(|t| t.as_array())
+                .map(|t| t.as_slice())
+                .unwrap_or(&[]);
+            for tag in tags.iter().filter_map(|t| t.as_str()) {
+                entry.tags.insert(intern(&format!("{}:{}", elem, tag)));
+                if elem == "pos" {
+                    apply_part_of_speech(&mut entry, tag);
+                } else if elem == "misc" && tag == "uk" {
+                    entry.usually_kana = true;
+                }
+            }
+        }
+
+        for source in sense
+            .get("languageSource")
+            .and_then(|s| s.as_array())
+            .map(|s| s.as_slice())
+            .unwrap_or(&[])
+            .iter()
+        {
+            entry.loan_sources.push(LoanSource {
+                lang: source
+                    .get("lang")
+                    .and_then(|l| l.as_str())
+                    .unwrap_or("eng")
+                    .into(),
+                word: escape_html(source.get("text").and_then(|t| t.as_str()).unwrap_or("")),
+                partial: !source.get("full").and_then(|f| f.as_bool()).unwrap_or(true),
+                wasei: source
+                    .get("wasei")
+                    .and_then(|w| w.as_bool())
+                    .unwrap_or(false),
+                // The sense's glosses are pushed below.
+                sense: entry.definitions.len(),
+            });
+        }
+
+        // We're ignoring definitions that aren't written in English.  The
+        // XML parser keeps the text escaped, so we escape it here to match.
+        let glosses: Vec<String> = sense
+            .get("gloss")?
+            .as_array()?
+            .iter()
+            .filter(|g| g.get("lang").and_then(|l| l.as_str()).unwrap_or("eng") == "eng")
+            .filter_map(|g| {
+                let gloss_type = g
+                    .get("type")
+                    .and_then(|t| t.as_str())
+                    .and_then(GlossType::from_name);
+                Some(format_gloss(
+                    &escape_html(g.get("text")?.as_str()?),
+                    gloss_type,
+                ))
+            })
+            .collect();
+        if !glosses.is_empty() {
+            entry.definitions.push(glosses.join("; "));
+        }
+    }
+
+    // See the corresponding code in the XML parser.
+    if entry.writings.is_empty() {
+        entry.usually_kana = true;
+    }
+
+    // jmdict-simplified only says whether a form is common (i.e. has one of
+    // the "news1", "ichi1", "spec1", "spec2", or "gai1" priority tags), so
+    // we use the same priority as those tags.
+    let forms = json.get(if entry.usually_kana { "kana" } else { "kanji" })?;
+    let is_common = forms
+        .as_array()?
+        .iter()
+        .any(|f| f.get("common").and_then(|c| c.as_bool()).unwrap_or(false));
+    if is_common {
+        entry.priority = 6000;
+    }
+
+    Some(entry)
 }
 
 //================================================================
@@ -143,19 +525,43 @@ impl<R: BufRead> Iterator for Parser<R> {
             if tag.starts_with("&") && tag.ends_with(";") {
                 entry
                     .tags
-                    .insert(format!("{}:{}", elem, (&tag[1..(tag.len() - 1)])));
+                    .insert(intern(&format!("{}:{}", elem, (&tag[1..(tag.len() - 1)]))));
             }
         }
 
         loop {
             match self.xml_parser.read_event_into(&mut self.buf) {
+                // Loanword sources without the source word are written as
+                // empty elements.
+                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"lsource" => {
+                    let sense = self.cur_entry.definitions.len().saturating_sub(1);
+                    self.cur_entry
+                        .loan_sources
+                        .push(loan_source_from_xml(e, sense));
+                }
                 Ok(Event::Start(ref e)) => match e.name().as_ref() {
                     b"keb" => {
                         self.cur_xml_elem = Elem::Keb;
                     }
+                    b"lsource" => {
+                        let sense = self.cur_entry.definitions.len().saturating_sub(1);
+                        self.cur_entry
+                            .loan_sources
+                            .push(loan_source_from_xml(e, sense));
+                        self.cur_xml_elem = Elem::LoanSource;
+                    }
                     b"reb" => {
                         self.cur_xml_elem = Elem::Reb;
                     }
+                    b"ke_inf" => {
+                        self.cur_xml_elem = Elem::WritingInfo;
+                    }
+                    b"re_inf" => {
+                        self.cur_xml_elem = Elem::ReadingInfo;
+                    }
+                    b"re_restr" => {
+                        self.cur_xml_elem = Elem::ReadingRestriction;
+                    }
                     b"pos" => {
                         self.cur_xml_elem = Elem::Pos;
                     }
@@ -179,22 +585,41 @@ impl<R: BufRead> Iterator for Parser<R> {
 
                         // Start new definition within the entry.
                         if self.cur_entry.definitions.is_empty()
-                            || self.cur_entry.definitions.last().unwrap().trim().len() > 0
+                            || !self.cur_entry.definitions.last().unwrap().trim().is_empty()
                         {
                             self.cur_entry.definitions.push("".into());
                         }
                     }
                     b"gloss" => {
-                        // If there are no attributes, that means it's
+                        // If there's no language attribute, that means it's
                         // English.  We're ignoring definitions that aren't
                         // written in English.
-                        if e.attributes().count() == 0 {
+                        let mut is_english = true;
+                        self.cur_gloss_type = None;
+                        for attr in e.attributes().filter_map(|a| a.ok()) {
+                            match attr.key.as_ref() {
+                                b"xml:lang" => is_english = &*attr.value == b"eng",
+                                b"g_type" => {
+                                    self.cur_gloss_type =
+                                        GlossType::from_name(&String::from_utf8_lossy(&attr.value))
+                                }
+                                _ => {}
+                            }
+                        }
+                        if is_english {
                             self.cur_xml_elem = Elem::Gloss;
                         }
                     }
                     b"name_type" => {
                         self.cur_entry.pos = PartOfSpeech::Noun;
                     }
+                    name if !IGNORED_ELEMENTS.contains(&name) => {
+                        warnings::warn_once(
+                            warnings::Category::JmdictElement,
+                            "jmdict",
+                            format!("unknown element <{}>", String::from_utf8_lossy(name)),
+                        );
+                    }
                     _ => {}
                 },
                 Ok(Event::End(ref e)) => {
@@ -229,8 +654,8 @@ impl<R: BufRead> Iterator for Parser<R> {
                             &self.kanji_priorities
                         };
                         for p_text in priorities.iter() {
-                            let p = if p_text.starts_with("nf") {
-                                (&p_text[2..]).parse::<u32>().unwrap().saturating_sub(1) * 500
+                            let p = if let Some(n) = p_text.strip_prefix("nf") {
+                                n.parse::<u32>().unwrap().saturating_sub(1) * 500
                             } else {
                                 match p_text.as_str() {
                                     "news1" | "ichi1" | "gai1" => 6000,
@@ -244,24 +669,45 @@ impl<R: BufRead> Iterator for Parser<R> {
                         // Reset for next entry, and return the `WordEntry`.
                         self.kanji_priorities.clear();
                         self.kana_priorities.clear();
-                        return Some(std::mem::replace(&mut self.cur_entry, WordEntry::new()));
+                        return Some(std::mem::take(&mut self.cur_entry));
                     }
                 }
                 Ok(Event::Text(e)) => {
-                    let text = std::str::from_utf8(&*e).unwrap().into();
+                    let text: String = std::str::from_utf8(&e).unwrap().into();
                     match self.cur_xml_elem {
                         Elem::Gloss => {
+                            let gloss = format_gloss(&text, self.cur_gloss_type);
                             self.cur_entry
                                 .definitions
                                 .last_mut()
                                 .unwrap()
-                                .push_str(&format!("{}; ", text));
+                                .push_str(&format!("{}; ", gloss));
                         }
                         Elem::Keb => {
-                            self.cur_entry.writings.push(text);
+                            self.cur_entry.push_writing(text);
                         }
                         Elem::Reb => {
-                            self.cur_entry.readings.push(text);
+                            self.cur_entry.push_reading(text);
+                        }
+                        Elem::WritingInfo => {
+                            if let Some(info) = self.cur_entry.writing_info.last_mut() {
+                                info.push(intern(entity_name(&text)));
+                            }
+                        }
+                        Elem::ReadingInfo => {
+                            if let Some(info) = self.cur_entry.reading_info.last_mut() {
+                                info.push(intern(entity_name(&text)));
+                            }
+                        }
+                        Elem::LoanSource => {
+                            if let Some(source) = self.cur_entry.loan_sources.last_mut() {
+                                source.word = text;
+                            }
+                        }
+                        Elem::ReadingRestriction => {
+                            if let Some(r) = self.cur_entry.reading_restrictions.last_mut() {
+                                r.push(text);
+                            }
                         }
                         Elem::Misc => {
                             add_tag(&mut self.cur_entry, "misc", &text);
@@ -286,206 +732,7 @@ impl<R: BufRead> Iterator for Parser<R> {
                         Elem::Pos => {
                             add_tag(&mut self.cur_entry, "pos", &text);
 
-                            use PartOfSpeech::*;
-                            match text.as_str() {
-                                // Expression marker.
-                                "&exp;" => {
-                                    self.cur_entry.pos |= Expression;
-                                },
-
-                                // The copula, だ, and words that use it as an ending.
-                                "&cop-da;" => {
-                                    self.cur_entry.pos |= Copula;
-                                    self.cur_entry.conj |= ConjugationClass::Copula;
-                                },
-
-                                // i-adjectives.
-                                "&adj-i;" => {
-                                    self.cur_entry.pos |= Adjective;
-                                    self.cur_entry.conj |= ConjugationClass::IAdjective;
-                                },
-
-                                // The adjective いい and compounds that end with it.
-                                "&adj-ix;" => {
-                                    self.cur_entry.pos |= Adjective;
-                                    self.cur_entry.conj |= ConjugationClass::IrregularIAdjective;
-                                },
-
-                                // Words other than i-adjectives that
-                                // (conjugation aside) gramatically behave
-                                // similarly to them.  This specifically does
-                                // *not* include things like na-adjectives, which
-                                // require an additional particle to behave
-                                // that way.
-                                "&adj-pn;" => { // Pre-noun adjectival.
-                                    self.cur_entry.pos |= Adjective;
-                                },
-
-                                // Ichidan verbs.
-                                "&v1;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::IchidanVerb;
-                                },
-
-                                // Godan verbs.
-                                "&vn;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::GodanVerbNu;
-                                }
-                                "&v5u;" | "&v5n;" | "&v4b;" | "&v5b;" | "&v4g;"
-                                | "&v5g;" | "&v4h;" | "&v4k;" | "&v5k;" | "&v4m;"
-                                | "&v5m;" | "&v4r;" | "&v5r;" | "&v4s;" | "&v5s;"
-                                | "&v4t;" | "&v5t;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= match &text[3..4] {
-                                        "u" => ConjugationClass::GodanVerbU,
-                                        "t" => ConjugationClass::GodanVerbTsu,
-                                        "r" => ConjugationClass::GodanVerbRu,
-                                        "k" => ConjugationClass::GodanVerbKu,
-                                        "g" => ConjugationClass::GodanVerbGu,
-                                        "n" => ConjugationClass::GodanVerbNu,
-                                        "h" => ConjugationClass::GodanVerbHu,
-                                        "b" => ConjugationClass::GodanVerbBu,
-                                        "m" => ConjugationClass::GodanVerbMu,
-                                        "s" => ConjugationClass::GodanVerbSu,
-                                        _ => unreachable!(),
-                                    };
-                                }
-
-                                // する and verbs that end with it and conjugate
-                                // like it.
-                                "&vs-i;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::SuruVerb;
-                                },
-
-                                // Verbs ending in する but that don't quite
-                                // conjugate like it.
-                                "&vs-s;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::SuruVerbSC;
-                                },
-
-                                // 来る and verbs that end with it and conjugate
-                                // like it.
-                                "&vk;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::KuruVerb;
-                                },
-
-                                // 行く and verbs that end with it or its variants
-                                // (いく and ゆく) and conjugate like it.
-                                "&v5k-s;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::IkuVerb;
-                                }
-
-                                // Special class of verbs that end with either
-                                // さる or しゃる.
-                                "&v5aru;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::SharuVerb;
-                                },
-
-                                // ある ("to be") and verbs that end with and
-                                // conjugate like it.
-                                "&v5r-i;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::AruVerb;
-                                },
-
-                                // 呉れる / くれる and words the end with it.
-                                "&v1-s;" => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::KureruVerb;
-                                }
-
-                                // Other irregular verbs.
-                                "&vz;" | // ずる verb.
-                                "&v5u-s;" // Special class of う verbs.
-                                => {
-                                    self.cur_entry.pos |= Verb;
-                                    self.cur_entry.conj |= ConjugationClass::IrregularVerb;
-                                },
-
-                                // Words that essentially classify as nouns.
-                                "&vs;" | // So-called する verb, grammatically a noun.
-                                "&adj-na;" | // な adjective, grammatically a noun.
-                                "&adj-no;" | // の adjective, grammatically a noun.
-                                "&adj-t;" | // たる adjective, grammatically a noun.
-                                "&n-adv;" | // Adverbial noun.
-                                "&n-pref;" | // Noun used as prefix.
-                                "&n-suf;" | // Noun used as suffix.
-                                "&n-t;" | // Noun, temporal.
-                                "&n;" | // Noun
-                                "&pn;" | // Pronoun.
-                                "&num;" => {
-                                    self.cur_entry.pos |= Noun;
-                                }
-
-                                // Adverbs
-                                "&adv-to;" |
-                                "&adv;" => {
-                                    self.cur_entry.pos |= Adverb;
-                                }
-
-                                // Particle
-                                "&prt;" => {
-                                    self.cur_entry.pos |= Particle;
-                                }
-
-                                // Conjunction.
-                                "&conj;" => {
-                                    self.cur_entry.pos |= Conjunction;
-                                }
-
-                                // Categories that we don't care about or don't know
-                                // what to do with right now.
-                                "&vt;" | // Transitive verb.
-                                "&vi;" | // Intransitive verb.
-                                "&adj-f;" | // Noun or verb acting prenominally.
-                                "&ctr;" | // Counter.
-                                "&int;" | // Interjection.
-                                "&aux;" | // Auxiliary.
-                                "&aux-v;" | // Auxiliary verb.
-                                "&aux-adj;" | // Auxiliary adjective.
-                                "&pref;" | // Prefix.
-                                "&suf;" | // Suffix.
-                                "&unc;" | // Unclassified.
-                                // Archaic verbs.
-                                "&adj-kari;" | // Archaic.
-                                "&adj-ku;" | // Archaic.
-                                "&adj-nari;" | // Archaic.
-                                "&adj-shiku;" | // Archaic.
-                                "&vr;" | // Irregular る verb whose plain ending is り. Pretty much all archaic.
-                                "&vs-c;" | // Precursors to する, archaic.
-                                "&v2a-s;" | // Nidan verb, archaic.
-                                "&v2b-k;" | // Nidan verb, archaic.
-                                "&v2d-s;" | // Nidan verb, archaic.
-                                "&v2g-k;" | // Nidan verb, archaic.
-                                "&v2g-s;" | // Nidan verb, archaic.
-                                "&v2h-k;" | // Nidan verb, archaic.
-                                "&v2h-s;" | // Nidan verb, archaic.
-                                "&v2k-k;" | // Nidan verb, archaic.
-                                "&v2k-s;" | // Nidan verb, archaic.
-                                "&v2m-s;" | // Nidan verb, archaic.
-                                "&v2n-s;" | // Nidan verb, archaic.
-                                "&v2r-k;" | // Nidan verb, archaic.
-                                "&v2r-s;" | // Nidan verb, archaic.
-                                "&v2s-s;" | // Nidan verb, archaic.
-                                "&v2t-k;" | // Nidan verb, archaic.
-                                "&v2t-s;" | // Nidan verb, archaic.
-                                "&v2w-s;" | // Nidan verb, archaic.
-                                "&v2y-k;" | // Nidan verb, archaic.
-                                "&v2y-s;" | // Nidan verb, archaic.
-                                "&v2z-s;" // Nidan verb, archaic.
-                                => {
-                                },
-
-                                // Unknown classification string.
-                                _ => {
-                                }
-                            }
+                            apply_part_of_speech(&mut self.cur_entry, entity_name(&text));
                         }
                         Elem::Sense => {}
                         Elem::None => {}
@@ -508,10 +755,454 @@ impl<R: BufRead> Iterator for Parser<R> {
     }
 }
 
+/// The kind of a gloss, from the JMDict `g_type` attribute.  Plain glosses
+/// have no type.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum GlossType {
+    Literal,
+    Figurative,
+    Explanation,
+    Trademark,
+}
+
+impl GlossType {
+    /// Accepts both the JMDict names and the jmdict-simplified ones.
+    fn from_name(name: &str) -> Option<GlossType> {
+        match name {
+            "lit" | "literal" => Some(GlossType::Literal),
+            "fig" | "figurative" => Some(GlossType::Figurative),
+            "expl" | "explanation" => Some(GlossType::Explanation),
+            "tm" | "trademark" => Some(GlossType::Trademark),
+            _ => None,
+        }
+    }
+}
+
+/// Formats a gloss so that literal, figurative, etc. glosses stand out from
+/// plain ones, as in a printed dictionary.
+fn format_gloss(text: &str, gloss_type: Option<GlossType>) -> String {
+    match gloss_type {
+        Some(GlossType::Literal) => format!("<i>lit.</i> {}", text),
+        Some(GlossType::Figurative) => format!("<i>fig.</i> {}", text),
+        Some(GlossType::Explanation) => format!("<i>{}</i>", text),
+        Some(GlossType::Trademark) => format!("{}™", text),
+        None => text.into(),
+    }
+}
+
+/// Reads the attributes of an `lsource` element of the sense at index
+/// `sense` in the entry's definitions.  The word itself is the element's
+/// text.
+fn loan_source_from_xml(e: &quick_xml::events::BytesStart, sense: usize) -> LoanSource {
+    let mut source = LoanSource {
+        lang: "eng".into(), // The default, per the JMDict DTD.
+        word: String::new(),
+        partial: false,
+        wasei: false,
+        sense,
+    };
+    for attr in e.attributes().filter_map(|a| a.ok()) {
+        let value = String::from_utf8_lossy(&attr.value);
+        match attr.key.as_ref() {
+            b"xml:lang" => source.lang = value.into_owned(),
+            b"ls_type" => source.partial = value == "part",
+            b"ls_wasei" => source.wasei = value == "y",
+            _ => {}
+        }
+    }
+    source
+}
+
+/// Returns the name of an entity reference, e.g. "iK" for "&iK;".
+fn entity_name(text: &str) -> &str {
+    text.trim().trim_start_matches('&').trim_end_matches(';')
+}
+
+/// Returns a short label (e.g. "usu. kana") and the full JMDict description
+/// (e.g. "word usually written using kana alone") of the part of speech or
+/// misc entity `name`, or `None` if it isn't a known one.
+pub fn entity_description(name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match name {
+        // Parts of speech.
+        "n" => ("noun", "noun (common) (futsuumeishi)"),
+        "pn" => ("pronoun", "pronoun"),
+        "n-pr" => ("proper noun", "proper noun"),
+        "n-adv" => ("adv. noun", "adverbial noun (fukushitekimeishi)"),
+        "n-t" => ("temporal noun", "noun (temporal) (jisoumeishi)"),
+        "n-pref" => ("noun prefix", "noun, used as a prefix"),
+        "n-suf" => ("noun suffix", "noun, used as a suffix"),
+        "adj-i" => ("i-adj.", "adjective (keiyoushi)"),
+        "adj-ix" => ("i-adj. (yoi/ii)", "adjective (keiyoushi) - yoi/ii class"),
+        "adj-na" => (
+            "na-adj.",
+            "adjectival nouns or quasi-adjectives (keiyodoshi)",
+        ),
+        "adj-no" => (
+            "no-adj.",
+            "nouns which may take the genitive case particle 'no'",
+        ),
+        "adj-pn" => ("pre-noun adj.", "pre-noun adjectival (rentaishi)"),
+        "adj-t" => ("taru-adj.", "'taru' adjective"),
+        "adj-f" => ("prenominal", "noun or verb acting prenominally"),
+        "adv" => ("adverb", "adverb (fukushi)"),
+        "adv-to" => ("to-adverb", "adverb taking the 'to' particle"),
+        "aux" => ("aux.", "auxiliary"),
+        "aux-v" => ("aux. verb", "auxiliary verb"),
+        "aux-adj" => ("aux. adj.", "auxiliary adjective"),
+        "conj" => ("conj.", "conjunction"),
+        "cop" => ("copula", "copula"),
+        "ctr" => ("counter", "counter"),
+        "exp" => ("expression", "expressions (phrases, clauses, etc.)"),
+        "int" => ("interj.", "interjection (kandoushi)"),
+        "num" => ("numeric", "numeric"),
+        "pref" => ("prefix", "prefix"),
+        "suf" => ("suffix", "suffix"),
+        "prt" => ("particle", "particle"),
+        "unc" => ("unclassified", "unclassified"),
+        "v1" => ("ichidan verb", "Ichidan verb"),
+        "v1-s" => (
+            "ichidan verb (kureru)",
+            "Ichidan verb - kureru special class",
+        ),
+        "v5aru" => ("godan verb (aru)", "Godan verb - -aru special class"),
+        "v5b" => ("godan verb", "Godan verb with 'bu' ending"),
+        "v5g" => ("godan verb", "Godan verb with 'gu' ending"),
+        "v5k" => ("godan verb", "Godan verb with 'ku' ending"),
+        "v5k-s" => ("godan verb (iku)", "Godan verb - Iku/Yuku special class"),
+        "v5m" => ("godan verb", "Godan verb with 'mu' ending"),
+        "v5n" => ("godan verb", "Godan verb with 'nu' ending"),
+        "v5r" => ("godan verb", "Godan verb with 'ru' ending"),
+        "v5r-i" => (
+            "godan verb (irregular)",
+            "Godan verb with 'ru' ending (irregular verb)",
+        ),
+        "v5s" => ("godan verb", "Godan verb with 'su' ending"),
+        "v5t" => ("godan verb", "Godan verb with 'tsu' ending"),
+        "v5u" => ("godan verb", "Godan verb with 'u' ending"),
+        "v5u-s" => (
+            "godan verb (special)",
+            "Godan verb with 'u' ending (special class)",
+        ),
+        "vk" => ("kuru verb", "Kuru verb - special class"),
+        "vs" => (
+            "suru verb",
+            "noun or participle which takes the aux. verb suru",
+        ),
+        "vs-i" => ("suru verb", "suru verb - included"),
+        "vs-s" => ("suru verb (special)", "suru verb - special class"),
+        "vs-c" => ("su verb", "su verb - precursor to the modern suru"),
+        "vz" => (
+            "zuru verb",
+            "Ichidan verb - zuru verb (alternative form of -jiru verbs)",
+        ),
+        "vi" => ("intransitive", "intransitive verb"),
+        "vt" => ("transitive", "transitive verb"),
+
+        // Misc.
+        "uk" => ("usu. kana", "word usually written using kana alone"),
+        "abbr" => ("abbr.", "abbreviation"),
+        "arch" => ("archaic", "archaic"),
+        "chn" => ("children's", "children's language"),
+        "col" => ("colloquial", "colloquial"),
+        "dated" => ("dated", "dated term"),
+        "derog" => ("derogatory", "derogatory"),
+        "fam" => ("familiar", "familiar language"),
+        "fem" => ("female", "female term or language"),
+        "hist" => ("historical", "historical term"),
+        "hon" => ("honorific", "honorific or respectful (sonkeigo) language"),
+        "hum" => ("humble", "humble (kenjougo) language"),
+        "id" => ("idiomatic", "idiomatic expression"),
+        "joc" => ("jocular", "jocular, humorous term"),
+        "male" => ("male", "male term or language"),
+        "net-sl" => ("internet slang", "Internet slang"),
+        "obs" => ("obsolete", "obsolete term"),
+        "on-mim" => ("onomatopoeia", "onomatopoeic or mimetic word"),
+        "poet" => ("poetical", "poetical term"),
+        "pol" => ("polite", "polite (teineigo) language"),
+        "proverb" => ("proverb", "proverb"),
+        "rare" => ("rare", "rare term"),
+        "sens" => ("sensitive", "sensitive"),
+        "sl" => ("slang", "slang"),
+        "vulg" => ("vulgar", "vulgar expression or word"),
+        "X" => (
+            "rude",
+            "rude or X-rated term (not displayed in educational software)",
+        ),
+        "yoji" => ("yojijukugo", "yojijukugo"),
+        _ => return None,
+    })
+}
+
+/// Applies the part of speech `name` (a JMDict entity name such as "v5k")
+/// to `entry`.
+fn apply_part_of_speech(entry: &mut WordEntry, name: &str) {
+    use PartOfSpeech::*;
+    match name {
+        // Expression marker.
+        "exp" => {
+            entry.pos |= Expression;
+        },
+
+        // The copula, だ, and words that use it as an ending.
+        "cop-da" => {
+            entry.pos |= Copula;
+            entry.conj |= ConjugationClass::Copula;
+        },
+
+        // i-adjectives.
+        "adj-i" => {
+            entry.pos |= Adjective;
+            entry.conj |= ConjugationClass::IAdjective;
+        },
+
+        // The adjective いい and compounds that end with it.
+        "adj-ix" => {
+            entry.pos |= Adjective;
+            entry.conj |= ConjugationClass::IrregularIAdjective;
+        },
+
+        // Words other than i-adjectives that
+        // (conjugation aside) gramatically behave
+        // similarly to them.  This specifically does
+        // *not* include things like na-adjectives, which
+        // require an additional particle to behave
+        // that way.
+        "adj-pn" => { // Pre-noun adjectival.
+            entry.pos |= Adjective;
+        },
+
+        // Ichidan verbs.
+        "v1" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::IchidanVerb;
+        },
+
+        // Godan verbs.
+        "vn" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::GodanVerbNu;
+        }
+        "v5u" | "v5n" | "v4b" | "v5b" | "v4g"
+        | "v5g" | "v4h" | "v4k" | "v5k" | "v4m"
+        | "v5m" | "v4r" | "v5r" | "v4s" | "v5s"
+        | "v4t" | "v5t" => {
+            entry.pos |= Verb;
+            entry.conj |= match &name[2..3] {
+                "u" => ConjugationClass::GodanVerbU,
+                "t" => ConjugationClass::GodanVerbTsu,
+                "r" => ConjugationClass::GodanVerbRu,
+                "k" => ConjugationClass::GodanVerbKu,
+                "g" => ConjugationClass::GodanVerbGu,
+                "n" => ConjugationClass::GodanVerbNu,
+                "h" => ConjugationClass::GodanVerbHu,
+                "b" => ConjugationClass::GodanVerbBu,
+                "m" => ConjugationClass::GodanVerbMu,
+                "s" => ConjugationClass::GodanVerbSu,
+                _ => unreachable!(),
+            };
+        }
+
+        // する and verbs that end with it and conjugate
+        // like it.
+        "vs-i" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::SuruVerb;
+        },
+
+        // Verbs ending in する but that don't quite
+        // conjugate like it.
+        "vs-s" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::SuruVerbSC;
+        },
+
+        // 来る and verbs that end with it and conjugate
+        // like it.
+        "vk" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::KuruVerb;
+        },
+
+        // 行く and verbs that end with it or its variants
+        // (いく and ゆく) and conjugate like it.
+        "v5k-s" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::IkuVerb;
+        }
+
+        // Special class of verbs that end with either
+        // さる or しゃる.
+        "v5aru" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::SharuVerb;
+        },
+
+        // ある ("to be") and verbs that end with and
+        // conjugate like it.
+        "v5r-i" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::AruVerb;
+        },
+
+        // 呉れる / くれる and words the end with it.
+        "v1-s" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::KureruVerb;
+        }
+
+        // Verbs ending in ずる, an older form of じる.
+        "vz" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::ZuruVerb;
+        },
+
+        // Classical す verbs, the precursors of modern する verbs
+        // (e.g. 愛す).  They conjugate like godan す verbs.
+        "vs-c" => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::GodanVerbSu;
+        },
+
+        // Other irregular verbs.
+        "v5u-s" // Special class of う verbs.
+        => {
+            entry.pos |= Verb;
+            entry.conj |= ConjugationClass::IrregularVerb;
+        },
+
+        // Words that essentially classify as nouns.
+        "vs" | // So-called する verb, grammatically a noun.
+        "adj-na" | // な adjective, grammatically a noun.
+        "adj-no" | // の adjective, grammatically a noun.
+        "adj-t" | // たる adjective, grammatically a noun.
+        "n-adv" | // Adverbial noun.
+        "n-pref" | // Noun used as prefix.
+        "n-suf" | // Noun used as suffix.
+        "n-t" | // Noun, temporal.
+        "n" => { // Noun
+            entry.pos |= Noun;
+        }
+
+        "pn" => {
+            entry.pos |= Pronoun;
+        }
+
+        "num" => {
+            entry.pos |= Numeric;
+        }
+
+        "ctr" => {
+            entry.pos |= Counter;
+        }
+
+        "int" => {
+            entry.pos |= Interjection;
+        }
+
+        "aux" |
+        "aux-v" |
+        "aux-adj" => {
+            entry.pos |= Auxiliary;
+        }
+
+        "pref" => {
+            entry.pos |= Prefix;
+        }
+
+        "suf" => {
+            entry.pos |= Suffix;
+        }
+
+        // Adverbs
+        "adv-to" |
+        "adv" => {
+            entry.pos |= Adverb;
+        }
+
+        // Particle
+        "prt" => {
+            entry.pos |= Particle;
+        }
+
+        // Conjunction.
+        "conj" => {
+            entry.pos |= Conjunction;
+        }
+
+        // Categories that we don't care about or don't know
+        // what to do with right now.
+        "vt" | // Transitive verb.
+        "vi" | // Intransitive verb.
+        "adj-f" | // Noun or verb acting prenominally.
+        "unc" | // Unclassified.
+        // Archaic verbs.
+        "adj-kari" | // Archaic.
+        "adj-ku" | // Archaic.
+        "adj-nari" | // Archaic.
+        "adj-shiku" | // Archaic.
+        "vr" | // Irregular る verb whose plain ending is り. Pretty much all archaic.
+        "v2a-s" | // Nidan verb, archaic.
+        "v2b-k" | // Nidan verb, archaic.
+        "v2d-s" | // Nidan verb, archaic.
+        "v2g-k" | // Nidan verb, archaic.
+        "v2g-s" | // Nidan verb, archaic.
+        "v2h-k" | // Nidan verb, archaic.
+        "v2h-s" | // Nidan verb, archaic.
+        "v2k-k" | // Nidan verb, archaic.
+        "v2k-s" | // Nidan verb, archaic.
+        "v2m-s" | // Nidan verb, archaic.
+        "v2n-s" | // Nidan verb, archaic.
+        "v2r-k" | // Nidan verb, archaic.
+        "v2r-s" | // Nidan verb, archaic.
+        "v2s-s" | // Nidan verb, archaic.
+        "v2t-k" | // Nidan verb, archaic.
+        "v2t-s" | // Nidan verb, archaic.
+        "v2w-s" | // Nidan verb, archaic.
+        "v2y-k" | // Nidan verb, archaic.
+        "v2y-s" | // Nidan verb, archaic.
+        "v2z-s" // Nidan verb, archaic.
+        => {
+        },
+
+        // Unknown classification string.
+        _ => {
+        }
+    }
+}
+
+/// Elements of JMDict (and JMnedict) files that the XML parser knows about
+/// but doesn't use.  Any other unhandled element is reported as a warning,
+/// since it may mean the file format has changed.
+const IGNORED_ELEMENTS: &[&[u8]] = &[
+    b"JMdict",
+    b"JMnedict",
+    b"entry",
+    b"ent_seq",
+    b"k_ele",
+    b"r_ele",
+    b"re_nokanji",
+    b"stagk",
+    b"stagr",
+    b"xref",
+    b"ant",
+    b"s_inf",
+    b"pri",
+    b"example",
+    b"ex_srce",
+    b"ex_text",
+    b"ex_sent",
+    b"trans",
+    b"trans_det",
+];
+
 enum Elem {
     None,
     Keb,
     Reb,
+    WritingInfo,
+    ReadingInfo,
+    ReadingRestriction,
+    LoanSource,
     Pos,
     WritingPriority,
     ReadingPriority,
@@ -533,12 +1224,19 @@ impl std::ops::BitOr for PartOfSpeech {
         use PartOfSpeech::*;
 
         let class_to_priority = |c| match c {
-            Copula => 9,
-            Particle => 8,
-            Conjunction => 7,
-            Verb => 6,
-            Adjective => 5,
-            Adverb => 4,
+            Copula => 16,
+            Particle => 15,
+            Conjunction => 14,
+            Interjection => 13,
+            Verb => 12,
+            Adjective => 11,
+            Auxiliary => 10,
+            Pronoun => 9,
+            Counter => 8,
+            Adverb => 7,
+            Numeric => 6,
+            Prefix => 5,
+            Suffix => 4,
             Noun => 3,
             Expression => 2,
             Unknown => 0,
@@ -580,7 +1278,7 @@ impl std::ops::BitOr for ConjugationClass {
 
             SuruVerbSC => 7,
 
-            SuruVerb | KuruVerb | IkuVerb | KureruVerb | AruVerb | SharuVerb => 6,
+            SuruVerb | ZuruVerb | KuruVerb | IkuVerb | KureruVerb | AruVerb | SharuVerb => 6,
 
             IrregularVerb => 5,
 
@@ -618,3 +1316,77 @@ impl std::ops::BitOrAssign for ConjugationClass {
         *self = *self | rhs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<JMdict>
+<entry>
+<ent_seq>1</ent_seq>
+<k_ele><keb>麺麭</keb><ke_inf>&ateji;</ke_inf><ke_pri>gai1</ke_pri></k_ele>
+<r_ele><reb>パン</reb><re_pri>gai1</re_pri></r_ele>
+<sense>
+<pos>&n;</pos>
+<lsource xml:lang="por">pão</lsource>
+<gloss>bread</gloss>
+<gloss g_type="lit">R&amp;D &lt;test&gt;</gloss>
+</sense>
+<sense>
+<misc>&abbr;</misc>
+<lsource xml:lang="eng" ls_type="part" ls_wasei="y">pan &amp; pot</lsource>
+<gloss>pan</gloss>
+</sense>
+</entry>
+</JMdict>"#;
+
+    const JSON: &str = r#"{
+  "id": "1",
+  "kanji": [{"common": true, "text": "麺麭", "tags": ["ateji"]}],
+  "kana": [{"common": true, "text": "パン", "tags": [], "appliesToKanji": ["*"]}],
+  "sense": [
+    {
+      "partOfSpeech": ["n"],
+      "misc": [],
+      "languageSource": [{"lang": "por", "full": true, "wasei": false, "text": "pão"}],
+      "gloss": [
+        {"lang": "eng", "type": null, "text": "bread"},
+        {"lang": "eng", "type": "literal", "text": "R&D <test>"}
+      ]
+    },
+    {
+      "partOfSpeech": [],
+      "misc": ["abbr"],
+      "languageSource": [{"lang": "eng", "full": false, "wasei": true, "text": "pan & pot"}],
+      "gloss": [{"lang": "eng", "type": null, "text": "pan"}]
+    }
+  ]
+}"#;
+
+    #[test]
+    fn xml_and_json_give_the_same_word() {
+        let from_xml: Vec<WordEntry> = Parser::from_reader(XML.as_bytes()).collect();
+        assert_eq!(from_xml.len(), 1);
+        let xml = &from_xml[0];
+        let json = word_from_simplified_json(&serde_json::from_str(JSON).unwrap()).unwrap();
+
+        assert_eq!(
+            xml.definitions,
+            vec!["bread; <i>lit.</i> R&amp;D &lt;test&gt;", "pan"]
+        );
+        assert_eq!(xml.definitions, json.definitions);
+        assert_eq!(xml.loan_sources[1].word, "pan &amp; pot");
+        assert_eq!(xml.loan_sources, json.loan_sources);
+
+        assert_eq!(xml.writings, json.writings);
+        assert_eq!(xml.readings, json.readings);
+        assert_eq!(xml.writing_info, json.writing_info);
+        assert_eq!(xml.reading_info, json.reading_info);
+        assert_eq!(xml.reading_restrictions, json.reading_restrictions);
+        assert_eq!(xml.conj, json.conj);
+        assert_eq!(xml.pos, json.pos);
+        assert_eq!(xml.usually_kana, json.usually_kana);
+        assert_eq!(xml.priority, json.priority);
+        assert_eq!(xml.tags, json.tags);
+    }
+}
@@ -0,0 +1,620 @@
+//! Reading and writing merged entries as newline-delimited JSON, one entry
+//! per line, for processing with external tools.
+//!
+//! Each line is an object with the entry's `keys` as [key, priority] pairs,
+//! its `type` ("term", "name", or "kanji"), and the data of that type.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::intern::intern;
+use crate::jmdict::{ConjugationClass, LoanSource, PartOfSpeech, WordEntry};
+use crate::merged::{MergedEntry, MergedKind};
+use crate::yomichan::{Definition, InflectionType, KanjiEntry, Phonetics, TermEntry};
+
+pub fn write_entries(entries: &[MergedEntry], output_path: &Path) -> std::io::Result<()> {
+    let mut out = BufWriter::new(std::fs::File::create(output_path)?);
+    for entry in entries.iter() {
+        out.write_all(entry_to_json(entry).to_string().as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+pub fn read_entries(path: &Path) -> std::io::Result<Vec<MergedEntry>> {
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|json| entry_from_json(&json))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: line {}: not a valid entry", path.display(), i + 1),
+                )
+            })?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+//----------------------------------------------------------------
+// To json.
+
+fn entry_to_json(entry: &MergedEntry) -> Value {
+    let keys: Vec<Value> = entry
+        .keys
+        .iter()
+        .map(|(key, priority)| Value::Array(vec![key.as_str().into(), (*priority).into()]))
+        .collect();
+
+    match entry.kind {
+        MergedKind::Term {
+            ref kana,
+            ref pitch_accent,
+            ref phonetics,
+            ref word,
+            ref senses,
+        } => json!({
+            "keys": keys,
+            "type": "term",
+            "kana": kana.as_str(),
+            "pitch_accent": pitch_accent.clone(),
+            "nasal": phonetics.nasal.clone(),
+            "devoiced": phonetics.devoiced.clone(),
+            "word": word_to_json(word),
+            "senses": senses.iter().map(term_to_json).collect::<Vec<_>>(),
+        }),
+        MergedKind::Name {
+            ref items,
+            ref pitch_accents,
+        } => json!({
+            "keys": keys,
+            "type": "name",
+            "name": items.iter().map(term_to_json).collect::<Vec<_>>(),
+            "pitch_accents": pitch_accents.clone(),
+        }),
+        MergedKind::Kanji(ref kanji) => json!({
+            "keys": keys,
+            "type": "kanji",
+            "kanji": kanji.iter().map(kanji_to_json).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn word_to_json(word: &WordEntry) -> Value {
+    let mut tags: Vec<&str> = word.tags.iter().map(|t| &**t).collect();
+    tags.sort_unstable();
+
+    json!({
+        "writings": word.writings.clone(),
+        "readings": word.readings.clone(),
+        "definitions": word.definitions.clone(),
+        "conj": format!("{:?}", word.conj),
+        "pos": format!("{:?}", word.pos),
+        "usually_kana": word.usually_kana,
+        "writing_info": word.writing_info.iter().map(|m| markers_to_json(m)).collect::<Vec<_>>(),
+        "reading_info": word.reading_info.iter().map(|m| markers_to_json(m)).collect::<Vec<_>>(),
+        "reading_restrictions": word.reading_restrictions.clone(),
+        "loan_sources": word.loan_sources.iter().map(loan_source_to_json).collect::<Vec<_>>(),
+        "priority": word.priority,
+        "tags": tags,
+    })
+}
+
+fn loan_source_to_json(source: &LoanSource) -> Value {
+    json!({
+        "lang": source.lang.as_str(),
+        "word": source.word.as_str(),
+        "partial": source.partial,
+        "wasei": source.wasei,
+        "sense": source.sense,
+    })
+}
+
+fn markers_to_json(markers: &[Arc<str>]) -> Value {
+    markers.iter().map(|m| &**m).collect::<Vec<_>>().into()
+}
+
+fn term_to_json(term: &TermEntry) -> Value {
+    json!({
+        "dict_name": &*term.dict_name,
+        "writing": term.writing.as_str(),
+        "reading": term.reading.as_str(),
+        "definitions": definition_to_json(&term.definitions),
+        "infl": format!("{:?}", term.infl),
+        "tags": term.tags.iter().map(|t| &**t).collect::<Vec<_>>(),
+        "commonness": term.commonness,
+    })
+}
+
+fn definition_to_json(definition: &Definition) -> Value {
+    match definition {
+        Definition::Def(text) => text.as_str().into(),
+        Definition::List((header, list)) => json!({
+            "header": header.as_str(),
+            "list": list.iter().map(definition_to_json).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn kanji_to_json(kanji: &KanjiEntry) -> Value {
+    json!({
+        "dict_name": &*kanji.dict_name,
+        "kanji": kanji.kanji.as_str(),
+        "onyomi": kanji.onyomi.clone(),
+        "kunyomi": kanji.kunyomi.clone(),
+        "meanings": kanji.meanings.clone(),
+        "strokes": kanji.strokes,
+        "components": kanji.components.clone(),
+        "decomposition": kanji.decomposition.clone(),
+        "stroke_order": kanji.stroke_order.clone(),
+        "examples": kanji
+            .examples
+            .iter()
+            .map(|(writing, reading, gloss)| {
+                Value::Array(vec![
+                    writing.as_str().into(),
+                    reading.as_str().into(),
+                    gloss.as_str().into(),
+                ])
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+//----------------------------------------------------------------
+// From json.  These return `None` if the json isn't in the expected format.
+
+fn entry_from_json(json: &Value) -> Option<MergedEntry> {
+    let keys = json
+        .get("keys")?
+        .as_array()?
+        .iter()
+        .map(|key| Some((key.get(0)?.as_str()?.into(), key.get(1)?.as_u64()? as u32)))
+        .collect::<Option<Vec<(String, u32)>>>()?;
+    if keys.is_empty() {
+        return None;
+    }
+
+    let kind = match json.get("type")?.as_str()? {
+        "term" => MergedKind::Term {
+            kana: json.get("kana")?.as_str()?.into(),
+            pitch_accent: json
+                .get("pitch_accent")?
+                .as_array()?
+                .iter()
+                .map(|a| a.as_u64().map(|a| a as u32))
+                .collect::<Option<_>>()?,
+            // Not in dumps from older versions.
+            phonetics: Phonetics {
+                nasal: optional_numbers(json.get("nasal"))?,
+                devoiced: optional_numbers(json.get("devoiced"))?,
+            },
+            word: word_from_json(json.get("word")?)?,
+            senses: json
+                .get("senses")?
+                .as_array()?
+                .iter()
+                .map(term_from_json)
+                .collect::<Option<_>>()?,
+        },
+        "name" => {
+            // Older dumps have a single name object instead of a list.
+            let name = json.get("name")?;
+            let items = match name.as_array() {
+                Some(items) => items
+                    .iter()
+                    .map(term_from_json)
+                    .collect::<Option<Vec<_>>>()?,
+                None => vec![term_from_json(name)?],
+            };
+            if items.is_empty() {
+                return None;
+            }
+            // Not in dumps from older versions.
+            let pitch_accents = match json.get("pitch_accents") {
+                Some(accents) => accents
+                    .as_object()?
+                    .iter()
+                    .map(|(reading, list)| Some((reading.clone(), optional_numbers(Some(list))?)))
+                    .collect::<Option<_>>()?,
+                None => BTreeMap::new(),
+            };
+            MergedKind::Name {
+                items,
+                pitch_accents,
+            }
+        }
+        "kanji" => {
+            // Older dumps have a single kanji object instead of a list.
+            let kanji = json.get("kanji")?;
+            let items = match kanji.as_array() {
+                Some(items) => items
+                    .iter()
+                    .map(kanji_from_json)
+                    .collect::<Option<Vec<_>>>()?,
+                None => vec![kanji_from_json(kanji)?],
+            };
+            if items.is_empty() {
+                return None;
+            }
+            MergedKind::Kanji(items)
+        }
+        _ => return None,
+    };
+
+    Some(MergedEntry { keys, kind })
+}
+
+fn word_from_json(json: &Value) -> Option<WordEntry> {
+    let mut word = WordEntry {
+        writings: strings_from_json(json.get("writings")?)?,
+        readings: strings_from_json(json.get("readings")?)?,
+        definitions: strings_from_json(json.get("definitions")?)?,
+        conj: variant_from_name(CONJUGATION_CLASSES, json.get("conj")?.as_str()?)?,
+        pos: variant_from_name(PARTS_OF_SPEECH, json.get("pos")?.as_str()?)?,
+        usually_kana: json.get("usually_kana")?.as_bool()?,
+        writing_info: Vec::new(),
+        reading_info: Vec::new(),
+        reading_restrictions: Vec::new(),
+        loan_sources: Vec::new(),
+        priority: json.get("priority")?.as_u64()? as u32,
+        tags: strings_from_json(json.get("tags")?)?
+            .iter()
+            .map(|t| intern(t))
+            .collect::<HashSet<_>>(),
+    };
+
+    // Entries are keyed on their first reading.
+    if word.readings.is_empty() {
+        return None;
+    }
+
+    // Form markers, restrictions, and loan sources were added later, so
+    // they're optional.
+    let lists = |name: &str| -> Option<Vec<Vec<String>>> {
+        match json.get(name) {
+            Some(lists) => lists.as_array()?.iter().map(strings_from_json).collect(),
+            None => Some(Vec::new()),
+        }
+    };
+    let interned = |lists: Vec<Vec<String>>| -> Vec<Vec<Arc<str>>> {
+        lists
+            .iter()
+            .map(|l| l.iter().map(|m| intern(m)).collect())
+            .collect()
+    };
+    word.writing_info = interned(lists("writing_info")?);
+    word.reading_info = interned(lists("reading_info")?);
+    word.reading_restrictions = lists("reading_restrictions")?;
+    word.writing_info.resize(word.writings.len(), Vec::new());
+    word.reading_info.resize(word.readings.len(), Vec::new());
+    word.reading_restrictions
+        .resize(word.readings.len(), Vec::new());
+    if let Some(sources) = json.get("loan_sources") {
+        word.loan_sources = sources
+            .as_array()?
+            .iter()
+            .map(loan_source_from_json)
+            .collect::<Option<_>>()?;
+    }
+
+    Some(word)
+}
+
+fn loan_source_from_json(json: &Value) -> Option<LoanSource> {
+    Some(LoanSource {
+        lang: json.get("lang")?.as_str()?.into(),
+        word: json.get("word")?.as_str()?.into(),
+        partial: json.get("partial")?.as_bool()?,
+        wasei: json.get("wasei")?.as_bool()?,
+        // Files written before senses were recorded don't have it.
+        sense: json.get("sense").and_then(|s| s.as_u64()).unwrap_or(0) as usize,
+    })
+}
+
+fn term_from_json(json: &Value) -> Option<TermEntry> {
+    Some(TermEntry {
+        dict_name: intern(json.get("dict_name")?.as_str()?),
+        writing: json.get("writing")?.as_str()?.into(),
+        reading: json.get("reading")?.as_str()?.into(),
+        definitions: definition_from_json(json.get("definitions")?)?,
+        infl: variant_from_name(INFLECTION_TYPES, json.get("infl")?.as_str()?)?,
+        tags: strings_from_json(json.get("tags")?)?
+            .iter()
+            .map(|t| intern(t))
+            .collect(),
+        commonness: json.get("commonness")?.as_i64()? as i32,
+    })
+}
+
+fn definition_from_json(json: &Value) -> Option<Definition> {
+    if let Some(text) = json.as_str() {
+        return Some(Definition::Def(text.into()));
+    }
+
+    Some(Definition::List((
+        json.get("header")?.as_str()?.into(),
+        json.get("list")?
+            .as_array()?
+            .iter()
+            .map(definition_from_json)
+            .collect::<Option<_>>()?,
+    )))
+}
+
+fn kanji_from_json(json: &Value) -> Option<KanjiEntry> {
+    Some(KanjiEntry {
+        dict_name: intern(json.get("dict_name")?.as_str()?),
+        kanji: json.get("kanji")?.as_str()?.into(),
+        onyomi: strings_from_json(json.get("onyomi")?)?,
+        kunyomi: strings_from_json(json.get("kunyomi")?)?,
+        meanings: strings_from_json(json.get("meanings")?)?,
+        // These were added later, so they're optional.
+        strokes: json
+            .get("strokes")
+            .and_then(|s| s.as_u64())
+            .map(|s| s as u32),
+        components: match json.get("components") {
+            Some(components) => strings_from_json(components)?,
+            None => Vec::new(),
+        },
+        decomposition: json
+            .get("decomposition")
+            .and_then(|d| d.as_str())
+            .map(|d| d.into()),
+        stroke_order: json
+            .get("stroke_order")
+            .and_then(|s| s.as_str())
+            .map(|s| s.into()),
+        examples: match json.get("examples") {
+            Some(examples) => examples
+                .as_array()?
+                .iter()
+                .map(|e| {
+                    let e = strings_from_json(e)?;
+                    if e.len() != 3 {
+                        return None;
+                    }
+                    Some((e[0].clone(), e[1].clone(), e[2].clone()))
+                })
+                .collect::<Option<_>>()?,
+            None => Vec::new(),
+        },
+    })
+}
+
+/// Reads an optional list of numbers, which is empty if missing.  Returns
+/// `None` if it's malformed.
+fn optional_numbers(json: Option<&Value>) -> Option<Vec<u32>> {
+    match json {
+        Some(list) => list
+            .as_array()?
+            .iter()
+            .map(|n| n.as_u64().map(|n| n as u32))
+            .collect(),
+        None => Some(Vec::new()),
+    }
+}
+
+fn strings_from_json(json: &Value) -> Option<Vec<String>> {
+    json.as_array()?
+        .iter()
+        .map(|s| s.as_str().map(|s| s.into()))
+        .collect()
+}
+
+/// Finds the variant whose `Debug` name is `name`.
+fn variant_from_name<T: Copy + std::fmt::Debug>(variants: &[T], name: &str) -> Option<T> {
+    variants
+        .iter()
+        .find(|v| format!("{:?}", v) == name)
+        .copied()
+}
+
+const CONJUGATION_CLASSES: &[ConjugationClass] = &[
+    ConjugationClass::Other,
+    ConjugationClass::Copula,
+    ConjugationClass::IchidanVerb,
+    ConjugationClass::GodanVerbU,
+    ConjugationClass::GodanVerbTsu,
+    ConjugationClass::GodanVerbRu,
+    ConjugationClass::GodanVerbKu,
+    ConjugationClass::GodanVerbGu,
+    ConjugationClass::GodanVerbNu,
+    ConjugationClass::GodanVerbHu,
+    ConjugationClass::GodanVerbBu,
+    ConjugationClass::GodanVerbMu,
+    ConjugationClass::GodanVerbSu,
+    ConjugationClass::SuruVerb,
+    ConjugationClass::SuruVerbSC,
+    ConjugationClass::ZuruVerb,
+    ConjugationClass::KuruVerb,
+    ConjugationClass::IkuVerb,
+    ConjugationClass::KureruVerb,
+    ConjugationClass::AruVerb,
+    ConjugationClass::SharuVerb,
+    ConjugationClass::IrregularVerb,
+    ConjugationClass::IAdjective,
+    ConjugationClass::IrregularIAdjective,
+];
+
+const PARTS_OF_SPEECH: &[PartOfSpeech] = &[
+    PartOfSpeech::Unknown,
+    PartOfSpeech::Copula,
+    PartOfSpeech::Noun,
+    PartOfSpeech::Particle,
+    PartOfSpeech::Conjunction,
+    PartOfSpeech::Verb,
+    PartOfSpeech::Adverb,
+    PartOfSpeech::Adjective,
+    PartOfSpeech::Expression,
+    PartOfSpeech::Pronoun,
+    PartOfSpeech::Counter,
+    PartOfSpeech::Interjection,
+    PartOfSpeech::Auxiliary,
+    PartOfSpeech::Prefix,
+    PartOfSpeech::Suffix,
+    PartOfSpeech::Numeric,
+];
+
+const INFLECTION_TYPES: &[InflectionType] = &[
+    InflectionType::VerbIchidan,
+    InflectionType::VerbGodan,
+    InflectionType::VerbSuru,
+    InflectionType::VerbKuru,
+    InflectionType::IAdjective,
+    InflectionType::None,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(dict_name: &str, writing: &str, reading: &str, definitions: Definition) -> TermEntry {
+        TermEntry {
+            dict_name: intern(dict_name),
+            writing: writing.into(),
+            reading: reading.into(),
+            definitions,
+            infl: InflectionType::VerbGodan,
+            tags: vec![intern("v5")],
+            commonness: 3,
+        }
+    }
+
+    fn entries() -> Vec<MergedEntry> {
+        let mut word = WordEntry::new();
+        word.push_writing("麺麭".into());
+        word.push_reading("パン".into());
+        word.writing_info[0].push(intern("ateji"));
+        word.reading_restrictions[0].push("麺麭".into());
+        word.definitions = vec!["bread".into(), "R&amp;D".into()];
+        word.conj = ConjugationClass::GodanVerbSu;
+        word.pos = PartOfSpeech::Verb;
+        word.priority = 6000;
+        word.tags.insert(intern("pos:n"));
+        word.tags.insert(intern("misc:ateji"));
+        word.loan_sources.push(LoanSource {
+            lang: "por".into(),
+            word: "pão".into(),
+            partial: true,
+            wasei: false,
+            sense: 1,
+        });
+
+        let definitions = Definition::List((
+            "".into(),
+            vec![
+                Definition::Def("bread".into()),
+                Definition::List(("［名］".into(), vec![Definition::Def("pan".into())])),
+            ],
+        ));
+
+        let mut pitch_accents = BTreeMap::new();
+        pitch_accents.insert("タナカ".to_string(), vec![0]);
+
+        vec![
+            MergedEntry {
+                keys: vec![("麺麭".into(), 0), ("ぱん".into(), 7)],
+                kind: MergedKind::Term {
+                    kana: "パン".into(),
+                    pitch_accent: vec![1],
+                    phonetics: Phonetics {
+                        nasal: vec![2],
+                        devoiced: Vec::new(),
+                    },
+                    word,
+                    senses: vec![
+                        term("jmdict", "麺麭", "パン", definitions),
+                        term(
+                            "other",
+                            "麺麭",
+                            "パン",
+                            Definition::Def("<b>bread</b>".into()),
+                        ),
+                    ],
+                },
+            },
+            MergedEntry {
+                keys: vec![("田中".into(), 2)],
+                kind: MergedKind::Name {
+                    items: vec![term(
+                        "jmnedict",
+                        "田中",
+                        "たなか",
+                        Definition::Def("Tanaka".into()),
+                    )],
+                    pitch_accents,
+                },
+            },
+            MergedEntry {
+                keys: vec![("猫".into(), 1)],
+                kind: MergedKind::Kanji(vec![KanjiEntry {
+                    dict_name: intern("kanjidic"),
+                    kanji: "猫".into(),
+                    onyomi: vec!["ビョウ".into()],
+                    kunyomi: vec!["ねこ".into()],
+                    meanings: vec!["cat".into()],
+                    strokes: Some(11),
+                    components: vec!["犭".into(), "田".into()],
+                    decomposition: Some("⿰犭苗".into()),
+                    stroke_order: None,
+                    examples: vec![("猫".into(), "ねこ".into(), "cat".into())],
+                }]),
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = entries();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write_entries(&entries, file.path()).unwrap();
+        let read = read_entries(file.path()).unwrap();
+
+        assert_eq!(read.len(), entries.len());
+        for (a, b) in entries.iter().zip(read.iter()) {
+            assert_eq!(a.keys, b.keys);
+            assert_eq!(entry_to_json(a), entry_to_json(b));
+        }
+        // And directly, in case a field is lost the same way both times.
+        match (&entries[0].kind, &read[0].kind) {
+            (
+                MergedKind::Term {
+                    word: word_a,
+                    senses: senses_a,
+                    ..
+                },
+                MergedKind::Term {
+                    word: word_b,
+                    senses: senses_b,
+                    ..
+                },
+            ) => {
+                assert_eq!(word_a.loan_sources, word_b.loan_sources);
+                assert_eq!(word_a.tags, word_b.tags);
+                assert_eq!(word_a.conj, word_b.conj);
+                assert_eq!(senses_a[0].definitions, senses_b[0].definitions);
+            }
+            _ => panic!("not a term entry"),
+        }
+    }
+}
@@ -0,0 +1,533 @@
+//! Utilities for working with kana and other Japanese text.
+
+use std::convert::TryFrom;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Numerical difference between hiragana and katakana in scalar values.
+/// Hirgana is lower than katakana.
+const KANA_DIFF: u32 = 0x30a1 - 0x3041;
+
+pub fn is_kana(ch: char) -> bool {
+    let c = ch as u32;
+
+    (0x3041..=0x3096).contains(&c) // Hiragana.
+    || (0x3099..=0x309c).contains(&c) // Combining marks.
+    || (0x309d..=0x309e).contains(&c) // Iterating marks.
+    || (0x30a1..=0x30f6).contains(&c) // Katakana.
+    || c == 0x30fc // Prolonged sound mark.
+    || (0x30fd..=0x30fe).contains(&c) // Iterating marks.
+}
+
+pub fn is_hiragana(ch: char) -> bool {
+    let c = ch as u32;
+
+    (0x3041..=0x3096).contains(&c) // Hiragana.
+    || (0x3099..=0x309c).contains(&c) // Combining marks.
+    || (0x309d..=0x309e).contains(&c) // Iterating marks.
+    || c == 0x30fc // Prolonged sound mark.
+    || (0x30fd..=0x30fe).contains(&c) // Iterating marks.
+}
+
+/// Whether `ch` is a kanji: a CJK unified ideograph of the basic block or
+/// extension A, or the iteration mark 々.
+pub fn is_kanji(ch: char) -> bool {
+    ('\u{4e00}'..='\u{9fff}').contains(&ch) || ('\u{3400}'..='\u{4dbf}').contains(&ch) || ch == '々'
+}
+
+/// Removes all non-kana text from a `&str`, and returns
+/// a `String` of the result.
+pub fn strip_non_kana(text: &str) -> String {
+    let mut new_text = String::new();
+    for ch in text.chars() {
+        if is_kana(ch) {
+            new_text.push(ch);
+        }
+    }
+    new_text
+}
+
+pub fn hiragana_to_katakana(text: &str) -> String {
+    let mut new_text = String::new();
+    for ch in text.chars() {
+        let c = ch as u32;
+        new_text.push(
+            if (0x3041..=0x3096).contains(&c) || (0x309d..=0x309e).contains(&c) {
+                char::try_from(c + KANA_DIFF).unwrap_or(ch)
+            } else {
+                ch
+            },
+        );
+    }
+    new_text
+}
+
+pub fn katakana_to_hiragana(text: &str) -> String {
+    let mut new_text = String::new();
+    for ch in text.chars() {
+        let c = ch as u32;
+        new_text.push(
+            if (0x30a1..=0x30f6).contains(&c) || (0x30fd..=0x30fe).contains(&c) {
+                char::try_from(c - KANA_DIFF).unwrap_or(ch)
+            } else {
+                ch
+            },
+        );
+    }
+    new_text
+}
+
+pub fn is_all_kana(text: &str) -> bool {
+    let mut all_kana = true;
+    for ch in text.chars() {
+        all_kana &= is_kana(ch);
+    }
+    all_kana
+}
+
+pub fn is_all_hiragana(text: &str) -> bool {
+    let mut all_hiragana = true;
+    for ch in text.chars() {
+        all_hiragana &= is_hiragana(ch);
+    }
+    all_hiragana
+}
+
+/// Counts the morae of a kana word.  Small kana (other than っ) combine
+/// with the preceding kana into a single mora.
+pub fn mora_count(text: &str) -> usize {
+    const SMALL_KANA: &str = "ぁぃぅぇぉゃゅょゎァィゥェォャュョヮ";
+    text.chars()
+        .filter(|&ch| is_kana(ch) && !SMALL_KANA.contains(ch))
+        .count()
+}
+
+/// Returns the variants of a word containing Latin letters or digits that
+/// it might appear as in text: lowercase, and with full-width letters and
+/// digits.  Returns nothing for words without any.
+///
+/// The word is expected to be normalized already, i.e. with half-width
+/// Latin characters.
+pub fn latin_variants(text: &str) -> Vec<String> {
+    if !text.chars().any(|ch| ch.is_ascii_alphanumeric()) {
+        return Vec::new();
+    }
+
+    let to_fullwidth = |text: &str| -> String {
+        text.chars()
+            .map(|ch| {
+                if ch.is_ascii_alphanumeric() {
+                    char::try_from(ch as u32 - 0x21 + 0xff01).unwrap_or(ch)
+                } else {
+                    ch
+                }
+            })
+            .collect()
+    };
+
+    let lowercase = text.to_ascii_lowercase();
+    let mut variants = vec![to_fullwidth(text), to_fullwidth(&lowercase), lowercase];
+    variants.sort();
+    variants.dedup();
+    variants.retain(|v| v != text);
+    variants
+}
+
+/// Returns the spelling variants of a katakana loanword that it might appear
+/// as in text: with the long vowel mark ー and the explicit vowel
+/// interchanged (メール/メイル, ウィンドー/ウィンドウ), with or without a
+/// final ー (コンピューター/コンピュータ), and with ヴ and the バ row
+/// interchanged (ヴァイオリン/バイオリン).  Returns nothing for words that
+/// aren't all katakana.
+pub fn loanword_variants(text: &str) -> Vec<String> {
+    const E_ROW: &str = "エケゲセゼテデネヘベペメレェ";
+    const O_ROW: &str = "オコゴソゾトドノホボポモヨロヲォョ";
+    // Endings that are often written with or without a final ー, as in
+    // words from English "-er", "-or" and "-ar".
+    const LONG_ENDINGS: &str = "タダラサザナカガパバマャ";
+    const VU: &[(&str, &str)] = &[
+        ("ヴァ", "バ"),
+        ("ヴィ", "ビ"),
+        ("ヴェ", "ベ"),
+        ("ヴォ", "ボ"),
+        ("ヴ", "ブ"),
+    ];
+
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() < 2
+        || !chars
+            .iter()
+            .all(|&ch| ('ァ'..='ヺ').contains(&ch) || ch == 'ー')
+    {
+        return Vec::new();
+    }
+
+    let mut variants = Vec::new();
+
+    // ー <-> explicit vowel.
+    let mut to_vowel = String::new();
+    let mut from_vowel = String::new();
+    for (i, &ch) in chars.iter().enumerate() {
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        match (prev, ch) {
+            (Some(p), 'ー') if E_ROW.contains(p) => to_vowel.push('イ'),
+            (Some(p), 'ー') if O_ROW.contains(p) => to_vowel.push('ウ'),
+            _ => to_vowel.push(ch),
+        }
+        match (prev, ch) {
+            (Some(p), 'イ') if E_ROW.contains(p) => from_vowel.push('ー'),
+            (Some(p), 'ウ') if O_ROW.contains(p) => from_vowel.push('ー'),
+            _ => from_vowel.push(ch),
+        }
+    }
+    variants.push(to_vowel);
+    variants.push(from_vowel);
+
+    // Final ー.
+    let last = chars[chars.len() - 1];
+    if last == 'ー' && mora_count(text) > 2 {
+        variants.push(chars[..(chars.len() - 1)].iter().collect());
+    } else if LONG_ENDINGS.contains(last) && mora_count(text) > 2 {
+        variants.push(format!("{}ー", text));
+    }
+
+    // ヴ <-> バ row.
+    let mut folded = text.to_string();
+    for (vu, ba) in VU.iter() {
+        folded = folded.replace(vu, ba);
+    }
+    variants.push(folded);
+    if !text.contains('ヴ') {
+        let mut unfolded = String::new();
+        for ch in chars.iter() {
+            match VU.iter().find(|(_, ba)| ba.starts_with(*ch)) {
+                Some((vu, _)) => unfolded.push_str(vu),
+                None => unfolded.push(*ch),
+            }
+        }
+        variants.push(unfolded);
+    }
+
+    variants.sort();
+    variants.dedup();
+    variants.retain(|v| v != text);
+    variants
+}
+
+/// Returns the spelling variants of a word that it might appear as in older
+/// printed text: with iteration marks (こゝろ, 時々, いすゞ) instead of
+/// repeated characters or the other way around, and with full-size kana
+/// instead of small ones (きよう for きょう).  Returns nothing if there are
+/// no such variants.
+pub fn old_text_variants(text: &str) -> Vec<String> {
+    const SMALL: &[(char, char)] = &[
+        ('ぁ', 'あ'),
+        ('ぃ', 'い'),
+        ('ぅ', 'う'),
+        ('ぇ', 'え'),
+        ('ぉ', 'お'),
+        ('っ', 'つ'),
+        ('ゃ', 'や'),
+        ('ゅ', 'ゆ'),
+        ('ょ', 'よ'),
+        ('ァ', 'ア'),
+        ('ィ', 'イ'),
+        ('ゥ', 'ウ'),
+        ('ェ', 'エ'),
+        ('ォ', 'オ'),
+        ('ッ', 'ツ'),
+        ('ャ', 'ヤ'),
+        ('ュ', 'ユ'),
+        ('ョ', 'ヨ'),
+    ];
+
+    // The voiced version of a kana, e.g. ず for す.
+    let voiced = |ch: char| -> Option<char> {
+        let text = format!("{}\u{3099}", ch);
+        let mut composed = text.nfc();
+        match (composed.next(), composed.next()) {
+            (Some(v), None) => Some(v),
+            _ => None,
+        }
+    };
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut variants = Vec::new();
+
+    // Repeated characters -> iteration marks.
+    let mut contracted = String::new();
+    for (i, &ch) in chars.iter().enumerate() {
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        match prev {
+            Some(p) if p == ch && is_kanji(ch) => contracted.push('々'),
+            Some(p) if p == ch && is_hiragana(ch) => contracted.push('ゝ'),
+            Some(p) if p == ch && is_kana(ch) => contracted.push('ヽ'),
+            Some(p) if voiced(p) == Some(ch) && is_hiragana(ch) => contracted.push('ゞ'),
+            Some(p) if voiced(p) == Some(ch) && is_kana(ch) => contracted.push('ヾ'),
+            _ => contracted.push(ch),
+        }
+    }
+    variants.push(contracted);
+
+    // Iteration marks -> repeated characters.
+    let mut expanded = String::new();
+    let mut prev: Option<char> = None;
+    for &ch in chars.iter() {
+        let ch = match (prev, ch) {
+            (Some(p), '々') | (Some(p), 'ゝ') | (Some(p), 'ヽ') => p,
+            (Some(p), 'ゞ') | (Some(p), 'ヾ') => voiced(p).unwrap_or(ch),
+            _ => ch,
+        };
+        expanded.push(ch);
+        prev = Some(ch);
+    }
+    variants.push(expanded);
+
+    // Small kana -> full-size kana.
+    variants.push(
+        chars
+            .iter()
+            .map(|&ch| SMALL.iter().find(|s| s.0 == ch).map(|s| s.1).unwrap_or(ch))
+            .collect(),
+    );
+
+    variants.sort();
+    variants.dedup();
+    variants.retain(|v| v != text);
+    variants
+}
+
+/// Converts half-width katakana (and half-width Japanese punctuation) to
+/// their normal full-width equivalents.
+///
+/// Half-width voicing marks are combined with the preceding kana where
+/// possible.
+pub fn halfwidth_to_fullwidth(text: &str) -> String {
+    // Full-width equivalents of U+FF61 through U+FF9D.
+    const FULLWIDTH: &[char] = &[
+        '。', '「', '」', '、', '・', 'ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ',
+        'ー', 'ア', 'イ', 'ウ', 'エ', 'オ', 'カ', 'キ', 'ク', 'ケ', 'コ', 'サ', 'シ', 'ス', 'セ',
+        'ソ', 'タ', 'チ', 'ツ', 'テ', 'ト', 'ナ', 'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ',
+        'ホ', 'マ', 'ミ', 'ム', 'メ', 'モ', 'ヤ', 'ユ', 'ヨ', 'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ',
+        'ン',
+    ];
+
+    // Katakana whose voiced and semi-voiced forms directly follow them.
+    const VOICEABLE: &str = "カキクケコサシスセソタチツテトハヒフヘホ";
+    const SEMI_VOICEABLE: &str = "ハヒフヘホ";
+
+    let mut new_text = String::new();
+    for ch in text.chars() {
+        let c = ch as u32;
+        if (0xff61..=0xff9d).contains(&c) {
+            new_text.push(FULLWIDTH[(c - 0xff61) as usize]);
+        } else if c == 0xff9e || c == 0xff9f {
+            // Voicing marks.
+            let prev = new_text.chars().last().unwrap_or(' ');
+            let voiced = if c == 0xff9e {
+                match prev {
+                    _ if VOICEABLE.contains(prev) => char::try_from(prev as u32 + 1).ok(),
+                    'ウ' => Some('ヴ'),
+                    'ワ' => Some('ヷ'),
+                    'ヲ' => Some('ヺ'),
+                    _ => None,
+                }
+            } else if SEMI_VOICEABLE.contains(prev) {
+                char::try_from(prev as u32 + 2).ok()
+            } else {
+                None
+            };
+
+            match voiced {
+                Some(voiced_ch) => {
+                    new_text.pop();
+                    new_text.push(voiced_ch);
+                }
+                None => new_text.push(if c == 0xff9e { '゛' } else { '゜' }),
+            }
+        } else {
+            new_text.push(ch);
+        }
+    }
+    new_text
+}
+
+/// Normalizes headwords and readings from source data, so that variations
+/// in how they're encoded don't prevent them from matching.
+///
+/// This trims whitespace and applies NFKC normalization, which among other
+/// things converts half-width katakana to full-width and full-width Latin
+/// characters to ASCII.
+pub fn normalize(text: &str) -> String {
+    nfkc(text.trim())
+}
+
+/// Unicode NFC normalization.
+pub fn nfc(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// Unicode NFKC normalization.
+///
+/// Among other things, this converts half-width katakana and full-width
+/// Latin characters to their normal forms.
+pub fn nfkc(text: &str) -> String {
+    text.nfkc().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_trims_and_folds_widths() {
+        assert_eq!(normalize("  ｶﾞｯｺｳ\n"), "ガッコウ");
+        assert_eq!(normalize("ＡＢＣ１２３"), "ABC123");
+        assert_eq!(normalize("食べる"), "食べる");
+    }
+
+    #[test]
+    fn kanji() {
+        assert!(is_kanji('漢'));
+        assert!(is_kanji('々'));
+        assert!(is_kanji('\u{3400}'));
+        assert!(!is_kanji('か'));
+        assert!(!is_kanji('ー'));
+        assert!(!is_kanji('A'));
+    }
+
+    #[test]
+    fn kana_conversion() {
+        assert_eq!(hiragana_to_katakana("たべる"), "タベル");
+        assert_eq!(katakana_to_hiragana("タベル"), "たべる");
+        assert_eq!(hiragana_to_katakana("食べる"), "食ベル");
+        assert_eq!(katakana_to_hiragana("ラーメン"), "らーめん");
+        assert_eq!(hiragana_to_katakana("ゝゞ"), "ヽヾ");
+        // No hiragana equivalent.
+        assert_eq!(katakana_to_hiragana("ヷ"), "ヷ");
+    }
+
+    #[test]
+    fn mora_count_combines_small_kana() {
+        assert_eq!(mora_count(""), 0);
+        assert_eq!(mora_count("きょう"), 2);
+        assert_eq!(mora_count("がっこう"), 4);
+        assert_eq!(mora_count("コンピューター"), 6);
+        assert_eq!(mora_count("東京"), 0);
+    }
+
+    #[test]
+    fn halfwidth_katakana() {
+        assert_eq!(halfwidth_to_fullwidth("ｶﾞｯｺｳ"), "ガッコウ");
+        assert_eq!(halfwidth_to_fullwidth("ﾊﾟﾝ"), "パン");
+        assert_eq!(halfwidth_to_fullwidth("ｳﾞｧ"), "ヴァ");
+        assert_eq!(halfwidth_to_fullwidth("ﾞ"), "゛");
+    }
+
+    #[test]
+    fn loanword_spellings() {
+        assert_eq!(loanword_variants("メール"), vec!["メイル"]);
+        assert_eq!(loanword_variants("コンピューター"), vec!["コンピュータ"]);
+        assert_eq!(loanword_variants("ヴァイオリン"), vec!["バイオリン"]);
+        assert_eq!(loanword_variants("バイオリン"), vec!["ヴァイオリン"]);
+        assert!(loanword_variants("たべる").is_empty());
+        assert!(loanword_variants("ア").is_empty());
+    }
+
+    #[test]
+    fn old_text_spellings() {
+        assert_eq!(old_text_variants("こころ"), vec!["こゝろ"]);
+        assert_eq!(old_text_variants("時々"), vec!["時時"]);
+        assert_eq!(old_text_variants("いすゞ"), vec!["いすず"]);
+        assert_eq!(old_text_variants("きょう"), vec!["きよう"]);
+        assert!(old_text_variants("たべる").is_empty());
+    }
+
+    //------------------------------------------------------------
+    // Property tests, over random strings from a fixed seed.
+
+    /// A xorshift generator, so the tests are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u32) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as u32
+        }
+
+        /// A string of up to 8 characters from the given scalar value
+        /// ranges.
+        fn string(&mut self, ranges: &[(u32, u32)]) -> String {
+            let len = self.below(9);
+            (0..len)
+                .map(|_| {
+                    let (lo, hi) = ranges[self.below(ranges.len() as u32) as usize];
+                    char::try_from(lo + self.below(hi - lo + 1)).unwrap()
+                })
+                .collect()
+        }
+    }
+
+    const CASES: usize = 2000;
+    const HIRAGANA: (u32, u32) = (0x3041, 0x3096);
+    const KATAKANA: (u32, u32) = (0x30a1, 0x30f6);
+    const HALFWIDTH_KATAKANA: (u32, u32) = (0xff61, 0xff9f);
+    const FULLWIDTH_LATIN: (u32, u32) = (0xff01, 0xff5e);
+    const KANJI: (u32, u32) = (0x4e00, 0x4e20);
+
+    #[test]
+    fn prop_kana_round_trip() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..CASES {
+            let hiragana = rng.string(&[HIRAGANA]);
+            let katakana = hiragana_to_katakana(&hiragana);
+            assert!(is_all_kana(&katakana));
+            assert_eq!(katakana_to_hiragana(&katakana), hiragana);
+
+            let katakana = rng.string(&[KATAKANA, (0x30fc, 0x30fc)]);
+            let hiragana = katakana_to_hiragana(&katakana);
+            assert!(is_all_hiragana(&hiragana));
+            assert_eq!(hiragana_to_katakana(&hiragana), katakana);
+        }
+    }
+
+    #[test]
+    fn prop_mora_count() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..CASES {
+            let text = rng.string(&[HIRAGANA, KANJI]);
+            assert_eq!(mora_count(&text), mora_count(&hiragana_to_katakana(&text)));
+            assert!(mora_count(&text) <= text.chars().count());
+        }
+    }
+
+    #[test]
+    fn prop_normalize_is_idempotent() {
+        let mut rng = Rng(0x5851_f42d_4c95_7f2d);
+        for _ in 0..CASES {
+            let text = rng.string(&[HIRAGANA, HALFWIDTH_KATAKANA, FULLWIDTH_LATIN, KANJI]);
+            let normalized = normalize(&text);
+            assert_eq!(normalize(&normalized), normalized);
+            assert!(!normalized.contains(|ch| ('\u{ff61}'..='\u{ff9f}').contains(&ch)));
+        }
+    }
+
+    #[test]
+    fn prop_variants_exclude_the_word() {
+        let mut rng = Rng(0x1405_7b7e_f767_814f);
+        for _ in 0..CASES {
+            let katakana = rng.string(&[KATAKANA, (0x30fc, 0x30fc)]);
+            let mixed = rng.string(&[HIRAGANA, KANJI, (0x3005, 0x3005), (0x309d, 0x309e)]);
+            for (text, variants) in [
+                (katakana.clone(), loanword_variants(&katakana)),
+                (mixed.clone(), old_text_variants(&mixed)),
+            ] {
+                assert!(!variants.contains(&text));
+                assert!(variants.iter().all(|v| !v.is_empty()));
+                assert!(variants.windows(2).all(|w| w[0] < w[1]));
+            }
+        }
+    }
+}
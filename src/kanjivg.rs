@@ -0,0 +1,325 @@
+//! Stroke-order diagrams from KanjiVG.
+//!
+//! KanjiVG has one SVG file per kanji, named after its code point in
+//! hexadecimal (e.g. "04e00.svg" for 一), with one path per stroke in stroke
+//! order.  Kobo e-readers can't display SVG, so the strokes are rasterized
+//! into a strip of panels, one per stroke, each showing the strokes so far
+//! with the new one in black and a dot at its start.  The result is a PNG
+//! image, embedded in the entry as a data URI.
+
+use std::io::prelude::*;
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::Crc;
+use regex::Regex;
+
+use crate::yomichan::base64_encode;
+
+lazy_static! {
+    static ref PATH_DATA: Regex = Regex::new(r#"<path[^>]*\sd="([^"]*)""#).unwrap();
+    static ref PATH_TOKEN: Regex =
+        Regex::new(r"[MmCcSsLlZz]|-?(?:[0-9]+\.?[0-9]*|\.[0-9]+)").unwrap();
+}
+
+const VIEW_BOX_SIZE: f32 = 109.0; // Of all KanjiVG files.
+const PANEL_SIZE: usize = 48;
+const PANELS_PER_ROW: usize = 8;
+const STROKE_RADIUS: f32 = 1.6;
+const START_DOT_RADIUS: f32 = 2.8;
+const OLD_STROKE_SHADE: u8 = 170;
+
+/// Loads the stroke-order diagram of `kanji` from the KanjiVG directory
+/// `dir`, as a data URI.
+///
+/// Returns `None` if there's no file for the kanji or it has no strokes.
+pub fn load_diagram(dir: &Path, kanji: &str) -> Option<String> {
+    let mut chars = kanji.chars();
+    let ch = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+
+    let svg = std::fs::read_to_string(dir.join(format!("{:05x}.svg", ch as u32))).ok()?;
+    let strokes: Vec<Vec<(f32, f32)>> = PATH_DATA
+        .captures_iter(&svg)
+        .map(|caps| parse_path(&caps[1]))
+        .filter(|stroke| !stroke.is_empty())
+        .collect();
+    if strokes.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "data:image/png;base64,{}",
+        base64_encode(&render_png(&strokes))
+    ))
+}
+
+/// Parses SVG path data into a polyline, with curves flattened.  Only the
+/// commands that KanjiVG uses are supported.
+fn parse_path(data: &str) -> Vec<(f32, f32)> {
+    const CURVE_STEPS: usize = 12;
+
+    let mut points = Vec::new();
+    let mut cur = (0.0f32, 0.0f32);
+    let mut start = cur;
+    let mut last_control: Option<(f32, f32)> = None;
+
+    let mut command = 'M';
+    let mut numbers: Vec<f32> = Vec::new();
+    let mut tokens = PATH_TOKEN.find_iter(data).map(|m| m.as_str()).peekable();
+    loop {
+        // Gather the numbers for the current command.
+        while let Some(n) = tokens.peek().and_then(|t| t.parse::<f32>().ok()) {
+            numbers.push(n);
+            tokens.next();
+        }
+
+        let relative = command.is_ascii_lowercase();
+        let arg_count = match command.to_ascii_uppercase() {
+            'M' | 'L' => 2,
+            'C' => 6,
+            'S' => 4,
+            _ => 0,
+        };
+        if arg_count > 0 {
+            for args in numbers.chunks_exact(arg_count) {
+                let offset = if relative { cur } else { (0.0, 0.0) };
+                let p = |i: usize| (args[i] + offset.0, args[i + 1] + offset.1);
+                match command.to_ascii_uppercase() {
+                    'M' if points.is_empty() => {
+                        cur = p(0);
+                        start = cur;
+                        points.push(cur);
+                        last_control = None;
+                    }
+                    'M' | 'L' => {
+                        cur = p(0);
+                        points.push(cur);
+                        last_control = None;
+                    }
+                    _ => {
+                        let (c1, c2, end) = if arg_count == 6 {
+                            (p(0), p(2), p(4))
+                        } else {
+                            // Reflection of the previous control point.
+                            let c1 = match last_control {
+                                Some(c) => (2.0 * cur.0 - c.0, 2.0 * cur.1 - c.1),
+                                None => cur,
+                            };
+                            (c1, p(0), p(2))
+                        };
+                        for step in 1..=CURVE_STEPS {
+                            let t = step as f32 / CURVE_STEPS as f32;
+                            let u = 1.0 - t;
+                            let bezier = |a: f32, b: f32, c: f32, d: f32| {
+                                u * u * u * a
+                                    + 3.0 * u * u * t * b
+                                    + 3.0 * u * t * t * c
+                                    + t * t * t * d
+                            };
+                            points.push((
+                                bezier(cur.0, c1.0, c2.0, end.0),
+                                bezier(cur.1, c1.1, c2.1, end.1),
+                            ));
+                        }
+                        last_control = Some(c2);
+                        cur = end;
+                    }
+                }
+            }
+        } else if command == 'Z' || command == 'z' {
+            cur = start;
+            points.push(cur);
+        }
+        numbers.clear();
+
+        match tokens.next() {
+            Some(token) => command = token.chars().next().unwrap(),
+            None => break,
+        }
+    }
+
+    points
+}
+
+/// Renders the stroke-order panels of `strokes` as a grayscale PNG.
+fn render_png(strokes: &[Vec<(f32, f32)>]) -> Vec<u8> {
+    let columns = strokes.len().min(PANELS_PER_ROW);
+    let rows = strokes.len().div_ceil(PANELS_PER_ROW);
+    let width = columns * PANEL_SIZE;
+    let height = rows * PANEL_SIZE;
+    let mut pixels = vec![255u8; width * height];
+
+    let scale = PANEL_SIZE as f32 / VIEW_BOX_SIZE;
+    let mut stamp = |x: f32, y: f32, radius: f32, shade: u8| {
+        let x_range = ((x - radius).floor().max(0.0) as usize)..((x + radius).ceil() as usize + 1);
+        for py in ((y - radius).floor().max(0.0) as usize)..((y + radius).ceil() as usize + 1) {
+            for px in x_range.clone() {
+                let (dx, dy) = (px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if px < width && py < height && dx * dx + dy * dy <= radius * radius {
+                    let pixel = &mut pixels[py * width + px];
+                    *pixel = (*pixel).min(shade);
+                }
+            }
+        }
+    };
+
+    for panel in 0..strokes.len() {
+        let origin = (
+            ((panel % PANELS_PER_ROW) * PANEL_SIZE) as f32,
+            ((panel / PANELS_PER_ROW) * PANEL_SIZE) as f32,
+        );
+        let to_panel = |(x, y): (f32, f32)| (origin.0 + x * scale, origin.1 + y * scale);
+
+        for (i, stroke) in strokes[..=panel].iter().enumerate() {
+            let shade = if i == panel { 0 } else { OLD_STROKE_SHADE };
+            for segment in stroke.windows(2) {
+                let (a, b) = (to_panel(segment[0]), to_panel(segment[1]));
+                let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+                let steps = (length * 2.0).ceil().max(1.0) as usize;
+                for step in 0..=steps {
+                    let t = step as f32 / steps as f32;
+                    stamp(
+                        a.0 + (b.0 - a.0) * t,
+                        a.1 + (b.1 - a.1) * t,
+                        STROKE_RADIUS,
+                        shade,
+                    );
+                }
+            }
+        }
+
+        let start = to_panel(strokes[panel][0]);
+        stamp(start.0, start.1, START_DOT_RADIUS, 0);
+    }
+
+    encode_png(&pixels, width, height)
+}
+
+/// Encodes 8-bit grayscale pixels as a PNG image.
+fn encode_png(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    fn write_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let mut crc = Crc::new();
+        crc.update(&out[start..]);
+        out.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]); // 8-bit grayscale, no interlacing.
+
+    // Each row starts with its filter type, which is always "none" here.
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    for row in pixels.chunks(width) {
+        encoder.write_all(&[0]).unwrap();
+        encoder.write_all(row).unwrap();
+    }
+    let image_data = encoder.finish().unwrap();
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &image_data);
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::ZlibDecoder;
+
+    /// The chunks of a PNG file, as (kind, data, stored CRC).
+    fn png_chunks(png: &[u8]) -> Vec<(String, Vec<u8>, u32)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let kind = String::from_utf8(rest[4..8].to_vec()).unwrap();
+            let data = rest[8..(8 + len)].to_vec();
+            let crc_bytes = &rest[(8 + len)..(12 + len)];
+            let crc = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+            chunks.push((kind, data, crc));
+            rest = &rest[(12 + len)..];
+        }
+        chunks
+    }
+
+    /// A bitwise CRC-32, as in the PNG specification, to check against.
+    fn reference_crc(kind: &str, data: &[u8]) -> u32 {
+        let mut crc = 0xffff_ffffu32;
+        for &byte in kind.as_bytes().iter().chain(data.iter()) {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn png_structure() {
+        let (width, height) = (3, 2);
+        let png = encode_png(&[0, 128, 255, 255, 128, 0], width, height);
+        let chunks = png_chunks(&png);
+
+        let kinds: Vec<&str> = chunks.iter().map(|c| c.0.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        for (kind, data, crc) in chunks.iter() {
+            assert_eq!(*crc, reference_crc(kind, data), "{} chunk", kind);
+        }
+        // The CRC of an empty IEND chunk is always the same.
+        assert_eq!(chunks[2].2, 0xae42_6082);
+
+        let header = &chunks[0].1;
+        assert_eq!(&header[..4], &(width as u32).to_be_bytes());
+        assert_eq!(&header[4..8], &(height as u32).to_be_bytes());
+        assert_eq!(&header[8..], &[8, 0, 0, 0, 0]);
+
+        let mut rows = Vec::new();
+        ZlibDecoder::new(&chunks[1].1[..])
+            .read_to_end(&mut rows)
+            .unwrap();
+        assert_eq!(rows, [0, 0, 128, 255, 0, 255, 128, 0]);
+    }
+
+    #[test]
+    fn rendered_panels() {
+        let strokes = vec![
+            vec![(10.0, 10.0), (90.0, 10.0)],
+            vec![(50.0, 10.0), (50.0, 90.0)],
+        ];
+        let chunks = png_chunks(&render_png(&strokes));
+        let header = &chunks[0].1;
+        assert_eq!(&header[..4], &(2 * PANEL_SIZE as u32).to_be_bytes());
+        assert_eq!(&header[4..8], &(PANEL_SIZE as u32).to_be_bytes());
+
+        let mut rows = Vec::new();
+        ZlibDecoder::new(&chunks[1].1[..])
+            .read_to_end(&mut rows)
+            .unwrap();
+        let row_len = 2 * PANEL_SIZE + 1;
+        assert_eq!(rows.len(), PANEL_SIZE * row_len);
+        let pixel = |x: usize, y: usize| rows[y * row_len + 1 + x];
+
+        // The first stroke is black in the first panel, and gray in the
+        // second.  The second stroke is only in the second panel.
+        let (x, y) = ((50.0 * PANEL_SIZE as f32 / VIEW_BOX_SIZE) as usize, 4);
+        assert_eq!(pixel(x, y), 0);
+        assert_eq!(pixel(PANEL_SIZE + x + 8, y), OLD_STROKE_SHADE);
+        assert_eq!(pixel(x, 30), 255);
+        assert_eq!(pixel(PANEL_SIZE + x, 30), 0);
+    }
+}
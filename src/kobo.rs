@@ -4,19 +4,87 @@ use std::collections::HashMap;
 use std::io::prelude::*;
 use std::io::BufWriter;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use flate2::read::GzEncoder;
+use flate2::read::{GzDecoder, GzEncoder};
+use regex::Regex;
 use unicode_categories::UnicodeCategories;
 
 use crate::generic_dict::Entry;
+use crate::observer;
+use crate::warnings;
+
+lazy_static! {
+    static ref WORD: Regex = Regex::new(r"(?s)<w>(.*?)</w>").unwrap();
+    static ref KEY: Regex = Regex::new(r#"<a name="([^"]*)"\s*/?>(?:</a>)?"#).unwrap();
+}
+
+/// How much to compress the dictionary files.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Compression {
+    Store,      // No compression at all.
+    Level(u32), // Gzip/deflate level, 0-9.
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::Level(1)
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Compression, String> {
+        if text == "store" {
+            return Ok(Compression::Store);
+        }
+        match text.parse::<u32>() {
+            Ok(level) if level <= 9 => Ok(Compression::Level(level)),
+            _ => Err(format!(
+                "\"{}\" is not a compression level (0-9 or \"store\")",
+                text
+            )),
+        }
+    }
+}
+
+pub fn write_dictionary(
+    entries: &[Entry],
+    output_path: &Path,
+    compression: Compression,
+) -> std::io::Result<()> {
+    write_dictionary_to(
+        entries,
+        BufWriter::new(std::fs::File::create(output_path)?),
+        compression,
+    )
+}
+
+/// The most files a zip archive can have without the Zip64 extensions.
+const ZIP_MAX_MEMBERS: usize = 0xffff;
+
+/// The largest size, and offset, a zip archive can have without the Zip64
+/// extensions.
+const ZIP_MAX_SIZE: u64 = 0xffff_ffff;
+
+/// Same as `write_dictionary()`, but writes the dictionary zip archive to
+/// `output`, e.g. a `Cursor<Vec<u8>>` to keep it in memory.
+///
+/// Note that this still needs `marisa-build` and a temporary directory to
+/// build the `words` index.
+pub fn write_dictionary_to<W: Write + Seek>(
+    entries: &[Entry],
+    output: W,
+    compression: Compression,
+) -> std::io::Result<()> {
+    observer::phase_started("Writing");
 
-pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Result<()> {
     // Sorted, de-duplicated list of keys.
     let all_keys = {
         let max_priority = entries
             .iter()
-            .map(|e| &e.keys[..])
-            .flatten()
+            .flat_map(|e| &e.keys[..])
             .fold(0u32, |a, b| a.max(b.1));
         let mut keys = HashMap::new();
         for entry in entries.iter() {
@@ -67,19 +135,22 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
         {
             Ok(output) => {
                 if !output.status.success() {
-                    eprintln!(
-                        "Error: \"marisa-build\" exited with a failure:\n{}",
+                    return Err(std::io::Error::other(format!(
+                        "\"marisa-build\" exited with a failure:\n{}",
                         String::from_utf8_lossy(&output.stderr)
-                    );
-                    std::process::exit(1);
+                    )));
                 }
             }
             Err(e) => {
-                eprintln!("Error: attempt to run \"marisa-build\" failed: {}", e);
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    eprintln!("Make sure you have marisa-build installed and in your path, and that you have the permissions needed to run it.");
-                }
-                std::process::exit(1);
+                let hint = if e.kind() == std::io::ErrorKind::NotFound {
+                    "\nMake sure you have marisa-build installed and in your path, and that you have the permissions needed to run it."
+                } else {
+                    ""
+                };
+                return Err(std::io::Error::new(
+                    e.kind(),
+                    format!("attempt to run \"marisa-build\" failed: {}{}", e, hint),
+                ));
             }
         };
 
@@ -101,15 +172,16 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
         for key in entry.keys.iter() {
             let prefix = dictionary_prefix(&key.0);
 
-            let a = prefix_entries.entry(prefix).or_insert(Vec::new());
+            let a = prefix_entries.entry(prefix).or_default();
             a.push((key.0.clone(), entry.definition.clone(), key.1));
         }
     }
 
     for entries in prefix_entries.values_mut() {
-        // Sort by key, and then within key by priority, to prep for the
-        // merging below.
-        entries.sort_by_key(|a| (a.0.clone(), a.2));
+        // Sort by key, and then within key by priority and inverse entry
+        // length, to prep for the merging below.  This also determines the
+        // order that entries with the same key are shown in.
+        entries.sort_by_key(|a| (a.0.clone(), a.2, -(a.1.len() as isize)));
 
         // Merge entries with the same key, so that Kobo e-readers show all
         // matches (their software is weird, and often omits duplicate exact
@@ -131,48 +203,143 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
         entries.sort_by_key(|a| (a.2, -(a.1.len() as isize)));
     }
 
+    //----------------------------------------------------------------
+    // Generate and compress the prefix entry files.
+
+    // Sorted so that the output is deterministic.
+    let mut prefix_entries: Vec<(String, Vec<(String, String, u32)>)> =
+        prefix_entries.drain().collect();
+    prefix_entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let gz_level = match compression {
+        Compression::Store => flate2::Compression::none(),
+        Compression::Level(level) => flate2::Compression::new(level),
+    };
+
+    // Compression is by far the slowest part of writing the dictionary, so
+    // we split the files into chunks and compress them in parallel.
+    observer::check_cancelled()?;
+    let compressed_count = AtomicUsize::new(0);
+    let compress_chunk = |chunk: &[(String, Vec<(String, String, u32)>)]| {
+        chunk
+            .iter()
+            .map(|(prefix, prefix_entry_list)| {
+                let data = prefix_file_data(prefix_entry_list, gz_level);
+                let done = compressed_count.fetch_add(1, Ordering::Relaxed) + 1;
+                observer::progress("Writing", done, prefix_entries.len());
+                (format!("{}.html", prefix), data)
+            })
+            .collect::<Vec<_>>()
+    };
+    let thread_count = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let prefix_files: Vec<(String, Vec<u8>)> = if thread_count == 1 {
+        // Also covers targets without threads, which report no parallelism.
+        compress_chunk(&prefix_entries)
+    } else {
+        let chunk_size = prefix_entries.len().div_ceil(thread_count).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = prefix_entries
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || compress_chunk(chunk)))
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    };
+
     //----------------------------------------------------------------
     // Write the Kobo dictionary file.
 
+    observer::check_cancelled()?;
+    write_archive(
+        output,
+        &words,
+        words_original.as_bytes(),
+        &prefix_files,
+        compression,
+    )
+}
+
+/// Writes the files of a Kobo dictionary to a zip archive: the `words`
+/// index, its `words.original` text, and the gzipped prefix files.
+fn write_archive<W: Write + Seek>(
+    output: W,
+    words: &[u8],
+    words_original: &[u8],
+    prefix_files: &[(String, Vec<u8>)],
+    compression: Compression,
+) -> std::io::Result<()> {
+    // Past the limits of the original zip format, the archive is written
+    // with the Zip64 extensions, which e-readers may not support.
+    let member_count = prefix_files.len() + 2;
+    let total_size = prefix_files
+        .iter()
+        .map(|(_, data)| data.len() as u64)
+        .sum::<u64>()
+        + words.len() as u64
+        + words_original.len() as u64;
+    if member_count > ZIP_MAX_MEMBERS {
+        warnings::warn(
+            warnings::Category::DictionarySize,
+            "output",
+            format!(
+                "{} files in the archive, more than the {} that zip files without Zip64 can hold; the e-reader may not be able to open it",
+                member_count, ZIP_MAX_MEMBERS
+            ),
+        );
+    }
+    if total_size > ZIP_MAX_SIZE {
+        warnings::warn(
+            warnings::Category::DictionarySize,
+            "output",
+            format!(
+                "{} MiB of data, more than the 4 GiB that zip files without Zip64 can hold; the e-reader may not be able to open it",
+                total_size >> 20
+            ),
+        );
+    }
+
+    let file_options = match compression {
+        Compression::Store | Compression::Level(0) => {
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored)
+        }
+        Compression::Level(_) => zip::write::FileOptions::default(),
+    };
+
     // Open the output zip archive.
-    let mut zip_out = zip::ZipWriter::new(BufWriter::new(std::fs::File::create(output_path)?));
+    let mut zip_out = zip::ZipWriter::new(output);
 
     // Write the words and words.original files.
+    // Files over 4 GiB have to be declared before they're written.
+    let large = |data: &[u8]| data.len() as u64 >= ZIP_MAX_SIZE;
     zip_out
-        .start_file("words", zip::write::FileOptions::default())
+        .start_file("words", file_options.large_file(large(words)))
         .unwrap();
-    zip_out.write_all(&words).unwrap();
+    zip_out.write_all(words).unwrap();
     zip_out
-        .start_file("words.original", zip::write::FileOptions::default())
+        .start_file(
+            "words.original",
+            file_options.large_file(large(words_original)),
+        )
         .unwrap();
-    zip_out.write_all(words_original.as_bytes()).unwrap();
-
-    // Write all of the prefix entry files.
-    for (prefix, prefix_entry_list) in prefix_entries.iter() {
-        // Generate the html.
-        let mut html = String::new();
-        html.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?><html>");
-        for (key, definition, _) in prefix_entry_list.iter() {
-            html.push_str(&format!(
-                "<w><p><a name=\"{}\" />{}</p></w>",
-                key, definition
-            ));
-        }
-        html.push_str("</html>");
-
-        // Compress with gzip.
-        let mut gzhtml = Vec::new();
-        let mut gz = GzEncoder::new(html.as_bytes(), flate2::Compression::fast());
-        gz.read_to_end(&mut gzhtml).unwrap();
-
-        // Write the file to the zip file.
+    zip_out.write_all(words_original).unwrap();
+
+    // Write all of the prefix entry files.  These are already gzipped, so
+    // there's no point in compressing them again.
+    for (filename, data) in prefix_files.iter() {
         zip_out
             .start_file(
-                &format!("{}.html", prefix),
-                zip::write::FileOptions::default(),
+                filename.as_str(),
+                zip::write::FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored),
             )
             .unwrap();
-        zip_out.write_all(&gzhtml).unwrap();
+        zip_out.write_all(data).unwrap();
     }
 
     zip_out.finish().unwrap();
@@ -180,6 +347,302 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
     Ok(())
 }
 
+/// Generates the gzipped html of a single prefix entry file.
+fn prefix_file_data(
+    prefix_entry_list: &[(String, String, u32)],
+    gz_level: flate2::Compression,
+) -> Vec<u8> {
+    let mut html = String::new();
+    html.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?><html>");
+    for (key, definition, _) in prefix_entry_list.iter() {
+        html.push_str(&format!(
+            "<w><p><a name=\"{}\" />{}</p></w>",
+            key, definition
+        ));
+    }
+    html.push_str("</html>");
+
+    let mut gzhtml = Vec::new();
+    let mut gz = GzEncoder::new(html.as_bytes(), gz_level);
+    gz.read_to_end(&mut gzhtml).unwrap();
+
+    gzhtml
+}
+
+/// Reads the entries of an existing Kobo dictionary.
+///
+/// This works for dictionaries written by `write_dictionary()` as well as
+/// most other unencrypted dicthtml dictionaries, including some of the stock
+/// Kobo ones.  Entries that have the exact same definition are merged into
+/// a single entry with multiple keys.
+pub fn read_dictionary(path: &Path) -> std::io::Result<Vec<Entry>> {
+    lazy_static! {
+        static ref VARIANTS: Regex = Regex::new(r"(?s)<var>(.*?)</var>").unwrap();
+        static ref VARIANT: Regex = Regex::new(r#"<variant name="([^"]*)"\s*/?>"#).unwrap();
+    }
+
+    let mut zip_in = zip::ZipArchive::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+
+    // Key priorities, if available.  These are stored inverted in
+    // `words.original`, so we invert them back.
+    let priorities: HashMap<String, u32> = match zip_in.by_name("words.original") {
+        Ok(mut f) => {
+            let mut text = String::new();
+            f.read_to_string(&mut text)?;
+            let stored: Vec<(&str, u32)> = text
+                .lines()
+                .filter_map(|line| {
+                    let mut parts = line.split('\t');
+                    let key = parts.next()?;
+                    let priority = parts.next()?.trim().parse::<u32>().ok()?;
+                    Some((key, priority))
+                })
+                .collect();
+            let max_priority = stored.iter().fold(0u32, |a, b| a.max(b.1));
+            stored
+                .iter()
+                .map(|&(key, p)| (key.into(), max_priority - p))
+                .collect()
+        }
+        Err(_) => HashMap::new(),
+    };
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut definition_to_entry: HashMap<String, usize> = HashMap::new();
+    for i in 0..zip_in.len() {
+        let mut f = zip_in.by_index(i)?;
+        if !f.name().ends_with(".html") {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        let html = prefix_file_html(f.name(), data)?;
+
+        let mut keyless = 0;
+        for word in WORD.captures_iter(&html) {
+            let mut content = word[1].to_string();
+
+            // Collect the keys.
+            let mut keys = Vec::new();
+            if let Some(key) = KEY.captures(&content) {
+                keys.push(key[1].to_string());
+            }
+            if let Some(variants) = VARIANTS.captures(&content) {
+                for variant in VARIANT.captures_iter(&variants[1]) {
+                    keys.push(variant[1].to_string());
+                }
+            }
+            if keys.is_empty() {
+                keyless += 1;
+                continue;
+            }
+
+            // Extract the definition.
+            content = VARIANTS.replace_all(&content, "").into_owned();
+            content = KEY.replace(&content, "").into_owned();
+            let definition = match content.trim() {
+                c if c.starts_with("<p>") && c.ends_with("</p>") => c[3..(c.len() - 4)].to_string(),
+                c => c.to_string(),
+            };
+
+            let entry_idx = *definition_to_entry
+                .entry(definition.clone())
+                .or_insert_with(|| {
+                    entries.push(Entry {
+                        keys: Vec::new(),
+                        definition,
+                    });
+                    entries.len() - 1
+                });
+            for key in keys.drain(..) {
+                let priority = priorities.get(&key).copied().unwrap_or(0);
+                entries[entry_idx].keys.push((key, priority));
+            }
+        }
+        if keyless > 0 {
+            warnings::warn(
+                warnings::Category::KoboEntry,
+                &path.display().to_string(),
+                format!("{}: skipped {} entries without a key", f.name(), keyless),
+            );
+        }
+    }
+
+    for entry in entries.iter_mut() {
+        entry.keys.sort_by_key(|a| (a.1, a.0.len(), a.0.clone()));
+        entry.keys.dedup();
+    }
+
+    Ok(entries)
+}
+
+/// Decompresses the data of a prefix entry file, if needed, and checks that
+/// it's text.
+fn prefix_file_html(name: &str, mut data: Vec<u8>) -> std::io::Result<String> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+        data = decompressed;
+    }
+    String::from_utf8(data).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "\"{}\" in the Kobo dictionary isn't valid html (the dictionary may be encrypted)",
+                name
+            ),
+        )
+    })
+}
+
+/// What a Kobo e-reader shows when a string is selected.
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    pub key: String,              // The key that matched.
+    pub prefix_file: String,      // The prefix entry file it was found in.
+    pub definitions: Vec<String>, // Html, in the order they're shown.
+}
+
+/// Looks up `selection` in the Kobo dictionary at `path` the way a Kobo
+/// e-reader does, as far as it's known: the selection is lowercased, the
+/// prefix entry file for it is opened, and the entries whose key is an
+/// exact match are shown.  If there are none, the last character of the
+/// selection is dropped and the lookup is tried again.
+///
+/// Kobo e-readers don't deinflect anything themselves, so inflected forms
+/// only match through the inflection keys in the dictionary.
+///
+/// Returns `None` if no part of the selection matches.
+pub fn simulate_lookup(path: &Path, selection: &str) -> std::io::Result<Option<Lookup>> {
+    let mut zip_in = zip::ZipArchive::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+
+    // Prefix entry file name -> html.  Missing files are empty.
+    let mut prefix_files: HashMap<String, String> = HashMap::new();
+
+    let mut candidate: Vec<char> = selection.trim().to_lowercase().chars().collect();
+    while !candidate.is_empty() {
+        let key: String = candidate.iter().collect();
+        let prefix_file = format!("{}.html", dictionary_prefix(&key));
+        if !prefix_files.contains_key(&prefix_file) {
+            let html = match zip_in.by_name(&prefix_file) {
+                Ok(mut f) => {
+                    let mut data = Vec::new();
+                    f.read_to_end(&mut data)?;
+                    prefix_file_html(&prefix_file, data)?
+                }
+                Err(_) => String::new(),
+            };
+            prefix_files.insert(prefix_file.clone(), html);
+        }
+
+        let definitions: Vec<String> = WORD
+            .captures_iter(&prefix_files[&prefix_file])
+            .filter(|word| match KEY.captures(&word[1]) {
+                Some(word_key) => word_key[1] == key,
+                None => false,
+            })
+            .map(|word| KEY.replace(&word[1], "").trim().to_string())
+            .collect();
+        if !definitions.is_empty() {
+            return Ok(Some(Lookup {
+                key,
+                prefix_file,
+                definitions,
+            }));
+        }
+
+        candidate.pop();
+    }
+
+    Ok(None)
+}
+
+/// The size of one prefix entry file of a Kobo dictionary.
+#[derive(Debug, Clone)]
+pub struct PrefixFileStats {
+    pub name: String,
+    pub gzip_size: u64, // As stored in the dictionary.
+    pub html_size: u64, // Decompressed.
+    pub entries: usize,
+}
+
+/// Gathers the sizes of the prefix entry files of the Kobo dictionary at
+/// `path`, largest first.
+///
+/// Kobo e-readers decompress a whole prefix entry file for every lookup, so
+/// large ones are slow to open.  There's no way to split them up further:
+/// the e-reader works out the file name from the selected text itself (see
+/// `dictionary_prefix()`), so every key must be in the file for its prefix.
+pub fn prefix_file_stats(path: &Path) -> std::io::Result<Vec<PrefixFileStats>> {
+    let mut zip_in = zip::ZipArchive::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+
+    let mut stats = Vec::new();
+    for i in 0..zip_in.len() {
+        let mut f = zip_in.by_index(i)?;
+        if !f.name().ends_with(".html") {
+            continue;
+        }
+        let name = f.name().to_string();
+        let gzip_size = f.size();
+
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        let html = prefix_file_html(&name, data)?;
+        stats.push(PrefixFileStats {
+            name,
+            gzip_size,
+            html_size: html.len() as u64,
+            entries: WORD.find_iter(&html).count(),
+        });
+    }
+
+    stats.sort_by(|a, b| b.gzip_size.cmp(&a.gzip_size).then(a.name.cmp(&b.name)));
+    Ok(stats)
+}
+
+/// The language suffix of a Kobo dictionary file name, e.g. "-ja-en" for
+/// "dicthtml-ja-en.zip", which identifies the dictionary in a Kobo's
+/// database.  Returns None if `file_name` isn't a Kobo dictionary file
+/// name.
+pub fn dictionary_suffix(file_name: &str) -> Option<&str> {
+    file_name
+        .strip_prefix("dicthtml")?
+        .strip_suffix(".zip")
+        .filter(|s| !s.is_empty())
+}
+
+/// Generates the SQL that registers a dictionary in a Kobo's
+/// `KoboReader.sqlite`, which firmware versions without
+/// `.kobo/custom-dict/` support need before they show a dictionary.
+/// `file_name` is the dictionary's file name on the device, e.g.
+/// "dicthtml-ja-en.zip", `name` the name to show in the dictionary list,
+/// `size` its size in bytes, and `date` the day it was built as (year,
+/// month, day).
+///
+/// Returns None if `file_name` isn't a Kobo dictionary file name.
+pub fn registration_sql(
+    file_name: &str,
+    name: &str,
+    size: u64,
+    date: (i64, i64, i64),
+) -> Option<String> {
+    let suffix = dictionary_suffix(file_name)?;
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+
+    Some(format!(
+        "INSERT OR REPLACE INTO Dictionary \
+         (Suffix, Name, Installed, Size, LastModified, IsSynced) \
+         VALUES ({}, {}, 'true', {}, '{:04}-{:02}-{:02}T00:00:00Z', 'true');\n",
+        quote(suffix),
+        quote(name),
+        size,
+        date.0,
+        date.1,
+        date.2
+    ))
+}
+
 fn dictionary_prefix(key: &str) -> String {
     // See: https://pgaskin.net/dictutil/dicthtml/prefixes.html, which covers
     // the non-Japanese parts of this.
@@ -197,15 +660,15 @@ fn dictionary_prefix(key: &str) -> String {
     let ch = prefix[0] as u32;
 
     // Cyrillic and Japanese kana.
-    if (ch >= 0x0400 && ch <= 0x052f)
-        || (ch >= 0x2de0 && ch <= 0x2dff)
-        || (ch >= 0xa640 && ch <= 0xa69f)
-        || (ch >= 0x3040 && ch <= 0x30ff)
+    if (0x0400..=0x052f).contains(&ch)
+        || (0x2de0..=0x2dff).contains(&ch)
+        || (0xa640..=0xa69f).contains(&ch)
+        || (0x3040..=0x30ff).contains(&ch)
     {
         prefix.iter().collect()
     }
     // Basic Unicode plane Japanese Kanji / Chinese characters.
-    else if (ch >= 0x3400 && ch <= 0x4dbf) || (ch >= 0x4e00 && ch <= 0x9fff) {
+    else if (0x3400..=0x4dbf).contains(&ch) || (0x4e00..=0x9fff).contains(&ch) {
         prefix.iter().take(1).collect()
     }
     // Unicode letter class.
@@ -223,3 +686,70 @@ fn dictionary_prefix(key: &str) -> String {
         "11".into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn read_member<R: Read + Seek>(zip_in: &mut zip::ZipArchive<R>, name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        zip_in
+            .by_name(name)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn registration() {
+        assert_eq!(
+            registration_sql("dicthtml-ja-en.zip", "日本語 - English", 1234, (2024, 3, 9)),
+            Some(
+                "INSERT OR REPLACE INTO Dictionary \
+                 (Suffix, Name, Installed, Size, LastModified, IsSynced) \
+                 VALUES ('-ja-en', '日本語 - English', 'true', 1234, \
+                 '2024-03-09T00:00:00Z', 'true');\n"
+                    .into()
+            )
+        );
+        assert!(registration_sql("dicthtml-ja.zip", "It's", 1, (2024, 1, 1))
+            .unwrap()
+            .contains("'-ja', 'It''s'"));
+        assert_eq!(registration_sql("dicthtml.zip", "", 1, (2024, 1, 1)), None);
+        assert_eq!(registration_sql("ja-en.zip", "", 1, (2024, 1, 1)), None);
+        assert_eq!(
+            registration_sql("dicthtml-ja-en", "", 1, (2024, 1, 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn archive_past_the_zip_member_limit() {
+        let file_count = ZIP_MAX_MEMBERS + 5000;
+        let prefix_files: Vec<(String, Vec<u8>)> = (0..file_count)
+            .map(|i| (format!("{:05x}.html", i), i.to_string().into_bytes()))
+            .collect();
+
+        let mut archive = Cursor::new(Vec::new());
+        write_archive(
+            &mut archive,
+            b"trie",
+            b"word\t1\n",
+            &prefix_files,
+            Compression::default(),
+        )
+        .unwrap();
+
+        let mut zip_in = zip::ZipArchive::new(archive).unwrap();
+        assert_eq!(zip_in.len(), file_count + 2);
+        assert_eq!(read_member(&mut zip_in, "words"), b"trie");
+        assert_eq!(read_member(&mut zip_in, "words.original"), b"word\t1\n");
+        for i in [0, ZIP_MAX_MEMBERS - 2, ZIP_MAX_MEMBERS, file_count - 1].iter() {
+            let (name, data) = &prefix_files[*i];
+            assert_eq!(&read_member(&mut zip_in, name), data);
+        }
+    }
+}
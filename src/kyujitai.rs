@@ -0,0 +1,54 @@
+//! Traditional kanji forms (旧字体), for looking up words in texts printed
+//! before the 1946 character reform, e.g. 學校 for 学校 and 體 for 体.
+//!
+//! The mapping from the modern forms is in `dictionaries/kyujitai.tsv`.
+
+use std::collections::HashMap;
+
+const TABLE: &str = include_str!("../dictionaries/kyujitai.tsv");
+
+lazy_static! {
+    /// The traditional forms of each modern kanji that has any.
+    static ref KYUJITAI: HashMap<char, Vec<char>> = {
+        let mut table = HashMap::new();
+        for line in TABLE.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t').map(|f| f.chars().next().unwrap());
+            let modern = fields.next().unwrap();
+            table.insert(modern, fields.collect());
+        }
+        table
+    };
+}
+
+/// Returns the spellings of `text` with its modern kanji in their
+/// traditional forms.  All of the kanji are replaced at once, since texts
+/// don't mix the two forms.  Kanji with several traditional forms (弁 →
+/// 辨/瓣/辯) give a variant for each.  Returns nothing if `text` has no
+/// kanji with a traditional form.
+pub fn kyujitai_variants(text: &str) -> Vec<String> {
+    let alternatives: Vec<&[char]> = text
+        .chars()
+        .filter_map(|c| KYUJITAI.get(&c).map(|forms| forms.as_slice()))
+        .collect();
+    let count = alternatives
+        .iter()
+        .map(|forms| forms.len())
+        .max()
+        .unwrap_or(0);
+
+    // Like `kyukana::historical_variants()`, the nth variant uses the nth
+    // form of each kanji, or its last form if it has fewer.
+    (0..count)
+        .map(|n| {
+            text.chars()
+                .map(|c| match KYUJITAI.get(&c) {
+                    Some(forms) => forms[n.min(forms.len() - 1)],
+                    None => c,
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,146 @@
+//! Historical kana orthography (旧仮名遣い), for looking up words in older
+//! literature.
+//!
+//! Historical spellings can't be derived from modern ones exactly: modern
+//! い, for example, can be historical い, ひ or ゐ depending on the word.
+//! Instead, each modern spelling is mapped to a few likely historical ones
+//! by applying the common correspondences consistently across the word:
+//!
+//! - Long vowels: しょう → しやう/せう/せふ, きゅう → きう/きふ, こう →
+//!   こふ/かう/かふ.
+//! - Word-internal わいうえお → はひふへほ (かわ → かは, 思う → 思ふ).
+//! - いえお → ゐゑを (いる → ゐる, こえ → こゑ).
+
+use crate::kana::is_hiragana;
+
+/// The vowel rows of the hiragana syllables, by consonant.
+const ROWS: &[[char; 5]] = &[
+    ['か', 'き', 'く', 'け', 'こ'],
+    ['が', 'ぎ', 'ぐ', 'げ', 'ご'],
+    ['さ', 'し', 'す', 'せ', 'そ'],
+    ['ざ', 'じ', 'ず', 'ぜ', 'ぞ'],
+    ['た', 'ち', 'つ', 'て', 'と'],
+    ['だ', 'ぢ', 'づ', 'で', 'ど'],
+    ['な', 'に', 'ぬ', 'ね', 'の'],
+    ['は', 'ひ', 'ふ', 'へ', 'ほ'],
+    ['ば', 'び', 'ぶ', 'べ', 'ぼ'],
+    ['ぱ', 'ぴ', 'ぷ', 'ぺ', 'ぽ'],
+    ['ま', 'み', 'む', 'め', 'も'],
+    ['ら', 'り', 'る', 'れ', 'ろ'],
+];
+
+/// The (row, vowel index) of a hiragana syllable.
+fn row_of(ch: char) -> Option<(&'static [char; 5], usize)> {
+    ROWS.iter()
+        .find_map(|row| row.iter().position(|&c| c == ch).map(|i| (row, i)))
+}
+
+/// The historical alternatives of the modern kana at the start of `chars`,
+/// most likely first, along with how many characters they replace.
+/// `medial` is whether the kana is inside the word.
+fn alternatives(chars: &[char], medial: bool) -> Option<(usize, Vec<String>)> {
+    let next = |i: usize| chars.get(i).copied();
+
+    // E.g. しょう -> しやう, せう, せふ.
+    if let (Some(c), Some('ょ'), Some('う')) = (next(0), next(1), next(2)) {
+        if let Some((row, 1)) = row_of(c) {
+            return Some((
+                3,
+                vec![
+                    format!("{}やう", c),
+                    format!("{}う", row[3]),
+                    format!("{}ふ", row[3]),
+                ],
+            ));
+        }
+    }
+
+    // E.g. きゅう -> きう, きふ.
+    if let (Some(c), Some('ゅ'), Some('う')) = (next(0), next(1), next(2)) {
+        if let Some((_, 1)) = row_of(c) {
+            return Some((3, vec![format!("{}う", c), format!("{}ふ", c)]));
+        }
+    }
+
+    // E.g. こう -> こふ, かう, かふ.
+    if let (Some(c), Some('う')) = (next(0), next(1)) {
+        if let Some((row, 4)) = row_of(c) {
+            return Some((
+                2,
+                vec![
+                    format!("{}ふ", c),
+                    format!("{}う", row[0]),
+                    format!("{}ふ", row[0]),
+                ],
+            ));
+        }
+    }
+
+    let alts: &[&str] = match (next(0)?, medial) {
+        ('わ', true) => &["は"],
+        // Word-internal いえお stay as they are in many words, e.g. in the
+        // ending of 高い, so that's tried first.
+        ('い', true) => &["い", "ひ", "ゐ"],
+        ('う', true) => &["ふ"],
+        ('え', true) => &["え", "へ", "ゑ"],
+        ('お', true) => &["お", "ほ", "を"],
+        ('い', false) => &["い", "ゐ"],
+        ('え', false) => &["え", "ゑ"],
+        ('お', false) => &["お", "を"],
+        _ => return None,
+    };
+    Some((1, alts.iter().map(|a| a.to_string()).collect()))
+}
+
+/// Returns likely historical kana spellings of `text`.  Only the hiragana
+/// at the end of the word are respelled, so this works for both readings
+/// (ちょうちょう → てふてふ) and words with okurigana (思う → 思ふ).
+pub fn historical_variants(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let tail_start = chars
+        .iter()
+        .rposition(|&ch| !is_hiragana(ch))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let (head, tail) = chars.split_at(tail_start);
+
+    // Split the tail into spans of (text, historical alternatives).
+    let mut spans: Vec<(String, Vec<String>)> = Vec::new();
+    let mut i = 0;
+    while i < tail.len() {
+        let medial = !head.is_empty() || i > 0;
+        match alternatives(&tail[i..], medial) {
+            Some((len, alts)) => {
+                spans.push((tail[i..(i + len)].iter().collect(), alts));
+                i += len;
+            }
+            None => {
+                spans.push((tail[i].to_string(), Vec::new()));
+                i += 1;
+            }
+        }
+    }
+
+    // The nth variant uses the nth alternative of every span (or its
+    // last, if it has fewer), so that the correspondences are applied
+    // consistently.
+    let head: String = head.iter().collect();
+    let max_alts = spans.iter().map(|s| s.1.len()).max().unwrap_or(0);
+    let mut variants: Vec<String> = (0..max_alts)
+        .map(|n| {
+            let mut variant = head.clone();
+            for (modern, alts) in spans.iter() {
+                match alts.get(n).or(alts.last()) {
+                    Some(alt) => variant.push_str(alt),
+                    None => variant.push_str(modern),
+                }
+            }
+            variant
+        })
+        .collect();
+
+    variants.sort();
+    variants.dedup();
+    variants.retain(|v| v != text);
+    variants
+}
@@ -0,0 +1,43 @@
+//! The dictionary building pipeline, for use by tools other than the
+//! command line builder, e.g. to look words up in the merged data without
+//! writing a dictionary file.  See `dictionary::Dictionary`.
+
+#![allow(dead_code)]
+#![allow(clippy::large_enum_variant)]
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::type_complexity)]
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod anki;
+pub mod compounds;
+pub mod coverage;
+pub mod dictionary;
+pub mod encoding;
+pub mod generic_dict;
+pub mod gloss;
+pub mod intern;
+pub mod jmdict;
+pub mod jsonl;
+pub mod kana;
+pub mod kanjivg;
+pub mod kobo;
+pub mod kyujitai;
+pub mod kyukana;
+pub mod merged;
+pub mod observer;
+pub mod optimize;
+pub mod patch;
+pub mod pdic;
+pub mod pitch;
+pub mod preview;
+pub mod radicals;
+pub mod reverse;
+pub mod sanitize;
+pub mod slob;
+pub mod source;
+pub mod stardict;
+pub mod warnings;
+pub mod wiktionary;
+pub mod yomichan;
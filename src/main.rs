@@ -1,355 +1,1474 @@
 #![allow(dead_code)]
 
-#[macro_use]
-extern crate lazy_static;
+mod cli;
 
-use std::collections::HashMap;
-use std::convert::TryFrom;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::path::Path;
 
+use clap::{CommandFactory, Parser};
 use flate2::read::GzDecoder;
 use furigana_gen::FuriganaGenerator;
+use regex::Regex;
+use serde_json::{json, Value};
 
-mod generic_dict;
-mod jmdict;
-mod kobo;
-mod stardict;
-mod yomichan;
+use kobo_jp_dict::{
+    anki, compounds, coverage, encoding, generic_dict, gloss, intern, jmdict, jsonl, kana, kanjivg,
+    kobo, merged, observer, optimize, patch, pdic, pitch, preview, radicals, reverse, sanitize,
+    slob, source, stardict, warnings, wiktionary, yomichan,
+};
 
 use generic_dict::LangMode;
-use jmdict::WordEntry;
+use kana::hiragana_to_katakana;
+use source::DictionarySource;
 
 fn main() -> io::Result<()> {
-    let matches = clap::Command::new("Kobo Japanese Dictionary Builder")
-        .version(clap::crate_version!())
-        .arg(
-            clap::Arg::new("OUTPUT")
-                .help("The output filepath to write the new dictionary to.")
-                .required(true)
-                .index(1),
-        )
-        .arg(
-            clap::Arg::new("pitch_accent")
-                .short('p')
-                .long("pitch_accent")
-                .help("Path to a custom pitch accent file in .tsv format.  Will be used instead of the bundled pitch accent data.")
-                .value_name("PATH")
-                .takes_value(true),
-        )
-        .arg(
-            clap::Arg::new("yomichan_dict")
-                .short('y')
-                .long("yomichan")
-                .help("Path to a zipped Yomichan dictionary.  Will add either additional definitions to existing entries or completely new entries, depending the dictionary.")
-                .value_name("PATH")
-                .takes_value(true)
-                .multiple_occurrences(true),
-        )
-        .arg(
-            clap::Arg::new("katakana_pronunciation")
-                .short('k')
-                .long("katakana")
-                .help("Use katakana instead of hiragana for word pronunciation."),
-        )
-        .arg(
-            clap::Arg::new("use_move_terms")
-                .short('m')
-                .long("use_move_terms")
-                .help("Use the terms \"other-move\" and \"self-move\" instead of \"transitive\" and \"intransitive\".  The former is more accurate to how Japanese works, but the latter are more commonly known and used."),
-        )
-        .arg(
-            clap::Arg::new("use_japanese_terms")
-                .short('j')
-                .long("use_japanese_terms")
-                .help("Use the Japanese terms for \"verb\", \"transitive\", etc. instead of English in entry headers."),
-        )
-        .arg(
-            clap::Arg::new("generate_furigana")
-                .short('f')
-                .long("generate_furigana")
-                .help("Auto-generate furigana on native Japanese definitions."),
-        )
-        .arg(
-            clap::Arg::new("stardict_format")
-                .short('s')
-                .long("stardict_format")
-                .help("Generate a (zipped) StarDict dictionary instead of a Kobo dictionary."),
-        )
-        .get_matches();
-
-    // Output zip archive path.
-    let output_filename = matches.value_of("OUTPUT").unwrap();
-
-    let do_stardict_format = matches.is_present("stardict_format");
+    let cli = cli::Cli::parse();
 
-    //----------------------------------------------------------------
-    // Read in all the files.
+    // Print shell completions, if requested.
+    if let Some(cli::Command::Completions { shell }) = cli.command {
+        clap_complete::generate(
+            shell,
+            &mut cli::Cli::command(),
+            clap::crate_name!(),
+            &mut io::stdout(),
+        );
+        return Ok(());
+    }
 
-    println!("Extracting bundled data...");
+    // Inspect a dictionary, if requested.  This doesn't need any of the
+    // dictionary building below either.
+    if let Some(cli::Command::Inspect {
+        ref dictionary,
+        top,
+    }) = cli.command
+    {
+        let stats = kobo::prefix_file_stats(dictionary)?;
+        if stats.is_empty() {
+            println!("No prefix entry files.");
+            return Ok(());
+        }
 
-    // Parse the bundled JMDict XML data.
-    const JM_DATA: &[u8] = include_bytes!("../dictionaries/JMdict_e.xml.gz");
-    let jm_table = {
-        let mut jm_table: HashMap<(String, String), Vec<WordEntry>> = HashMap::new(); // (Kanji, Kana)
-        let parser = jmdict::Parser::from_reader(BufReader::new(GzDecoder::new(JM_DATA)));
-        for entry in parser {
-            let reading = strip_non_kana(&hiragana_to_katakana(&entry.readings[0].trim()));
-            let writing = if entry.writings.len() > 0 {
-                entry.writings[0].clone()
-            } else {
-                entry.readings[0].trim().into()
-            };
+        let total_gzip: u64 = stats.iter().map(|s| s.gzip_size).sum();
+        let total_entries: usize = stats.iter().map(|s| s.entries).sum();
+        let mut gzip_sizes: Vec<u64> = stats.iter().map(|s| s.gzip_size).collect();
+        gzip_sizes.sort_unstable();
+        println!("Prefix entry files: {}", stats.len());
+        println!("    Entries: {}", total_entries);
+        println!("    Total size: {} KiB", total_gzip / 1024);
+        println!(
+            "    Median size: {} KiB",
+            gzip_sizes[gzip_sizes.len() / 2] / 1024
+        );
+        println!("Largest prefix entry files:");
+        for s in stats.iter().take(top) {
+            println!(
+                "    {}: {} KiB ({} KiB uncompressed), {} entries, {:.1}% of the total",
+                s.name,
+                s.gzip_size / 1024,
+                s.html_size / 1024,
+                s.entries,
+                s.gzip_size as f64 * 100.0 / total_gzip.max(1) as f64
+            );
+        }
+        return Ok(());
+    }
+
+    // Text encodings of input files, by path or file name.
+    let encodings: Encodings = cli
+        .sources
+        .encoding
+        .iter()
+        .map(|value| {
+            let item = value.rsplit_once('=').and_then(|(path, label)| {
+                Some((path.trim().to_string(), encoding::for_label(label)?))
+            });
+            item.unwrap_or_else(|| {
+                eprintln!("Error: invalid value for encoding: \"{}\"", value);
+                std::process::exit(1);
+            })
+        })
+        .collect();
 
-            let e = jm_table.entry((writing, reading)).or_insert(Vec::new());
-            e.push(entry);
+    // Simulate lookups, if requested.  This doesn't need any of the
+    // dictionary building below.
+    if let Some(cli::Command::Simulate {
+        ref dictionary,
+        ref words,
+    }) = cli.command
+    {
+        for word in words.iter() {
+            println!("{}", word);
+            match kobo::simulate_lookup(dictionary, word)? {
+                Some(lookup) => {
+                    println!("    Matched key: {}", lookup.key);
+                    println!("    Prefix file: {}", lookup.prefix_file);
+                    for (i, definition) in lookup.definitions.iter().enumerate() {
+                        let text: String = sanitize::strip_tags(definition)
+                            .split_whitespace()
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        let mut excerpt: String = text.chars().take(80).collect();
+                        if excerpt.len() < text.len() {
+                            excerpt.push_str("...");
+                        }
+                        println!("    {}. {}", i + 1, excerpt);
+                    }
+                }
+                None => println!("    No entry."),
+            }
         }
-        jm_table
+        return Ok(());
+    }
+
+    // Words to export to Anki, if doing an Anki export.
+    let anki_export = if let Some(cli::Command::ExportAnki {
+        ref word_list,
+        ref output,
+    }) = cli.command
+    {
+        let words = read_word_list(word_list, &encodings)?;
+        Some((words, output.as_path()))
+    } else {
+        None
     };
-    println!("    Metadata entries: {}", jm_table.len());
 
-    // Open and parse the pitch accent data.
-    const PA_DATA: &[u8] = include_bytes!("../dictionaries/accents.tsv.gz");
-    let pa_table = {
-        let mut pa_table: HashMap<(String, String), Vec<u32>> = HashMap::new(); // (Kanji, Kana), Pitch Accent
+    // Words to preview, if doing a preview.
+    let preview = if let Some(cli::Command::Preview {
+        ref words,
+        sample,
+        ref output,
+    }) = cli.command
+    {
+        let words: Vec<String> = words.iter().map(|w| w.trim().into()).collect();
+        let sample_size = sample.unwrap_or(if words.is_empty() { 10 } else { 0 });
+        Some((words, sample_size, output.as_path()))
+    } else {
+        None
+    };
 
-        // Use the passed file if specified on the command line.  Otherwise use the bundled one.
-        let mut data = Vec::new();
-        if let Some(path) = matches.value_of("pitch_accent") {
-            File::open(path)?.read_to_end(&mut data)?;
+    // Books to measure the coverage of, if doing a coverage analysis.
+    let coverage = if let Some(cli::Command::Coverage {
+        ref corpus,
+        ref tokenizer,
+        top,
+    }) = cli.command
+    {
+        let tokenizer = match tokenizer.as_str() {
+            "longest-match" => coverage::Tokenizer::LongestMatch,
+            "mecab" => coverage::Tokenizer::Mecab,
+            _ => coverage::Tokenizer::Auto,
+        };
+        Some((corpus.as_path(), tokenizer, top))
+    } else {
+        None
+    };
+
+    // The book and output path, if building a dictionary for a book.
+    let book = if let Some(cli::Command::BuildForBook {
+        ref book,
+        ref output,
+    }) = cli.command
+    {
+        Some((book.as_path(), output.as_path()))
+    } else {
+        None
+    };
+
+    let log = Log {
+        json: cli.output_options.log_format == "json",
+    };
+
+    // Versioned output file, if doing an update: (output path, "latest"
+    // link path, source fingerprint, fingerprint file path).
+    let update = if let Some(cli::Command::Update {
+        ref output_dir,
+        ref name,
+        force,
+    }) = cli.command
+    {
+        let dir = output_dir.as_path();
+        let extension = if cli.output_options.format == "jsonl" {
+            "jsonl"
         } else {
-            GzDecoder::new(PA_DATA).read_to_end(&mut data)?;
+            "zip"
         };
-        let reader = std::io::Cursor::new(data);
-
-        for line in reader.lines() {
-            let line = line.unwrap_or_else(|_| "".into());
-            let parts: Vec<_> = line.split("\t").map(|a| a.trim()).collect();
-            assert_eq!(parts.len(), 3);
-            let accents: Vec<u32> = parts[2]
-                .split(|ch: char| !ch.is_digit(10))
-                .filter(|s| !s.is_empty())
-                .map(|a| a.parse::<u32>().unwrap())
-                .collect();
 
-            let (writing, reading) = if is_all_kana(parts[0]) && parts[1].is_empty() {
-                (parts[0].into(), hiragana_to_katakana(parts[0]))
-            } else {
-                (parts[0].into(), hiragana_to_katakana(parts[1]))
-            };
+        let fingerprint = source_fingerprint()?;
+        let fingerprint_path = dir.join(format!("{}.sources", name));
+        if !force
+            && std::fs::read_to_string(&fingerprint_path).ok().as_deref()
+                == Some(fingerprint.as_str())
+        {
+            log.step("Sources and options unchanged since the last update, skipping the build");
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(dir)?;
+        Some((
+            dir.join(format!("{}-{}.{}", name, date_stamp(), extension)),
+            dir.join(format!("{}-latest.{}", name, extension)),
+            fingerprint,
+            fingerprint_path,
+        ))
+    } else {
+        None
+    };
+
+    // Output file path.
+    let output_filename = if let Some((_, path)) = anki_export {
+        path
+    } else if let Some((_, _, path)) = preview {
+        path
+    } else if let Some((ref path, _, _, _)) = update {
+        path.as_path()
+    } else if let Some((_, path)) = book {
+        path
+    } else if coverage.is_some() {
+        // Nothing is written.
+        Path::new("")
+    } else {
+        cli.output.as_deref().unwrap()
+    };
 
-            pa_table.insert((writing, reading), accents);
+    let output_format = if anki_export.is_some() || preview.is_some() || coverage.is_some() {
+        // Anki cards and previews are rendered the same as the Kobo
+        // dictionary entries.
+        OutputFormat::Kobo
+    } else if cli.output_options.stardict_format {
+        OutputFormat::StarDict
+    } else {
+        match cli.output_options.format.as_str() {
+            "stardict" => OutputFormat::StarDict,
+            "koreader" => OutputFormat::KOReader,
+            "slob" => OutputFormat::Slob,
+            "jsonl" => OutputFormat::Jsonl,
+            _ => OutputFormat::Kobo,
         }
-        pa_table
     };
-    println!("    Pitch Accent entries: {}", pa_table.len());
 
-    println!("Loading dictionaries...");
+    // Check the registration of the Kobo dictionary before building it:
+    // (SQL file path, dictionary file name, dictionary title).
+    let registration = match cli.output_options.register_sql {
+        Some(ref path) => {
+            if cli.command.is_some() || output_format != OutputFormat::Kobo {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--register-sql only works when building a Kobo dictionary",
+                ));
+            }
+            let file_name: String = output_filename
+                .file_name()
+                .map(|name| name.to_string_lossy().into())
+                .unwrap_or_default();
+            let suffix = kobo::dictionary_suffix(&file_name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "can't register \"{}\", it isn't named like a Kobo dictionary (e.g. \"dicthtml-ja-en.zip\")",
+                        file_name
+                    ),
+                )
+            })?;
+            let title = match cli.output_options.title {
+                Some(ref title) => title.clone(),
+                None if suffix.ends_with("-en") => "日本語 - English".into(),
+                None => "日本語".into(),
+            };
+            Some((path.clone(), file_name, title))
+        }
+        None => None,
+    };
+
+    let mut timer = PhaseTimer::new(cli.output_options.profile_phases, log.json);
+
+    // Per-source entry limits.
+    let source_limits: HashMap<String, usize> = cli
+        .sources
+        .limit
+        .iter()
+        .map(|value| {
+            let limit = value
+                .rsplit_once('=')
+                .and_then(|(source, n)| Some((source.trim().into(), n.trim().parse().ok()?)));
+            limit.unwrap_or_else(|| {
+                eprintln!("Error: invalid value for limit: \"{}\"", value);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    let source_limit = |path: &str| -> usize {
+        // Windows paths may be given on any platform, so both kinds of
+        // separator are handled here rather than by `Path`.
+        let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        let stem = match file_name.rfind('.') {
+            Some(i) if i > 0 => &file_name[..i],
+            _ => file_name,
+        };
+        source_limits
+            .get(path)
+            .or_else(|| source_limits.get(stem))
+            .copied()
+            .unwrap_or(usize::MAX)
+    };
+
+    //----------------------------------------------------------------
+    // Read in all the files.
+
+    log.step("Loading dictionaries");
 
     // For auto-adding furigana to native Japanese dictionary entries.
-    let furigana_generator = if matches.is_present("generate_furigana") {
+    let furigana_generator = if cli.rendering.generate_furigana {
         Some(FuriganaGenerator::new(0, true, false))
     } else {
         None
     };
 
-    // Open and parse Yomichan dictionaries.
-    let mut yomi_term_table: HashMap<(String, String), Vec<yomichan::TermEntry>> = HashMap::new(); // (Kanji, Kana)
-    let mut yomi_name_table: HashMap<(String, String), Vec<yomichan::TermEntry>> = HashMap::new(); // (Kanji, Kana)
-    let mut yomi_kanji_table: HashMap<String, Vec<yomichan::KanjiEntry>> = HashMap::new(); // Kanji
-    if let Some(paths) = matches.values_of("yomichan_dict") {
-        for path in paths {
-            let mut entry_count = 0usize;
-
-            let (mut word_entries, mut name_entries, mut kanji_entries) =
-                yomichan::parse(std::path::Path::new(path), furigana_generator.as_ref()).unwrap();
-
-            // Put all of the word entries into the terms table.
-            entry_count += word_entries.len();
-            for entry in word_entries.drain(..) {
-                let reading = strip_non_kana(&hiragana_to_katakana(entry.reading.trim()));
-                let writing: String = entry.writing.trim().into();
-                if writing.is_empty() {
-                    let entry_list = yomi_term_table
-                        .entry((entry.reading.trim().into(), reading))
-                        .or_insert(Vec::new());
-                    entry_list.push(entry);
-                } else if reading.is_empty() && is_all_kana(&writing) {
-                    let derived_reading = hiragana_to_katakana(&writing);
-                    let entry_list = yomi_term_table
-                        .entry((writing, derived_reading))
-                        .or_insert(Vec::new());
-                    entry_list.push(entry);
-                } else {
-                    let entry_list = yomi_term_table
-                        .entry((writing, reading))
-                        .or_insert(Vec::new());
-                    entry_list.push(entry);
-                }
+    // Use the passed JMDict file if specified on the command line.
+    // Otherwise use the bundled one.
+    const JM_DATA: &[u8] = include_bytes!("../dictionaries/JMdict_e.xml.gz");
+    let mut sources: Vec<Box<dyn DictionarySource>> = Vec::new();
+    if let Some(ref path) = cli.sources.jmdict {
+        sources.push(jmdict::open_source(path)?);
+    } else {
+        sources.push(Box::new(jmdict::Source::new(
+            "jmdict",
+            BufReader::new(GzDecoder::new(JM_DATA)),
+        )));
+    }
+
+    // Use the passed pitch accent files if specified on the command line,
+    // in order so that later ones take precedence.  Otherwise use the
+    // bundled one.
+    const PA_DATA: &[u8] = include_bytes!("../dictionaries/accents.tsv.gz");
+    if !cli.sources.pitch_accent.is_empty() {
+        for path in cli.sources.pitch_accent.iter() {
+            let data = read_text_file(path, &encodings)?.into_bytes();
+            sources.push(Box::new(pitch::Source::new(&path.to_string_lossy(), data)));
+        }
+    } else {
+        let mut data = Vec::new();
+        GzDecoder::new(PA_DATA).read_to_end(&mut data)?;
+        sources.push(Box::new(pitch::Source::new("accents", data)));
+    }
+
+    // Kanji component files.
+    for path in cli.sources.kanji_components.iter() {
+        let data = read_text_file(path, &encodings)?.into_bytes();
+        sources.push(Box::new(radicals::Source::new(
+            &path.to_string_lossy(),
+            data,
+        )));
+    }
+
+    // Yomichan dictionaries.
+    for path in cli.sources.yomichan_dict.iter() {
+        sources.push(Box::new(yomichan::Source {
+            path: path.clone(),
+            furigana_generator: furigana_generator.as_ref(),
+            strip_images: cli.sources.strip_images,
+            group_by_pos: cli.rendering.group_by_pos,
+            strict: cli.sources.strict,
+        }));
+    }
+
+    // PDIC text dictionaries.
+    for path in cli.sources.pdic.iter() {
+        let data = read_text_file(path, &encodings)?.into_bytes();
+        sources.push(Box::new(pdic::Source::new(&path.to_string_lossy(), data)));
+    }
+
+    // Wiktionary dump.
+    if let Some(ref path) = cli.sources.wiktionary {
+        sources.push(wiktionary::open_source(path)?);
+    }
+
+    let mut tables = source::Tables::new();
+    tables.dedupe = match cli.sources.dedupe_yomichan.as_str() {
+        "replace" => source::Dedupe::Replace,
+        "append" => source::Dedupe::Append,
+        _ => source::Dedupe::Off,
+    };
+    for source in sources.drain(..) {
+        let name = source.name();
+        let count = tables
+            .load(source, source_limit(&name))
+            .unwrap_or_else(|e| {
+                eprintln!("Error: couldn't read \"{}\": {}", name, e);
+                std::process::exit(1);
+            });
+        log.count(&name, "entries", count);
+        timer.end_phase(&format!("{} parsing", name));
+    }
+    let source::Tables {
+        mut jm_table,
+        pa_table,
+        pa_pos_table,
+        phonetics_table,
+        mut yomi_term_table,
+        mut yomi_name_table,
+        mut yomi_kanji_table,
+        yomi_freq_table,
+        kanji_component_table,
+        kanji_decomposition_table,
+        ..
+    } = tables;
+
+    // Add the kanji component data to the kanji entries.
+    for (kanji, items) in yomi_kanji_table.iter_mut() {
+        for item in items.iter_mut() {
+            if let Some(components) = kanji_component_table.get(kanji) {
+                item.components = components.clone();
             }
+            if let Some(decomposition) = kanji_decomposition_table.get(kanji) {
+                item.decomposition = Some(decomposition.clone());
+            }
+        }
+    }
+    if let Some(ref dir) = cli.sources.kanjivg {
+        for (kanji, items) in yomi_kanji_table.iter_mut() {
+            let diagram = kanjivg::load_diagram(dir, kanji);
+            for item in items.iter_mut() {
+                item.stroke_order = diagram.clone();
+            }
+        }
+        timer.end_phase("Stroke order diagrams");
+    }
+
+    // Find the source data that doesn't match any JMDict word, if requested.
+    // Kobo dictionaries are only read later, so they're added to the report
+    // then.
+    let mut unmatched_report = String::new();
+    if cli.output_options.report_unmatched.is_some() {
+        let mut pitch: Vec<&(String, String)> = pa_table
+            .keys()
+            .filter(|key| !jm_table.contains_key(*key))
+            .collect();
+        pitch.sort_unstable();
+        unmatched_report.push_str(&format!("# Pitch accent rows: {}\n", pitch.len()));
+        for (writing, reading) in pitch.iter() {
+            unmatched_report.push_str(&format!("{}\t{}\n", writing, reading));
+        }
+
+        let mut terms: Vec<(&str, &str, &str)> = yomi_term_table
+            .iter()
+            .filter(|(key, _)| !jm_table.contains_key(*key))
+            .flat_map(|(key, items)| {
+                items
+                    .iter()
+                    .map(move |item| (&*item.dict_name, key.0.as_str(), key.1.as_str()))
+            })
+            .collect();
+        terms.sort_unstable();
+        terms.dedup();
+        unmatched_report.push_str(&format!("# Yomichan terms: {}\n", terms.len()));
+        for (dict_name, writing, reading) in terms.iter() {
+            unmatched_report.push_str(&format!("{}\t{}\t{}\n", dict_name, writing, reading));
+        }
+
+        log.count("", "Unmatched pitch accent rows", pitch.len());
+        log.count("", "Unmatched Yomichan terms", terms.len());
+    }
 
-            // Put all of the name entries into the names table.
-            entry_count += name_entries.len();
-            for entry in name_entries.drain(..) {
-                let reading = strip_non_kana(&hiragana_to_katakana(entry.reading.trim()));
-                let writing: String = entry.writing.trim().into();
-                if writing.is_empty() {
-                    let entry_list = yomi_name_table
-                        .entry((entry.reading.trim().into(), reading))
-                        .or_insert(Vec::new());
-                    entry_list.push(entry);
-                } else {
-                    let entry_list = yomi_name_table
-                        .entry((writing, reading))
-                        .or_insert(Vec::new());
-                    entry_list.push(entry);
+    // Use frequency data from the Yomichan dictionaries to improve the word
+    // priorities.  JMDict's priorities are roughly in units of frequency
+    // rank (e.g. "nf02" is ranks 500-1000), so ranks can be used as-is.
+    if !yomi_freq_table.is_empty() {
+        for (key, items) in jm_table.iter_mut() {
+            let rank = yomi_freq_table
+                .get(key)
+                .or_else(|| yomi_freq_table.get(&(key.0.clone(), String::new())));
+            if let Some(&rank) = rank {
+                for item in items.iter_mut() {
+                    item.priority = item.priority.min(rank);
                 }
             }
+        }
+    }
+
+    // Restrict to the given vocabulary, if requested.
+    //
+    // Words are compared in katakana, so that readings match regardless of
+    // kana type.
+    let read_word_set = |path: &Path| -> io::Result<HashSet<String>> {
+        Ok(read_word_list(path, &encodings)?
+            .iter()
+            .map(|w| hiragana_to_katakana(w))
+            .collect())
+    };
+    let include_words = cli
+        .sources
+        .include_words
+        .as_deref()
+        .map(read_word_set)
+        .transpose()?;
+    if let Some(ref include_words) = include_words {
+        let keep = |forms: &[&String]| -> bool {
+            forms
+                .iter()
+                .any(|f| include_words.contains(&hiragana_to_katakana(f)))
+        };
+
+        jm_table.retain(|_, items| {
+            items.retain(|e| {
+                let forms: Vec<_> = e.writings.iter().chain(e.readings.iter()).collect();
+                keep(&forms)
+            });
+            !items.is_empty()
+        });
+        yomi_name_table.retain(|(writing, reading), _| keep(&[writing, reading]));
+        yomi_kanji_table.retain(|kanji, _| keep(&[kanji]));
+    }
+
+    // Checked here so that an invalid pattern is reported before merging.
+    let exclude_pattern = cli.sources.exclude_dict_pattern.as_deref().map(|pattern| {
+        Regex::new(pattern).unwrap_or_else(|e| {
+            eprintln!("Error: invalid exclusion pattern: {}", e);
+            std::process::exit(1);
+        })
+    });
+
+    if let Some(ref types) = cli.sources.name_types {
+        // Given names are usually tagged with their gender instead.
+        const NAME_TYPE_ALIASES: &[(&str, &[&str])] = &[("given", &["masc", "fem"])];
 
-            // Put all of the kanji entries into the kanji table.
-            entry_count += kanji_entries.len();
-            for entry in kanji_entries.drain(..) {
-                let entry_list = yomi_kanji_table
-                    .entry(entry.kanji.clone())
-                    .or_insert(Vec::new());
-                entry_list.push(entry);
+        let mut types: Vec<&str> = types
+            .split(',')
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .collect();
+        for &(name_type, aliases) in NAME_TYPE_ALIASES.iter() {
+            if types.contains(&name_type) {
+                types.extend_from_slice(aliases);
             }
+        }
 
-            println!("    {} entries: {}", path, entry_count);
+        yomi_name_table.retain(|_, items| {
+            items.retain(|e| e.tags.iter().any(|tag| types.contains(&&**tag)));
+            !items.is_empty()
+        });
+    }
+
+    timer.end_phase("Filtering");
+
+    // Per-source definition limits, compared the same way as the renames
+    // below.
+    let max_senses: HashMap<String, usize> = cli
+        .sources
+        .max_senses
+        .iter()
+        .map(|value| {
+            let limit = value.rsplit_once('=').and_then(|(source, n)| {
+                Some((source.trim().to_lowercase(), n.trim().parse().ok()?))
+            });
+            limit.unwrap_or_else(|| {
+                eprintln!("Error: invalid value for max-senses: \"{}\"", value);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    if !max_senses.is_empty() {
+        for items in yomi_term_table.values_mut() {
+            items.retain_mut(|item| match max_senses.get(&*item.dict_name) {
+                Some(&max) => {
+                    let mut count = 0;
+                    item.definitions.retain(&mut |_| {
+                        count += 1;
+                        count <= max
+                    })
+                }
+                None => true,
+            });
+        }
+    }
+
+    // Rename source dictionaries.  Names are compared the same way
+    // dictionary titles are normalized when loading (see
+    // `yomichan::parse()`).
+    let dict_renames: HashMap<String, std::sync::Arc<str>> = cli
+        .sources
+        .rename_dict
+        .iter()
+        .map(|value| {
+            let rename = value
+                .split_once('=')
+                .map(|(from, to)| (from.trim().to_lowercase(), intern::intern(to.trim())));
+            rename.unwrap_or_else(|| {
+                eprintln!("Error: invalid value for rename-dict: \"{}\"", value);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    if !dict_renames.is_empty() {
+        for item in yomi_term_table
+            .values_mut()
+            .chain(yomi_name_table.values_mut())
+            .flatten()
+        {
+            if let Some(name) = dict_renames.get(&*item.dict_name) {
+                item.dict_name = name.clone();
+            }
+        }
+        for item in yomi_kanji_table.values_mut().flatten() {
+            if let Some(name) = dict_renames.get(&*item.dict_name) {
+                item.dict_name = name.clone();
+            }
         }
     }
 
     //----------------------------------------------------------------
     // Generate the new dictionary entries.
-    println!("Generating dictionary entries...");
-    let entries = generic_dict::generate_entries(
-        &yomi_term_table,
-        &yomi_name_table,
-        &yomi_kanji_table,
-        &jm_table,
-        &pa_table,
-        generic_dict::EntrySettings {
-            lang_mode: if matches.is_present("use_japanese_terms") {
-                LangMode::Japanese
-            } else if matches.is_present("use_move_terms") {
-                LangMode::EnglishAlt
-            } else {
-                LangMode::English
-            },
-            use_katakana_pronunciation: matches.is_present("katakana_pronunciation"),
+    log.step("Generating dictionary entries");
+    let entry_settings = generic_dict::EntrySettings {
+        lang_mode: if cli.rendering.use_japanese_terms {
+            LangMode::Japanese
+        } else if cli.rendering.use_move_terms {
+            LangMode::EnglishAlt
+        } else {
+            LangMode::English
+        },
+        use_katakana_pronunciation: cli.rendering.katakana_pronunciation,
+        phonetic_detail: cli.rendering.phonetic_detail,
+
+        // Not needed for plain StarDict, since most StarDict readers
+        // have built-in inflection handling.  For KOReader they're
+        // written as synonyms instead.
+        generate_inflection_keys: output_format != OutputFormat::StarDict,
+        conjugation_level: match cli.keys.conjugation_level.as_str() {
+            "minimal" => generic_dict::ConjugationLevel::Minimal,
+            "extensive" => generic_dict::ConjugationLevel::Extensive,
+            _ => generic_dict::ConjugationLevel::Standard,
+        },
 
-            // Not needed for StarDict on KOReader, which has build-in
-            // inflection handling.
-            generate_inflection_keys: !do_stardict_format,
+        katakana_keys: !cli.keys.no_katakana_keys,
+        loanword_variants: cli.keys.loanword_variants,
+        old_text_variants: cli.keys.old_text_variants,
+        historical_kana: cli.keys.historical_kana,
+        kyujitai_keys: cli.keys.kyujitai_keys,
+        max_keys: cli.keys.max_keys,
+        onomatopoeia: cli.keys.onomatopoeia,
+        collapse_sections: cli.rendering.collapse_sections,
+        text_scale: match cli.rendering.text_scale.as_str() {
+            "compact" => generic_dict::TextScale::Compact,
+            "large" => generic_dict::TextScale::Large,
+            _ => generic_dict::TextScale::Normal,
+        },
 
-            add_separators: !do_stardict_format,
+        priority_weights: {
+            let defaults = generic_dict::PriorityWeights::default();
+            generic_dict::PriorityWeights {
+                kanji: cli.keys.weight_kanji.unwrap_or(defaults.kanji),
+                name: cli.keys.weight_name.unwrap_or(defaults.name),
+                common_divisor: cli.keys.weight_common.unwrap_or(defaults.common_divisor),
+                kana_preferred_divisor: cli
+                    .keys
+                    .weight_kana_preferred
+                    .unwrap_or(defaults.kana_preferred_divisor),
+                verb_divisor: cli.keys.weight_verb.unwrap_or(defaults.verb_divisor),
+                i_adjective_divisor: cli
+                    .keys
+                    .weight_i_adjective
+                    .unwrap_or(defaults.i_adjective_divisor),
+                inflection_penalty: cli
+                    .keys
+                    .weight_inflection
+                    .unwrap_or(defaults.inflection_penalty),
+                irregular_penalty: cli
+                    .keys
+                    .weight_irregular
+                    .unwrap_or(defaults.irregular_penalty),
+            }
         },
-    );
+
+        irregular_forms: match cli.keys.irregular_forms.as_str() {
+            "demote" => generic_dict::IrregularForms::Demote,
+            "exclude" => generic_dict::IrregularForms::Exclude,
+            _ => generic_dict::IrregularForms::Keep,
+        },
+        tag_style: match cli.rendering.tag_style.as_deref() {
+            Some("code") => Some(generic_dict::TagStyle::Code),
+            Some("short") => Some(generic_dict::TagStyle::Short),
+            Some("long") => Some(generic_dict::TagStyle::Long),
+            _ => None,
+        },
+
+        show_loan_sources: cli.rendering.show_lsource,
+        show_conjugations: cli.rendering.show_conjugations,
+        conjugation_accents: cli.rendering.conjugation_accents,
+        kana_learner_keys: cli.keys.kana_keys_only,
+        furigana_headers: cli.rendering.furigana_headers,
+        mark_affixes: cli.rendering.mark_affixes,
+        kanji_examples: cli.rendering.kanji_examples,
+        max_name_senses: cli.rendering.max_name_senses,
+
+        max_header_writings: cli.rendering.max_header_writings,
+        extra_writings: match cli.rendering.extra_writings.as_str() {
+            "hide" => generic_dict::ExtraWritings::Hide,
+            _ => generic_dict::ExtraWritings::OtherForms,
+        },
+        compact_headers: cli.rendering.compact_popup,
+        separator: match cli.rendering.entry_separator.as_deref() {
+            _ if output_format != OutputFormat::Kobo => generic_dict::Separator::None,
+            None if cli.rendering.compact_popup => generic_dict::Separator::None,
+            Some("blank") => generic_dict::Separator::BlankLine,
+            Some("none") => generic_dict::Separator::None,
+            _ => generic_dict::Separator::Rule,
+        },
+    };
+    let mut merged_entries = merged::merge_entries(
+        yomi_term_table,
+        yomi_name_table,
+        yomi_kanji_table,
+        jm_table,
+        pa_table,
+        pa_pos_table,
+        phonetics_table,
+        entry_settings,
+    )?;
+    if let Some(ref path) = cli.sources.patch {
+        let patches = patch::read_patches(path)?;
+        let unused = patch::apply_patches(&mut merged_entries, &patches, entry_settings);
+        log.count("", "Patches", patches.len());
+        if unused > 0 {
+            log.count("", "Patches matching no entry", unused);
+        }
+    }
+    if let Some(ref rules) = cli.sources.normalize_glosses {
+        let rules = gloss::GlossRule::parse_list(rules).unwrap_or_else(|| {
+            eprintln!("Error: invalid value for normalize_glosses: \"{}\"", rules);
+            std::process::exit(1);
+        });
+        gloss::normalize_entries(&mut merged_entries, &rules);
+    }
+    if let Some(threshold) = cli.sources.dedupe_similar {
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            eprintln!(
+                "Error: invalid value for dedupe-similar: \"{}\" (must be over 0 and at most 1)",
+                threshold
+            );
+            std::process::exit(1);
+        }
+        let removed = merged::remove_similar_senses(&mut merged_entries, threshold);
+        log.count("", "Repeated definitions removed", removed);
+    }
+    if let Some(max_rank) = cli.keys.affix_keys {
+        merged::add_affix_keys(
+            &mut merged_entries,
+            &yomi_freq_table,
+            max_rank,
+            entry_settings,
+        );
+    }
+
+    // Add previously dumped entries.
+    for path in cli.sources.from_jsonl.iter() {
+        let name = path.to_string_lossy();
+        let mut jsonl_entries = jsonl::read_entries(path)?;
+        jsonl_entries.truncate(source_limit(&name));
+        log.count(&name, "entries", jsonl_entries.len());
+        merged_entries.extend(jsonl_entries);
+    }
+
+    // Remove the excluded words and the definitions matching the exclusion
+    // pattern, if requested.  This is done after merging so that a word
+    // can't come back through another source.
+    if let Some(ref path) = cli.sources.exclude_words {
+        let words = read_word_set(path)?;
+        let removed = merged::remove_words(&mut merged_entries, &words);
+        log.count("", "Excluded entries", removed);
+    }
+    if let Some(ref regex) = exclude_pattern {
+        let removed = merged::remove_definitions(&mut merged_entries, |text| regex.is_match(text));
+        log.count("", "Entries left without definitions", removed);
+    }
+
+    // Keep only the entries the book needs, if building for a book.
+    if let Some((path, _)) = book {
+        let texts = coverage::read_corpus(path)?;
+        if texts.is_empty() {
+            eprintln!(
+                "Error: \"{}\" isn't an EPUB or text file.",
+                path.to_string_lossy()
+            );
+            std::process::exit(1);
+        }
+        let keys: HashSet<&str> = merged_entries
+            .iter()
+            .flat_map(|e| e.keys.iter().map(|k| k.0.as_str()))
+            .collect();
+        let vocabulary = coverage::vocabulary(&texts, &keys);
+        merged_entries.retain(|e| e.keys.iter().any(|k| vocabulary.contains(&k.0)));
+        log.count("", "Entries for the book", merged_entries.len());
+        timer.end_phase("Book vocabulary");
+    }
+
+    if cli.output_options.key_stats {
+        let stats = merged::key_stats(&merged_entries);
+        let round = |average: f64| (average * 100.0).round() / 100.0;
+        log.count("", "Keys", stats.total);
+        log.count("", "Average keys per entry", round(stats.average));
+        for kind in stats.kinds.iter() {
+            log.count(&kind.kind, "entries", kind.entries);
+            log.count(&kind.kind, "keys", kind.keys);
+            log.count(&kind.kind, "average keys per entry", round(kind.average));
+        }
+        let largest: Vec<String> = stats
+            .largest
+            .iter()
+            .map(|(key, count)| format!("{} ({})", key, count))
+            .collect();
+        log.list("Entries with the most keys", &largest);
+    }
+
+    // Write the merged entries instead of a dictionary, if requested.
+    if output_format == OutputFormat::Jsonl {
+        log.step("Writing merged entries to disk");
+        jsonl::write_entries(&merged_entries, output_filename)?;
+        if let Some(ref path) = cli.output_options.report_unmatched {
+            File::create(path)?.write_all(unmatched_report.as_bytes())?;
+        }
+        if let Some((ref path, ref latest, ref fingerprint, ref fingerprint_path)) = update {
+            finish_update(path, latest, fingerprint, fingerprint_path)?;
+        }
+        timer.end_phase("Writing");
+        report_warnings(&cli)?;
+        timer.print_total();
+        return Ok(());
+    }
+
+    let mut entries = generic_dict::generate_entries(&merged_entries, entry_settings)?;
+
+    // Headwords of the generated entries, for the key dump.  Entries added
+    // after these (breakdowns, Kobo dictionary entries) use their first key.
+    let headwords: Vec<String> = if cli.output_options.dump_keys.is_some() {
+        merged_entries.iter().map(|e| e.headword().into()).collect()
+    } else {
+        Vec::new()
+    };
+
+    // Add the user's notes to the entries of the words they're for.
+    if let Some(ref path) = cli.sources.notes {
+        let notes = read_notes(path, &encodings)?;
+        let mut noted = 0;
+        for (entry, merged_entry) in entries.iter_mut().zip(merged_entries.iter()) {
+            let word = match merged_entry.kind {
+                merged::MergedKind::Term { ref word, .. } => word,
+                _ => continue,
+            };
+            let readings: Vec<String> = word
+                .readings
+                .iter()
+                .map(|r| hiragana_to_katakana(r))
+                .collect();
+            let mut forms: Vec<&String> =
+                word.writings.iter().chain(word.readings.iter()).collect();
+            forms.sort_unstable();
+            forms.dedup();
+            let mut found = false;
+            for form in forms {
+                for (reading, note) in notes.get(form).into_iter().flatten() {
+                    if reading.is_empty() || readings.contains(reading) {
+                        entry.definition.push_str(&format!("<p>{}</p>", note));
+                        found = true;
+                    }
+                }
+            }
+            if found {
+                noted += 1;
+            }
+        }
+        log.count("", "Entries with notes", noted);
+    }
+
+    if let Some(ref path) = cli.sources.compound_corpus {
+        let counts = compounds::count_compounds(path)?;
+        let breakdowns =
+            compounds::breakdown_entries(&entries, &counts, cli.sources.compound_min_count);
+        log.count("", "Compound breakdown entries", breakdowns.len());
+        entries.extend(breakdowns);
+    }
+
+    if cli.output_options.build_info {
+        entries.push(build_info_entry()?);
+    }
+
+    timer.end_phase("Entry generation");
+
+    // Add the entries of existing Kobo dictionaries.
+    if !cli.sources.add_kobo_dict.is_empty() {
+        // Keys of the generated entries, to find the Kobo entries that
+        // don't match any word.
+        let generated_keys: HashSet<String> = if cli.output_options.report_unmatched.is_some() {
+            entries
+                .iter()
+                .flat_map(|e| e.keys.iter().map(|k| k.0.clone()))
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        let mut unmatched: Vec<String> = Vec::new();
+        for path in cli.sources.add_kobo_dict.iter() {
+            let name = path.to_string_lossy();
+            let mut kobo_entries = kobo::read_dictionary(path)?;
+            kobo_entries.truncate(source_limit(&name));
+            log.count(&name, "entries", kobo_entries.len());
+            if cli.output_options.report_unmatched.is_some() {
+                for entry in kobo_entries.iter() {
+                    if !entry.keys.iter().any(|k| generated_keys.contains(&k.0)) {
+                        unmatched.push(format!("{}\t{}", name, entry.keys[0].0));
+                    }
+                }
+            }
+            entries.extend(kobo_entries);
+        }
+        if cli.output_options.report_unmatched.is_some() {
+            unmatched_report.push_str(&format!("# Kobo dictionary entries: {}\n", unmatched.len()));
+            for line in unmatched.iter() {
+                unmatched_report.push_str(line);
+                unmatched_report.push('\n');
+            }
+            log.count("", "Unmatched Kobo dictionary entries", unmatched.len());
+        }
+        timer.end_phase("Kobo dictionary reading");
+    }
+    if let Some(ref path) = cli.output_options.report_unmatched {
+        File::create(path)?.write_all(unmatched_report.as_bytes())?;
+    }
+
+    if let Some(limit) = cli.output_options.limit_entries {
+        entries.truncate(limit);
+    }
+
+    if let Some(max_keys) = cli.keys.max_keys {
+        let removed = generic_dict::cap_keys(&mut entries, max_keys);
+        log.count("", "Lookup keys removed", removed);
+    }
+
+    if let Some(ref path) = cli.output_options.dump_keys {
+        let mut out = io::BufWriter::new(File::create(path)?);
+        out.write_all(b"key,priority,headword\n")?;
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.keys.is_empty() {
+                continue;
+            }
+            let headword = headwords.get(i).unwrap_or(&entry.keys[0].0);
+            for (key, priority) in entry.keys.iter() {
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    csv_field(key),
+                    priority,
+                    csv_field(headword)
+                )?;
+            }
+        }
+        out.flush()?;
+    }
+
+    // Deal with media elements, which e-readers can't play.
+    let media_policy = match cli.rendering.media_policy.as_str() {
+        "keep-text" => sanitize::MediaPolicy::KeepText,
+        "footnote" => sanitize::MediaPolicy::Footnote,
+        _ => sanitize::MediaPolicy::Strip,
+    };
+    for entry in entries.iter_mut() {
+        entry.definition = sanitize::handle_media(&entry.definition, media_policy);
+    }
+
+    // Remove colors that don't work in the chosen theme.
+    let theme = match cli.rendering.theme.as_str() {
+        "dark" => sanitize::Theme::Dark,
+        "auto" => sanitize::Theme::Auto,
+        _ => sanitize::Theme::Light,
+    };
+    for entry in entries.iter_mut() {
+        entry.definition = sanitize::apply_theme(&entry.definition, theme);
+    }
+
+    // Clean up any html that e-readers might choke on.
+    let mut sanitize_report = String::new();
+    for (i, entry) in entries.iter_mut().enumerate() {
+        if i % 10_000 == 0 {
+            observer::check_cancelled()?;
+        }
+        let (html, changes) = sanitize::sanitize_html(&entry.definition);
+        if !changes.is_empty() {
+            sanitize_report.push_str(&format!(
+                "{}\t{}\n",
+                entry.keys[0].0,
+                sanitize::summarize_changes(&changes)
+            ));
+        }
+        entry.definition = html;
+    }
+    if let Some(ref path) = cli.output_options.report_sanitized {
+        File::create(path)?.write_all(sanitize_report.as_bytes())?;
+    }
+    timer.end_phase("Html cleanup");
+
+    //----------------------------------------------------------------
+    // Write the Anki export instead of a dictionary, if requested.
+    if let Some((words, _)) = anki_export {
+        log.step("Writing Anki cards to disk");
+        let missing = anki::write_tsv(&entries, &words, output_filename)?;
+        log.count("", "Cards", words.len() - missing.len());
+        if !missing.is_empty() {
+            log.list("Words with no entry", &missing);
+        }
+        timer.end_phase("Writing");
+        report_warnings(&cli)?;
+        timer.print_total();
+        return Ok(());
+    }
+
+    //----------------------------------------------------------------
+    // Write the preview instead of a dictionary, if requested.
+    if let Some((words, sample_size, _)) = preview {
+        log.step("Writing preview to disk");
+        let missing = preview::write_html(&entries, &words, sample_size, output_filename)?;
+        if !missing.is_empty() {
+            log.list("Words with no entry", &missing);
+        }
+        timer.end_phase("Writing");
+        report_warnings(&cli)?;
+        timer.print_total();
+        return Ok(());
+    }
+
+    //----------------------------------------------------------------
+    // Report the coverage of the books instead of writing a dictionary, if
+    // requested.
+    if let Some((corpus, tokenizer, top)) = coverage {
+        log.step("Measuring coverage");
+        let texts = coverage::read_corpus(corpus)?;
+        let result = coverage::measure(&entries, &texts, tokenizer).unwrap_or_else(|e| {
+            eprintln!("Error: couldn't run \"mecab\": {}", e);
+            std::process::exit(1);
+        });
+        timer.end_phase("Coverage");
+
+        println!("Books: {}", texts.len());
+        println!("Words: {}", result.tokens);
+        println!(
+            "Words with an entry: {} ({:.1}%)",
+            result.resolved,
+            result.resolved as f64 * 100.0 / result.tokens.max(1) as f64
+        );
+        if !result.misses.is_empty() {
+            println!("Most frequent words without an entry:");
+            for (word, count) in result.misses.iter().take(top) {
+                println!("    {}\t{}", word, count);
+            }
+        }
+        report_warnings(&cli)?;
+        timer.print_total();
+        return Ok(());
+    }
+
+    // Shrink the html, if requested.
+    let mut css = None;
+    if cli.output_options.optimize_size {
+        log.step("Optimizing size");
+        let size_before: usize = entries.iter().map(|e| e.definition.len()).sum();
+        for (i, entry) in entries.iter_mut().enumerate() {
+            if i % 10_000 == 0 {
+                observer::check_cancelled()?;
+            }
+            entry.definition = optimize::minify_html(&entry.definition);
+        }
+        if output_format != OutputFormat::Kobo {
+            css = Some(optimize::factor_styles(&mut entries));
+        }
+        let size_after = entries.iter().map(|e| e.definition.len()).sum::<usize>()
+            + css.as_ref().map_or(0, |css| css.len());
+        log.count("", "Html bytes before", size_before);
+        log.count("", "Html bytes after", size_after);
+        log.count(
+            "",
+            "Html bytes saved",
+            size_before.saturating_sub(size_after),
+        );
+        timer.end_phase("Size optimization");
+    }
 
     //----------------------------------------------------------------
     // Write the new dictionary file.
-    if do_stardict_format {
-        println!("Writing StarDict dictionary to disk...");
-        stardict::write_dictionary(&entries, std::path::Path::new(output_filename))?;
-    } else {
-        println!("Writing Kobo dictionary to disk...");
-        kobo::write_dictionary(&entries, std::path::Path::new(output_filename))?;
+    match output_format {
+        OutputFormat::Kobo => {
+            log.step("Writing Kobo dictionary to disk");
+            kobo::write_dictionary(
+                &entries,
+                output_filename,
+                cli.output_options.compression.unwrap_or_default(),
+            )?;
+            if let Some((ref path, ref file_name, ref title)) = registration {
+                let size = std::fs::metadata(output_filename)?.len();
+                // The file name was checked before building.
+                let sql = kobo::registration_sql(file_name, title, size, today()).unwrap();
+                File::create(path)?.write_all(sql.as_bytes())?;
+            }
+        }
+        OutputFormat::StarDict => {
+            log.step("Writing StarDict dictionary to disk");
+            stardict::write_dictionary(&entries, output_filename, false, css.as_deref())?;
+        }
+        OutputFormat::KOReader => {
+            log.step("Writing KOReader StarDict dictionary to disk");
+            stardict::write_dictionary(&entries, output_filename, true, css.as_deref())?;
+        }
+        OutputFormat::Slob => {
+            log.step("Writing slob dictionary to disk");
+            slob::write_dictionary(&entries, output_filename, css.as_deref())?;
+        }
+        OutputFormat::Jsonl => unreachable!(), // Written above.
     }
 
-    return Ok(());
+    if let Some(ref path) = cli.output_options.reverse_output {
+        log.step("Writing reverse dictionary to disk");
+        let mut reverse_entries = reverse::generate_entries(&merged_entries);
+        for entry in reverse_entries.iter_mut() {
+            entry.definition = sanitize::sanitize_html(&entry.definition).0;
+        }
+        log.count("", "Entries", reverse_entries.len());
+        kobo::write_dictionary(
+            &reverse_entries,
+            path,
+            cli.output_options.compression.unwrap_or_default(),
+        )?;
+    }
+    if let Some((ref path, ref latest, ref fingerprint, ref fingerprint_path)) = update {
+        finish_update(path, latest, fingerprint, fingerprint_path)?;
+    }
+    timer.end_phase("Writing");
+    report_warnings(&cli)?;
+    timer.print_total();
+
+    Ok(())
 }
 
-/// Panics if the bytes aren't utf8.
-fn bytes_to_string(bytes: &[u8]) -> String {
-    std::str::from_utf8(bytes).unwrap().into()
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum OutputFormat {
+    Kobo,
+    StarDict,
+    KOReader, // StarDict with inflected forms as synonyms.
+    Slob,
+    Jsonl, // The merged entries, before rendering.
 }
 
-/// Panics if the bytes aren't utf8.
-fn bytes_to_str(bytes: &[u8]) -> &str {
-    std::str::from_utf8(bytes).unwrap()
+/// Prints the time taken by each phase of the build, when enabled.  With
+/// "--log-format json" the times are always printed, as events.
+struct PhaseTimer {
+    enabled: bool,
+    json: bool,
+    start: std::time::Instant,
+    phase_start: std::time::Instant,
 }
 
-/// Numerical difference between hiragana and katakana in scalar values.
-/// Hirgana is lower than katakana.
-const KANA_DIFF: u32 = 0x30a1 - 0x3041;
+impl PhaseTimer {
+    fn new(enabled: bool, json: bool) -> PhaseTimer {
+        let now = std::time::Instant::now();
+        PhaseTimer {
+            enabled,
+            json,
+            start: now,
+            phase_start: now,
+        }
+    }
+
+    /// Prints the time since the end of the previous phase.
+    fn end_phase(&mut self, name: &str) {
+        let now = std::time::Instant::now();
+        let seconds = (now - self.phase_start).as_secs_f64();
+        if self.json {
+            println!(
+                "{}",
+                json!({"event": "phase", "phase": name, "seconds": seconds})
+            );
+        } else if self.enabled {
+            println!("    [{}: {:.2}s]", name, seconds);
+        }
+        self.phase_start = now;
+    }
 
-fn is_kana(ch: char) -> bool {
-    let c = ch as u32;
+    fn print_total(&self) {
+        let seconds = self.start.elapsed().as_secs_f64();
+        if self.json {
+            println!("{}", json!({"event": "total", "seconds": seconds}));
+        } else if self.enabled {
+            println!("    [Total: {:.2}s]", seconds);
+        }
+    }
+}
 
-    (c >= 0x3041 && c <= 0x3096) // Hiragana.
-    || (c >= 0x3099 && c <= 0x309c) // Combining marks.
-    || (c >= 0x309d && c <= 0x309e) // Iterating marks.
-    || (c >= 0x30a1 && c <= 0x30f6) // Katakana.
-    || c == 0x30fc // Prolonged sound mark.
-    || (c >= 0x30fd && c <= 0x30fe) // Iterating marks.
+/// Prints the progress of the build, either as text or, with "--log-format
+/// json", as one JSON object per line.
+struct Log {
+    json: bool,
 }
 
-fn is_hiragana(ch: char) -> bool {
-    let c = ch as u32;
+impl Log {
+    /// The start of a step of the build, e.g. "Loading dictionaries".
+    fn step(&self, message: &str) {
+        if self.json {
+            println!("{}", json!({"event": "step", "message": message}));
+        } else {
+            println!("{}...", message);
+        }
+    }
+
+    /// A count of something, e.g. "entries", optionally of a specific
+    /// source.  `source` is empty if not.  Averages are counts too, rounded
+    /// for printing by the caller.
+    fn count<T: Into<Value> + std::fmt::Display + Copy>(&self, source: &str, what: &str, count: T) {
+        if self.json {
+            let count: Value = count.into();
+            let source = if source.is_empty() {
+                Value::Null
+            } else {
+                Value::from(source)
+            };
+            println!(
+                "{}",
+                json!({"event": "count", "source": source, "what": what, "count": count})
+            );
+        } else if source.is_empty() {
+            println!("    {}: {}", what, count);
+        } else {
+            println!("    {} {}: {}", source, what, count);
+        }
+    }
 
-    (c >= 0x3041 && c <= 0x3096) // Hiragana.
-    || (c >= 0x3099 && c <= 0x309c) // Combining marks.
-    || (c >= 0x309d && c <= 0x309e) // Iterating marks.
-    || c == 0x30fc // Prolonged sound mark.
-    || (c >= 0x30fd && c <= 0x30fe) // Iterating marks.
+    /// A list of items, e.g. words with no entry.
+    fn list(&self, what: &str, items: &[String]) {
+        if self.json {
+            println!("{}", json!({"event": "list", "what": what, "items": items}));
+        } else {
+            println!("    {}: {}", what, items.join(", "));
+        }
+    }
 }
 
-/// Removes all non-kana text from a `&str`, and returns
-/// a `String` of the result.
-fn strip_non_kana(text: &str) -> String {
-    let mut new_text = String::new();
-    for ch in text.chars() {
-        if is_kana(ch) {
-            new_text.push(ch);
+/// Describes the command line and the size and modification time of each
+/// file named on it, to detect when a build would produce something new.
+fn source_fingerprint() -> io::Result<String> {
+    let mut fingerprint = format!("kobo_jp_dict {}\n", clap::crate_version!());
+    for arg in std::env::args_os().skip(1) {
+        let path = Path::new(&arg);
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => {
+                let modified = metadata
+                    .modified()?
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                fingerprint.push_str(&format!(
+                    "{}\t{}\t{}\n",
+                    path.display(),
+                    metadata.len(),
+                    modified
+                ));
+            }
+            _ => fingerprint.push_str(&format!("{}\n", arg.to_string_lossy())),
         }
     }
-    new_text
+    Ok(fingerprint)
 }
 
-fn hiragana_to_katakana(text: &str) -> String {
-    let mut new_text = String::new();
-    for ch in text.chars() {
-        let c = ch as u32;
-        new_text.push(
-            if (c >= 0x3041 && c <= 0x3096) || (c >= 0x309d && c <= 0x309e) {
-                char::try_from(c + KANA_DIFF).unwrap_or(ch)
-            } else {
-                ch
-            },
-        );
+/// Points the "latest" link of an update at the newly written `path`, and
+/// records the fingerprint of the sources it was built from.
+fn finish_update(
+    path: &Path,
+    latest: &Path,
+    fingerprint: &str,
+    fingerprint_path: &Path,
+) -> io::Result<()> {
+    if std::fs::symlink_metadata(latest).is_ok() {
+        std::fs::remove_file(latest)?;
     }
-    new_text
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(path.file_name().unwrap(), latest)?;
+    #[cfg(not(unix))]
+    std::fs::copy(path, latest)?;
+
+    std::fs::write(fingerprint_path, fingerprint)
 }
 
-fn katakana_to_hiragana(text: &str) -> String {
-    let mut new_text = String::new();
-    for ch in text.chars() {
-        let c = ch as u32;
-        new_text.push(
-            if (c >= 0x30a1 && c <= 0x30f6) || (c >= 0x30fd && c <= 0x30fe) {
-                char::try_from(c - KANA_DIFF).unwrap_or(ch)
-            } else {
-                ch
-            },
-        );
+/// Today's date (UTC) as "YYYYMMDD".
+fn date_stamp() -> String {
+    let (year, month, day) = today();
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+/// Today's date (UTC) as (year, month, day).
+fn today() -> (i64, i64, i64) {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+
+    // Days since 1970-01-01 to a civil date.  See:
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Generates the "--build-info" entry.  Each file named on the command line
+/// is listed with its size and a hash of its content, so that builds from
+/// different versions of the same source file can be told apart.
+fn build_info_entry() -> io::Result<generic_dict::Entry> {
+    use yomichan::escape_html;
+
+    let (year, month, day) = today();
+    let mut html = format!(
+        "<p><b>kobo_jp_dict {}</b><br/>Built: {:04}-{:02}-{:02}</p>",
+        clap::crate_version!(),
+        year,
+        month,
+        day
+    );
+
+    let args: Vec<_> = std::env::args_os().skip(1).collect();
+    let mut files = String::new();
+    for arg in args.iter() {
+        let path = Path::new(arg);
+        if path.is_file() {
+            // 64-bit FNV-1a.
+            let mut hash = 0xcbf2_9ce4_8422_2325u64;
+            let mut size = 0u64;
+            let mut reader = BufReader::new(File::open(path)?);
+            loop {
+                let buf = reader.fill_buf()?;
+                if buf.is_empty() {
+                    break;
+                }
+                for &byte in buf.iter() {
+                    hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+                }
+                size += buf.len() as u64;
+                let len = buf.len();
+                reader.consume(len);
+            }
+            files.push_str(&format!(
+                "<li>{} ({} bytes, {:016x})</li>",
+                escape_html(&path.display().to_string()),
+                size,
+                hash
+            ));
+        }
+    }
+    if !files.is_empty() {
+        html.push_str(&format!("<p>Files:</p><ul>{}</ul>", files));
     }
-    new_text
+
+    let options: Vec<String> = args
+        .iter()
+        .map(|arg| escape_html(&arg.to_string_lossy()))
+        .collect();
+    html.push_str(&format!("<p>Options: {}</p>", options.join(" ")));
+
+    Ok(generic_dict::Entry {
+        keys: vec![("じしょばん".into(), 0)],
+        definition: html,
+    })
 }
 
-fn is_all_kana(text: &str) -> bool {
-    let mut all_kana = true;
-    for ch in text.chars() {
-        all_kana &= is_kana(ch);
+/// Prints the summary of the warnings about the input data, or each of them
+/// with "--log-format json", and writes them
+/// to the file given with "--warnings-json", if any.
+fn report_warnings(cli: &cli::Cli) -> io::Result<()> {
+    if cli.output_options.log_format == "json" {
+        warnings::print_json_lines();
+    } else {
+        warnings::print_summary();
     }
-    all_kana
+    if let Some(ref path) = cli.output_options.warnings_json {
+        warnings::write_json(path)?;
+    }
+    Ok(())
 }
 
-fn is_all_hiragana(text: &str) -> bool {
-    let mut all_hiragana = true;
-    for ch in text.chars() {
-        all_hiragana &= is_hiragana(ch);
+/// Reads a notes file: tab-separated lines of headword, reading, and an html
+/// note.  The reading may be empty, in which case the note applies to all
+/// readings of the headword.
+///
+/// Returns a map of headword -> (reading in katakana, note).
+fn read_notes(
+    path: &Path,
+    encodings: &Encodings,
+) -> io::Result<HashMap<String, Vec<(String, String)>>> {
+    let text = read_text_file(path, encodings)?;
+    let mut notes: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for (i, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(3, '\t').collect();
+        if parts.len() != 3 {
+            eprintln!(
+                "Error: \"{}\", line {}: expected headword, reading, and note separated by tabs",
+                path.display(),
+                i + 1
+            );
+            std::process::exit(1);
+        }
+        notes
+            .entry(kana::normalize(parts[0].trim()))
+            .or_default()
+            .push((
+                hiragana_to_katakana(&kana::normalize(parts[1].trim())),
+                parts[2].trim().into(),
+            ));
     }
-    all_hiragana
+    Ok(notes)
+}
+
+/// Reads a list of words, one word per line, skipping empty lines.  A
+/// leading byte order mark, as written by some Windows editors, is ignored.
+fn read_word_list(path: &Path, encodings: &Encodings) -> io::Result<Vec<String>> {
+    let text = read_text_file(path, encodings)?;
+    Ok(text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(|l| l.trim().into())
+        .filter(|l: &String| !l.is_empty())
+        .collect())
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.into()
+    }
+}
+
+/// The encodings given with "--encoding", by path or file name.
+type Encodings = HashMap<String, &'static encoding_rs::Encoding>;
+
+/// Reads a text input file, decoding it from the encoding given for it in
+/// `encodings`, or else from its detected encoding.
+fn read_text_file(path: &Path, encodings: &Encodings) -> io::Result<String> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    let name = path.to_string_lossy();
+    let encoding = encodings
+        .get(&*name)
+        .or_else(|| encodings.get(&*path.file_name()?.to_string_lossy()))
+        .copied();
+    Ok(encoding::decode(&data, encoding, &name).into_owned())
 }
@@ -0,0 +1,774 @@
+//! The intermediate representation of dictionary entries: the data of all
+//! sources merged per word, along with its lookup keys, but not yet
+//! rendered to any output format.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashSet};
+
+use rustc_hash::FxHashMap;
+
+use crate::generic_dict::{generate_lookup_keys, is_prefix, is_suffix, EntrySettings};
+use crate::jmdict;
+use crate::kana::{hiragana_to_katakana, is_all_kana, is_kana, is_kanji, katakana_to_hiragana};
+use crate::observer;
+use crate::sanitize::strip_tags;
+use crate::yomichan::{self, Definition};
+
+type TermTable = FxHashMap<(String, String), Vec<yomichan::TermEntry>>;
+type KanjiTable = FxHashMap<String, Vec<yomichan::KanjiEntry>>;
+
+#[derive(Clone, Debug)]
+pub struct MergedEntry {
+    // Same as `generic_dict::Entry::keys`.
+    pub keys: Vec<(String, u32)>,
+    pub kind: MergedKind,
+}
+
+impl MergedEntry {
+    /// The form the entry is for: a word's first writing (or reading, if
+    /// it's usually written in kana), a name's writing, or the kanji.
+    pub fn headword(&self) -> &str {
+        match self.kind {
+            MergedKind::Term { ref word, .. } => {
+                let forms = if word.usually_kana || word.writings.is_empty() {
+                    &word.readings
+                } else {
+                    &word.writings
+                };
+                forms.first().unwrap_or(&self.keys[0].0)
+            }
+            MergedKind::Name { ref items, .. } => &items[0].writing,
+            MergedKind::Kanji(_) => &self.keys[0].0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum MergedKind {
+    Term {
+        kana: String, // The reading the entry is keyed on, in katakana.
+        pitch_accent: Vec<u32>,
+        phonetics: yomichan::Phonetics,
+
+        // Headword forms, readings, part of speech, tags, etc.
+        word: jmdict::WordEntry,
+
+        // The senses of the word, one group per source dictionary.
+        senses: Vec<yomichan::TermEntry>,
+    },
+    // All the name records with the same writing.
+    Name {
+        items: Vec<yomichan::TermEntry>,
+
+        // Reading, in katakana, -> accents, for the readings that have any.
+        pitch_accents: BTreeMap<String, Vec<u32>>,
+    },
+
+    // The data of each source dictionary for the same kanji.
+    Kanji(Vec<yomichan::KanjiEntry>),
+}
+
+/// Moves the part of speech specific accents of a word from its header
+/// accents to the part of speech groups of its definitions (see
+/// `yomichan::parse()`'s `group_by_pos`), e.g. "［副］ [0]".  A group gets
+/// the accents whose label shares a part of speech with it.
+///
+/// Accents that don't match any group are left in the header.
+fn place_pos_accents(
+    pitch_accent: &mut Vec<u32>,
+    senses: &mut [yomichan::TermEntry],
+    pos_accents: &[(String, u32)],
+) {
+    let shares_pos = |a: &str, b: &str| a.split('・').any(|pos| b.split('・').any(|p| p == pos));
+
+    let mut placed = vec![false; pos_accents.len()];
+    for sense in senses.iter_mut() {
+        if let Definition::List((_, ref mut groups)) = sense.definitions {
+            for group in groups.iter_mut() {
+                if let Definition::List((ref mut header, _)) = group {
+                    let label = match header.strip_prefix('［').and_then(|h| h.strip_suffix('］'))
+                    {
+                        Some(label) => label.to_string(),
+                        None => continue,
+                    };
+                    let mut accents = String::new();
+                    for (i, (pos, accent)) in pos_accents.iter().enumerate() {
+                        if shares_pos(pos, &label) {
+                            accents.push_str(&format!("[{}]", accent));
+                            placed[i] = true;
+                        }
+                    }
+                    if !accents.is_empty() {
+                        header.push_str(&format!(" {}", accents));
+                    }
+                }
+            }
+        }
+    }
+
+    for (i, (_, accent)) in pos_accents.iter().enumerate() {
+        if placed[i] {
+            if let Some(j) = pitch_accent.iter().position(|a| a == accent) {
+                pitch_accent.remove(j);
+            }
+        }
+    }
+}
+
+/// Merges the data of all sources into entries.
+///
+/// Only words that have both JMDict metadata and definitions from at least
+/// one source dictionary get an entry.  The tables are consumed, to avoid
+/// copying all of the definitions.
+///
+/// Fails only if the build is cancelled (see `observer`).
+pub fn merge_entries(
+    mut yomi_term_table: TermTable,
+    yomi_name_table: TermTable,
+    yomi_kanji_table: KanjiTable,
+    mut jm_table: FxHashMap<(String, String), Vec<jmdict::WordEntry>>,
+    pa_table: FxHashMap<(String, String), Vec<u32>>,
+    pa_pos_table: FxHashMap<(String, String), Vec<(String, u32)>>,
+    phonetics_table: FxHashMap<(String, String), yomichan::Phonetics>,
+    entry_settings: EntrySettings,
+) -> std::io::Result<Vec<MergedEntry>> {
+    observer::phase_started("Merging");
+    infer_expression_conjugations(&mut jm_table);
+
+    let mut entries =
+        Vec::with_capacity(yomi_kanji_table.len() + jm_table.len() + yomi_name_table.len());
+    let mut key_cap = entry_settings.max_keys.map(KeyCap::new);
+
+    // Kanji entries.
+    let mut examples = kanji_examples(&yomi_kanji_table, &jm_table, entry_settings.kanji_examples);
+    for (kanji, mut items) in yomi_kanji_table.into_iter() {
+        items[0].examples = examples.remove(&kanji).unwrap_or_default();
+        let mut keys = vec![(kanji, entry_settings.priority_weights.kanji)];
+        if let Some(ref mut key_cap) = key_cap {
+            key_cap.add(&mut keys);
+        }
+        entries.push(MergedEntry {
+            keys,
+            kind: MergedKind::Kanji(items),
+        });
+    }
+
+    // Onomatopoeia dictionaries often write their headwords in katakana,
+    // while JMDict writes them in hiragana.
+    if entry_settings.onomatopoeia {
+        merge_katakana_onomatopoeia(&mut yomi_term_table, &jm_table);
+    }
+
+    // Term entries.
+    let term_count = jm_table.len();
+    for (i, (key, items)) in jm_table.into_iter().enumerate() {
+        if i % 10_000 == 0 {
+            observer::progress("Merging", i, term_count);
+            observer::check_cancelled()?;
+        }
+
+        // Find matching entries in the source dictionaries.
+        let senses = match yomi_term_table.remove(&key) {
+            Some(senses) if !senses.is_empty() => senses,
+            _ => continue,
+        };
+        let mut pitch_accent = pa_table.get(&key).cloned().unwrap_or_else(Vec::new);
+        let phonetics = phonetics_table.get(&key).cloned().unwrap_or_default();
+        let mut senses = senses;
+        if let Some(pos_accents) = pa_pos_table.get(&key) {
+            place_pos_accents(&mut pitch_accent, &mut senses, pos_accents);
+        }
+
+        for jm_entry in items.into_iter() {
+            let mut keys = generate_lookup_keys(&jm_entry, entry_settings);
+            if let Some(ref mut key_cap) = key_cap {
+                key_cap.add(&mut keys);
+            }
+            entries.push(MergedEntry {
+                keys,
+                kind: MergedKind::Term {
+                    kana: key.1.clone(),
+                    pitch_accent: pitch_accent.clone(),
+                    phonetics: phonetics.clone(),
+                    word: jm_entry,
+                    senses: senses.clone(),
+                },
+            });
+        }
+    }
+
+    // Name entries, one per writing.
+    let mut names: FxHashMap<String, (Vec<yomichan::TermEntry>, BTreeMap<String, Vec<u32>>)> =
+        FxHashMap::default();
+    for (key, items) in yomi_name_table.into_iter() {
+        let name = names
+            .entry(key.0.clone())
+            .or_insert((Vec::new(), BTreeMap::new()));
+        if let Some(accents) = pa_table.get(&key) {
+            if !accents.is_empty() {
+                name.1.insert(key.1, accents.clone());
+            }
+        }
+        name.0.extend(items);
+    }
+    for (writing, (mut items, pitch_accents)) in names.into_iter() {
+        // Most common first, and without records that only differ in
+        // their source dictionary or score.
+        items.sort_by(|a, b| {
+            (b.commonness, &a.reading, &a.tags, &a.definitions).cmp(&(
+                a.commonness,
+                &b.reading,
+                &b.tags,
+                &b.definitions,
+            ))
+        });
+        let mut seen = BTreeSet::new();
+        items.retain(|item| {
+            seen.insert((
+                item.reading.clone(),
+                item.tags.clone(),
+                item.definitions.clone(),
+            ))
+        });
+
+        let mut keys = vec![(writing, entry_settings.priority_weights.name)];
+        if let Some(ref mut key_cap) = key_cap {
+            key_cap.add(&mut keys);
+        }
+        entries.push(MergedEntry {
+            keys,
+            kind: MergedKind::Name {
+                items,
+                pitch_accents,
+            },
+        });
+    }
+
+    if let Some(key_cap) = key_cap {
+        key_cap.remove_evicted(&mut entries);
+    }
+
+    entries.sort_by_key(|a| a.keys[0].0.len());
+
+    Ok(entries)
+}
+
+/// The distinct lookup keys of all entries, for limiting their number
+/// while they're generated (see `EntrySettings::max_keys`).
+///
+/// A key shared by several entries counts once, with the best priority any
+/// of them gives it.  When there are too many, the keys with the worst
+/// priority are evicted from all entries, and longer keys before shorter
+/// ones at the same priority.  An entry's first key, its headword, is never
+/// evicted.
+struct KeyCap {
+    max_keys: usize,
+
+    // Key -> (best priority, whether it's the first key of an entry).
+    keys: FxHashMap<String, (u32, bool)>,
+
+    // Candidates for eviction, worst first.  Candidates whose key got a
+    // better priority or became a headword since are skipped.
+    candidates: BinaryHeap<(u32, usize, Reverse<String>)>,
+
+    evicted: HashSet<String>,
+}
+
+impl KeyCap {
+    fn new(max_keys: usize) -> KeyCap {
+        KeyCap {
+            max_keys,
+            keys: FxHashMap::default(),
+            candidates: BinaryHeap::new(),
+            evicted: HashSet::new(),
+        }
+    }
+
+    /// Adds the keys of a new entry, evicting keys if there are too many.
+    /// Evicted keys are removed from `keys`, but not from the entries
+    /// added before; see `remove_evicted()`.
+    fn add(&mut self, keys: &mut Vec<(String, u32)>) {
+        for (i, (key, priority)) in keys.iter().enumerate() {
+            let is_first = i == 0;
+            if !is_first && self.evicted.contains(key) {
+                continue;
+            }
+            if is_first {
+                self.evicted.remove(key);
+            }
+            let info = self.keys.entry(key.clone()).or_insert((u32::MAX, is_first));
+            info.1 |= is_first;
+            if *priority < info.0 {
+                info.0 = *priority;
+                if !info.1 {
+                    self.candidates
+                        .push((*priority, key.len(), Reverse(key.clone())));
+                }
+            }
+        }
+
+        while self.keys.len() > self.max_keys {
+            let (priority, _, Reverse(key)) = match self.candidates.pop() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            if self.keys.get(&key) == Some(&(priority, false)) {
+                self.keys.remove(&key);
+                self.evicted.insert(key);
+            }
+        }
+
+        let mut i = 0;
+        keys.retain(|key| {
+            i += 1;
+            i == 1 || !self.evicted.contains(&key.0)
+        });
+    }
+
+    /// Removes the keys that were evicted after they were added from
+    /// `entries`.
+    fn remove_evicted(&self, entries: &mut [MergedEntry]) {
+        for entry in entries.iter_mut() {
+            let mut i = 0;
+            entry.keys.retain(|key| {
+                i += 1;
+                i == 1 || !self.evicted.contains(&key.0)
+            });
+        }
+    }
+}
+
+/// Moves the definitions of katakana headwords with no JMDict entry, e.g.
+/// ニコニコ, to the hiragana headword of the same onomatopoeia, e.g. にこにこ,
+/// if JMDict has that.
+fn merge_katakana_onomatopoeia(
+    yomi_term_table: &mut TermTable,
+    jm_table: &FxHashMap<(String, String), Vec<jmdict::WordEntry>>,
+) {
+    let moves: Vec<((String, String), (String, String))> = yomi_term_table
+        .keys()
+        .filter(|key| is_all_kana(&key.0) && !jm_table.contains_key(*key))
+        .filter_map(|key| {
+            let target = (katakana_to_hiragana(&key.0), key.1.clone());
+            let is_onomatopoeia = jm_table
+                .get(&target)
+                .is_some_and(|words| words.iter().any(|w| w.tags.contains("misc:on-mim")));
+            if target != *key && is_onomatopoeia {
+                Some((key.clone(), target))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    for (key, target) in moves {
+        let senses = yomi_term_table.remove(&key).unwrap();
+        yomi_term_table.entry(target).or_default().extend(senses);
+    }
+}
+
+/// Finds the `count` most common words that contain each kanji, as
+/// (writing, reading, gloss).  The gloss is the first one of the word.
+fn kanji_examples(
+    yomi_kanji_table: &KanjiTable,
+    jm_table: &FxHashMap<(String, String), Vec<jmdict::WordEntry>>,
+    count: usize,
+) -> FxHashMap<String, Vec<(String, String, String)>> {
+    if count == 0 {
+        return FxHashMap::default();
+    }
+
+    let mut examples: FxHashMap<String, Vec<(u32, &jmdict::WordEntry)>> = FxHashMap::default();
+
+    for word in jm_table.values().flatten() {
+        if word.writings.is_empty() || word.definitions.is_empty() {
+            continue;
+        }
+        let mut kanji: Vec<char> = word.writings[0].chars().collect();
+        kanji.sort_unstable();
+        kanji.dedup();
+        for ch in kanji.iter() {
+            let ch = ch.to_string();
+            if yomi_kanji_table.contains_key(&ch) {
+                examples.entry(ch).or_default().push((word.priority, word));
+            }
+        }
+    }
+
+    examples
+        .into_iter()
+        .map(|(kanji, mut words)| {
+            words.sort_by(|a, b| (a.0, &a.1.writings[0]).cmp(&(b.0, &b.1.writings[0])));
+            let words = words
+                .iter()
+                .take(count)
+                .map(|(_, word)| {
+                    let gloss = word.definitions[0].split("; ").next().unwrap_or("");
+                    (
+                        word.writings[0].clone(),
+                        word.readings[0].clone(),
+                        gloss.into(),
+                    )
+                })
+                .collect();
+            (kanji, words)
+        })
+        .collect()
+}
+
+/// Gives expressions that JMDict doesn't mark with a conjugation class the
+/// class of the verb or i-adjective they end with, so that their inflected
+/// forms get lookup keys too.  For example 気が付く gets the class of 付く.
+///
+/// The final word must follow a particle, so that e.g. an expression ending
+/// in a kanji that happens to also be a verb on its own isn't matched.
+fn infer_expression_conjugations(
+    jm_table: &mut FxHashMap<(String, String), Vec<jmdict::WordEntry>>,
+) {
+    use jmdict::{ConjugationClass, PartOfSpeech};
+
+    const PARTICLES: &[char] = &['が', 'を', 'に', 'は', 'も', 'で', 'と', 'の', 'へ'];
+
+    // Form -> conjugation class, for all conjugable words.  Forms shared by
+    // words of different classes, e.g. いる (居る, ichidan, and 要る, godan),
+    // are ambiguous and map to `None`.
+    let mut classes: FxHashMap<String, Option<ConjugationClass>> = FxHashMap::default();
+    for entry in jm_table.values().flatten() {
+        if entry.conj == ConjugationClass::Other
+            || entry.conj == ConjugationClass::Copula
+            || entry.pos == PartOfSpeech::Expression
+        {
+            continue;
+        }
+        for form in entry.writings.iter().chain(entry.readings.iter()) {
+            let class = classes.entry(form.clone()).or_insert(Some(entry.conj));
+            if *class != Some(entry.conj) {
+                *class = None;
+            }
+        }
+    }
+
+    for entry in jm_table.values_mut().flatten() {
+        if entry.pos != PartOfSpeech::Expression || entry.conj != ConjugationClass::Other {
+            continue;
+        }
+
+        // The longest final word, in any form, that has a single class.
+        let class = entry
+            .writings
+            .iter()
+            .chain(entry.readings.iter())
+            .filter_map(|form| {
+                form.char_indices()
+                    .filter(|&(_, c)| PARTICLES.contains(&c))
+                    .map(|(i, c)| &form[(i + c.len_utf8())..])
+                    .filter_map(|word| Some((word.len(), (*classes.get(word)?)?)))
+                    .max_by_key(|&(len, _)| len)
+            })
+            .max_by_key(|&(len, _)| len);
+        if let Some((_, class)) = class {
+            entry.conj = class;
+        }
+    }
+}
+
+/// Adds the words of the frequency list that start with a prefix or end
+/// with a suffix, and have no entry of their own, as keys of the affix's
+/// entry.  For example 合理的 for 〜的.
+///
+/// Only words with a frequency rank of at most `max_rank` are added, and
+/// affixes only match up to three characters.
+pub fn add_affix_keys(
+    entries: &mut [MergedEntry],
+    freq_table: &FxHashMap<(String, String), u32>,
+    max_rank: u32,
+    entry_settings: EntrySettings,
+) {
+    const MAX_AFFIX_CHARS: usize = 3;
+
+    // Affix -> entry indices.
+    let mut prefixes: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+    let mut suffixes: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+    let mut existing_keys: HashSet<&str> = HashSet::new();
+    for (i, entry) in entries.iter().enumerate() {
+        existing_keys.extend(entry.keys.iter().map(|k| k.0.as_str()));
+        if let MergedKind::Term { ref word, .. } = entry.kind {
+            let table = match (is_prefix(word), is_suffix(word)) {
+                (true, false) => &mut prefixes,
+                (false, true) => &mut suffixes,
+                _ => continue,
+            };
+            let forms = if word.writings.is_empty() {
+                &word.readings[0..1]
+            } else {
+                &word.writings[..]
+            };
+            for form in forms.iter() {
+                table.entry(form.as_str()).or_insert(Vec::new()).push(i);
+            }
+        }
+    }
+
+    // (entry index, key).
+    let mut new_keys: Vec<(usize, String)> = Vec::new();
+    for ((writing, _), &rank) in freq_table.iter() {
+        if rank > max_rank || existing_keys.contains(writing.as_str()) {
+            continue;
+        }
+
+        let boundaries: Vec<usize> = writing.char_indices().map(|(i, _)| i).skip(1).collect();
+        for &i in boundaries.iter().take(MAX_AFFIX_CHARS) {
+            for &entry_i in prefixes.get(&writing[..i]).into_iter().flatten() {
+                new_keys.push((entry_i, writing.clone()));
+            }
+        }
+        for &i in boundaries.iter().rev().take(MAX_AFFIX_CHARS) {
+            for &entry_i in suffixes.get(&writing[i..]).into_iter().flatten() {
+                new_keys.push((entry_i, writing.clone()));
+            }
+        }
+    }
+    new_keys.sort_unstable();
+    new_keys.dedup();
+
+    // Compounds come after words that are exact matches, like inflections.
+    let penalty = entry_settings.priority_weights.inflection_penalty;
+    for (entry_i, key) in new_keys.into_iter() {
+        let keys = &mut entries[entry_i].keys;
+        let priority = keys[0].1.saturating_add(penalty);
+        keys.push((key, priority));
+    }
+    for entry in entries.iter_mut() {
+        entry.keys.sort_by_key(|a| (a.1, a.0.len(), a.0.clone()));
+    }
+}
+
+/// Removes the entries for any of `words`, which are in katakana: words
+/// with one of them as a writing or reading, names with one of them as
+/// their writing or reading, and kanji that are one of them.
+///
+/// Returns the number of entries removed.
+pub fn remove_words(entries: &mut Vec<MergedEntry>, words: &HashSet<String>) -> usize {
+    let excluded = |form: &str| words.contains(&hiragana_to_katakana(form));
+    let len = entries.len();
+    entries.retain(|entry| match entry.kind {
+        MergedKind::Term { ref word, .. } => !word
+            .writings
+            .iter()
+            .chain(word.readings.iter())
+            .any(|form| excluded(form)),
+        MergedKind::Name { ref items, .. } => !items
+            .iter()
+            .any(|item| excluded(&item.writing) || excluded(&item.reading)),
+        MergedKind::Kanji(ref items) => !items.iter().any(|item| excluded(&item.kanji)),
+    });
+    len - entries.len()
+}
+
+/// Removes the Yomichan definitions for which `is_excluded` returns true,
+/// and then the entries that have no Yomichan definitions left.  Items
+/// that had no definitions to begin with (e.g. some names) are kept.
+///
+/// Returns the number of entries removed.
+pub fn remove_definitions<F: FnMut(&str) -> bool>(
+    entries: &mut Vec<MergedEntry>,
+    mut is_excluded: F,
+) -> usize {
+    let mut keep = |text: &str| !is_excluded(text);
+    let len = entries.len();
+    entries.retain_mut(|entry| match entry.kind {
+        MergedKind::Term { ref mut senses, .. } => {
+            senses.retain_mut(|sense| {
+                sense.definitions.is_empty() || sense.definitions.retain(&mut keep)
+            });
+            !senses.is_empty()
+        }
+        MergedKind::Name { ref mut items, .. } => {
+            items.retain_mut(|item| {
+                item.definitions.is_empty() || item.definitions.retain(&mut keep)
+            });
+            !items.is_empty()
+        }
+        MergedKind::Kanji(_) => true,
+    });
+    len - entries.len()
+}
+
+/// Removes the definitions of a source dictionary that mostly repeat the
+/// definitions of an earlier one for the same word, e.g. the glosses of two
+/// JMDict-based dictionaries.  A source's definitions are removed when at
+/// least `threshold` (over 0, at most 1) of their words also appear in an
+/// earlier source's definitions.
+///
+/// Returns the number of definitions removed.
+pub fn remove_similar_senses(entries: &mut [MergedEntry], threshold: f64) -> usize {
+    let mut removed = 0;
+    for entry in entries.iter_mut() {
+        let senses = match entry.kind {
+            MergedKind::Term { ref mut senses, .. } if senses.len() > 1 => senses,
+            _ => continue,
+        };
+
+        let mut kept_words: Vec<HashSet<String>> = Vec::new();
+        let mut keep = Vec::with_capacity(senses.len());
+        for sense in senses.iter() {
+            let html =
+                yomichan::definition_to_html(&sense.definitions, sense.definitions.depth(), true);
+            let words = definition_words(&strip_tags(&html));
+            let is_repeat = !words.is_empty()
+                && kept_words.iter().any(|kept| {
+                    let shared = words.iter().filter(|w| kept.contains(*w)).count();
+                    shared as f64 / words.len() as f64 >= threshold
+                });
+            keep.push(!is_repeat);
+            if is_repeat {
+                removed += 1;
+            } else {
+                kept_words.push(words);
+            }
+        }
+
+        let mut keep = keep.into_iter();
+        senses.retain(|_| keep.next().unwrap());
+    }
+    removed
+}
+
+/// The distinct words of a definition's text, lowercased.  Kanji and kana
+/// count as one word each, since Japanese isn't written with spaces.
+fn definition_words(text: &str) -> HashSet<String> {
+    let mut words = HashSet::new();
+    let mut word = String::new();
+    for ch in text.chars() {
+        if ch.is_alphanumeric() && !is_cjk(ch) {
+            word.extend(ch.to_lowercase());
+            continue;
+        }
+        if !word.is_empty() {
+            words.insert(std::mem::take(&mut word));
+        }
+        if is_cjk(ch) {
+            words.insert(ch.to_string());
+        }
+    }
+    if !word.is_empty() {
+        words.insert(word);
+    }
+    words
+}
+
+fn is_cjk(ch: char) -> bool {
+    is_kana(ch) || is_kanji(ch)
+}
+
+/// Statistics about the lookup keys of a set of entries, for "--key-stats".
+pub struct KeyStats {
+    pub total: usize,
+    /// Keys per entry, over all entries.
+    pub average: f64,
+    /// Each kind of entry, by part of speech for words.
+    pub kinds: Vec<KindKeyStats>,
+    /// (first key, key count) of the entries with the most keys, most keys
+    /// first.
+    pub largest: Vec<(String, usize)>,
+}
+
+/// The key statistics of one kind of entry.
+pub struct KindKeyStats {
+    pub kind: String,
+    pub entries: usize,
+    pub keys: usize,
+    /// Keys per entry.
+    pub average: f64,
+}
+
+/// Collects statistics about the lookup keys of `entries`: the total, the
+/// entry and key counts and average keys per entry for each kind of entry,
+/// and the entries with the most keys.
+pub fn key_stats(entries: &[MergedEntry]) -> KeyStats {
+    const TOP_ENTRY_COUNT: usize = 20;
+
+    // Kind -> (entry count, key count).
+    let mut kinds: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for entry in entries.iter() {
+        let kind = match entry.kind {
+            MergedKind::Term { ref word, .. } => format!("{:?}", word.pos),
+            MergedKind::Name { .. } => "Name".into(),
+            MergedKind::Kanji(_) => "Kanji".into(),
+        };
+        let counts = kinds.entry(kind).or_insert((0, 0));
+        counts.0 += 1;
+        counts.1 += entry.keys.len();
+    }
+
+    let mut largest: Vec<&MergedEntry> = entries.iter().filter(|e| !e.keys.is_empty()).collect();
+    largest.sort_by(|a, b| {
+        b.keys
+            .len()
+            .cmp(&a.keys.len())
+            .then(a.keys[0].0.cmp(&b.keys[0].0))
+    });
+
+    let total: usize = entries.iter().map(|e| e.keys.len()).sum();
+    KeyStats {
+        total,
+        average: total as f64 / entries.len().max(1) as f64,
+        kinds: kinds
+            .into_iter()
+            .map(|(kind, (entry_count, key_count))| KindKeyStats {
+                kind,
+                entries: entry_count,
+                keys: key_count,
+                average: key_count as f64 / entry_count as f64,
+            })
+            .collect(),
+        largest: largest
+            .iter()
+            .take(TOP_ENTRY_COUNT)
+            .map(|e| (e.keys[0].0.clone(), e.keys.len()))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[(&str, u32)]) -> Vec<(String, u32)> {
+        keys.iter().map(|&(k, p)| (k.to_string(), p)).collect()
+    }
+
+    #[test]
+    fn key_cap_evicts_the_worst_keys_while_adding() {
+        let mut key_cap = KeyCap::new(3);
+
+        let mut first = keys(&[("あ", 0), ("い", 5), ("う", 9)]);
+        key_cap.add(&mut first);
+        assert_eq!(first, keys(&[("あ", 0), ("い", 5), ("う", 9)]));
+
+        // い counts once, with its better priority here.  う and お are
+        // the worst of the five distinct keys.
+        let mut second = keys(&[("え", 0), ("い", 1), ("お", 7)]);
+        key_cap.add(&mut second);
+        assert_eq!(second, keys(&[("え", 0), ("い", 1)]));
+
+        // Evicted keys are dropped as soon as they're added again, but an
+        // entry's headword is always kept, and counts again.  That leaves
+        // い as the worst key.
+        let mut third = keys(&[("う", 3), ("お", 0)]);
+        key_cap.add(&mut third);
+        assert_eq!(third, keys(&[("う", 3)]));
+
+        let mut entries: Vec<MergedEntry> = [first, second, third]
+            .iter()
+            .map(|keys| MergedEntry {
+                keys: keys.clone(),
+                kind: MergedKind::Kanji(Vec::new()),
+            })
+            .collect();
+        key_cap.remove_evicted(&mut entries);
+        assert_eq!(entries[0].keys, keys(&[("あ", 0), ("う", 9)]));
+        assert_eq!(entries[1].keys, keys(&[("え", 0)]));
+        assert_eq!(entries[2].keys, keys(&[("う", 3)]));
+    }
+}
@@ -0,0 +1,71 @@
+//! Hooks for following the progress of a build from outside, and for
+//! cancelling it, e.g. from a GUI running the build on another thread.
+//!
+//! There is at most one observer at a time, set with `set_observer()`.
+//! Without one, all of this does nothing.
+
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    static ref OBSERVER: RwLock<Option<Arc<dyn BuildObserver>>> = RwLock::new(None);
+}
+
+/// Receives build events.  All methods have do-nothing defaults.
+///
+/// The methods may be called from several threads at once, so they should
+/// return quickly.
+pub trait BuildObserver: Send + Sync {
+    /// A phase of the build started, e.g. "Merging".
+    fn phase_started(&self, _phase: &str) {}
+
+    /// `fraction` (0.0 to 1.0) of the current phase is done.
+    fn progress(&self, _phase: &str, _fraction: f32) {}
+
+    /// A warning about the input data was recorded.  See `warnings`.
+    fn warning(&self, _category: &str, _source: &str, _message: &str) {}
+
+    /// Returns true if the build should stop.  It's checked between items,
+    /// and the build then fails with an error of kind `Interrupted`.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Sets the observer that receives the events of all builds, replacing the
+/// previous one.  `None` removes it.
+pub fn set_observer(observer: Option<Arc<dyn BuildObserver>>) {
+    *OBSERVER.write().unwrap() = observer;
+}
+
+fn with_observer<F: FnOnce(&dyn BuildObserver)>(f: F) {
+    if let Some(ref observer) = *OBSERVER.read().unwrap() {
+        f(&**observer);
+    }
+}
+
+pub fn phase_started(phase: &str) {
+    with_observer(|o| o.phase_started(phase));
+}
+
+/// Reports that `done` of `total` items of `phase` are done.
+pub fn progress(phase: &str, done: usize, total: usize) {
+    with_observer(|o| o.progress(phase, done as f32 / total.max(1) as f32));
+}
+
+pub fn warning(category: &str, source: &str, message: &str) {
+    with_observer(|o| o.warning(category, source, message));
+}
+
+/// Returns an `Interrupted` error if the build has been cancelled.
+pub fn check_cancelled() -> std::io::Result<()> {
+    let mut cancelled = false;
+    with_observer(|o| cancelled = o.is_cancelled());
+    if cancelled {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "build cancelled",
+        ))
+    } else {
+        Ok(())
+    }
+}
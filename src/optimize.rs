@@ -0,0 +1,145 @@
+//! Size optimizations of the rendered entry html, for "--optimize-size".
+//!
+//! Much of the html of a dictionary is the same few inline styles repeated
+//! in every entry: the font sizes of headers and labels, the hanging
+//! indents of kanji entries, and so on.  `minify_html()` writes these more
+//! compactly, which works for every output format.  StarDict dictionaries
+//! can also have a stylesheet, which KOReader and GoldenDict apply, so for
+//! those `factor_styles()` replaces the most repeated styles with short
+//! class names.  Kobo dictionaries can't, so they only get the former.
+
+use std::collections::HashMap;
+
+use regex::{Captures, Regex};
+
+use crate::generic_dict::Entry;
+
+lazy_static! {
+    static ref START_TAG: Regex = Regex::new(r"(?s)<([a-zA-Z][a-zA-Z0-9]*)([^>]*)>").unwrap();
+    static ref TAG_OR_TEXT: Regex = Regex::new(r"(?s)<[^>]*>|[^<]+").unwrap();
+    static ref STYLE_ATTRIBUTE: Regex = Regex::new(r#"(?s)\s*\bstyle="([^"]*)""#).unwrap();
+    static ref CLASS_ATTRIBUTE: Regex = Regex::new(r"\bclass\s*=").unwrap();
+    static ref SPACES: Regex = Regex::new(r"\s{2,}").unwrap();
+}
+
+/// Rewrites `html` to a shorter equivalent: inline styles without the
+/// optional spaces and final semicolon, spans that are only bold or italic
+/// as <b> and <i>, and runs of whitespace as a single space.
+///
+/// `html` is expected to be balanced, as `sanitize::sanitize_html()`
+/// leaves it.
+pub fn minify_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut open_spans: Vec<&str> = Vec::new(); // The tag each open span was written as.
+    for token in TAG_OR_TEXT.find_iter(html) {
+        let token = token.as_str();
+        if !token.starts_with('<') {
+            out.push_str(&SPACES.replace_all(token, " "));
+            continue;
+        }
+        if token == "</span>" {
+            out.push_str(&format!("</{}>", open_spans.pop().unwrap_or("span")));
+            continue;
+        }
+
+        let tag = STYLE_ATTRIBUTE.replace_all(token, |caps: &Captures| {
+            let style = compact_style(&caps[1]);
+            if style.is_empty() {
+                String::new()
+            } else {
+                format!(" style=\"{}\"", style)
+            }
+        });
+        if tag.starts_with("<span") && !tag.ends_with("/>") {
+            let replacement = match tag.as_ref() {
+                "<span style=\"font-weight:bold\">" => "b",
+                "<span style=\"font-style:italic\">" => "i",
+                _ => "span",
+            };
+            open_spans.push(replacement);
+            if replacement != "span" {
+                out.push_str(&format!("<{}>", replacement));
+                continue;
+            }
+        }
+        out.push_str(&tag);
+    }
+    out
+}
+
+/// Writes a css declaration block as "property:value;property:value".
+fn compact_style(style: &str) -> String {
+    style
+        .split(';')
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            Some(format!("{}:{}", property.trim(), value.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Replaces the inline styles that are repeated often enough to be worth it
+/// with classes, and returns the stylesheet defining them.  Elements that
+/// already have a class keep their inline style.
+///
+/// The most used styles get the shortest class names.
+pub fn factor_styles(entries: &mut [Entry]) -> String {
+    // Style -> number of uses.
+    let mut uses: HashMap<String, usize> = HashMap::new();
+    for entry in entries.iter() {
+        for caps in START_TAG.captures_iter(&entry.definition) {
+            if let Some(style) = factorable_style(&caps[2]) {
+                *uses.entry(style.into()).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut styles: Vec<(String, usize)> = uses.into_iter().collect();
+    styles.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    // Style -> class name.
+    let mut classes: HashMap<String, String> = HashMap::new();
+    let mut css = String::new();
+    for (style, count) in styles.into_iter() {
+        let class = format!("s{}", classes.len());
+        let rule = format!(".{}{{{}}}\n", class, style);
+        let inline_len = " style=\"\"".len() + style.len();
+        let class_len = " class=\"\"".len() + class.len();
+        if count * inline_len <= count * class_len + rule.len() {
+            continue;
+        }
+        css.push_str(&rule);
+        classes.insert(style, class);
+    }
+    if classes.is_empty() {
+        return css;
+    }
+
+    for entry in entries.iter_mut() {
+        entry.definition = START_TAG
+            .replace_all(&entry.definition, |caps: &Captures| {
+                let class = factorable_style(&caps[2]).and_then(|style| classes.get(style));
+                match class {
+                    Some(class) => {
+                        let attributes = STYLE_ATTRIBUTE
+                            .replace(&caps[2], format!(" class=\"{}\"", class).as_str());
+                        format!("<{}{}>", &caps[1], attributes)
+                    }
+                    None => caps[0].to_string(),
+                }
+            })
+            .into();
+    }
+    css
+}
+
+/// The inline style of a tag with the given attributes, unless it has none
+/// or also has a class.
+fn factorable_style(attributes: &str) -> Option<&str> {
+    if CLASS_ATTRIBUTE.is_match(attributes) {
+        return None;
+    }
+    STYLE_ATTRIBUTE
+        .captures(attributes)
+        .map(|caps| caps.get(1).unwrap().as_str())
+}
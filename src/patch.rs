@@ -0,0 +1,398 @@
+//! Patches that correct the data of specific words after merging, so that
+//! known mistakes in the source dictionaries can be fixed locally.
+//!
+//! A patch file is a JSON list of objects.  Each one names a word by its
+//! `writing` (its first writing, or its reading for words without one) and
+//! optionally its `reading`, and gives any of:
+//!
+//! - `"delete": true` to leave the word out of the dictionary.
+//! - `"delete_senses": [n, ...]` to delete the nth definitions (counting
+//!   from 1) of each source dictionary, or only of the source dictionary
+//!   named by `"dict"`.
+//! - `"readings": [...]` to replace the word's readings.
+//! - `"priority": n` to set the word's priority.  Lower is more common.
+//! - `"definitions": [...]` to replace the word's definitions with the given
+//!   html ones, under the source dictionary named by `"dict"` if given.
+
+use std::convert::TryFrom;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::generic_dict::{generate_lookup_keys, EntrySettings};
+use crate::intern::intern;
+use crate::kana::{self, hiragana_to_katakana};
+use crate::merged::{MergedEntry, MergedKind};
+use crate::yomichan::Definition;
+
+#[derive(Clone, Debug)]
+pub struct Patch {
+    writing: String,
+    reading: Option<String>, // In katakana.
+    dict: Option<String>,
+
+    delete: bool,
+    delete_senses: Vec<usize>,
+    readings: Option<Vec<String>>,
+    priority: Option<u32>,
+    definitions: Option<Vec<String>>,
+}
+
+pub fn read_patches(path: &Path) -> std::io::Result<Vec<Patch>> {
+    let invalid = |message: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+
+    let text = std::fs::read_to_string(path)?;
+    let json: Value = serde_json::from_str(text.trim_start_matches('\u{feff}'))
+        .map_err(|e| invalid(format!("invalid json: {}", e)))?;
+    let items = json
+        .as_array()
+        .ok_or_else(|| invalid("not a list of patches".into()))?;
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            patch_from_json(item).ok_or_else(|| invalid(format!("patch {}: not valid", i + 1)))
+        })
+        .collect()
+}
+
+/// Returns `None` if the patch is malformed.
+fn patch_from_json(json: &Value) -> Option<Patch> {
+    let strings = |json: Option<&Value>| -> Option<Option<Vec<String>>> {
+        match json {
+            None => Some(None),
+            Some(list) => Some(Some(
+                list.as_array()?
+                    .iter()
+                    .map(|s| s.as_str().map(|s| s.into()))
+                    .collect::<Option<Vec<String>>>()?,
+            )),
+        }
+    };
+
+    Some(Patch {
+        writing: kana::normalize(json.get("writing")?.as_str()?.trim()),
+        reading: match json.get("reading") {
+            Some(r) => Some(hiragana_to_katakana(&kana::normalize(r.as_str()?.trim()))),
+            None => None,
+        },
+        dict: match json.get("dict") {
+            Some(d) => Some(d.as_str()?.trim().into()),
+            None => None,
+        },
+        delete: match json.get("delete") {
+            Some(d) => d.as_bool()?,
+            None => false,
+        },
+        delete_senses: match json.get("delete_senses") {
+            Some(list) => list
+                .as_array()?
+                .iter()
+                .map(|n| n.as_u64().filter(|&n| n > 0).map(|n| n as usize))
+                .collect::<Option<Vec<usize>>>()?,
+            None => Vec::new(),
+        },
+        readings: strings(json.get("readings"))?
+            .map(|readings| readings.iter().map(|r| kana::normalize(r.trim())).collect()),
+        priority: match json.get("priority") {
+            Some(p) => Some(u32::try_from(p.as_u64()?).ok()?),
+            None => None,
+        },
+        definitions: strings(json.get("definitions"))?,
+    })
+}
+
+/// Applies `patches` to the term entries they name.  Entries whose readings
+/// or priority change get new lookup keys.
+///
+/// Returns the number of patches that didn't match any entry.
+pub fn apply_patches(
+    entries: &mut Vec<MergedEntry>,
+    patches: &[Patch],
+    entry_settings: EntrySettings,
+) -> usize {
+    let mut unused = 0;
+    for patch in patches.iter() {
+        let mut matched = false;
+        for entry in entries.iter_mut() {
+            if !matches(entry, patch) {
+                continue;
+            }
+            matched = true;
+            apply_patch(entry, patch, entry_settings);
+        }
+        if !matched {
+            unused += 1;
+        }
+    }
+
+    // Deleted entries, and entries left without definitions.
+    entries.retain(|entry| match entry.kind {
+        MergedKind::Term { ref senses, .. } => !senses.is_empty(),
+        _ => true,
+    });
+
+    unused
+}
+
+fn matches(entry: &MergedEntry, patch: &Patch) -> bool {
+    match entry.kind {
+        MergedKind::Term {
+            ref kana, ref word, ..
+        } => {
+            let headword = word.writings.first().unwrap_or(&word.readings[0]);
+            *headword == patch.writing
+                && match patch.reading {
+                    Some(ref reading) => {
+                        kana == reading
+                            || word
+                                .readings
+                                .iter()
+                                .any(|r| hiragana_to_katakana(r) == *reading)
+                    }
+                    None => true,
+                }
+        }
+        _ => false,
+    }
+}
+
+fn apply_patch(entry: &mut MergedEntry, patch: &Patch, entry_settings: EntrySettings) {
+    let (kana, word, senses) = match entry.kind {
+        MergedKind::Term {
+            ref mut kana,
+            ref mut word,
+            ref mut senses,
+            ..
+        } => (kana, word, senses),
+        _ => return,
+    };
+
+    if patch.delete {
+        senses.clear();
+        return;
+    }
+
+    let applies_to_dict = |dict_name: &str| match patch.dict {
+        Some(ref dict) => dict_name == dict,
+        None => true,
+    };
+
+    if !patch.delete_senses.is_empty() {
+        for sense in senses.iter_mut() {
+            if !applies_to_dict(&sense.dict_name) {
+                continue;
+            }
+            if let Definition::List((_, ref mut list)) = sense.definitions {
+                let mut i = 0;
+                list.retain(|_| {
+                    i += 1;
+                    !patch.delete_senses.contains(&i)
+                });
+            }
+        }
+        senses.retain(|sense| !sense.definitions.is_empty());
+    }
+
+    if let Some(ref definitions) = patch.definitions {
+        let definitions = Definition::List((
+            "".into(),
+            definitions
+                .iter()
+                .map(|d| Definition::Def(d.clone()))
+                .collect(),
+        ));
+        match patch.dict {
+            Some(ref dict) => match senses.iter().position(|s| &*s.dict_name == dict.as_str()) {
+                Some(i) => senses[i].definitions = definitions,
+                None if !senses.is_empty() => {
+                    let mut sense = senses[0].clone();
+                    sense.dict_name = intern(dict);
+                    sense.definitions = definitions;
+                    senses.push(sense);
+                }
+                None => {}
+            },
+            None => {
+                senses.truncate(1);
+                if let Some(sense) = senses.first_mut() {
+                    sense.definitions = definitions;
+                }
+            }
+        }
+    }
+
+    let mut changed_keys = false;
+    if let Some(ref readings) = patch.readings {
+        if !readings.is_empty() {
+            word.readings = readings.clone();
+            word.reading_info = vec![Vec::new(); readings.len()];
+            word.reading_restrictions = vec![Vec::new(); readings.len()];
+            *kana = kana::strip_non_kana(&hiragana_to_katakana(&readings[0]));
+            changed_keys = true;
+        }
+    }
+    if let Some(priority) = patch.priority {
+        word.priority = priority;
+        changed_keys = true;
+    }
+    if changed_keys {
+        entry.keys = generate_lookup_keys(word, entry_settings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use crate::jmdict;
+    use crate::yomichan::{InflectionType, TermEntry};
+
+    fn read(json: &str) -> std::io::Result<Vec<Patch>> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(json.as_bytes()).unwrap();
+        read_patches(file.path())
+    }
+
+    /// A term entry with the given senses of each source dictionary.
+    fn entry(writing: &str, reading: &str, dicts: &[(&str, &[&str])]) -> MergedEntry {
+        let mut word = jmdict::WordEntry::new();
+        word.push_writing(writing.into());
+        word.push_reading(reading.into());
+        let senses = dicts
+            .iter()
+            .map(|&(dict, definitions)| TermEntry {
+                dict_name: intern(dict),
+                writing: writing.into(),
+                reading: reading.into(),
+                definitions: Definition::List((
+                    "".into(),
+                    definitions
+                        .iter()
+                        .map(|&d| Definition::Def(d.into()))
+                        .collect(),
+                )),
+                infl: InflectionType::None,
+                tags: Vec::new(),
+                commonness: 0,
+            })
+            .collect();
+        MergedEntry {
+            keys: generate_lookup_keys(&word, EntrySettings::default()),
+            kind: MergedKind::Term {
+                kana: hiragana_to_katakana(reading),
+                pitch_accent: Vec::new(),
+                phonetics: Default::default(),
+                word,
+                senses,
+            },
+        }
+    }
+
+    fn definitions(entry: &MergedEntry) -> Vec<(String, usize)> {
+        match entry.kind {
+            MergedKind::Term { ref senses, .. } => senses
+                .iter()
+                .map(|s| (s.dict_name.to_string(), s.definitions.len()))
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn reads_patches_and_rejects_invalid_ones() {
+        let patches = read(
+            r#"[{"writing": "日本", "reading": "にほん", "delete_senses": [2], "dict": "jmdict"}]"#,
+        )
+        .unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].reading.as_deref(), Some("ニホン"));
+        assert_eq!(patches[0].delete_senses, vec![2]);
+        assert_eq!(patches[0].dict.as_deref(), Some("jmdict"));
+
+        // Invalid patches, including priorities that don't fit in a u32.
+        for json in [
+            r#"{"writing": "日本"}"#,
+            r#"[{"reading": "にほん"}]"#,
+            r#"[{"writing": "日本", "delete_senses": [0]}]"#,
+            r#"[{"writing": "日本", "priority": 4294967296}]"#,
+        ] {
+            let error = read(json).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{}", json);
+        }
+    }
+
+    #[test]
+    fn applies_each_kind_of_patch() {
+        let mut entries = vec![
+            entry(
+                "日本",
+                "にほん",
+                &[
+                    ("jmdict", &["Japan", "Japanese"]),
+                    ("other", &["Japan", "Nippon"]),
+                ],
+            ),
+            entry(
+                "日本",
+                "にっぽん",
+                &[
+                    ("jmdict", &["Japan", "Japanese"]),
+                    ("other", &["Japan", "Nippon"]),
+                ],
+            ),
+            entry("本", "ほん", &[("jmdict", &["book"])]),
+            entry("猫", "ねこ", &[("jmdict", &["cat"])]),
+        ];
+        let patches = read(
+            r#"[
+                {"writing": "日本", "reading": "にほん", "delete_senses": [2]},
+                {"writing": "日本", "reading": "にっぽん", "delete_senses": [1, 2], "dict": "other"},
+                {"writing": "本", "readings": ["もと"], "priority": 100},
+                {"writing": "猫", "delete": true},
+                {"writing": "犬", "delete": true}
+            ]"#,
+        )
+        .unwrap();
+
+        let unused = apply_patches(&mut entries, &patches, EntrySettings::default());
+        assert_eq!(unused, 1);
+        assert_eq!(entries.len(), 3);
+
+        // Without "dict", senses are deleted from every source dictionary,
+        // and with it, only from that one.
+        let dicts = |list: &[(&str, usize)]| -> Vec<(String, usize)> {
+            list.iter().map(|&(d, n)| (d.to_string(), n)).collect()
+        };
+        assert_eq!(
+            definitions(&entries[0]),
+            dicts(&[("jmdict", 1), ("other", 1)])
+        );
+        assert_eq!(definitions(&entries[1]), dicts(&[("jmdict", 2)]));
+
+        // New readings and priorities give new lookup keys.
+        match entries[2].kind {
+            MergedKind::Term {
+                ref kana, ref word, ..
+            } => {
+                assert_eq!(kana, "モト");
+                assert_eq!(word.readings, vec!["もと".to_string()]);
+                assert_eq!(word.priority, 100);
+                assert_eq!(
+                    entries[2].keys,
+                    generate_lookup_keys(word, EntrySettings::default())
+                );
+                assert!(entries[2].keys.iter().any(|(key, _)| key == "もと"));
+                assert!(!entries[2].keys.iter().any(|(key, _)| key == "ほん"));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
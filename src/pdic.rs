@@ -0,0 +1,189 @@
+//! Parses PDIC text dictionaries, such as Eijiro (英辞郎) and its J-E part
+//! Waeijiro (和英辞郎).
+//!
+//! Two line formats are supported, and can be mixed:
+//!
+//! - Eijiro text: "■headword {品詞} : translation", where the headword
+//!   may be followed by its reading, as in "■学校｛がっこう｝ : school".
+//! - PDIC one-line text: "headword /// translation".
+//!
+//! Only lines with Japanese headwords are used, since entries are keyed on
+//! JMDict words; the E-J lines of Eijiro are skipped.  Consecutive lines
+//! with the same headword are merged into one entry, one sense per line.
+//!
+//! The translations' markup is converted to html: "{名}" part of speech
+//! labels, "◆" notes, "■・" examples, "【用例】"-style labels, and the
+//! "～" that stands for the headword in phrases and examples.  PDIC
+//! binary dictionaries (.dic) aren't supported; export them as one-line
+//! text first.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::intern::intern;
+use crate::kana::is_all_kana;
+use crate::source::{DictionarySource, SourceItem};
+use crate::yomichan::{escape_html, Definition, InflectionType, TermEntry};
+
+pub struct Source {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Source {
+    /// `data` is the content of the file.
+    pub fn new(name: &str, data: Vec<u8>) -> Source {
+        Source {
+            name: name.into(),
+            data,
+        }
+    }
+}
+
+impl DictionarySource for Source {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        let text = String::from_utf8(self.data).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a UTF-8 PDIC text file",
+            )
+        })?;
+
+        // Shown as the source of the definitions, e.g. "EIJIRO-1448".
+        let dict_name = intern(
+            &Path::new(&self.name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or(self.name.clone()),
+        );
+
+        let mut entries: Vec<TermEntry> = Vec::new();
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let (writing, reading, definition) = match parse_line(line) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            match entries.last_mut() {
+                Some(entry) if entry.writing == writing && entry.reading == reading => {
+                    if let Definition::List((_, ref mut senses)) = entry.definitions {
+                        senses.push(Definition::Def(definition));
+                    }
+                }
+                _ => entries.push(term_entry(&dict_name, writing, reading, definition)),
+            }
+        }
+
+        Ok(Box::new(entries.into_iter().map(SourceItem::Term)))
+    }
+}
+
+fn term_entry(
+    dict_name: &Arc<str>,
+    writing: String,
+    reading: String,
+    definition: String,
+) -> TermEntry {
+    TermEntry {
+        dict_name: dict_name.clone(),
+        writing,
+        reading,
+        definitions: Definition::List(("".into(), vec![Definition::Def(definition)])),
+        infl: InflectionType::None,
+        tags: Vec::new(),
+        commonness: 0,
+    }
+}
+
+/// Parses a line into (writing, reading, definition html).  Returns `None`
+/// for lines that aren't entries or don't have a Japanese headword.
+fn parse_line(line: &str) -> Option<(String, String, String)> {
+    let line = line.trim();
+    let (headword, translation) = match line.strip_prefix('■') {
+        Some(rest) => rest.split_once(" : ")?,
+        None => line.split_once(" /// ")?,
+    };
+
+    // The part of speech label, e.g. "{名}" or "{名-1}".
+    let (headword, pos) = match (headword.find('{'), headword.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (
+            format!("{}{}", &headword[..start], &headword[(end + 1)..]),
+            Some(headword[(start + 1)..end].trim().to_string()),
+        ),
+        _ => (headword.to_string(), None),
+    };
+
+    // The reading, e.g. "学校｛がっこう｝".
+    let (writing, reading) = match (headword.find('｛'), headword.find('｝')) {
+        (Some(start), Some(end)) if start < end => (
+            headword[..start].trim().to_string(),
+            headword[(start + '｛'.len_utf8())..end].trim().to_string(),
+        ),
+        _ => (headword.trim().to_string(), String::new()),
+    };
+    if writing.is_empty() || writing.is_ascii() {
+        return None;
+    }
+    let reading = if reading.is_empty() && is_all_kana(&writing) {
+        writing.clone()
+    } else {
+        reading
+    };
+
+    let mut definition = String::new();
+    if let Some(pos) = pos {
+        definition.push_str(&format!("<i>（{}）</i> ", escape_html(&pos)));
+    }
+    definition.push_str(&markup_to_html(translation.trim(), &writing));
+    Some((writing, reading, definition))
+}
+
+/// Converts the markup of a translation of `headword` to html.
+///
+/// A "～" (or the similar "〜") stands for the headword, e.g. "～を見る"
+/// under 夢, and is replaced by it in bold.  Headwords that have a ～
+/// themselves, e.g. "～的", are affixes, and their translations use it as
+/// is.
+fn markup_to_html(text: &str, headword: &str) -> String {
+    let html = escape_html(text)
+        .replace("■・", "<br/>・")
+        .replace("◆", "<br/>◆")
+        .replace("【", "<b>【")
+        .replace("】", "】</b>");
+    if headword.contains(['～', '〜']) {
+        return html;
+    }
+    let headword = format!("<b>{}</b>", escape_html(headword));
+    html.replace(['～', '〜'], &headword)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eijiro_lines() {
+        let (writing, reading, definition) =
+            parse_line("■夢｛ゆめ｝ {名} : dream◆～を見る to have a dream").unwrap();
+        assert_eq!((writing.as_str(), reading.as_str()), ("夢", "ゆめ"));
+        assert_eq!(
+            definition,
+            "<i>（名）</i> dream<br/>◆<b>夢</b>を見る to have a dream"
+        );
+
+        let (_, _, definition) = parse_line("■～的 : -ic◆～な -ical").unwrap();
+        assert_eq!(definition, "-ic<br/>◆～な -ical");
+
+        assert!(parse_line("■dream : 夢").is_none());
+    }
+
+    #[test]
+    fn one_line_text() {
+        let (writing, reading, definition) = parse_line("ねこ /// cat <animal>").unwrap();
+        assert_eq!((writing.as_str(), reading.as_str()), ("ねこ", "ねこ"));
+        assert_eq!(definition, "cat &lt;animal&gt;");
+    }
+}
@@ -0,0 +1,95 @@
+//! Parses pitch accent files.
+//!
+//! These are tab-separated files with one word per line, in the format
+//! "writing, reading, accents", where the accents are a list of numbers
+//! separated by any non-digit characters.  The reading may be empty if the
+//! writing is all kana.  Windows line endings and a leading byte order mark
+//! are accepted.
+//!
+//! Accents that only apply to some parts of speech can be labelled as in the
+//! Kanjium data, e.g. "(副)0,(名)3" or "(名;形動)0,1".  A label only applies
+//! to the accent right after it.
+
+use crate::source::{DictionarySource, SourceItem};
+use crate::warnings;
+
+pub struct Source {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Source {
+    /// `data` is the uncompressed content of the file.
+    pub fn new(name: &str, data: Vec<u8>) -> Source {
+        Source {
+            name: name.into(),
+            data,
+        }
+    }
+}
+
+impl DictionarySource for Source {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        let text = String::from_utf8_lossy(&self.data).into_owned();
+        let items: Vec<SourceItem> = text
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(i, line)| {
+                let parts: Vec<_> = line.split("\t").map(|a| a.trim()).collect();
+                if parts.len() != 3 {
+                    warnings::warn(
+                        warnings::Category::PitchAccentLine,
+                        &self.name,
+                        format!("line {}: expected 3 columns, found {}", i + 1, parts.len()),
+                    );
+                    return None;
+                }
+                let accents: Vec<u32> = parts[2]
+                    .split(|ch: char| !ch.is_ascii_digit())
+                    .filter(|s| !s.is_empty())
+                    .map(|a| a.parse::<u32>().unwrap())
+                    .collect();
+                let pos_accents = parse_pos_accents(parts[2]);
+
+                let mut items = vec![SourceItem::PitchAccent(
+                    parts[0].into(),
+                    parts[1].into(),
+                    accents,
+                )];
+                if !pos_accents.is_empty() {
+                    items.push(SourceItem::PosPitchAccent(
+                        parts[0].into(),
+                        parts[1].into(),
+                        pos_accents,
+                    ));
+                }
+                Some(items)
+            })
+            .flatten()
+            .collect();
+
+        Ok(Box::new(items.into_iter()))
+    }
+}
+
+/// Parses the part of speech labelled accents of an accent list, as
+/// (label, accent) pairs.  Labels are returned in the same style as the
+/// part of speech groups of Yomichan definitions, e.g. "名・形動".
+fn parse_pos_accents(text: &str) -> Vec<(String, u32)> {
+    text.split([',', '、'])
+        .filter_map(|item| {
+            let item = item.trim();
+            let rest = item.strip_prefix('(')?;
+            let end = rest.find(')')?;
+            let label = rest[..end].trim().replace(';', "・");
+            let accent = rest[(end + 1)..].trim().parse::<u32>().ok()?;
+            Some((label, accent))
+        })
+        .collect()
+}
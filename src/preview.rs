@@ -0,0 +1,97 @@
+//! Renders entries to a standalone html file, for checking how they look
+//! without copying a dictionary to an e-reader.
+
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::generic_dict::{Entry, EntryIndex};
+
+/// Approximates the look of the Kobo dictionary popup.
+const STYLE: &str = "
+body { background: #ddd; margin: 0; padding: 1em; }
+.popup {
+    background: #fff;
+    color: #000;
+    border: 2px solid #000;
+    width: 32em;
+    max-width: 95%;
+    margin: 0 auto 1.5em auto;
+    padding: 0.6em 0.9em;
+    font-family: Georgia, serif;
+    font-size: 16px;
+    line-height: 1.3;
+}
+.popup-title {
+    font-family: sans-serif;
+    font-size: 0.8em;
+    color: #555;
+    border-bottom: 1px solid #999;
+    margin-bottom: 0.4em;
+}
+";
+
+/// Writes a page showing the entries of each word in `words`, followed by
+/// the entries of `sample_size` randomly chosen keys.
+///
+/// Returns the words that had no matching entries.
+pub fn write_html(
+    entries: &[Entry],
+    words: &[String],
+    sample_size: usize,
+    output_path: &Path,
+) -> std::io::Result<Vec<String>> {
+    let index = EntryIndex::new(entries);
+
+    let mut words = words.to_vec();
+    let mut rng = seed();
+    for _ in 0..sample_size.min(entries.len()) {
+        rng = xorshift(rng);
+        let entry = &entries[(rng % entries.len() as u64) as usize];
+        words.push(entry.keys[0].0.clone());
+    }
+
+    let mut missing = Vec::new();
+    let mut out = BufWriter::new(std::fs::File::create(output_path)?);
+
+    out.write_all(b"<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"/>")?;
+    out.write_all(format!("<title>Dictionary preview</title><style>{}</style>", STYLE).as_bytes())?;
+    out.write_all(b"</head><body>\n")?;
+
+    for word in words.iter() {
+        let matches = index.lookup(word);
+        if matches.is_empty() {
+            missing.push(word.clone());
+            continue;
+        }
+
+        out.write_all(b"<div class=\"popup\">")?;
+        let title = word.replace('&', "&amp;").replace('<', "&lt;");
+        out.write_all(format!("<div class=\"popup-title\">{}</div>", title).as_bytes())?;
+        for entry in matches.iter() {
+            out.write_all(entry.definition.as_bytes())?;
+        }
+        out.write_all(b"</div>\n")?;
+    }
+
+    out.write_all(b"</body></html>\n")?;
+    out.flush()?;
+
+    Ok(missing)
+}
+
+/// A seed for picking sample entries, so that each run shows different ones.
+fn seed() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    nanos | 1 // Xorshift needs a non-zero state.
+}
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
@@ -0,0 +1,65 @@
+//! Parses kanji component files.
+//!
+//! Two formats are supported, both UTF-8:
+//!
+//! - KRADFILE (e.g. "kradfile-u"): one kanji per line, in the format
+//!   "kanji : component component ...".  Lines starting with "#" are
+//!   comments.
+//! - IDS files (e.g. CHISE "ids.txt"): tab-separated lines in the format
+//!   "codepoint, kanji, ideographic description sequence".  Lines starting
+//!   with ";" are comments.
+//!
+//! Windows line endings and a leading byte order mark are accepted.
+
+use crate::source::{DictionarySource, SourceItem};
+
+pub struct Source {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Source {
+    /// `data` is the uncompressed content of the file.
+    pub fn new(name: &str, data: Vec<u8>) -> Source {
+        Source {
+            name: name.into(),
+            data,
+        }
+    }
+}
+
+impl DictionarySource for Source {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        let text = String::from_utf8_lossy(&self.data).into_owned();
+        let items: Vec<SourceItem> = text
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+            .filter_map(|line| {
+                if let Some((kanji, components)) = line.split_once(" : ") {
+                    Some(SourceItem::KanjiComponents(
+                        kanji.trim().into(),
+                        components.split_whitespace().map(|c| c.into()).collect(),
+                    ))
+                } else {
+                    let parts: Vec<_> = line.split('\t').map(|a| a.trim()).collect();
+                    if parts.len() < 3 || parts[1] == parts[2] {
+                        // Not decomposable.
+                        return None;
+                    }
+                    Some(SourceItem::KanjiDecomposition(
+                        parts[1].into(),
+                        parts[2].into(),
+                    ))
+                }
+            })
+            .collect();
+
+        Ok(Box::new(items.into_iter()))
+    }
+}
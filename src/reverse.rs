@@ -0,0 +1,107 @@
+//! Generation of a reverse (English to Japanese) dictionary from the JMDict
+//! glosses of the merged entries.
+//!
+//! Each Japanese word gets one entry, keyed on the English words of its
+//! glosses, showing the word, its reading, and its first gloss.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::generic_dict::Entry;
+use crate::kana::katakana_to_hiragana;
+use crate::merged::{MergedEntry, MergedKind};
+
+lazy_static! {
+    static ref MARKUP: Regex = Regex::new(r"<[^>]*>|&[a-zA-Z0-9#]+;|\([^)]*\)").unwrap();
+    static ref WORD: Regex = Regex::new(r"[a-zA-Z][a-zA-Z'-]*").unwrap();
+}
+
+/// Words that are too common to be useful as keys.
+const STOP_WORDS: &[&str] = &[
+    "a",
+    "an",
+    "and",
+    "as",
+    "at",
+    "be",
+    "by",
+    "for",
+    "from",
+    "in",
+    "into",
+    "is",
+    "it",
+    "of",
+    "on",
+    "one",
+    "one's",
+    "oneself",
+    "or",
+    "someone",
+    "something",
+    "that",
+    "the",
+    "to",
+    "with",
+];
+
+/// Added to the priority of keys that are only part of a gloss, so that
+/// words whose gloss is exactly the key are shown first.
+const PARTIAL_MATCH_PENALTY: u32 = 50000;
+
+pub fn generate_entries(merged: &[MergedEntry]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for merged_entry in merged.iter() {
+        let word = match merged_entry.kind {
+            MergedKind::Term { ref word, .. } => word,
+            _ => continue,
+        };
+        if word.definitions.is_empty() {
+            continue;
+        }
+
+        // English word -> priority.
+        let mut keys: HashMap<String, u32> = HashMap::new();
+        for definition in word.definitions.iter() {
+            for gloss in definition.split("; ") {
+                let gloss = MARKUP.replace_all(gloss, " ").to_lowercase();
+                let gloss_words: Vec<&str> = WORD
+                    .find_iter(&gloss)
+                    .map(|m| m.as_str())
+                    .filter(|w| w.len() > 1 && !STOP_WORDS.contains(w))
+                    .collect();
+                for gloss_word in gloss_words.iter() {
+                    let priority = if gloss_words.len() == 1 {
+                        word.priority
+                    } else {
+                        word.priority.saturating_add(PARTIAL_MATCH_PENALTY)
+                    };
+                    let p = keys.entry((*gloss_word).into()).or_insert(priority);
+                    *p = (*p).min(priority);
+                }
+            }
+        }
+        if keys.is_empty() {
+            continue;
+        }
+
+        let reading = katakana_to_hiragana(&word.readings[0]);
+        let headword = if word.usually_kana || word.writings.is_empty() {
+            reading.clone()
+        } else {
+            word.writings[0].clone()
+        };
+        let mut definition = format!("<hr/><b>{}</b>", headword);
+        if headword != reading {
+            definition.push_str(&format!(" 【{}】", reading));
+        }
+        definition.push_str(&format!("<p>{}</p>", word.definitions[0]));
+
+        let mut keys: Vec<(String, u32)> = keys.into_iter().collect();
+        keys.sort_by_key(|a| (a.1, a.0.len(), a.0.clone()));
+        entries.push(Entry { keys, definition });
+    }
+
+    entries
+}
@@ -0,0 +1,446 @@
+//! Cleanup of generated entry html, for elements that e-readers can't
+//! display properly.
+
+use regex::{Captures, Regex};
+
+/// What to do with audio, video, and other media elements.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MediaPolicy {
+    Strip,    // Remove the element along with any text inside it.
+    KeepText, // Replace the element with the text inside it.
+    Footnote, // Like `KeepText`, but also list the media files at the end.
+}
+
+/// The color scheme the entries are shown in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Theme {
+    Light, // Keep the colors of the source dictionaries.
+    Dark,  // Remove all text and background colors.
+    Auto,  // Remove backgrounds, and black, white and gray text colors.
+}
+
+lazy_static! {
+    // Media elements with content.  The `regex` crate doesn't support
+    // back-references, so the closing tag isn't required to match the
+    // opening tag.
+    static ref MEDIA_ELEMENT: Regex =
+        Regex::new(r"(?is)<(audio|video|object)\b([^>]*)>(.*?)</(?:audio|video|object)\s*>").unwrap();
+
+    // Media elements without content.
+    static ref MEDIA_VOID_ELEMENT: Regex =
+        Regex::new(r"(?is)<(?:source|track|embed)\b[^>]*>").unwrap();
+
+    // Links to audio files.
+    static ref AUDIO_LINK: Regex = Regex::new(
+        r#"(?is)<a\b[^>]*href\s*=\s*"([^"]*\.(?:mp3|ogg|oga|opus|wav|m4a|aac|flac))"[^>]*>(.*?)</a\s*>"#
+    )
+    .unwrap();
+
+    static ref STYLE_ATTRIBUTE: Regex = Regex::new(r#"(?is)\s*\bstyle\s*=\s*"([^"]*)""#).unwrap();
+    static ref SRC_ATTRIBUTE: Regex = Regex::new(r#"(?is)\bsrc\s*=\s*"([^"]*)""#).unwrap();
+    static ref TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+
+    // For the sanitizer.  These only match at the start of the text.
+    static ref START_TAG: Regex =
+        Regex::new(r#"^<(/?)([a-zA-Z][a-zA-Z0-9]*)((?:[^>"']|"[^"]*"|'[^']*')*?)(/?)>"#).unwrap();
+    static ref START_ENTITY: Regex =
+        Regex::new(r"^&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z][a-zA-Z0-9]{0,31});").unwrap();
+    static ref ATTRIBUTE: Regex = Regex::new(
+        r#"([a-zA-Z_:][-a-zA-Z0-9_:.]*)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>/=`]+)))?"#
+    )
+    .unwrap();
+}
+
+/// Tags that are passed through by the sanitizer.
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "rb",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+/// Tags that never have content, and are written self-closing.
+const VOID_TAGS: &[&str] = &["br", "hr", "img"];
+
+/// Tags that are removed along with their content.
+const DROPPED_TAGS: &[&str] = &["script", "style", "head", "title"];
+
+/// Attributes that are passed through by the sanitizer.
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "alt", "class", "colspan", "lang", "name", "rowspan", "src", "style", "title",
+];
+
+/// Named entities that are passed through by the sanitizer.  Others are
+/// escaped.
+const ALLOWED_ENTITIES: &[&str] = &[
+    "amp", "apos", "gt", "hellip", "lt", "mdash", "middot", "nbsp", "ndash", "quot", "times",
+];
+
+/// Applies `policy` to all media elements in `html`.
+pub fn handle_media(html: &str, policy: MediaPolicy) -> String {
+    if !(html.contains("<audio")
+        || html.contains("<video")
+        || html.contains("<object")
+        || html.contains("<embed")
+        || html.contains("<source")
+        || html.contains("<track")
+        || html.contains("href="))
+    {
+        return html.into();
+    }
+
+    let mut footnotes: Vec<String> = Vec::new();
+    let mut replace = |inner: &str, kind: &str, src: Option<&str>| -> String {
+        let text = strip_tags(inner);
+        match policy {
+            MediaPolicy::Strip => String::new(),
+            MediaPolicy::KeepText => text,
+            MediaPolicy::Footnote => {
+                footnotes.push(format!("{}: {}", kind, src.unwrap_or("?")));
+                format!("{}<sup>[{}]</sup>", text, footnotes.len())
+            }
+        }
+    };
+
+    let html = AUDIO_LINK.replace_all(html, |caps: &Captures| {
+        replace(&caps[2], "audio", Some(&caps[1]))
+    });
+    let html = MEDIA_ELEMENT.replace_all(&html, |caps: &Captures| {
+        // The source may be on the element itself or on a child `source`
+        // element.
+        let src = SRC_ATTRIBUTE
+            .captures(&caps[2])
+            .or_else(|| SRC_ATTRIBUTE.captures(&caps[3]))
+            .map(|src_caps| src_caps[1].to_string());
+        let inner = MEDIA_VOID_ELEMENT.replace_all(&caps[3], "");
+        replace(&inner, &caps[1].to_lowercase(), src.as_deref())
+    });
+    let mut html = MEDIA_VOID_ELEMENT.replace_all(&html, "").into_owned();
+
+    if !footnotes.is_empty() {
+        html.push_str("<p style=\"font-size: 0.8em;\">");
+        for (i, note) in footnotes.iter().enumerate() {
+            if i > 0 {
+                html.push_str("<br/>");
+            }
+            html.push_str(&format!("[{}] {}", i + 1, note));
+        }
+        html.push_str("</p>");
+    }
+
+    html
+}
+
+/// Makes `html` safe for display in a Kobo dictionary.
+///
+/// Tags and attributes not in the allowed lists are removed, unclosed tags
+/// are closed, stray closing tags are removed, and stray `&` and `<`
+/// characters and unknown entities are escaped.
+///
+/// Returns the sanitized html along with a description of each change made.
+pub fn sanitize_html(html: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(html.len());
+    let mut changes = Vec::new();
+    let mut open_tags: Vec<String> = Vec::new();
+
+    let mut rest = html;
+    while let Some(i) = rest.find(['<', '&']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with('&') {
+            match START_ENTITY.captures(rest) {
+                Some(caps) => {
+                    let name = &caps[1];
+                    if name.starts_with('#') || ALLOWED_ENTITIES.contains(&name) {
+                        out.push_str(&caps[0]);
+                    } else {
+                        out.push_str("&amp;");
+                        out.push_str(&caps[0][1..]);
+                        changes.push(format!("escaped unknown entity &{};", name));
+                    }
+                    rest = &rest[caps[0].len()..];
+                }
+                None => {
+                    out.push_str("&amp;");
+                    changes.push("escaped bare &".into());
+                    rest = &rest[1..];
+                }
+            }
+        } else if rest.starts_with("<!--") {
+            rest = match rest.find("-->") {
+                Some(end) => &rest[(end + 3)..],
+                None => "",
+            };
+            changes.push("removed comment".into());
+        } else if let Some(caps) = START_TAG.captures(rest) {
+            let is_close = !caps[1].is_empty();
+            let name = caps[2].to_ascii_lowercase();
+            let is_self_closing = !caps[4].is_empty();
+            rest = &rest[caps[0].len()..];
+
+            if DROPPED_TAGS.contains(&name.as_str()) {
+                if !is_close && !is_self_closing {
+                    let close_tag = format!("</{}", name);
+                    rest = match rest.to_ascii_lowercase().find(&close_tag) {
+                        Some(end) => match rest[end..].find('>') {
+                            Some(gt) => &rest[(end + gt + 1)..],
+                            None => "",
+                        },
+                        None => "",
+                    };
+                }
+                changes.push(format!("removed <{}> and its content", name));
+            } else if !ALLOWED_TAGS.contains(&name.as_str()) {
+                changes.push(format!("removed <{}>", name));
+            } else if is_close {
+                if VOID_TAGS.contains(&name.as_str()) {
+                    // Nothing to do: void tags are always written
+                    // self-closing.
+                } else if let Some(idx) = open_tags.iter().rposition(|t| *t == name) {
+                    for tag in open_tags.drain((idx + 1)..).rev() {
+                        out.push_str(&format!("</{}>", tag));
+                        changes.push(format!("closed unclosed <{}>", tag));
+                    }
+                    open_tags.pop();
+                    out.push_str(&format!("</{}>", name));
+                } else {
+                    changes.push(format!("removed stray </{}>", name));
+                }
+            } else {
+                out.push('<');
+                out.push_str(&name);
+                for attr in ATTRIBUTE.captures_iter(&caps[3]) {
+                    let attr_name = attr[1].to_ascii_lowercase();
+                    if !ALLOWED_ATTRIBUTES.contains(&attr_name.as_str()) {
+                        changes.push(format!(
+                            "removed attribute \"{}\" from <{}>",
+                            attr_name, name
+                        ));
+                        continue;
+                    }
+                    let value = attr
+                        .get(2)
+                        .or_else(|| attr.get(3))
+                        .or_else(|| attr.get(4))
+                        .map(|m| m.as_str())
+                        .unwrap_or("");
+                    out.push_str(&format!(" {}=\"{}\"", attr_name, escape_attribute(value)));
+                }
+
+                if VOID_TAGS.contains(&name.as_str()) {
+                    out.push_str("/>");
+                } else if is_self_closing {
+                    out.push_str(&format!("></{}>", name));
+                } else {
+                    out.push('>');
+                    open_tags.push(name);
+                }
+            }
+        } else {
+            out.push_str("&lt;");
+            changes.push("escaped bare <".into());
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+
+    for tag in open_tags.drain(..).rev() {
+        out.push_str(&format!("</{}>", tag));
+        changes.push(format!("closed unclosed <{}>", tag));
+    }
+
+    (out, changes)
+}
+
+/// Summarizes a list of changes from `sanitize_html()` on a single line,
+/// with repeated changes merged.
+pub fn summarize_changes(changes: &[String]) -> String {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for change in changes.iter() {
+        match counts.iter_mut().find(|c| c.0 == change) {
+            Some(c) => c.1 += 1,
+            None => counts.push((change, 1)),
+        }
+    }
+
+    counts
+        .iter()
+        .map(|&(change, count)| {
+            if count > 1 {
+                format!("{} (x{})", change, count)
+            } else {
+                change.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Escapes an attribute value for use within double quotes, leaving valid
+/// entities alone.
+fn escape_attribute(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    for (i, ch) in value.char_indices() {
+        match ch {
+            '&' if !START_ENTITY.is_match(&value[i..]) => text.push_str("&amp;"),
+            '<' => text.push_str("&lt;"),
+            '"' => text.push_str("&quot;"),
+            _ => text.push(ch),
+        }
+    }
+    text
+}
+
+/// Removes the colors of inline styles in `html` that don't work in the
+/// given theme.  E-readers' dark modes invert the popup, so that fixed
+/// backgrounds and black or white text become unreadable.
+pub fn apply_theme(html: &str, theme: Theme) -> String {
+    if theme == Theme::Light || !html.contains("style") {
+        return html.into();
+    }
+
+    STYLE_ATTRIBUTE
+        .replace_all(html, |caps: &Captures| {
+            let declarations: Vec<&str> = caps[1]
+                .split(';')
+                .filter(|declaration| {
+                    let (property, value) = match declaration.split_once(':') {
+                        Some((p, v)) => (p.trim().to_lowercase(), v.trim()),
+                        None => return !declaration.trim().is_empty(),
+                    };
+                    let is_background = property.starts_with("background");
+                    let is_color = property == "color" || property == "border-color";
+                    match theme {
+                        Theme::Dark => !is_background && !is_color,
+                        _ => !is_background && (!is_color || !is_gray(value)),
+                    }
+                })
+                .map(|declaration| declaration.trim())
+                .collect();
+            if declarations.is_empty() {
+                String::new()
+            } else {
+                format!(" style=\"{};\"", declarations.join("; "))
+            }
+        })
+        .into()
+}
+
+/// Whether a css color is black, white, or a gray, e.g. "#333" or
+/// "rgb(255, 255, 255)".
+fn is_gray(color: &str) -> bool {
+    let color = color.trim().to_lowercase();
+    let channels: Vec<String> = if let Some(hex) = color.strip_prefix('#') {
+        match hex.len() {
+            3 | 4 => hex.chars().take(3).map(|c| c.to_string()).collect(),
+            6 | 8 => (0..3).map(|i| hex[i * 2..i * 2 + 2].to_string()).collect(),
+            _ => return false,
+        }
+    } else if let Some(args) = color
+        .strip_prefix("rgba(")
+        .or_else(|| color.strip_prefix("rgb("))
+    {
+        args.trim_end_matches(')')
+            .split(',')
+            .take(3)
+            .map(|c| c.trim().to_string())
+            .collect()
+    } else {
+        return ["black", "white", "gray", "grey", "silver"].contains(&color.as_str());
+    };
+    channels.len() == 3 && channels[0] == channels[1] && channels[1] == channels[2]
+}
+
+/// Removes all html tags from `html`, leaving only the text.
+pub fn strip_tags(html: &str) -> String {
+    TAG.replace_all(html, "").trim().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_disallowed_tags_and_attributes() {
+        let (html, changes) = sanitize_html(
+            r#"<DIV onclick="go()" style='color: red'><font size=2>big</font><script>alert("<b>")</script><br></DIV>"#,
+        );
+        assert_eq!(html, r#"<div style="color: red">big<br/></div>"#);
+        assert_eq!(
+            changes,
+            [
+                "removed attribute \"onclick\" from <div>",
+                "removed <font>",
+                "removed <font>",
+                "removed <script> and its content",
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_bare_characters_and_unknown_entities() {
+        let (html, changes) = sanitize_html("R&D &amp; Q&A &foo; &#x3042; 1 < 2");
+        assert_eq!(html, "R&amp;D &amp; Q&amp;A &amp;foo; &#x3042; 1 &lt; 2");
+        assert_eq!(
+            changes,
+            [
+                "escaped bare &",
+                "escaped bare &",
+                "escaped unknown entity &foo;",
+                "escaped bare <",
+            ]
+        );
+    }
+
+    #[test]
+    fn closes_unclosed_tags() {
+        let (html, changes) = sanitize_html("<ol><li><b>one</li><li>two</ol></i><p>three");
+        assert_eq!(html, "<ol><li><b>one</b></li><li>two</li></ol><p>three</p>");
+        assert_eq!(
+            changes,
+            [
+                "closed unclosed <b>",
+                "closed unclosed <li>",
+                "removed stray </i>",
+                "closed unclosed <p>",
+            ]
+        );
+        assert_eq!(
+            summarize_changes(&changes),
+            "closed unclosed <b>; closed unclosed <li>; removed stray </i>; closed unclosed <p>"
+        );
+    }
+}
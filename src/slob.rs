@@ -0,0 +1,335 @@
+//! Types and functions for building and outputting an Aard 2 "slob"
+//! dictionary, for Aard 2 on Android and GoldenDict-ng.
+//!
+//! A slob file is a header, a list of references (key -> item), sorted by
+//! the ICU root collation of their keys, and a store of zlib-compressed bins
+//! of items.  Readers binary-search the references at various collation
+//! strengths, so they must be sorted with a real Unicode collator.  See
+//! https://github.com/itkach/slob for the reference implementation.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use icu_collator::{AlternateHandling, Collator, CollatorOptions, Strength};
+
+use crate::generic_dict::Entry;
+
+const MAGIC: &[u8] = b"!-1SLOB\x1F";
+const HTML_TYPE: &str = "text/html;charset=utf-8";
+const CSS_TYPE: &str = "text/css";
+const CSS_KEY: &str = "~/css/style.css";
+
+// Uncompressed size at which a bin is closed, the same as slob.py's.
+const BIN_SIZE: usize = 512 * 1024;
+
+/// Writes a slob dictionary, named after the file.
+///
+/// `css`, if given, is written as the dictionary's stylesheet.
+pub fn write_dictionary(
+    entries: &[Entry],
+    output_path: &Path,
+    css: Option<&str>,
+) -> std::io::Result<()> {
+    let dict_name = output_path.file_stem().unwrap().to_string_lossy();
+    let mut output = BufWriter::new(std::fs::File::create(output_path)?);
+    write_dictionary_to(entries, &mut output, &dict_name, css)?;
+    output.flush()
+}
+
+/// Same as `write_dictionary()`, but writes the dictionary to `output`,
+/// with `label` as its name.
+pub fn write_dictionary_to<W: Write>(
+    entries: &[Entry],
+    mut output: W,
+    label: &str,
+    css: Option<&str>,
+) -> std::io::Result<()> {
+    // The items, with the index of their content type.  The stylesheet, if
+    // any, comes first.  (content_type, data)
+    let mut content_types = vec![HTML_TYPE];
+    let mut items: Vec<(u8, Vec<u8>)> = Vec::with_capacity(entries.len() + 1);
+    let link = if let Some(css) = css {
+        content_types.push(CSS_TYPE);
+        items.push((1, css.as_bytes().to_vec()));
+        format!("<link rel=\"stylesheet\" href=\"{}\"/>", CSS_KEY)
+    } else {
+        String::new()
+    };
+    for entry in entries.iter() {
+        let html = format!(
+            "<html><head><meta charset=\"utf-8\"/>{}</head><body>{}</body></html>",
+            link, entry.definition
+        );
+        items.push((0, html.into_bytes()));
+    }
+
+    // Pack the items into bins, and note where each one went.
+    // (bin_index, item_index)
+    let mut bins: Vec<Vec<&(u8, Vec<u8>)>> = vec![Vec::new()];
+    let mut locations = Vec::with_capacity(items.len());
+    let mut bin_size = 0;
+    for item in items.iter() {
+        if bin_size >= BIN_SIZE || bins.last().unwrap().len() > u16::MAX as usize {
+            bins.push(Vec::new());
+            bin_size = 0;
+        }
+        let bin_index = bins.len() as u32 - 1;
+        let bin = bins.last_mut().unwrap();
+        locations.push((bin_index, bin.len() as u16));
+        bin.push(item);
+        bin_size += item.1.len();
+    }
+
+    // References, sorted by the collation of their key, and then by
+    // priority and entry.  (key, priority, item_index)
+    let refs: Vec<(&str, u32, usize)> = {
+        let mut refs = Vec::new();
+        let first_entry = if css.is_some() {
+            refs.push((CSS_KEY, 0, 0));
+            1
+        } else {
+            0
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            for (key, priority) in entry.keys.iter() {
+                // Keys are written with a 16-bit length.
+                if key.len() > u16::MAX as usize {
+                    continue;
+                }
+                refs.push((key.as_str(), *priority, first_entry + i));
+            }
+        }
+
+        let collator = collator();
+        refs.sort_by(|a, b| {
+            collator
+                .compare(a.0, b.0)
+                .then_with(|| (a.1, a.2).cmp(&(b.1, b.2)))
+        });
+        refs
+    };
+
+    let ref_list = item_list(refs.iter().map(|&(key, _, item_index)| {
+        let (bin_index, index_in_bin) = locations[item_index];
+        let mut data = Vec::new();
+        push_text(&mut data, key);
+        data.extend(&bin_index.to_be_bytes());
+        data.extend(&index_in_bin.to_be_bytes());
+        push_tiny_text(&mut data, ""); // No fragment.
+        data
+    }));
+
+    let mut store = Vec::with_capacity(bins.len());
+    for bin in bins.iter() {
+        // Item positions, and then the items, each with its length.
+        let mut content = Vec::new();
+        let mut position = 0u32;
+        for item in bin.iter() {
+            content.extend(&position.to_be_bytes());
+            position += 4 + item.1.len() as u32;
+        }
+        for item in bin.iter() {
+            content.extend(&(item.1.len() as u32).to_be_bytes());
+            content.extend(&item.1);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&content)?;
+        let compressed = encoder.finish()?;
+
+        let mut data = Vec::new();
+        data.extend(&(bin.len() as u32).to_be_bytes());
+        data.extend(bin.iter().map(|item| item.0));
+        data.extend(&(compressed.len() as u32).to_be_bytes());
+        data.extend(&compressed);
+        store.push(data);
+    }
+    let store_list = item_list(store.into_iter());
+
+    //----------------------------------------------------------------
+    // Write the file.
+
+    let mut header = Vec::new();
+    header.extend(MAGIC);
+    header.extend(&random_uuid());
+    push_tiny_text(&mut header, "utf-8");
+    push_tiny_text(&mut header, "zlib");
+    let version = format!("kobo_jp_dict {}", env!("CARGO_PKG_VERSION"));
+    let tags = [("label", label), ("created.by", version.as_str())];
+    header.push(tags.len() as u8);
+    for (key, value) in tags.iter() {
+        push_tiny_text(&mut header, key);
+        // Tag values are padded, so that they can be edited in place.
+        let mut value = value.as_bytes()[..value.len().min(255)].to_vec();
+        value.resize(255, 0);
+        header.push(255);
+        header.extend(&value);
+    }
+    header.push(content_types.len() as u8);
+    for content_type in content_types.iter() {
+        push_text(&mut header, content_type);
+    }
+    header.extend(&(items.len() as u32).to_be_bytes());
+    let store_offset = header.len() + 16 + ref_list.len();
+    let file_size = store_offset + store_list.len();
+    header.extend(&(store_offset as u64).to_be_bytes());
+    header.extend(&(file_size as u64).to_be_bytes());
+
+    output.write_all(&header)?;
+    output.write_all(&ref_list)?;
+    output.write_all(&store_list)?;
+    Ok(())
+}
+
+/// The collator that slob readers expect the references to be sorted with:
+/// the root collation, at identical strength, ignoring punctuation and
+/// spaces at the lower strengths.
+fn collator() -> Collator {
+    let mut options = CollatorOptions::new();
+    options.strength = Some(Strength::Identical);
+    options.alternate_handling = Some(AlternateHandling::Shifted);
+    Collator::try_new(&Default::default(), options).expect("ICU collation data is built in")
+}
+
+/// A list of items as slob stores them: the count, the position of each
+/// item relative to the end of the positions, and then the items.
+fn item_list<I: Iterator<Item = Vec<u8>>>(items: I) -> Vec<u8> {
+    let items: Vec<Vec<u8>> = items.collect();
+    let mut data = Vec::new();
+    data.extend(&(items.len() as u32).to_be_bytes());
+    let mut position = 0u64;
+    for item in items.iter() {
+        data.extend(&position.to_be_bytes());
+        position += item.len() as u64;
+    }
+    for item in items.iter() {
+        data.extend(item);
+    }
+    data
+}
+
+/// Text with a 16-bit length.
+fn push_text(data: &mut Vec<u8>, text: &str) {
+    data.extend(&(text.len() as u16).to_be_bytes());
+    data.extend(text.as_bytes());
+}
+
+/// Text with an 8-bit length.
+fn push_tiny_text(data: &mut Vec<u8>, text: &str) {
+    data.push(text.len() as u8);
+    data.extend(text.as_bytes());
+}
+
+/// A version 4 (random) UUID, which readers use to tell dictionaries apart.
+fn random_uuid() -> [u8; 16] {
+    let mut uuid = [0u8; 16];
+    for (i, chunk) in uuid.chunks_mut(8).enumerate() {
+        let hash = RandomState::new().hash_one((i, std::time::SystemTime::now()));
+        chunk.copy_from_slice(&hash.to_le_bytes());
+    }
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+
+    /// A minimal slob reader: the (key, content) of each reference, in
+    /// order.
+    fn read(data: &[u8]) -> Vec<(String, String)> {
+        let mut pos = 0;
+        let mut take = |n: usize| {
+            pos += n;
+            &data[(pos - n)..pos]
+        };
+        let u8_at = |b: &[u8]| b[0] as usize;
+        let u16_at = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]) as usize;
+        let u32_at = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize;
+
+        assert_eq!(take(MAGIC.len()), MAGIC);
+        take(16);
+        for _ in 0..2 {
+            let len = u8_at(take(1));
+            take(len); // Encoding and compression.
+        }
+        for _ in 0..u8_at(take(1)) {
+            let len = u8_at(take(1));
+            take(len);
+            assert_eq!(u8_at(take(1)), 255);
+            take(255);
+        }
+        let mut content_types = Vec::new();
+        for _ in 0..u8_at(take(1)) {
+            let len = u16_at(take(2));
+            content_types.push(take(len).to_vec());
+        }
+        take(4 + 8); // Blob count, store offset.
+        assert_eq!(u32_at(&take(8)[4..]), data.len());
+
+        let list = |start: usize| {
+            let count = u32_at(&data[start..]);
+            let data_start = start + 4 + count * 8;
+            (0..count)
+                .map(|i| data_start + u32_at(&data[(start + 4 + i * 8 + 4)..]))
+                .collect::<Vec<_>>()
+        };
+        let refs = list(pos);
+        let store_offset = u32_at(&data[(pos - 12)..]);
+        let bins = list(store_offset);
+
+        refs.iter()
+            .map(|&at| {
+                let key_len = u16_at(&data[at..]);
+                let key = std::str::from_utf8(&data[(at + 2)..(at + 2 + key_len)]).unwrap();
+                let bin_index = u32_at(&data[(at + 2 + key_len)..]);
+                let item_index = u16_at(&data[(at + 6 + key_len)..]);
+
+                let bin = bins[bin_index];
+                let count = u32_at(&data[bin..]);
+                let compressed_len = u32_at(&data[(bin + 4 + count)..]);
+                let compressed = &data[(bin + 8 + count)..(bin + 8 + count + compressed_len)];
+                let mut content = Vec::new();
+                ZlibDecoder::new(compressed)
+                    .read_to_end(&mut content)
+                    .unwrap();
+                let item = count * 4 + u32_at(&content[(item_index * 4)..]);
+                let len = u32_at(&content[item..]);
+                let text = std::str::from_utf8(&content[(item + 4)..(item + 4 + len)]).unwrap();
+                (key.to_string(), text.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let entry = |keys: &[(&str, u32)], definition: &str| Entry {
+            keys: keys.iter().map(|&(k, p)| (k.to_string(), p)).collect(),
+            definition: definition.into(),
+        };
+        let entries = vec![
+            entry(&[("猫", 0), ("ねこ", 5)], "cat"),
+            entry(&[("ネコ", 0)], "cat (katakana)"),
+            entry(&[("cat", 0), ("Cat", 0)], "猫"),
+        ];
+        let mut data = Vec::new();
+        write_dictionary_to(&entries, &mut data, "test", Some("p { margin: 0; }")).unwrap();
+
+        let refs = read(&data);
+        let keys: Vec<&str> = refs.iter().map(|(key, _)| key.as_str()).collect();
+        // Case and kana type only break ties: lowercase and hiragana first.
+        assert_eq!(
+            keys,
+            ["~/css/style.css", "cat", "Cat", "ねこ", "ネコ", "猫"]
+        );
+        assert_eq!(refs[0].1, "p { margin: 0; }");
+        assert!(refs[3].1.contains("<body>cat</body>"));
+        assert!(refs[3].1.contains("href=\"~/css/style.css\""));
+        assert_eq!(refs[3].1, refs[5].1);
+    }
+}
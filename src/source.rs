@@ -0,0 +1,433 @@
+//! A common interface for the input dictionaries, so that they can all be
+//! loaded the same way regardless of their format.
+//!
+//! Each source yields `SourceItem`s, which are normalized and sorted into
+//! the `Tables` that entry generation works from.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use rustc_hash::FxHashMap;
+
+use crate::jmdict;
+use crate::kana::{self, hiragana_to_katakana, is_all_kana, strip_non_kana};
+use crate::observer;
+use crate::warnings;
+use crate::yomichan;
+
+/// An input dictionary.
+pub trait DictionarySource {
+    /// A short name for the source, used in messages and for `--limit`.
+    fn name(&self) -> String;
+
+    /// Parses the source into items.
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>>;
+}
+
+/// A single item from an input dictionary.
+#[derive(Clone, Debug)]
+pub enum SourceItem {
+    // Metadata about a word (writings, readings, part of speech, priority,
+    // etc.), as in JMDict.  Only words with metadata get term entries.
+    Word(jmdict::WordEntry),
+
+    // The definitions of a word.
+    Term(yomichan::TermEntry),
+
+    // The definitions of a name.
+    Name(yomichan::TermEntry),
+
+    Kanji(yomichan::KanjiEntry),
+
+    // Frequency or pitch accent data for a word.
+    TermMeta(yomichan::TermMetaEntry),
+
+    // (writing, reading, accents).  Takes precedence over pitch accent
+    // data from `TermMeta` items.
+    PitchAccent(String, String, Vec<u32>),
+
+    // (writing, reading, (part of speech label, accent)), for the accents
+    // of a `PitchAccent` item that only apply to some parts of speech.
+    // Always comes right after the `PitchAccent` item of the same word.
+    PosPitchAccent(String, String, Vec<(String, u32)>),
+
+    // (kanji, components), as in KRADFILE.
+    KanjiComponents(String, Vec<String>),
+
+    // (kanji, ideographic description sequence), e.g. ("好", "⿰女子").
+    KanjiDecomposition(String, String),
+}
+
+/// How to handle Yomichan dictionaries that are loaded more than once, e.g.
+/// two versions of the same dictionary.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Dedupe {
+    Off, // Keep everything.
+
+    // A later source with the same dictionary title replaces the earlier
+    // one, and definitions identical to ones already loaded for the same
+    // word are dropped.
+    Replace,
+
+    // All sources are kept, but definitions identical to ones already
+    // loaded for the same word are dropped.
+    Append,
+}
+
+/// The items of all sources, keyed for entry generation.
+///
+/// Word keys are (writing, reading) pairs, with the reading in katakana.
+pub struct Tables {
+    pub jm_table: FxHashMap<(String, String), Vec<jmdict::WordEntry>>,
+    pub pa_table: FxHashMap<(String, String), Vec<u32>>,
+    pub pa_pos_table: FxHashMap<(String, String), Vec<(String, u32)>>, // (POS label, accent)
+    pub phonetics_table: FxHashMap<(String, String), yomichan::Phonetics>,
+    pub yomi_term_table: FxHashMap<(String, String), Vec<yomichan::TermEntry>>,
+    pub yomi_name_table: FxHashMap<(String, String), Vec<yomichan::TermEntry>>,
+    pub yomi_kanji_table: FxHashMap<String, Vec<yomichan::KanjiEntry>>,
+    pub yomi_freq_table: FxHashMap<(String, String), u32>, // Frequency rank
+    pub kanji_component_table: FxHashMap<String, Vec<String>>,
+    pub kanji_decomposition_table: FxHashMap<String, String>,
+
+    pub dedupe: Dedupe,
+
+    // The titles of the Yomichan dictionaries loaded so far.
+    loaded_dicts: HashSet<Arc<str>>,
+
+    // The name of the source being loaded, and the source each pitch
+    // accent file row came from, for reporting overridden accents.
+    current_source: Arc<str>,
+    pa_sources: FxHashMap<(String, String), Arc<str>>,
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tables {
+    pub fn new() -> Tables {
+        // Pre-sized for the bundled JMDict data.
+        const JM_ENTRY_COUNT_HINT: usize = 200_000;
+
+        Tables {
+            jm_table: FxHashMap::with_capacity_and_hasher(JM_ENTRY_COUNT_HINT, Default::default()),
+            pa_table: FxHashMap::default(),
+            pa_pos_table: FxHashMap::default(),
+            phonetics_table: FxHashMap::default(),
+            yomi_term_table: FxHashMap::default(),
+            yomi_name_table: FxHashMap::default(),
+            yomi_kanji_table: FxHashMap::default(),
+            yomi_freq_table: FxHashMap::default(),
+            kanji_component_table: FxHashMap::default(),
+            kanji_decomposition_table: FxHashMap::default(),
+            dedupe: Dedupe::Off,
+            loaded_dicts: HashSet::new(),
+            current_source: "".into(),
+            pa_sources: FxHashMap::default(),
+        }
+    }
+
+    /// Loads all of the items of `source`, taking at most `limit` items of
+    /// each kind.
+    ///
+    /// Returns the number of items loaded.
+    pub fn load(
+        &mut self,
+        source: Box<dyn DictionarySource + '_>,
+        limit: usize,
+    ) -> std::io::Result<usize> {
+        observer::phase_started(&format!("{} parsing", source.name()));
+        self.current_source = source.name().into();
+
+        let mut counts = HashMap::new();
+        let mut total = 0;
+        let mut dicts: HashSet<Arc<str>> = HashSet::new();
+        for item in source.parse()? {
+            let count = counts
+                .entry(std::mem::discriminant(&item))
+                .or_insert(0usize);
+            if *count >= limit {
+                continue;
+            }
+            *count += 1;
+            total += 1;
+            if total % 10_000 == 0 {
+                observer::check_cancelled()?;
+            }
+
+            let dict_name = match item {
+                SourceItem::Term(ref e) | SourceItem::Name(ref e) => Some(&e.dict_name),
+                SourceItem::Kanji(ref e) => Some(&e.dict_name),
+                _ => None,
+            };
+            if let Some(dict_name) = dict_name {
+                if !dicts.contains(dict_name) {
+                    dicts.insert(dict_name.clone());
+                    if self.dedupe == Dedupe::Replace && self.loaded_dicts.contains(dict_name) {
+                        self.remove_dict(dict_name);
+                    }
+                }
+            }
+
+            self.add(item);
+        }
+        self.loaded_dicts.extend(dicts);
+
+        Ok(total)
+    }
+
+    /// Removes all of the items of the Yomichan dictionary titled
+    /// `dict_name`.
+    fn remove_dict(&mut self, dict_name: &str) {
+        for table in [&mut self.yomi_term_table, &mut self.yomi_name_table] {
+            table.retain(|_, items| {
+                items.retain(|e| &*e.dict_name != dict_name);
+                !items.is_empty()
+            });
+        }
+        self.yomi_kanji_table.retain(|_, items| {
+            items.retain(|e| &*e.dict_name != dict_name);
+            !items.is_empty()
+        });
+    }
+
+    /// Normalizes `item` and adds it to the appropriate table.
+    pub fn add(&mut self, item: SourceItem) {
+        match item {
+            SourceItem::Word(mut entry) => {
+                for text in entry
+                    .writings
+                    .iter_mut()
+                    .chain(entry.readings.iter_mut())
+                    .chain(entry.reading_restrictions.iter_mut().flatten())
+                {
+                    *text = kana::normalize(text);
+                }
+
+                let reading = strip_non_kana(&hiragana_to_katakana(entry.readings[0].trim()));
+                let writing = if !entry.writings.is_empty() {
+                    entry.writings[0].clone()
+                } else {
+                    entry.readings[0].trim().into()
+                };
+
+                let e = self.jm_table.entry((writing, reading)).or_default();
+                e.push(entry);
+            }
+
+            SourceItem::Term(mut entry) => {
+                entry.writing = kana::normalize(&entry.writing);
+                entry.reading = kana::normalize(&entry.reading);
+                let reading = strip_non_kana(&hiragana_to_katakana(entry.reading.trim()));
+                let writing: String = entry.writing.trim().into();
+                let key = if writing.is_empty() {
+                    (entry.reading.trim().into(), reading)
+                } else if reading.is_empty() && is_all_kana(&writing) {
+                    let derived_reading = hiragana_to_katakana(&writing);
+                    (writing, derived_reading)
+                } else {
+                    (writing, reading)
+                };
+                let items = self.yomi_term_table.entry(key).or_default();
+                if self.dedupe != Dedupe::Off
+                    && items.iter().any(|e| e.definitions == entry.definitions)
+                {
+                    return;
+                }
+                items.push(entry);
+            }
+
+            SourceItem::Name(mut entry) => {
+                entry.writing = kana::normalize(&entry.writing);
+                entry.reading = kana::normalize(&entry.reading);
+                let reading = strip_non_kana(&hiragana_to_katakana(entry.reading.trim()));
+                let writing: String = entry.writing.trim().into();
+                let key = if writing.is_empty() {
+                    (entry.reading.trim().into(), reading)
+                } else {
+                    (writing, reading)
+                };
+                self.yomi_name_table.entry(key).or_default().push(entry);
+            }
+
+            SourceItem::Kanji(entry) => {
+                self.yomi_kanji_table
+                    .entry(entry.kanji.clone())
+                    .or_default()
+                    .push(entry);
+            }
+
+            SourceItem::TermMeta(mut entry) => {
+                entry.writing = kana::normalize(&entry.writing);
+                entry.reading = kana::normalize(&entry.reading);
+                let writing: String = entry.writing.trim().into();
+                let reading = if entry.reading.trim().is_empty() && is_all_kana(&writing) {
+                    hiragana_to_katakana(&writing)
+                } else {
+                    strip_non_kana(&hiragana_to_katakana(entry.reading.trim()))
+                };
+                match entry.meta {
+                    yomichan::TermMeta::Frequency(rank) => {
+                        let r = self
+                            .yomi_freq_table
+                            .entry((writing, reading))
+                            .or_insert(rank);
+                        *r = (*r).min(rank);
+                    }
+                    yomichan::TermMeta::PitchAccent(accents, phonetics) => {
+                        if !phonetics.is_empty() {
+                            self.phonetics_table
+                                .entry((writing.clone(), reading.clone()))
+                                .or_insert(phonetics);
+                        }
+                        self.pa_table.entry((writing, reading)).or_insert(accents);
+                    }
+                }
+            }
+
+            SourceItem::PitchAccent(writing, reading, accents) => {
+                let key = pitch_accent_key(&writing, &reading);
+                self.pa_pos_table.remove(&key);
+                let previous_source = self
+                    .pa_sources
+                    .insert(key.clone(), self.current_source.clone());
+                // Only words from different files are reported: files that
+                // list a word twice are left to their authors.
+                match (self.pa_table.get(&key), previous_source) {
+                    (Some(previous_accents), Some(previous_source))
+                        if *previous_accents != accents
+                            && previous_source != self.current_source =>
+                    {
+                        warnings::warn(
+                            warnings::Category::PitchAccentConflict,
+                            &self.current_source,
+                            format!(
+                                "{} ({}): {:?} replaces {:?} from \"{}\"",
+                                key.0, key.1, accents, previous_accents, previous_source
+                            ),
+                        );
+                    }
+                    _ => {}
+                }
+                self.pa_table.insert(key, accents);
+            }
+
+            SourceItem::PosPitchAccent(writing, reading, accents) => {
+                self.pa_pos_table
+                    .insert(pitch_accent_key(&writing, &reading), accents);
+            }
+
+            SourceItem::KanjiComponents(kanji, components) => {
+                self.kanji_component_table.insert(kanji, components);
+            }
+
+            SourceItem::KanjiDecomposition(kanji, ids) => {
+                self.kanji_decomposition_table.insert(kanji, ids);
+            }
+        }
+    }
+}
+
+/// Normalizes the writing and reading of a pitch accent item into a word
+/// key.
+fn pitch_accent_key(writing: &str, reading: &str) -> (String, String) {
+    let (writing, reading) = (kana::normalize(writing), kana::normalize(reading));
+    let reading = if is_all_kana(&writing) && reading.is_empty() {
+        hiragana_to_katakana(&writing)
+    } else {
+        hiragana_to_katakana(&reading)
+    };
+    (writing, reading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source with the given items.
+    struct Items(&'static str, Vec<SourceItem>);
+
+    impl DictionarySource for Items {
+        fn name(&self) -> String {
+            self.0.into()
+        }
+
+        fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+            Ok(Box::new(self.1.into_iter()))
+        }
+    }
+
+    fn pitch(accents: &[u32]) -> SourceItem {
+        SourceItem::PitchAccent("鏡".into(), "かがみ".into(), accents.to_vec())
+    }
+
+    fn term_meta(accents: &[u32]) -> SourceItem {
+        SourceItem::TermMeta(yomichan::TermMetaEntry {
+            dict_name: "nhk".into(),
+            writing: "鏡".into(),
+            reading: "かがみ".into(),
+            meta: yomichan::TermMeta::PitchAccent(accents.to_vec(), Default::default()),
+        })
+    }
+
+    /// The overridden pitch accent warnings about 鏡.
+    fn conflicts() -> Vec<(String, String)> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        warnings::write_json(file.path()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(file.path()).unwrap()).unwrap();
+        json.get(warnings::Category::PitchAccentConflict.name())
+            .and_then(|w| w.as_array())
+            .map(|w| w.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .map(|w| {
+                (
+                    w["source"].as_str().unwrap().to_string(),
+                    w["message"].as_str().unwrap().to_string(),
+                )
+            })
+            .filter(|(_, message)| message.starts_with("鏡 "))
+            .collect()
+    }
+
+    #[test]
+    fn pitch_accent_precedence_and_conflicts() {
+        let key = ("鏡".to_string(), "カガミ".to_string());
+        let mut tables = Tables::new();
+        let mut load = |name, items| {
+            tables
+                .load(Box::new(Items(name, items)), usize::MAX)
+                .unwrap();
+            tables.pa_table.get(&key).cloned()
+        };
+
+        // The first Yomichan accents are kept...
+        assert_eq!(
+            load("yomichan", vec![term_meta(&[3]), term_meta(&[0])]),
+            Some(vec![3])
+        );
+        // ...until a pitch accent file gives some, which Yomichan accents
+        // don't override.
+        assert_eq!(load("a.tsv", vec![pitch(&[0])]), Some(vec![0]));
+        assert_eq!(load("yomichan", vec![term_meta(&[3])]), Some(vec![0]));
+        // A file that lists a word twice isn't reported.
+        assert_eq!(load("a.tsv", vec![pitch(&[0]), pitch(&[3])]), Some(vec![3]));
+        assert!(conflicts().is_empty());
+
+        // A later file overrides an earlier one, and that's reported
+        // unless the accents are the same.
+        assert_eq!(load("b.tsv", vec![pitch(&[3])]), Some(vec![3]));
+        assert!(conflicts().is_empty());
+        assert_eq!(load("c.tsv", vec![pitch(&[0, 3])]), Some(vec![0, 3]));
+        assert_eq!(
+            conflicts(),
+            [(
+                "c.tsv".to_string(),
+                "鏡 (カガミ): [0, 3] replaces [3] from \"b.tsv\"".to_string()
+            )]
+        );
+    }
+}
@@ -6,7 +6,19 @@ use std::path::Path;
 
 use crate::generic_dict::Entry;
 
-pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Result<()> {
+/// Writes a zipped StarDict dictionary.
+///
+/// If `use_synonyms` is true, only the first key of each entry is written to
+/// the index, and the remaining keys are written as synonyms in a `.syn` file.
+/// This is what KOReader expects for inflected forms.
+///
+/// `css`, if given, is written as the dictionary's stylesheet.
+pub fn write_dictionary(
+    entries: &[Entry],
+    output_path: &Path,
+    use_synonyms: bool,
+    css: Option<&str>,
+) -> std::io::Result<()> {
     let dict_name = output_path.file_stem().unwrap().to_string_lossy();
 
     // Keys, sorted by string and then priority, with their priority and entry
@@ -14,14 +26,22 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
     let keys: Vec<(String, u32, usize)> = {
         let max_priority = entries
             .iter()
-            .map(|e| &e.keys[..])
-            .flatten()
+            .flat_map(|e| &e.keys[..])
             .fold(0u32, |a, b| a.max(b.1));
 
         let mut keys: Vec<(String, u32, usize)> = Vec::new();
 
         for (i, entry) in entries.iter().enumerate() {
-            for entry_key in entry.keys.iter() {
+            let entry_keys = if use_synonyms {
+                &entry.keys[..1]
+            } else {
+                &entry.keys[..]
+            };
+            for entry_key in entry_keys.iter() {
+                // StarDict doesn't support keys longer than 255 bytes.
+                if entry_key.0.len() > 255 {
+                    continue;
+                }
                 keys.push((entry_key.0.clone(), max_priority - entry_key.1, i));
             }
         }
@@ -35,6 +55,38 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
         keys
     };
 
+    // Synonyms, sorted by string, with the index of the key they point to.
+    // (synonym, key_index)
+    let synonyms: Vec<(String, usize)> = if use_synonyms {
+        let mut entry_to_key_idx = vec![None; entries.len()];
+        for (key_idx, &(_, _, entry_idx)) in keys.iter().enumerate() {
+            entry_to_key_idx[entry_idx] = Some(key_idx);
+        }
+
+        let mut synonyms: Vec<(String, usize)> = Vec::new();
+        for (entry, key_idx) in entries.iter().zip(entry_to_key_idx.iter()) {
+            if let Some(key_idx) = *key_idx {
+                for entry_key in entry.keys[1..].iter() {
+                    if entry_key.0.len() > 255 || entry_key.0 == entry.keys[0].0 {
+                        continue;
+                    }
+                    synonyms.push((entry_key.0.clone(), key_idx));
+                }
+            }
+        }
+
+        synonyms.sort_unstable_by(|a, b| match stardict_strcmp(&a.0, &b.0) {
+            std::cmp::Ordering::Equal => a.1.cmp(&b.1),
+            std::cmp::Ordering::Less => std::cmp::Ordering::Less,
+            std::cmp::Ordering::Greater => std::cmp::Ordering::Greater,
+        });
+        synonyms.dedup();
+
+        synonyms
+    } else {
+        Vec::new()
+    };
+
     // For the .dict file.
     let (dict_data, dict_offset_len) = {
         let mut data = Vec::new();
@@ -55,9 +107,6 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
         let mut count = 0usize;
 
         for &(ref key, _, entry_idx) in &keys {
-            if key.len() > 255 {
-                continue;
-            }
             let (offset, length) = dict_offset_len[entry_idx];
             data.extend(key.as_bytes());
             data.push(0); // Zero-terminated.
@@ -69,8 +118,21 @@ pub fn write_dictionary(entries: &[Entry], output_path: &Path) -> std::io::Resul
         (data, count)
     };
 
+    // For the .syn file.
+    let syn_data = {
+        let mut data = Vec::new();
+
+        for &(ref synonym, key_idx) in &synonyms {
+            data.extend(synonym.as_bytes());
+            data.push(0); // Zero-terminated.
+            data.extend(&(key_idx as u32).to_be_bytes());
+        }
+
+        data
+    };
+
     // For the .ifo file.
-    let ifo_data: String = format!(
+    let mut ifo_data: String = format!(
         "StarDict's dict ifo file
 version=3.0.0
 bookname={}
@@ -83,6 +145,9 @@ lang=ja-en
         idx_count,
         idx_data.len(),
     );
+    if use_synonyms {
+        ifo_data.push_str(&format!("synwordcount={}\n", synonyms.len()));
+    }
 
     //----------------------------------------------------------------
     // Write a zip file with all the files for the dictionary in it.
@@ -106,6 +171,15 @@ lang=ja-en
         .unwrap();
     zip_out.write_all(&idx_data).unwrap();
 
+    // Syn file.
+    if use_synonyms {
+        let syn_filepath = format!("{}.syn", base_path);
+        zip_out
+            .start_file(&syn_filepath, zip::write::FileOptions::default())
+            .unwrap();
+        zip_out.write_all(&syn_data).unwrap();
+    }
+
     // Ifo file.
     let ifo_filepath = format!("{}.ifo", base_path);
     zip_out
@@ -113,6 +187,15 @@ lang=ja-en
         .unwrap();
     zip_out.write_all(ifo_data.as_bytes()).unwrap();
 
+    // Css file.
+    if let Some(css) = css {
+        let css_filepath = format!("{}.css", base_path);
+        zip_out
+            .start_file(&css_filepath, zip::write::FileOptions::default())
+            .unwrap();
+        zip_out.write_all(css.as_bytes()).unwrap();
+    }
+
     zip_out.finish().unwrap();
     Ok(())
 }
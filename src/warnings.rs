@@ -0,0 +1,160 @@
+//! A registry of the non-fatal problems found in the input data (e.g.
+//! malformed entries that were skipped), so that they can be summarized at
+//! the end of the build rather than lost in the output or ignored.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::observer;
+
+lazy_static! {
+    static ref WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+    // The warnings recorded so far, for `warn_once()`.
+    static ref SEEN: Mutex<HashSet<Warning>> = Mutex::new(HashSet::new());
+}
+
+/// How many warnings of each category are printed in the summary.  The
+/// rest are only counted.
+const MAX_PRINTED_PER_CATEGORY: usize = 5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    JmdictElement,
+    JmdictWord,
+    YomichanEntry,
+    PitchAccentLine,
+    PitchAccentConflict,
+    KoboEntry,
+    DictionarySize,
+    TextEncoding,
+}
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::JmdictElement => "unknown JMDict elements",
+            Category::JmdictWord => "malformed JMDict words",
+            Category::YomichanEntry => "skipped Yomichan entries",
+            Category::PitchAccentLine => "malformed pitch accent lines",
+            Category::PitchAccentConflict => "overridden pitch accents",
+            Category::KoboEntry => "skipped Kobo dictionary entries",
+            Category::DictionarySize => "dictionary size limits",
+            Category::TextEncoding => "undecodable text",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Warning {
+    category: Category,
+    source: String, // The file or source name the problem is in.
+    message: String,
+}
+
+/// Records a warning.
+pub fn warn(category: Category, source: &str, message: String) {
+    observer::warning(category.name(), source, &message);
+    let warning = Warning {
+        category,
+        source: source.into(),
+        message,
+    };
+    SEEN.lock().unwrap().insert(warning.clone());
+    WARNINGS.lock().unwrap().push(warning);
+}
+
+/// Records a warning, unless an identical one has already been recorded.
+/// For problems that would otherwise be reported once per entry.
+pub fn warn_once(category: Category, source: &str, message: String) {
+    let warning = Warning {
+        category,
+        source: source.into(),
+        message,
+    };
+    let mut seen = SEEN.lock().unwrap();
+    if !seen.contains(&warning) {
+        observer::warning(category.name(), source, &warning.message);
+        seen.insert(warning.clone());
+        WARNINGS.lock().unwrap().push(warning);
+    }
+}
+
+/// Prints the number of warnings in each category, along with the first
+/// few of each.  Prints nothing if there were no warnings.
+pub fn print_summary() {
+    let warnings = WARNINGS.lock().unwrap();
+    if warnings.is_empty() {
+        return;
+    }
+
+    let mut by_category: BTreeMap<Category, Vec<&Warning>> = BTreeMap::new();
+    for warning in warnings.iter() {
+        by_category
+            .entry(warning.category)
+            .or_default()
+            .push(warning);
+    }
+
+    eprintln!("Warnings:");
+    for (category, items) in by_category.iter() {
+        eprintln!("    {}: {}", category.name(), items.len());
+        for warning in items.iter().take(MAX_PRINTED_PER_CATEGORY) {
+            eprintln!("        {}: {}", warning.source, warning.message);
+        }
+        if items.len() > MAX_PRINTED_PER_CATEGORY {
+            eprintln!(
+                "        ...and {} more",
+                items.len() - MAX_PRINTED_PER_CATEGORY
+            );
+        }
+    }
+}
+
+/// Prints each warning as a JSON object on its own line.
+pub fn print_json_lines() {
+    let warnings = WARNINGS.lock().unwrap();
+    for warning in warnings.iter() {
+        println!(
+            "{}",
+            json!({
+                "event": "warning",
+                "category": warning.category.name(),
+                "source": warning.source.as_str(),
+                "message": warning.message.as_str(),
+            })
+        );
+    }
+}
+
+/// Writes all warnings to `path`, as a JSON object with one list of
+/// warnings per category.
+pub fn write_json(path: &Path) -> std::io::Result<()> {
+    let warnings = WARNINGS.lock().unwrap();
+
+    let mut by_category: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for warning in warnings.iter() {
+        by_category
+            .entry(warning.category.name())
+            .or_default()
+            .push(json!({
+                "source": warning.source.as_str(),
+                "message": warning.message.as_str(),
+            }));
+    }
+
+    let json = Value::Object(
+        by_category
+            .into_iter()
+            .map(|(category, items)| (category.into(), Value::Array(items)))
+            .collect(),
+    );
+    let mut f = std::fs::File::create(path)?;
+    f.write_all(serde_json::to_string_pretty(&json)?.as_bytes())?;
+    f.write_all(b"\n")?;
+    Ok(())
+}
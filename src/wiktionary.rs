@@ -0,0 +1,453 @@
+//! Parses Japanese Wiktionary (ja.wiktionary.org) XML dumps, e.g.
+//! jawiktionary-latest-pages-articles.xml, as a source of Japanese
+//! definitions.
+//!
+//! Only the Japanese section of each article is used.  Its senses are
+//! grouped by part of speech under headers like "［名詞］", as with
+//! Yomichan's `group_by_pos`, followed by its etymology if it has one.
+//!
+//! Like other definition sources, pages only end up in entries if they
+//! match a JMDict word, by their title and the readings in their headword
+//! lines (e.g. "'''学校'''（がっこう）") or `ja-` templates.  Pages without
+//! any reading are skipped, unless their title is all kana.
+//!
+//! Dumps can be gzipped, but not bzip2-compressed as they're downloaded.
+
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use quick_xml::events::Event;
+use regex::Regex;
+
+use crate::intern::intern;
+use crate::kana::is_all_kana;
+use crate::source::{DictionarySource, SourceItem};
+use crate::yomichan::{Definition, InflectionType, TermEntry};
+
+/// The dictionary name the definitions are shown under.
+const DICT_NAME: &str = "Wiktionary";
+
+/// Section headings of parts of speech, as (heading, label).  The
+/// headings are either Japanese or the names of heading templates, e.g.
+/// "{{noun}}".
+const PARTS_OF_SPEECH: &[(&str, &str)] = &[
+    ("名詞", "名詞"),
+    ("noun", "名詞"),
+    ("固有名詞", "固有名詞"),
+    ("propn", "固有名詞"),
+    ("name", "固有名詞"),
+    ("代名詞", "代名詞"),
+    ("pronoun", "代名詞"),
+    ("動詞", "動詞"),
+    ("verb", "動詞"),
+    ("形容詞", "形容詞"),
+    ("adjective", "形容詞"),
+    ("adj", "形容詞"),
+    ("形容動詞", "形容動詞"),
+    ("副詞", "副詞"),
+    ("adverb", "副詞"),
+    ("adv", "副詞"),
+    ("連体詞", "連体詞"),
+    ("接続詞", "接続詞"),
+    ("conjunction", "接続詞"),
+    ("conj", "接続詞"),
+    ("感動詞", "感動詞"),
+    ("interjection", "感動詞"),
+    ("interj", "感動詞"),
+    ("助詞", "助詞"),
+    ("particle", "助詞"),
+    ("助動詞", "助動詞"),
+    ("auxverb", "助動詞"),
+    ("接頭辞", "接頭辞"),
+    ("prefix", "接頭辞"),
+    ("接尾辞", "接尾辞"),
+    ("suffix", "接尾辞"),
+    ("助数詞", "助数詞"),
+    ("counter", "助数詞"),
+    ("数詞", "数詞"),
+    ("numeral", "数詞"),
+    ("num", "数詞"),
+    ("成句", "成句"),
+    ("idiom", "成句"),
+    ("phrase", "成句"),
+    ("慣用句", "慣用句"),
+    ("ことわざ", "ことわざ"),
+    ("proverb", "ことわざ"),
+    ("略語", "略語"),
+    ("abbr", "略語"),
+];
+
+lazy_static! {
+    static ref TEMPLATE: Regex = Regex::new(r"\{\{([^{}]*)\}\}").unwrap();
+    static ref LINK: Regex = Regex::new(r"\[\[([^\[\]|]*)(?:\|([^\[\]]*))?\]\]").unwrap();
+    static ref REF: Regex = Regex::new(r"<ref[^>]*/>|<ref[^>]*>.*?</ref>|<!--.*?-->").unwrap();
+    static ref BOLD: Regex = Regex::new(r"'''(.*?)'''").unwrap();
+    static ref ITALIC: Regex = Regex::new(r"''(.*?)''").unwrap();
+    static ref JA_TEMPLATE: Regex = Regex::new(r"\{\{ja-[^|{}]*\|([^{}]*)\}\}").unwrap();
+}
+
+pub struct Source {
+    name: String,
+    reader: Box<dyn BufRead>,
+}
+
+/// Opens a Wiktionary dump as a dictionary source, detecting whether it's
+/// gzipped from its content.
+pub fn open_source(path: &Path) -> std::io::Result<Box<dyn DictionarySource>> {
+    let mut reader: Box<dyn BufRead> = Box::new(BufReader::new(std::fs::File::open(path)?));
+    if reader.fill_buf()?.starts_with(b"BZh") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "bzip2-compressed dumps aren't supported, decompress it (or recompress it with gzip) first",
+        ));
+    }
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        reader = Box::new(BufReader::new(GzDecoder::new(reader)));
+    }
+
+    Ok(Box::new(Source {
+        name: path.to_string_lossy().into_owned(),
+        reader,
+    }))
+}
+
+impl DictionarySource for Source {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        let dict_name = intern(DICT_NAME);
+        let pages = Pages {
+            xml_parser: quick_xml::Reader::from_reader(self.reader),
+            buf: Vec::new(),
+        };
+
+        Ok(Box::new(
+            pages
+                .flat_map(move |(title, text)| page_entries(&title, &text, &dict_name))
+                .map(SourceItem::Term),
+        ))
+    }
+}
+
+/// An iterator over the (title, wikitext) of the articles of a dump, parsing
+/// the input as it goes.  Talk pages, templates, etc. are skipped.
+struct Pages<R: BufRead> {
+    xml_parser: quick_xml::Reader<R>,
+    buf: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Elem {
+    Title,
+    Namespace,
+    Text,
+    None,
+}
+
+impl<R: BufRead> Iterator for Pages<R> {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<(String, String)> {
+        let mut title = String::new();
+        let mut namespace = String::new();
+        let mut text = String::new();
+        let mut cur_elem = Elem::None;
+
+        loop {
+            self.buf.clear();
+            match self.xml_parser.read_event_into(&mut self.buf) {
+                Ok(Event::Start(ref e)) => {
+                    cur_elem = match e.name().as_ref() {
+                        b"title" => Elem::Title,
+                        b"ns" => Elem::Namespace,
+                        b"text" => Elem::Text,
+                        _ => Elem::None,
+                    };
+                }
+                Ok(Event::Text(ref e)) => {
+                    let target = match cur_elem {
+                        Elem::Title => &mut title,
+                        Elem::Namespace => &mut namespace,
+                        Elem::Text => &mut text,
+                        Elem::None => continue,
+                    };
+                    match e.unescape() {
+                        Ok(t) => target.push_str(&t),
+                        Err(e) => panic!(
+                            "Error at position {}: {:?}",
+                            self.xml_parser.buffer_position(),
+                            e
+                        ),
+                    }
+                }
+                Ok(Event::End(ref e)) => {
+                    cur_elem = Elem::None;
+                    if e.name().as_ref() == b"page" {
+                        // Articles are in the main namespace, 0.
+                        if namespace.trim() == "0" && !text.is_empty() {
+                            return Some((title, text));
+                        }
+                        title.clear();
+                        namespace.clear();
+                        text.clear();
+                    }
+                }
+                Err(e) => {
+                    panic!(
+                        "Error at position {}: {:?}",
+                        self.xml_parser.buffer_position(),
+                        e
+                    )
+                }
+                Ok(Event::Eof) => {
+                    return None;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// The kind of content under a section heading.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Section {
+    PartOfSpeech,
+    Etymology,
+    Other, // Pronunciation, translations, related words, etc.
+}
+
+/// Returns the level and text of a heading line, e.g. (3, "名詞") for
+/// "=== 名詞 ===".
+fn heading(line: &str) -> Option<(usize, &str)> {
+    if !line.starts_with('=') || !line.ends_with('=') {
+        return None;
+    }
+    let level = line.chars().take_while(|&c| c == '=').count();
+    let text = line.trim_matches('=').trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some((level, text))
+}
+
+/// Returns the name of a heading, without template braces, template
+/// arguments, or numbering, e.g. "pron" for "{{pron|jpn}}" and "語源" for
+/// "語源2".
+fn heading_name(text: &str) -> &str {
+    let text = text.trim_start_matches("{{").trim_end_matches("}}");
+    let text = text.split('|').next().unwrap().trim();
+    text.trim_end_matches(|c: char| c.is_ascii_digit() || c == ' ')
+}
+
+/// Whether a level 2 heading is the heading of the Japanese section, e.g.
+/// "日本語", "{{ja}}" or "{{L|ja}}".
+fn is_japanese_heading(text: &str) -> bool {
+    if text.contains("日本語") {
+        return true;
+    }
+    let text = text.trim_start_matches("{{").trim_end_matches("}}");
+    let lang = text.rsplit('|').next().unwrap().trim();
+    lang == "ja" || lang == "jpn"
+}
+
+/// Returns the entries of the Japanese section of a page, one for each of
+/// its readings.
+fn page_entries(title: &str, wikitext: &str, dict_name: &Arc<str>) -> Vec<TermEntry> {
+    let mut readings: Vec<String> = Vec::new();
+    let mut groups: Vec<Definition> = Vec::new();
+    let mut etymology: Vec<String> = Vec::new();
+
+    let mut in_japanese = false;
+    let mut section = Section::Other;
+    for line in wikitext.lines() {
+        let line = line.trim();
+        if let Some((level, text)) = heading(line) {
+            if level <= 2 {
+                in_japanese = is_japanese_heading(text);
+                section = Section::Other;
+                continue;
+            }
+            let name = heading_name(text);
+            section = if let Some(&(_, label)) = PARTS_OF_SPEECH.iter().find(|p| p.0 == name) {
+                groups.push(Definition::List((format!("［{}］", label), Vec::new())));
+                Section::PartOfSpeech
+            } else if name == "語源" || name == "etym" || name == "etymology" {
+                Section::Etymology
+            } else {
+                Section::Other
+            };
+            continue;
+        }
+        if !in_japanese {
+            continue;
+        }
+
+        add_readings(line, &mut readings);
+
+        match section {
+            Section::PartOfSpeech => {
+                // Senses start with "#", sub-senses with "##", and examples
+                // with "#*" or "#:".
+                let depth = line.chars().take_while(|&c| c == '#').count();
+                let rest = &line[depth..];
+                if depth == 0 || rest.starts_with('*') || rest.starts_with(':') {
+                    continue;
+                }
+                let text = wikitext_to_html(rest);
+                if text.is_empty() {
+                    continue;
+                }
+                if let Some(Definition::List((_, ref mut senses))) = groups.last_mut() {
+                    match senses.last_mut() {
+                        Some(Definition::Def(ref mut sense)) if depth > 1 => {
+                            sense.push_str(&format!("<br/>・{}", text));
+                        }
+                        _ => senses.push(Definition::Def(text)),
+                    }
+                }
+            }
+            Section::Etymology => {
+                let text = wikitext_to_html(line.trim_start_matches(['*', ':']));
+                if !text.is_empty() {
+                    etymology.push(text);
+                }
+            }
+            Section::Other => {}
+        }
+    }
+
+    groups.retain(|group| match group {
+        Definition::List((_, ref senses)) => !senses.is_empty(),
+        Definition::Def(_) => true,
+    });
+    if groups.is_empty() {
+        return Vec::new();
+    }
+    if !etymology.is_empty() {
+        groups.push(Definition::List((
+            "［語源］".into(),
+            vec![Definition::Def(etymology.join("<br/>"))],
+        )));
+    }
+
+    if readings.is_empty() && is_all_kana(title) {
+        readings.push(title.into());
+    }
+    readings
+        .into_iter()
+        .map(|reading| TermEntry {
+            dict_name: dict_name.clone(),
+            writing: title.into(),
+            reading,
+            definitions: Definition::List(("".into(), groups.clone())),
+            infl: InflectionType::None,
+            tags: Vec::new(),
+            commonness: 0,
+        })
+        .collect()
+}
+
+/// Adds the readings given in `line` to `readings`, if it's a headword
+/// line like "'''学校'''（がっこう、歴史的仮名遣い：がくかう）" or has `ja-`
+/// templates with readings, like "{{ja-noun|がっこう}}".
+fn add_readings(line: &str, readings: &mut Vec<String>) {
+    let mut candidates: Vec<String> = Vec::new();
+
+    if line.starts_with("'''") {
+        if let Some(start) = line.find(['（', '(']) {
+            let rest = &line[start..];
+            let rest = &rest[rest.chars().next().unwrap().len_utf8()..];
+            let end = rest.find(['）', ')']).unwrap_or(rest.len());
+            candidates.extend(
+                wikitext_to_text(&rest[..end])
+                    .split(['、', ',', '，', '/', '／'])
+                    .map(|r| r.into()),
+            );
+        }
+    }
+    for caps in JA_TEMPLATE.captures_iter(line) {
+        candidates.extend(
+            caps[1]
+                .split('|')
+                .filter(|arg| !arg.contains('='))
+                .map(|arg| arg.into()),
+        );
+    }
+
+    for candidate in candidates.iter() {
+        let reading: String = candidate
+            .chars()
+            .filter(|&c| c != '・' && c != '-' && c != '.' && !c.is_whitespace())
+            .collect();
+        if !reading.is_empty() && is_all_kana(&reading) && !readings.contains(&reading) {
+            readings.push(reading);
+        }
+    }
+}
+
+/// Converts a line of wiki markup to html: links become their text,
+/// bold and italic become <b> and <i>, and templates are removed except for
+/// labels, e.g. "{{lb|ja|数学}}" becomes "（数学）".
+fn wikitext_to_html(text: &str) -> String {
+    let text = wikitext_to_text(text);
+    let text = BOLD.replace_all(&text, "<b>$1</b>");
+    let text = ITALIC.replace_all(&text, "<i>$1</i>");
+    text.trim().into()
+}
+
+/// Like `wikitext_to_html()`, but leaves bold and italic markup alone.
+fn wikitext_to_text(text: &str) -> String {
+    let mut text = REF.replace_all(text, "").into_owned();
+
+    // Innermost first, so that templates in the arguments of other
+    // templates are expanded.
+    loop {
+        let expanded = TEMPLATE
+            .replace_all(&text, |caps: &regex::Captures| expand_template(&caps[1]))
+            .into_owned();
+        if expanded == text {
+            break;
+        }
+        text = expanded;
+    }
+
+    LINK.replace_all(&text, |caps: &regex::Captures| {
+        let target = &caps[1];
+        if let Some((namespace, _)) = target.split_once(':') {
+            if ["Category", "カテゴリ", "File", "ファイル", "Image", "画像"]
+                .contains(&namespace.trim())
+            {
+                return String::new();
+            }
+        }
+        match caps.get(2) {
+            Some(label) => label.as_str().into(),
+            None => target.split('#').next().unwrap().into(),
+        }
+    })
+    .into_owned()
+}
+
+/// Returns the text a template should be replaced with.
+fn expand_template(content: &str) -> String {
+    let mut args = content.split('|').map(|a| a.trim());
+    let name = args.next().unwrap_or("");
+    let mut positional: Vec<&str> = args.filter(|a| !a.contains('=')).collect();
+    if positional.first().is_some_and(|&a| a == "ja" || a == "jpn") {
+        positional.remove(0);
+    }
+
+    match name {
+        "lb" | "label" | "context" | "cx" if !positional.is_empty() => {
+            format!("（{}）", positional.join("・"))
+        }
+        "l" | "m" | "link" | "ruby" | "ふりがな" => {
+            positional.first().copied().unwrap_or("").into()
+        }
+        _ => String::new(),
+    }
+}
@@ -1,25 +1,30 @@
 //! Parses Yomichan .zip dictionaries.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 
 use furigana_gen::FuriganaGenerator;
 use regex::Regex;
 use serde_json::Value;
 
+use crate::intern::intern;
+use crate::source::{DictionarySource, SourceItem};
+use crate::warnings;
+
 //----------------------------------------------------------------
 // Entry type for words.
 #[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct TermEntry {
-    pub dict_name: String,
+    pub dict_name: Arc<str>,
     pub writing: String,
     pub reading: String,
     pub definitions: Definition,
     pub infl: InflectionType,
-    pub tags: Vec<String>,
+    pub tags: Vec<Arc<str>>,
     pub commonness: i32, // Higher is more common.
 }
 
@@ -30,41 +35,65 @@ pub enum Definition {
     Def(String),
 }
 
+impl Default for Definition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Definition {
     pub fn new() -> Definition {
         Definition::List(("".into(), Vec::new()))
     }
 
     pub fn is_list(&self) -> bool {
-        match self {
-            &Definition::List(_) => true,
-            &Definition::Def(_) => false,
+        match *self {
+            Definition::List(_) => true,
+            Definition::Def(_) => false,
         }
     }
 
     pub fn depth(&self) -> usize {
-        match self {
-            &Definition::List((_, ref l)) => 1 + l.iter().fold(0usize, |a, b| a.max(b.depth())),
-            &Definition::Def(_) => 0,
+        match *self {
+            Definition::List((_, ref l)) => 1 + l.iter().fold(0usize, |a, b| a.max(b.depth())),
+            Definition::Def(_) => 0,
         }
     }
 
     pub fn len(&self) -> usize {
-        match self {
-            &Definition::List((_, ref l)) => l.len(),
-            &Definition::Def(_) => 1,
+        match *self {
+            Definition::List((_, ref l)) => l.len(),
+            Definition::Def(_) => 1,
         }
     }
 
     pub fn is_empty(&self) -> bool {
         match self {
             &Definition::List((ref h, ref l)) => h.trim().is_empty() && l.is_empty(),
-            &Definition::Def(ref s) => s.trim().is_empty(),
+            Definition::Def(s) => s.trim().is_empty(),
+        }
+    }
+
+    /// Removes the definitions whose text doesn't satisfy `f`, along with
+    /// any lists that are left empty as a result.
+    ///
+    /// Returns false if nothing is left of the definition.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, f: &mut F) -> bool {
+        match *self {
+            Definition::List((_, ref mut list)) => {
+                list.retain_mut(|d| d.retain(f));
+            }
+            Definition::Def(ref s) => {
+                if !f(s) {
+                    return false;
+                }
+            }
         }
+        !self.is_empty()
     }
 
     pub fn def_text(&self) -> &str {
-        if let &Definition::Def(ref text) = self {
+        if let Definition::Def(text) = self {
             text
         } else {
             panic!("Definition is a list, cannot fetch text.")
@@ -86,213 +115,408 @@ pub enum InflectionType {
 // Entry type for kanji.
 #[derive(Clone, Debug)]
 pub struct KanjiEntry {
-    pub dict_name: String,
+    pub dict_name: Arc<str>,
     pub kanji: String,
     pub onyomi: Vec<String>,
     pub kunyomi: Vec<String>,
     pub meanings: Vec<String>,
+    pub strokes: Option<u32>,
+
+    // From kanji component files, if given.
+    pub components: Vec<String>,
+    pub decomposition: Option<String>, // Ideographic description sequence.
+
+    // From KanjiVG, if given.  A data URI.
+    pub stroke_order: Option<String>,
+
+    // Common words that contain the kanji, as (writing, reading, gloss).
+    pub examples: Vec<(String, String, String)>,
+}
+
+//----------------------------------------------------------------
+// Entry type for term meta data (frequency, pitch accent, etc.).
+#[derive(Clone, Debug)]
+pub struct TermMetaEntry {
+    pub dict_name: Arc<str>,
+    pub writing: String,
+    pub reading: String, // May be empty if the dictionary doesn't specify.
+    pub meta: TermMeta,
+}
+
+#[derive(Clone, Debug)]
+pub enum TermMeta {
+    // Frequency rank.  Lower is more common.
+    Frequency(u32),
+
+    // Pitch accent positions, in the same format as the pitch accent file,
+    // and the phonetic details that some dictionaries (e.g. NHK ones) give
+    // along with them.
+    PitchAccent(Vec<u32>, Phonetics),
+}
+
+/// Sound changes of specific morae of a word.  Positions count morae from 1.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Phonetics {
+    pub nasal: Vec<u32>,    // Nasalized が行 morae, e.g. the が of 鏡.
+    pub devoiced: Vec<u32>, // Devoiced morae, e.g. the き of 聞く.
+}
+
+impl Phonetics {
+    pub fn is_empty(&self) -> bool {
+        self.nasal.is_empty() && self.devoiced.is_empty()
+    }
 }
 
 //----------------------------------------------------------------
 
+/// A zipped Yomichan dictionary as a dictionary source.  See `parse()` for
+/// the meaning of the options.
+pub struct Source<'a> {
+    pub path: std::path::PathBuf,
+    pub furigana_generator: Option<&'a FuriganaGenerator>,
+    pub strip_images: bool,
+    pub group_by_pos: bool,
+    pub strict: bool,
+}
+
+impl<'a> DictionarySource for Source<'a> {
+    fn name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    fn parse(self: Box<Self>) -> std::io::Result<Box<dyn Iterator<Item = SourceItem>>> {
+        let (words, names, kanji, meta) = parse(
+            &self.path,
+            self.furigana_generator,
+            self.strip_images,
+            self.group_by_pos,
+            self.strict,
+        )?;
+
+        Ok(Box::new(
+            words
+                .into_iter()
+                .map(SourceItem::Term)
+                .chain(names.into_iter().map(SourceItem::Name))
+                .chain(kanji.into_iter().map(SourceItem::Kanji))
+                .chain(meta.into_iter().map(SourceItem::TermMeta)),
+        ))
+    }
+}
+
+/// Parses a zipped Yomichan dictionary.
+///
+/// Images referenced by the dictionary are inlined into the definitions,
+/// unless `strip_images` is true, in which case they are replaced by their
+/// alt text.
+///
+/// If `group_by_pos` is true, the definitions of words that have more than
+/// one part of speech (e.g. 勉強, a noun and a する verb) are grouped by
+/// part of speech, under a header like "［名］".
+///
+/// Malformed banks and entries are skipped with a warning, unless `strict`
+/// is true, in which case they are returned as an error.
 pub fn parse(
     path: &Path,
     furigana_generator: Option<&FuriganaGenerator>,
-) -> std::io::Result<(Vec<TermEntry>, Vec<TermEntry>, Vec<KanjiEntry>)> // (words, names, kanji)
+    strip_images: bool,
+    group_by_pos: bool,
+    strict: bool,
+) -> std::io::Result<(
+    Vec<TermEntry>,
+    Vec<TermEntry>,
+    Vec<KanjiEntry>,
+    Vec<TermMetaEntry>,
+)> // (words, names, kanji, term meta)
+{
+    parse_archive(
+        BufReader::new(File::open(path)?),
+        &path.display().to_string(),
+        furigana_generator,
+        strip_images,
+        group_by_pos,
+        strict,
+    )
+}
+
+/// Same as `parse()`, but for a zipped Yomichan dictionary that's already
+/// open, e.g. one in memory.  `name` is used in warnings and errors.
+pub fn parse_archive<R: Read + Seek>(
+    archive: R,
+    name: &str,
+    furigana_generator: Option<&FuriganaGenerator>,
+    strip_images: bool,
+    group_by_pos: bool,
+    strict: bool,
+) -> std::io::Result<(
+    Vec<TermEntry>,
+    Vec<TermEntry>,
+    Vec<KanjiEntry>,
+    Vec<TermMetaEntry>,
+)> // (words, names, kanji, term meta)
 {
     let mut furigen = furigana_generator.map(|fg| fg.new_session(false));
 
-    let mut zip_in = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut zip_in = zip::ZipArchive::new(archive)?;
 
     let mut text = String::new();
 
     // Load index.json for meta-data about the dictionary.
+    let invalid = |problem: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: index.json: {}", name, problem),
+        )
+    };
     let index_json: Value = {
         text.clear();
         zip_in
             .by_name("index.json")
-            .expect("Yomichan dictionary isn't valid: no index.json.")
+            .map_err(|_| invalid("missing, so this isn't a Yomichan dictionary"))?
             .read_to_string(&mut text)
-            .expect("Yomichan dictionary isn't valid: invalid json.");
-        serde_json::from_str(&text).expect("Yomichan dictionary isn't valid: invalid json.")
+            .map_err(|e| invalid(&e.to_string()))?;
+        serde_json::from_str(&text).map_err(|e| invalid(&format!("invalid json: {}", e)))?
     };
 
     // Check the format version.
     match index_json.get("format") {
         Some(Value::Number(version)) if version.as_i64() == Some(3) => {}
-        _ => panic!("Yomichan dictionaries other than format version 3 are not supported."),
+        _ => return Err(invalid("only format version 3 is supported")),
     }
 
     // Get the normalized dictionary title.
     let dictionary_title: String = index_json
         .get("title")
-        .expect("Yomichan dictionary isn't valid: index in unexpected format.")
-        .as_str()
-        .expect("Yomichan dictionary isn't valid: index in unexpected format.")
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| invalid("no title"))?
         .to_lowercase()
-        .split("(")
-        .nth(0)
+        .split('(')
+        .next()
         .unwrap()
         .trim()
         .into();
 
     // Is this a name dictionary?
-    let is_name_dict = match dictionary_title.as_str() {
-        "jmnedict" => true,
-        _ => false,
-    };
+    let is_name_dict = dictionary_title == "jmnedict";
+
+    // Are frequencies given as ranks (as opposed to occurrence counts)?
+    // Only ranks can be meaningfully compared to JMDict's priorities, and
+    // most frequency dictionaries use them.
+    let is_rank_frequency =
+        index_json.get("frequencyMode").and_then(|m| m.as_str()) != Some("occurrence-based");
+
+    let dict_name = intern(&dictionary_title);
 
     // Loop through the bank-json files in the zip and build our entry list(s).
     let mut term_entries: HashMap<_, TermEntry> = HashMap::new();
+    // The part of speech label of each definition of the term entries.
+    let mut term_pos_labels: HashMap<(String, String), Vec<Option<String>>> = HashMap::new();
     let mut name_entries = Vec::new();
     let mut kanji_entries = Vec::new();
-    for i in 0..zip_in.len() {
-        // Open the file.
-        let mut f = zip_in.by_index(i).unwrap();
-        let filename: String = std::str::from_utf8(f.name_raw()).unwrap().into();
-        if !filename.ends_with(".json") {
-            continue;
-        }
+    let mut meta_entries = Vec::new();
+    let mut image_cache = HashMap::new();
 
-        // Load the json data.
-        text.clear();
-        f.read_to_string(&mut text)
-            .expect("Yomichan dictionary isn't valid: invalid json.");
-        let json: Value =
-            serde_json::from_str(&text).expect("Yomichan dictionary isn't valid: invalid json.");
-
-        // Parse the json into entries.
-        if filename.starts_with("term_bank_") {
-            // It's a term bank.
-
-            // Dividers for the 三省堂　スーパー大辞林 dictionary.
-            // But probably works for some other native Japanese
-            // dictionaries as well.
-            let dividers = &[
-                // The (?m) puts the regex into multi-line mode, so
-                // that ^ will match both newlines and start of text.
-                Regex::new("(?m)^■[一二三四五六七八九十]+■").unwrap(),
-                Regex::new("(?m)^[❶❷❸❹❺❻❼❽❾❿]+").unwrap(),
-                Regex::new("(?m)^（[０１２３４５６７８９]+）").unwrap(),
-            ];
-
-            for item in json.as_array().unwrap().iter() {
-                let mut tags: Vec<String> = item
-                    .get(2)
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .split(" ")
-                    .chain(item.get(7).unwrap().as_str().unwrap().split(" "))
-                    .map(|s| s.trim().into())
-                    .filter(|s: &String| !s.is_empty())
-                    .collect();
-                tags.sort();
-                tags.dedup();
-
-                let mut entry = TermEntry {
-                    dict_name: dictionary_title.clone(),
-                    writing: item.get(0).unwrap().as_str().unwrap().trim().into(),
-                    reading: item.get(1).unwrap().as_str().unwrap().trim().into(),
-                    infl: match item.get(3).unwrap().as_str().unwrap().trim() {
-                        "v1" => InflectionType::VerbIchidan,
-                        "v5" => InflectionType::VerbGodan,
-                        "vs" => InflectionType::VerbSuru,
-                        "vk" => InflectionType::VerbKuru,
-                        "adj-i" => InflectionType::IAdjective,
-                        _ => InflectionType::None,
-                    },
-                    commonness: item.get(4).unwrap().as_i64().unwrap() as i32,
-                    definitions: Definition::List((
-                        "".into(),
-                        vec![Definition::Def(
-                            item.get(5)
-                                .unwrap()
-                                .as_array()
-                                .unwrap()
-                                .iter()
-                                .map(|d| {
-                                    if let Some(s) = d.as_str() {
-                                        s.trim()
-                                    } else {
-                                        // Ignore the complex structured definitions for now.
-                                        // TODO: handle this properly.
-                                        ""
-                                    }
-                                })
-                                .collect::<Vec<&str>>()
-                                .join("; "),
-                        )],
-                    )),
-                    tags: tags,
+    // Banks are parsed one entry at a time, so a bank is never in memory as
+    // a whole `Value`, on a worker thread so that parsing overlaps with
+    // processing.  The banks are still processed in order, on this thread,
+    // since processing reads images from the archive, which is also why
+    // each bank's bytes are read into memory first.  Only a couple of banks
+    // are read ahead, to bound memory use.
+    const READ_AHEAD: usize = 2;
+    // How many parsed entries a worker gets ahead of processing.
+    const ITEM_BUFFER: usize = 256;
+    // Threads aren't available on all targets, which report no parallelism.
+    let use_threads = std::thread::available_parallelism().is_ok();
+    std::thread::scope(|scope| -> std::io::Result<()> {
+        // (filename, entries of the bank)
+        let mut pending: VecDeque<(String, Box<dyn Iterator<Item = Result<Value, String>>>)> =
+            VecDeque::new();
+        let mut next_index = 0;
+        loop {
+            while pending.len() < READ_AHEAD && next_index < zip_in.len() {
+                // Open the file.
+                let mut f = zip_in.by_index(next_index).unwrap();
+                next_index += 1;
+                let filename: String = std::str::from_utf8(f.name_raw()).unwrap().into();
+                if !filename.ends_with(".json")
+                    || !["term_bank_", "term_meta_bank_", "kanji_bank_"]
+                        .iter()
+                        .any(|prefix| filename.starts_with(prefix))
+                {
+                    continue;
+                }
+
+                // Read the json data, without decoding it into a string first.
+                let mut data = Vec::with_capacity(f.size() as usize);
+                let read_result = f.read_to_end(&mut data);
+                drop(f);
+                let items: Box<dyn Iterator<Item = Result<Value, String>>> = match read_result {
+                    Err(e) => Box::new(std::iter::once(Err(format!("unreadable: {}", e)))),
+                    Ok(_) if !use_threads => Box::new(ArrayItems::new(data)),
+                    Ok(_) => {
+                        let (sender, receiver) = std::sync::mpsc::sync_channel(ITEM_BUFFER);
+                        scope.spawn(move || {
+                            for item in ArrayItems::new(data) {
+                                // Processing stopped early, e.g. on an error.
+                                if sender.send(item).is_err() {
+                                    break;
+                                }
+                            }
+                        });
+                        Box::new(receiver.into_iter())
+                    }
                 };
+                pending.push_back((filename, items));
+            }
+            let (filename, items) = match pending.pop_front() {
+                Some(bank) => bank,
+                None => break,
+            };
 
-                if is_name_dict {
-                    name_entries.push(entry);
-                } else {
-                    // We do some extra work here to merge the definitions from
-                    // multiple entries for the same word.
-                    let key = (entry.writing.clone(), entry.reading.clone());
-                    let e = term_entries.entry(key.clone()).or_insert(TermEntry {
-                        dict_name: dictionary_title.clone(),
-                        writing: entry.writing.clone(),
-                        reading: entry.reading.clone(),
-                        definitions: Definition::List(("".into(), Vec::new())),
-                        infl: entry.infl,
-                        tags: Vec::new(),
-                        commonness: entry.commonness,
-                    });
-                    assert!(e.definitions.is_list());
-                    if let Definition::List((_, ref mut list_to)) = e.definitions {
-                        match entry.definitions {
-                            Definition::List((_, mut list_from)) => {
-                                list_to.extend(list_from.drain(..).filter_map(|d| {
-                                    process_definition(&key.0, &key.1, dividers, d, &mut furigen)
-                                }))
+            // Malformed banks are skipped from the first error on, unless
+            // we're being strict.
+            let mut bank_error = None;
+            let items = items.map_while(|item| match item {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    bank_error = Some(e);
+                    None
+                }
+            });
+            let mut skipped = 0usize;
+
+            // Parse the json into entries.
+            if filename.starts_with("term_bank_") {
+                // It's a term bank.
+
+                lazy_static! {
+                    // Dividers for the 三省堂　スーパー大辞林 dictionary.
+                    // But probably works for some other native Japanese
+                    // dictionaries as well.
+                    static ref DIVIDERS: [Regex; 3] = [
+                        // The (?m) puts the regex into multi-line mode, so
+                        // that ^ will match both newlines and start of text.
+                        Regex::new("(?m)^■[一二三四五六七八九十]+■").unwrap(),
+                        Regex::new("(?m)^[❶❷❸❹❺❻❼❽❾❿]+").unwrap(),
+                        Regex::new("(?m)^（[０１２３４５６７８９]+）").unwrap(),
+                    ];
+                }
+                let dividers = &*DIVIDERS;
+
+                for item in items {
+                    let fields = match TermItem::from_json(&item) {
+                        Some(fields) => fields,
+                        None => {
+                            skipped += 1;
+                            continue;
+                        }
+                    };
+
+                    let mut entry = TermEntry {
+                        dict_name: dict_name.clone(),
+                        writing: fields.writing.into(),
+                        reading: fields.reading.into(),
+                        infl: fields.infl,
+                        commonness: fields.commonness,
+                        definitions: Definition::List((
+                            "".into(),
+                            vec![Definition::Def(process_images(
+                                definition_items_to_html(fields.definitions),
+                                &mut zip_in,
+                                &mut image_cache,
+                                strip_images,
+                            ))],
+                        )),
+                        tags: fields.tags,
+                    };
+
+                    if is_name_dict {
+                        name_entries.push(entry);
+                    } else {
+                        // We do some extra work here to merge the definitions from
+                        // multiple entries for the same word.
+                        let key = (entry.writing.clone(), entry.reading.clone());
+                        let e = term_entries.entry(key.clone()).or_insert(TermEntry {
+                            dict_name: dict_name.clone(),
+                            writing: entry.writing.clone(),
+                            reading: entry.reading.clone(),
+                            definitions: Definition::List(("".into(), Vec::new())),
+                            infl: entry.infl,
+                            tags: Vec::new(),
+                            commonness: entry.commonness,
+                        });
+                        assert!(e.definitions.is_list());
+                        if let Definition::List((_, ref mut list_to)) = e.definitions {
+                            match entry.definitions {
+                                Definition::List((_, mut list_from)) => {
+                                    list_to.extend(list_from.drain(..).filter_map(|d| {
+                                        process_definition(
+                                            &key.0,
+                                            &key.1,
+                                            dividers,
+                                            d,
+                                            &mut furigen,
+                                        )
+                                    }))
+                                }
+                                Definition::Def(s) => list_to.push(Definition::Def(s)),
+                            }
+                            if group_by_pos {
+                                let label = pos_label(&entry.tags);
+                                term_pos_labels
+                                    .entry(key.clone())
+                                    .or_default()
+                                    .resize(list_to.len(), label);
                             }
-                            Definition::Def(s) => list_to.push(Definition::Def(s)),
                         }
+                        e.tags.append(&mut entry.tags);
+                        e.tags.sort_unstable();
+                        e.tags.dedup();
+                    }
+                }
+            } else if filename.starts_with("term_meta_bank_") {
+                // It's a term meta bank.
+                for item in items {
+                    match parse_meta_item(&item, is_rank_frequency) {
+                        Some(Some((writing, reading, meta))) => meta_entries.push(TermMetaEntry {
+                            dict_name: dict_name.clone(),
+                            writing,
+                            reading,
+                            meta,
+                        }),
+                        Some(None) => {}
+                        None => skipped += 1,
+                    }
+                }
+            } else if filename.starts_with("kanji_bank_") {
+                // It's a kanji bank.
+                for item in items {
+                    match parse_kanji_item(&item, &dict_name) {
+                        Some(entry) => kanji_entries.push(entry),
+                        None => skipped += 1,
                     }
-                    e.tags.extend(entry.tags.drain(..));
-                    e.tags.sort_unstable();
-                    e.tags.dedup();
                 }
             }
-        } else if filename.starts_with("kanji_bank_") {
-            // It's a kanji bank.
-            for item in json.as_array().unwrap().iter() {
-                let entry = KanjiEntry {
-                    dict_name: dictionary_title.clone(),
-                    kanji: item.get(0).unwrap().as_str().unwrap().trim().into(),
-                    onyomi: item
-                        .get(1)
-                        .unwrap()
-                        .as_str()
-                        .unwrap()
-                        .split(" ")
-                        .map(|s| s.trim().into())
-                        .filter(|s: &String| !s.is_empty())
-                        .collect(),
-                    kunyomi: item
-                        .get(2)
-                        .unwrap()
-                        .as_str()
-                        .unwrap()
-                        .split(" ")
-                        .map(|s| s.trim().into())
-                        .filter(|s: &String| !s.is_empty())
-                        .collect(),
-                    meanings: item
-                        .get(4)
-                        .unwrap()
-                        .as_array()
-                        .unwrap()
-                        .iter()
-                        .map(|s| s.as_str().unwrap().trim().into())
-                        .filter(|s: &String| !s.is_empty())
-                        .collect(),
-                };
-                kanji_entries.push(entry);
+
+            if let Some(e) = bank_error {
+                malformed(strict, name, &filename, &e)?;
+            }
+            if skipped > 0 {
+                malformed(
+                    strict,
+                    name,
+                    &filename,
+                    &format!("skipped {} malformed entries", skipped),
+                )?;
+            }
+        }
+        Ok(())
+    })?;
+
+    if group_by_pos {
+        for (key, entry) in term_entries.iter_mut() {
+            if let Some(labels) = term_pos_labels.get(key) {
+                group_definitions_by_pos(&mut entry.definitions, labels);
             }
         }
     }
@@ -301,7 +525,659 @@ pub fn parse(
     let mut term_entries: Vec<TermEntry> = term_entries.drain().map(|kv| kv.1).collect();
     term_entries.sort_unstable();
 
-    Ok((term_entries, name_entries, kanji_entries))
+    Ok((term_entries, name_entries, kanji_entries, meta_entries))
+}
+
+/// Returns the Japanese dictionary style part of speech label (e.g. "名・形動")
+/// for a term bank entry with the given tags, or `None` if the tags don't
+/// include a part of speech.
+fn pos_label(tags: &[Arc<str>]) -> Option<String> {
+    // In display order.
+    const LABELS: &[&str] = &[
+        "名", "スル", "動", "形", "形動", "副", "連語", "感", "接", "助",
+    ];
+
+    let tag_label = |tag: &str| match tag {
+        "n" | "pn" => Some("名"),
+        _ if tag.starts_with("n-") => Some("名"),
+        "vs" => Some("スル"),
+        "vk" | "vz" => Some("動"),
+        _ if tag.starts_with("v1") || tag.starts_with("v5") || tag.starts_with("vs-") => Some("動"),
+        "adj-i" | "adj-ix" => Some("形"),
+        "adj-na" => Some("形動"),
+        "adv" | "adv-to" => Some("副"),
+        "exp" => Some("連語"),
+        "int" => Some("感"),
+        "conj" => Some("接"),
+        "prt" => Some("助"),
+        _ => None,
+    };
+
+    let found: Vec<&str> = tags.iter().filter_map(|t| tag_label(t)).collect();
+    let labels: Vec<&str> = LABELS
+        .iter()
+        .copied()
+        .filter(|l| found.contains(l))
+        .collect();
+    if labels.is_empty() {
+        None
+    } else {
+        Some(labels.join("・"))
+    }
+}
+
+/// Groups the top-level definitions of `definitions` by their part of
+/// speech label, given in `labels`, if there's more than one label.
+/// Definitions without a label are grouped with the definitions before
+/// them.
+fn group_definitions_by_pos(definitions: &mut Definition, labels: &[Option<String>]) {
+    let mut distinct: Vec<&str> = labels.iter().filter_map(|l| l.as_deref()).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() < 2 {
+        return;
+    }
+
+    if let Definition::List((_, ref mut list)) = definitions {
+        // (label, definitions), in order of first appearance.
+        let mut groups: Vec<(String, Vec<Definition>)> = Vec::new();
+        let mut cur_label = String::new();
+        for (def, label) in list.drain(..).zip(labels.iter()) {
+            if let Some(label) = label {
+                cur_label = label.clone();
+            }
+            match groups.iter_mut().find(|g| g.0 == cur_label) {
+                Some(group) => group.1.push(def),
+                None => groups.push((cur_label.clone(), vec![def])),
+            }
+        }
+        *list = groups
+            .into_iter()
+            .map(|(label, defs)| {
+                let header = if label.is_empty() {
+                    label
+                } else {
+                    format!("［{}］", label)
+                };
+                Definition::List((header, defs))
+            })
+            .collect();
+    }
+}
+
+/// The fields of a term bank entry.
+struct TermItem<'a> {
+    writing: &'a str,
+    reading: &'a str,
+    infl: InflectionType,
+    commonness: i32,
+    definitions: &'a [Value],
+    tags: Vec<Arc<str>>,
+}
+
+impl<'a> TermItem<'a> {
+    /// Returns `None` if the entry is malformed.
+    fn from_json(item: &'a Value) -> Option<TermItem<'a>> {
+        let mut tags: Vec<Arc<str>> = item
+            .get(2)?
+            .as_str()?
+            .split(" ")
+            .chain(item.get(7)?.as_str()?.split(" "))
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(intern)
+            .collect();
+        tags.sort();
+        tags.dedup();
+
+        Some(TermItem {
+            writing: item.get(0)?.as_str()?.trim(),
+            reading: item.get(1)?.as_str()?.trim(),
+            infl: match item.get(3)?.as_str()?.trim() {
+                "v1" => InflectionType::VerbIchidan,
+                "v5" => InflectionType::VerbGodan,
+                "vs" => InflectionType::VerbSuru,
+                "vk" => InflectionType::VerbKuru,
+                "adj-i" => InflectionType::IAdjective,
+                _ => InflectionType::None,
+            },
+            commonness: item.get(4)?.as_i64()? as i32,
+            definitions: item.get(5)?.as_array()?,
+            tags,
+        })
+    }
+}
+
+/// Parses a term meta bank entry into a (writing, reading, meta) tuple.
+///
+/// Returns `Some(None)` for entries that are valid but unused, and `None`
+/// if the entry is malformed.
+fn parse_meta_item(
+    item: &Value,
+    is_rank_frequency: bool,
+) -> Option<Option<(String, String, TermMeta)>> {
+    let writing: String = item.get(0)?.as_str()?.trim().into();
+    let data = item.get(2)?;
+    let (reading, meta) = match item.get(1)?.as_str()? {
+        "freq" if is_rank_frequency => match parse_frequency(data) {
+            Some((reading, rank)) => (reading, TermMeta::Frequency(rank)),
+            None => return Some(None),
+        },
+        "pitch" => {
+            let pitches = data
+                .get("pitches")
+                .and_then(|p| p.as_array())
+                .map(|p| p.as_slice())
+                .unwrap_or(&[]);
+            let mut accents: Vec<u32> = pitches
+                .iter()
+                .filter_map(|p| p.get("position").and_then(|n| n.as_u64()))
+                .map(|n| n as u32)
+                .collect();
+            accents.dedup();
+            if accents.is_empty() {
+                return Some(None);
+            }
+
+            // Mora positions, given as either a number or a list of them.
+            let positions = |field: &str| -> Vec<u32> {
+                let mut positions: Vec<u32> = pitches
+                    .iter()
+                    .filter_map(|p| p.get(field))
+                    .flat_map(|v| match v {
+                        Value::Array(list) => list.iter().filter_map(|n| n.as_u64()).collect(),
+                        _ => v.as_u64().into_iter().collect::<Vec<_>>(),
+                    })
+                    .map(|n| n as u32)
+                    .collect();
+                positions.sort_unstable();
+                positions.dedup();
+                positions
+            };
+            let phonetics = Phonetics {
+                nasal: positions("nasal"),
+                devoiced: positions("devoice"),
+            };
+            (
+                data.get("reading")
+                    .and_then(|r| r.as_str())
+                    .unwrap_or("")
+                    .trim()
+                    .into(),
+                TermMeta::PitchAccent(accents, phonetics),
+            )
+        }
+        _ => return Some(None),
+    };
+
+    Some(Some((writing, reading, meta)))
+}
+
+/// Parses a kanji bank entry.  Returns `None` if the entry is malformed.
+fn parse_kanji_item(item: &Value, dict_name: &Arc<str>) -> Option<KanjiEntry> {
+    let split_readings = |readings: &str| -> Vec<String> {
+        readings
+            .split(" ")
+            .map(|s| s.trim().into())
+            .filter(|s: &String| !s.is_empty())
+            .collect()
+    };
+
+    Some(KanjiEntry {
+        dict_name: dict_name.clone(),
+        kanji: item.get(0)?.as_str()?.trim().into(),
+        onyomi: split_readings(item.get(1)?.as_str()?),
+        kunyomi: split_readings(item.get(2)?.as_str()?),
+        meanings: item
+            .get(4)?
+            .as_array()?
+            .iter()
+            .filter_map(|s| s.as_str())
+            .map(|s| s.trim().into())
+            .filter(|s: &String| !s.is_empty())
+            .collect(),
+        // The stroke count is a string in KANJIDIC-based dictionaries, but
+        // accept a number too.
+        strokes: item
+            .get(5)
+            .and_then(|stats| stats.get("strokes"))
+            .and_then(|s| {
+                s.as_u64()
+                    .map(|s| s as u32)
+                    .or_else(|| s.as_str().and_then(|s| s.trim().parse().ok()))
+            }),
+        components: Vec::new(),
+        decomposition: None,
+        stroke_order: None,
+        examples: Vec::new(),
+    })
+}
+
+/// The entries of a bank, i.e. the elements of a json array, parsed one at
+/// a time from the bank's bytes.
+///
+/// Yields an error, and then nothing, on the first problem in the json.
+struct ArrayItems {
+    data: Vec<u8>,
+    pos: usize,
+    started: bool,
+    done: bool,
+}
+
+impl ArrayItems {
+    fn new(data: Vec<u8>) -> ArrayItems {
+        ArrayItems {
+            data,
+            pos: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Skips whitespace and returns the byte after it, without consuming
+    /// that byte.
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(&byte) = self.data.get(self.pos) {
+            if !byte.is_ascii_whitespace() {
+                return Some(byte);
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    fn fail(&mut self, problem: String) -> Option<Result<Value, String>> {
+        self.done = true;
+        Some(Err(problem))
+    }
+}
+
+impl Iterator for ArrayItems {
+    type Item = Result<Value, String>;
+
+    fn next(&mut self) -> Option<Result<Value, String>> {
+        if self.done {
+            return None;
+        }
+
+        // Find the start of the next element, or the end of the array.
+        let at_end = if !self.started {
+            if self.peek() != Some(b'[') {
+                return self.fail("not a list of entries".into());
+            }
+            self.pos += 1;
+            self.started = true;
+            self.peek() == Some(b']')
+        } else {
+            match self.peek() {
+                Some(b']') => true,
+                Some(b',') => {
+                    self.pos += 1;
+                    false
+                }
+                _ => return self.fail("invalid json: expected `,` or `]`".into()),
+            }
+        };
+        if at_end {
+            self.pos += 1;
+            if self.peek().is_some() {
+                return self.fail("invalid json: trailing characters".into());
+            }
+            self.done = true;
+            return None;
+        }
+
+        let mut stream =
+            serde_json::Deserializer::from_slice(&self.data[self.pos..]).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(item)) => {
+                self.pos += stream.byte_offset();
+                Some(Ok(item))
+            }
+            Some(Err(e)) => self.fail(format!("invalid json: {}", e)),
+            None => self.fail("invalid json: unexpected end of data".into()),
+        }
+    }
+}
+
+/// Reports a problem with a bank file of a Yomichan dictionary: as an error
+/// if `strict` is true, and otherwise as a warning in the summary at the end
+/// of the build.
+fn malformed(strict: bool, name: &str, filename: &str, problem: &str) -> std::io::Result<()> {
+    if strict {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}: {}", name, filename, problem),
+        ))
+    } else {
+        warnings::warn(
+            warnings::Category::YomichanEntry,
+            name,
+            format!("{}: {}", filename, problem),
+        );
+        Ok(())
+    }
+}
+
+/// Parses the data of a frequency meta entry into a (reading, rank) pair.
+///
+/// The reading is empty if the entry doesn't specify one.
+fn parse_frequency(data: &Value) -> Option<(String, u32)> {
+    match data {
+        Value::Number(n) => n.as_u64().map(|n| (String::new(), n as u32)),
+
+        // Some dictionaries give the frequency as a display string, e.g.
+        // "1234㋕".  Use the leading number.
+        Value::String(s) => {
+            let digits: String = s
+                .trim()
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            digits.parse::<u32>().ok().map(|n| (String::new(), n))
+        }
+
+        Value::Object(obj) => {
+            if let Some(frequency) = obj.get("frequency") {
+                let reading = obj.get("reading").and_then(|r| r.as_str()).unwrap_or("");
+                parse_frequency(frequency).map(|(_, n)| (reading.trim().into(), n))
+            } else {
+                obj.get("value")
+                    .and_then(|n| n.as_u64())
+                    .map(|n| (String::new(), n as u32))
+            }
+        }
+
+        _ => None,
+    }
+}
+
+/// Converts the definition items of a term bank entry to a single string.
+///
+/// Plain text items are joined with semicolons, and structured-content
+/// items are rendered to html in their own block.
+fn definition_items_to_html(items: &[Value]) -> String {
+    let mut text = String::new();
+    let mut prev_is_plain = false;
+
+    for item in items.iter() {
+        let (item_text, is_plain) = match item {
+            Value::String(s) => (s.trim().to_string(), true),
+            Value::Object(obj) => match obj.get("type").and_then(|t| t.as_str()) {
+                Some("text") => (
+                    obj.get("text")
+                        .and_then(|t| t.as_str())
+                        .unwrap_or("")
+                        .trim()
+                        .to_string(),
+                    true,
+                ),
+                Some("image") => (structured_image_to_html(item), false),
+                Some("structured-content") => (
+                    obj.get("content")
+                        .map(structured_content_to_html)
+                        .unwrap_or_else(String::new),
+                    false,
+                ),
+                _ => (String::new(), false),
+            },
+            _ => (String::new(), false),
+        };
+
+        if item_text.trim().is_empty() {
+            continue;
+        }
+
+        if is_plain {
+            if prev_is_plain {
+                text.push_str("; ");
+            }
+            text.push_str(&item_text);
+        } else {
+            text.push_str("<div>");
+            text.push_str(&item_text);
+            text.push_str("</div>");
+        }
+        prev_is_plain = is_plain;
+    }
+
+    text
+}
+
+/// Renders Yomichan structured content as html.
+///
+/// Only the elements and styles that are reasonable to display on an
+/// e-reader are kept.  Unknown elements are replaced by their content.
+fn structured_content_to_html(content: &Value) -> String {
+    match content {
+        Value::String(s) => escape_html(s).replace("\n", "<br>"),
+
+        Value::Array(list) => list.iter().map(structured_content_to_html).collect(),
+
+        Value::Object(obj) => {
+            let tag = obj.get("tag").and_then(|t| t.as_str()).unwrap_or("");
+            let inner = obj
+                .get("content")
+                .map(structured_content_to_html)
+                .unwrap_or_else(String::new);
+
+            match tag {
+                "br" => "<br>".into(),
+
+                "img" => structured_image_to_html(content),
+
+                "ruby" | "rt" | "rp" | "table" | "thead" | "tbody" | "tfoot" | "tr" | "td"
+                | "th" | "span" | "div" | "ol" | "ul" | "li" | "details" | "summary" => {
+                    let mut html = format!("<{}", tag);
+
+                    // Table cell spans.
+                    for &(key, attr) in &[("colSpan", "colspan"), ("rowSpan", "rowspan")] {
+                        if let Some(n) = obj.get(key).and_then(|n| n.as_u64()) {
+                            html.push_str(&format!(" {}=\"{}\"", attr, n));
+                        }
+                    }
+
+                    if let Some(lang) = obj.get("lang").and_then(|l| l.as_str()) {
+                        html.push_str(&format!(" lang=\"{}\"", escape_html(lang)));
+                    }
+
+                    if let Some(style) = obj.get("style") {
+                        let css = structured_style_to_css(style);
+                        if !css.is_empty() {
+                            html.push_str(&format!(" style=\"{}\"", css));
+                        }
+                    }
+
+                    html.push('>');
+                    html.push_str(&inner);
+                    html.push_str(&format!("</{}>", tag));
+                    html
+                }
+
+                // Links, and anything we don't know what to do with.
+                _ => inner,
+            }
+        }
+
+        _ => String::new(),
+    }
+}
+
+/// Renders a Yomichan image reference as an html image tag.
+///
+/// The image path is relative to the root of the Yomichan dictionary's zip
+/// archive.
+fn structured_image_to_html(image: &Value) -> String {
+    let path = match image.get("path").and_then(|p| p.as_str()) {
+        Some(path) => path,
+        None => return String::new(),
+    };
+
+    let mut html = format!("<img src=\"{}\"", escape_html(path));
+    if let Some(alt) = image
+        .get("alt")
+        .or_else(|| image.get("title"))
+        .and_then(|a| a.as_str())
+    {
+        html.push_str(&format!(" alt=\"{}\"", escape_html(alt)));
+    }
+
+    // Sizes are in pixels unless specified otherwise.
+    let unit = match image.get("sizeUnits").and_then(|u| u.as_str()) {
+        Some("em") => "em",
+        _ => "px",
+    };
+    let mut style = String::new();
+    for &key in &["width", "height"] {
+        if let Some(n) = image.get(key).and_then(|n| n.as_f64()) {
+            style.push_str(&format!("{}: {}{}; ", key, n, unit));
+        }
+    }
+    if !style.is_empty() {
+        html.push_str(&format!(" style=\"{}\"", style.trim_end()));
+    }
+
+    html.push_str("/>");
+    html
+}
+
+/// Converts a Yomichan structured-content style object to inline css.
+fn structured_style_to_css(style: &Value) -> String {
+    let obj = match style.as_object() {
+        Some(obj) => obj,
+        None => return String::new(),
+    };
+
+    let mut css = String::new();
+    for (key, value) in obj.iter() {
+        // Only a subset of styles make sense on an e-reader.
+        let property = match key.as_str() {
+            "fontStyle" => "font-style",
+            "fontWeight" => "font-weight",
+            "fontSize" => "font-size",
+            "textDecorationLine" => "text-decoration",
+            "verticalAlign" => "vertical-align",
+            "textAlign" => "text-align",
+            "marginTop" => "margin-top",
+            "marginBottom" => "margin-bottom",
+            "marginLeft" => "margin-left",
+            "marginRight" => "margin-right",
+            "listStyleType" => "list-style-type",
+            _ => continue,
+        };
+
+        let value = match value {
+            Value::String(s) => escape_html(s),
+            // Margins given as plain numbers are in em.
+            Value::Number(n) => format!("{}em", n.as_f64().unwrap_or(0.0)),
+            // Text decorations can be given as a list.
+            Value::Array(list) => list
+                .iter()
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => continue,
+        };
+
+        css.push_str(&format!("{}: {}; ", property, value));
+    }
+
+    css.trim_end().into()
+}
+
+/// Inlines the images referenced by the image tags in `html` as base64 data
+/// URIs, loading them from the dictionary archive.
+///
+/// Images that can't be loaded or that are in formats that Kobo e-readers
+/// don't support are replaced by their alt text, as are all images if
+/// `strip` is true.
+///
+/// `cache` maps image paths to their data URIs, to avoid loading and
+/// encoding the same image multiple times.
+fn process_images<R: Read + std::io::Seek>(
+    html: String,
+    zip_in: &mut zip::ZipArchive<R>,
+    cache: &mut HashMap<String, Option<String>>,
+    strip: bool,
+) -> String {
+    lazy_static! {
+        // Matches the image tags produced by `structured_image_to_html()`.
+        static ref IMG_TAG: Regex =
+            Regex::new("<img src=\"([^\"]*)\"(?: alt=\"([^\"]*)\")?([^>]*)/>").unwrap();
+    }
+
+    if !html.contains("<img") {
+        return html;
+    }
+
+    IMG_TAG
+        .replace_all(&html, |caps: &regex::Captures| {
+            let alt = caps.get(2).map(|m| m.as_str()).unwrap_or("");
+            if strip {
+                return alt.to_string();
+            }
+
+            let path = unescape_html(&caps[1]);
+            let data_uri = cache
+                .entry(path.clone())
+                .or_insert_with(|| {
+                    let mime = match path.rsplit('.').next()?.to_lowercase().as_str() {
+                        "png" => "image/png",
+                        "jpg" | "jpeg" => "image/jpeg",
+                        "gif" => "image/gif",
+                        _ => return None,
+                    };
+                    let mut data = Vec::new();
+                    zip_in.by_name(&path).ok()?.read_to_end(&mut data).ok()?;
+                    Some(format!("data:{};base64,{}", mime, base64_encode(&data)))
+                })
+                .clone();
+
+            match data_uri {
+                Some(uri) => format!("<img src=\"{}\" alt=\"{}\"{}/>", uri, alt, &caps[3]),
+                None => alt.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Standard base64 encoding, with padding.
+pub fn base64_encode(data: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(CHARS[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Reverses `escape_html()`.
+fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&gt;", ">")
+        .replace("&lt;", "<")
+        .replace("&amp;", "&")
+}
+
+/// Escapes the characters that have special meaning in html.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 /// Recursively process definitions.
@@ -384,12 +1260,12 @@ fn split_definition_text(
 ) -> Definition {
     // Try each divider in turn, to divide into sub-definitions.
     for divider in dividers.iter() {
-        let match_count = divider.find_iter(&s).count();
+        let match_count = divider.find_iter(s).count();
         if match_count > 0 {
             let mut list: Vec<Definition> = divider
-                .split(&s)
+                .split(s)
                 .filter(|t| !t.trim().is_empty())
-                .map(|t| split_definition_text(t.into(), dividers, furigen))
+                .map(|t| split_definition_text(t, dividers, furigen))
                 .collect();
 
             if list.is_empty() {
@@ -469,7 +1345,7 @@ pub fn definition_to_html(def: &Definition, total_depth: usize, ordered_list: bo
             }
         }
 
-        &Definition::Def(ref s) => {
+        Definition::Def(s) => {
             if total_depth == 0 {
                 if ordered_list {
                     html.push_str("<ol><li>");